@@ -47,10 +47,25 @@ export class ApiClient {
     return response.json();
   }
 
-  async deleteFile(path: string): Promise<{ success: boolean }> {
-    const response = await fetch(`${API_BASE}/file/delete?path=${encodeURIComponent(path)}`, {
+  /**
+   * A non-empty directory comes back as `{ needsConfirmation: true, token }`;
+   * pass that token with `recursive` to delete the directory and its contents.
+   */
+  async deleteFile(
+    path: string,
+    options?: { recursive: true; token: string },
+  ): Promise<{ success: true } | { needsConfirmation: true; token: string }> {
+    let url = `${API_BASE}/file/delete?path=${encodeURIComponent(path)}`;
+    if (options) {
+      url += `&recursive=true&token=${encodeURIComponent(options.token)}`;
+    }
+    const response = await fetch(url, {
       method: 'DELETE',
     });
+    if (response.status === 409) {
+      const body = await response.json();
+      return { needsConfirmation: true, token: body.confirm_token };
+    }
     if (!response.ok) {
       throw new Error(`Failed to delete file: ${response.statusText}`);
     }
@@ -69,7 +69,18 @@ class ConnectionManager: ObservableObject {
     }
 
     private static func describe(_ error: KerrError) -> String {
-        return error.localizedDescription
+        switch error {
+        case .Timeout:
+            return "The host appears to be offline, or a firewall is blocking the connection. Check that kerr serve is running and try again."
+        case .HostUnreachable:
+            return "The host appears to be offline. Check that the connection string is current and that kerr serve is running."
+        case .ConnectionRefused(let message):
+            return "The host refused the connection. It may be running an incompatible kerr version. (\(message))"
+        case .ConnectionClosed:
+            return "The connection was closed. The host may have restarted; try again."
+        default:
+            return error.localizedDescription
+        }
     }
 
     func disconnect() {
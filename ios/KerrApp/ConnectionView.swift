@@ -40,6 +40,7 @@ struct ConnectionView: View {
     @State private var connections: [SavedConnection] = SavedConnection.load()
     @State private var connectingId: UUID? = nil
     @State private var showAddSheet = false
+    @State private var prefilledConnectionString = ""
     @State private var showScanner = false
     @State private var errorMessage: String? = nil
     @State private var successMessage: String? = nil
@@ -88,12 +89,15 @@ struct ConnectionView: View {
                 }
             }
         }
-        .fullScreenCover(isPresented: $showAddSheet) {
-            AddConnectionSheet { saved in
+        .fullScreenCover(isPresented: $showAddSheet, onDismiss: { prefilledConnectionString = "" }) {
+            AddConnectionSheet(initialConnectionString: prefilledConnectionString) { saved in
                 connections.append(saved)
                 SavedConnection.save(connections)
             }
         }
+        .onOpenURL { url in
+            handleOpenURL(url.absoluteString)
+        }
         .sheet(isPresented: $showScanner) {
             QRScannerSheet { payload in
                 handleScannedQR(payload)
@@ -212,9 +216,25 @@ struct ConnectionView: View {
         }
     }
 
+    // kerr://connect/<connection string> — open the add sheet pre-filled
+    private func handleOpenURL(_ url: String) {
+        errorMessage = nil
+        successMessage = nil
+        do {
+            prefilledConnectionString = try parseKerrUrl(url: url)
+            showAddSheet = true
+        } catch {
+            errorMessage = "Invalid Kerr link: \(error.localizedDescription)"
+        }
+    }
+
     private func handleScannedQR(_ payload: String) {
         errorMessage = nil
         successMessage = nil
+        if payload.hasPrefix("kerr://") {
+            handleOpenURL(payload)
+            return
+        }
         guard let data = payload.data(using: .utf8),
               let qr = try? JSONDecoder().decode(QRConnectionPayload.self, from: data)
         else {
@@ -250,7 +270,12 @@ struct AddConnectionSheet: View {
     @Environment(\.dismiss) private var dismiss
     @State private var alias = ""
     @State private var hostName = ""
-    @State private var connectionString = ""
+    @State private var connectionString: String
+
+    init(initialConnectionString: String = "", onSave: @escaping (SavedConnection) -> Void) {
+        self.onSave = onSave
+        _connectionString = State(initialValue: initialConnectionString)
+    }
 
     var body: some View {
         NavigationStack {
@@ -1,5 +1,6 @@
 use std::sync::Arc;
-use crate::{KerrError, Session, decode_addr, encode_addr, ALPN};
+use crate::{KerrError, ConnectionObserver, ConnectionPhase, Session, decode_addr, encode_addr, ALPN};
+use crate::session::{Observer, report_error, report_phase};
 
 pub struct Endpoint {
     inner: Arc<iroh::endpoint::Endpoint>,
@@ -7,9 +8,14 @@ pub struct Endpoint {
 
 impl Endpoint {
     pub async fn new() -> Result<Arc<Self>, KerrError> {
+        Self::bind(None).await
+    }
+
+    pub(crate) async fn bind(observer: Option<&Observer>) -> Result<Arc<Self>, KerrError> {
+        report_phase(observer, ConnectionPhase::Binding);
         let endpoint = iroh::endpoint::Endpoint::bind(iroh::endpoint::presets::N0)
             .await
-            .map_err(|e| KerrError::ConnectionFailed(e.to_string()))?;
+            .map_err(|e| report_error(observer, KerrError::ConnectionFailed(e.to_string())))?;
 
         Ok(Arc::new(Self {
             inner: Arc::new(endpoint),
@@ -18,20 +24,39 @@ impl Endpoint {
 
     pub fn connect(self: Arc<Self>, connection_string: String) -> Result<Arc<Session>, KerrError> {
         let runtime = crate::get_runtime();
-        runtime.block_on(async {
-            // Decode connection string
-            let addr = decode_addr(&connection_string)?;
-
-            // Connect to the remote
-            let conn = self
-                .inner
-                .connect(addr, ALPN)
-                .await
-                .map_err(|e| KerrError::ConnectionFailed(e.to_string()))?;
-
-            // Create session
-            Session::new(conn).await
-        })
+        runtime.block_on(self.connect_observed(connection_string, None))
+    }
+
+    // Same as connect, reporting progress to the observer
+    pub fn connect_with_observer(
+        self: Arc<Self>,
+        connection_string: String,
+        observer: Box<dyn ConnectionObserver>,
+    ) -> Result<Arc<Session>, KerrError> {
+        let runtime = crate::get_runtime();
+        runtime.block_on(self.connect_observed(connection_string, Some(Arc::new(observer))))
+    }
+
+    pub(crate) async fn connect_observed(
+        self: Arc<Self>,
+        connection_string: String,
+        observer: Option<Observer>,
+    ) -> Result<Arc<Session>, KerrError> {
+        report_phase(observer.as_ref(), ConnectionPhase::Connecting);
+
+        // Decode connection string
+        let addr = decode_addr(&connection_string).map_err(|e| report_error(observer.as_ref(), e))?;
+
+        // Connect to the remote
+        let conn = self
+            .inner
+            .connect(addr, ALPN)
+            .await
+            .map_err(|e| report_error(observer.as_ref(), KerrError::from_connect(e)))?;
+        report_phase(observer.as_ref(), ConnectionPhase::Connected);
+
+        // Create session
+        Session::new(conn, Arc::clone(&self), observer).await
     }
 
     pub fn connection_string(&self) -> Result<String, KerrError> {
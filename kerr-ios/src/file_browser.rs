@@ -1,5 +1,7 @@
+use std::collections::{HashMap, VecDeque};
 use std::sync::Arc;
-use tokio::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+use tokio::sync::{mpsc, oneshot, Mutex};
 use crate::{
     KerrError, FileEntry, FileMetadata, MessageEnvelope, MessagePayload,
     ClientMessage, ServerMessage, SessionType, send_envelope, recv_envelope,
@@ -17,7 +19,7 @@ impl FileBrowser {
         let (mut send, recv) = conn
             .open_bi()
             .await
-            .map_err(|e| KerrError::ConnectionFailed(e.to_string()))?;
+            .map_err(KerrError::from_connection)?;
 
         let session_id = format!("browser_{}", std::process::id());
 
@@ -221,3 +223,262 @@ impl FileBrowser {
         }
     }
 }
+
+// Upload chunk size for FileBrowserSession::write_file - same as the desktop browser
+const WRITE_CHUNK_SIZE: usize = 65536;
+
+// Gives each FileBrowserSession in this process its own session ID
+static NEXT_BROWSER_SESSION: AtomicU64 = AtomicU64::new(1);
+
+// Where replies read off the shared stream go
+#[derive(Default)]
+struct Routes {
+    // Requests on the main session, oldest first. The server answers a session's
+    // requests in order, so each reply belongs to the front entry.
+    pending: VecDeque<(u64, oneshot::Sender<ServerMessage>)>,
+    // Writes run on their own session each, so a stray reply can't shift `pending`
+    uploads: HashMap<String, mpsc::UnboundedSender<ServerMessage>>,
+    closed: bool,
+}
+
+struct BrowserStream {
+    send: Mutex<iroh::endpoint::SendStream>,
+    session_id: String,
+    next_request_id: AtomicU64,
+    routes: Arc<std::sync::Mutex<Routes>>,
+}
+
+// File browser over one stream of the session's connection. Unlike FileBrowser,
+// calls don't wait for each other: requests are pipelined and matched to replies
+// by request ID.
+pub struct FileBrowserSession {
+    stream: Arc<BrowserStream>,
+    reader_task: tokio::task::JoinHandle<()>,
+}
+
+impl FileBrowserSession {
+    pub(crate) async fn new(conn: Arc<iroh::endpoint::Connection>) -> Result<Arc<Self>, KerrError> {
+        let (mut send, mut recv) = conn
+            .open_bi()
+            .await
+            .map_err(KerrError::from_connection)?;
+
+        let session_id = format!(
+            "browser_{}_{}",
+            std::process::id(),
+            NEXT_BROWSER_SESSION.fetch_add(1, Ordering::Relaxed)
+        );
+        let hello_envelope = MessageEnvelope {
+            session_id: session_id.clone(),
+            payload: MessagePayload::Client(ClientMessage::Hello {
+                session_type: SessionType::FileBrowser,
+            }),
+        };
+        send_envelope(&mut send, &hello_envelope).await?;
+
+        let routes = Arc::new(std::sync::Mutex::new(Routes::default()));
+        let reader_task = {
+            let routes = Arc::clone(&routes);
+            let session_id = session_id.clone();
+            tokio::spawn(async move {
+                while let Ok(envelope) = recv_envelope(&mut recv).await {
+                    let MessagePayload::Server(msg) = envelope.payload else {
+                        continue;
+                    };
+                    let mut routes = routes.lock().unwrap();
+                    if let Some(upload) = routes.uploads.get(&envelope.session_id) {
+                        let _ = upload.send(msg);
+                    } else if envelope.session_id == session_id || envelope.session_id.is_empty() {
+                        // An empty session is a server rejecting a message it doesn't know
+                        if let Some((_, reply)) = routes.pending.pop_front() {
+                            let _ = reply.send(msg);
+                        }
+                    }
+                }
+
+                // Wake everyone still waiting; their channels close
+                let mut routes = routes.lock().unwrap();
+                routes.closed = true;
+                routes.pending.clear();
+                routes.uploads.clear();
+            })
+        };
+
+        Ok(Arc::new(Self {
+            stream: Arc::new(BrowserStream {
+                send: Mutex::new(send),
+                session_id,
+                next_request_id: AtomicU64::new(1),
+                routes,
+            }),
+            reader_task,
+        }))
+    }
+
+    pub(crate) fn is_closed(&self) -> bool {
+        self.stream.routes.lock().map(|r| r.closed).unwrap_or(true)
+    }
+
+    // Run on the kerr runtime: iroh streams need it, and Swift polls from its own executor
+    async fn run<T, F, Fut>(&self, op: F) -> Result<T, KerrError>
+    where
+        T: Send + 'static,
+        F: FnOnce(Arc<BrowserStream>) -> Fut,
+        Fut: std::future::Future<Output = Result<T, KerrError>> + Send + 'static,
+    {
+        crate::get_runtime()
+            .spawn(op(Arc::clone(&self.stream)))
+            .await
+            .map_err(|e| KerrError::NetworkError(e.to_string()))?
+    }
+
+    pub async fn list_dir(&self, path: String) -> Result<Vec<FileEntry>, KerrError> {
+        self.run(|stream| async move {
+            match stream.request(ClientMessage::FsReadDir { path }).await? {
+                ServerMessage::FsDirListing { entries_json } => parse_entries(&entries_json),
+                other => Err(unexpected(other)),
+            }
+        })
+        .await
+    }
+
+    pub async fn read_file(&self, path: String) -> Result<Vec<u8>, KerrError> {
+        self.run(|stream| async move {
+            match stream.request(ClientMessage::FsReadFile { path }).await? {
+                ServerMessage::FsFileContent { data } => Ok(data),
+                other => Err(unexpected(other)),
+            }
+        })
+        .await
+    }
+
+    pub async fn metadata(&self, path: String) -> Result<FileMetadata, KerrError> {
+        self.run(|stream| async move {
+            match stream.request(ClientMessage::FsMetadata { path }).await? {
+                ServerMessage::FsMetadataResponse { metadata_json } => parse_metadata(&metadata_json),
+                other => Err(unexpected(other)),
+            }
+        })
+        .await
+    }
+
+    pub async fn delete(&self, path: String) -> Result<(), KerrError> {
+        self.run(|stream| async move {
+            match stream.request(ClientMessage::FsDelete { path }).await? {
+                ServerMessage::FsDeleteResponse { success: true } => Ok(()),
+                ServerMessage::FsDeleteResponse { success: false } => {
+                    Err(KerrError::FileSystemError("Delete failed".to_string()))
+                }
+                other => Err(unexpected(other)),
+            }
+        })
+        .await
+    }
+
+    pub async fn write_file(&self, path: String, data: Vec<u8>) -> Result<(), KerrError> {
+        self.run(|stream| async move { stream.write_file(path, data).await }).await
+    }
+}
+
+impl Drop for FileBrowserSession {
+    fn drop(&mut self) {
+        self.reader_task.abort();
+    }
+}
+
+impl BrowserStream {
+    fn stream_closed() -> KerrError {
+        KerrError::NetworkError("File browser stream closed".to_string())
+    }
+
+    // Send one request on the main session and wait for its reply
+    async fn request(&self, msg: ClientMessage) -> Result<ServerMessage, KerrError> {
+        let request_id = self.next_request_id.fetch_add(1, Ordering::Relaxed);
+        let (reply_tx, reply_rx) = oneshot::channel();
+
+        // Queue and send under the send lock so queue order matches wire order
+        {
+            let mut send = self.send.lock().await;
+            {
+                let mut routes = self.routes.lock().unwrap();
+                if routes.closed {
+                    return Err(Self::stream_closed());
+                }
+                routes.pending.push_back((request_id, reply_tx));
+            }
+
+            let envelope = MessageEnvelope {
+                session_id: self.session_id.clone(),
+                payload: MessagePayload::Client(msg),
+            };
+            if let Err(e) = send_envelope(&mut *send, &envelope).await {
+                self.routes.lock().unwrap().pending.retain(|(id, _)| *id != request_id);
+                return Err(e);
+            }
+        }
+
+        reply_rx.await.map_err(|_| Self::stream_closed())
+    }
+
+    async fn write_file(&self, path: String, data: Vec<u8>) -> Result<(), KerrError> {
+        let request_id = self.next_request_id.fetch_add(1, Ordering::Relaxed);
+        let upload_session = format!("{}_write_{}", self.session_id, request_id);
+        let (reply_tx, mut reply_rx) = mpsc::unbounded_channel();
+        {
+            let mut routes = self.routes.lock().unwrap();
+            if routes.closed {
+                return Err(Self::stream_closed());
+            }
+            routes.uploads.insert(upload_session.clone(), reply_tx);
+        }
+
+        let size = data.len() as u64;
+        let mut messages = vec![
+            ClientMessage::Hello { session_type: SessionType::FileBrowser },
+            ClientMessage::StartUpload { path, size, is_dir: false, force: true },
+        ];
+        messages.extend(data.chunks(WRITE_CHUNK_SIZE).map(|chunk| ClientMessage::FileChunk { data: chunk.to_vec() }));
+        messages.push(ClientMessage::EndUpload);
+
+        let result: Result<(), KerrError> = async {
+            {
+                let mut send = self.send.lock().await;
+                for msg in messages {
+                    let envelope = MessageEnvelope {
+                        session_id: upload_session.clone(),
+                        payload: MessagePayload::Client(msg),
+                    };
+                    send_envelope(&mut *send, &envelope).await?;
+                }
+            }
+
+            // The server acks after EndUpload; a failure may come first, as an Error
+            match reply_rx.recv().await {
+                Some(ServerMessage::UploadAck) => Ok(()),
+                Some(other) => Err(unexpected(other)),
+                None => Err(Self::stream_closed()),
+            }
+        }
+        .await;
+
+        // End the server's handler for this write; anything it still sends is dropped
+        self.routes.lock().unwrap().uploads.remove(&upload_session);
+        let disconnect = MessageEnvelope {
+            session_id: upload_session,
+            payload: MessagePayload::Client(ClientMessage::Disconnect),
+        };
+        let _ = send_envelope(&mut *self.send.lock().await, &disconnect).await;
+
+        result
+    }
+}
+
+// Turn an error reply (or anything unexpected) into a KerrError
+fn unexpected(msg: ServerMessage) -> KerrError {
+    match msg {
+        ServerMessage::FsError { message } | ServerMessage::Error { message } => {
+            KerrError::FileSystemError(message)
+        }
+        _ => KerrError::FileSystemError("Unexpected response".to_string()),
+    }
+}
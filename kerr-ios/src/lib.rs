@@ -43,6 +43,19 @@ pub fn create_endpoint() -> Result<Arc<Endpoint>, KerrError> {
     })
 }
 
+// Bind a new endpoint and connect, reporting every phase to the observer
+pub fn connect_with_observer(
+    connection_string: String,
+    observer: Box<dyn ConnectionObserver>,
+) -> Result<Arc<Session>, KerrError> {
+    let observer: session::Observer = Arc::new(observer);
+    let runtime = get_runtime();
+    runtime.block_on(async {
+        let endpoint = Endpoint::bind(Some(&observer)).await?;
+        endpoint.connect_observed(connection_string, Some(observer)).await
+    })
+}
+
 pub fn decode_connection_string(conn_str: String) -> Result<String, KerrError> {
     // Decode and return a human-readable description
     // In practice, this validates the connection string
@@ -50,11 +63,36 @@ pub fn decode_connection_string(conn_str: String) -> Result<String, KerrError> {
     Ok(format!("Valid connection string"))
 }
 
+/// Extract and validate the connection string embedded in a `kerr://connect/<string>` URL
+pub fn parse_kerr_url(url: String) -> Result<String, KerrError> {
+    let conn_str = url
+        .trim()
+        .strip_prefix(CONNECTION_URL_PREFIX)
+        .ok_or_else(|| KerrError::InvalidConnectionString(format!("not a {CONNECTION_URL_PREFIX} URL")))?
+        .to_string();
+    decode_addr(&conn_str)?;
+    Ok(conn_str)
+}
+
+// URL prefix for deep links - must match CONNECTION_URL_PREFIX in the parent crate
+const CONNECTION_URL_PREFIX: &str = "kerr://connect/";
+
+// Marker for uncompressed connection strings - must match RAW_CONNECTION_STRING_PREFIX in the parent crate
+const RAW_CONNECTION_STRING_PREFIX: &str = "kerr-raw1:";
+
 // Helper to decode connection string (from parent crate logic)
 fn decode_addr(conn_str: &str) -> Result<iroh::EndpointAddr, KerrError> {
     let trimmed = conn_str.trim();
     eprintln!("[kerr] decode_addr: input length={}", trimmed.len());
 
+    // Uncompressed form: the JSON follows the marker directly
+    if let Some(json_str) = trimmed.strip_prefix(RAW_CONNECTION_STRING_PREFIX) {
+        return serde_json::from_str(json_str).map_err(|e| {
+            eprintln!("[kerr] decode_addr: raw json parse failed: {e}");
+            KerrError::InvalidConnectionString(format!("json parse failed: {e}"))
+        });
+    }
+
     // Decode base64 (URL-safe, no padding)
     let compressed = base64::engine::general_purpose::URL_SAFE_NO_PAD
         .decode(trimmed)
@@ -87,6 +125,9 @@ fn decode_addr(conn_str: &str) -> Result<iroh::EndpointAddr, KerrError> {
     Ok(addr)
 }
 
+// Gzip level for connection strings - must match CONNECTION_STRING_COMPRESSION in the parent crate
+const CONNECTION_STRING_COMPRESSION: u32 = 9;
+
 // Helper to encode connection string
 #[allow(dead_code)]
 fn encode_addr(addr: &iroh::EndpointAddr) -> Result<String, KerrError> {
@@ -95,7 +136,10 @@ fn encode_addr(addr: &iroh::EndpointAddr) -> Result<String, KerrError> {
     let json_str = serde_json::to_string(addr)
         .map_err(|e| KerrError::NetworkError(e.to_string()))?;
 
-    let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+    let mut encoder = flate2::write::GzEncoder::new(
+        Vec::new(),
+        flate2::Compression::new(CONNECTION_STRING_COMPRESSION),
+    );
     encoder
         .write_all(json_str.as_bytes())
         .map_err(|e| KerrError::NetworkError(e.to_string()))?;
@@ -118,6 +162,9 @@ pub enum SessionType {
     Ping,
     HttpProxy,
     Dns,
+    Echo,
+    UdpRelay,
+    ReverseRelay,
 }
 
 #[derive(Debug, Archive, RkyvSerialize, RkyvDeserialize)]
@@ -134,6 +181,30 @@ pub enum MessagePayload {
     Server(ServerMessage),
 }
 
+#[derive(Debug, Archive, RkyvSerialize, RkyvDeserialize)]
+#[rkyv(derive(Debug))]
+pub enum FsOp {
+    Mkdir { path: String },
+    Delete { path: String },
+    Rename { from: String, to: String },
+    Chmod { path: String, mode: u32 },
+}
+
+#[derive(Debug, Archive, RkyvSerialize, RkyvDeserialize)]
+#[rkyv(derive(Debug))]
+pub struct FsOpResult {
+    pub success: bool,
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Archive, RkyvSerialize, RkyvDeserialize)]
+#[rkyv(derive(Debug))]
+pub struct CompletedFile {
+    pub relative_path: String,
+    pub size: u64,
+    pub modified: Option<i64>,
+}
+
 #[derive(Debug, Archive, RkyvSerialize, RkyvDeserialize)]
 #[rkyv(derive(Debug))]
 pub enum ClientMessage {
@@ -157,6 +228,34 @@ pub enum ClientMessage {
     TcpClose { stream_id: u32 },
     PingRequest { data: Vec<u8> },
     DnsQuery { query_id: u32, query_data: Vec<u8> },
+    FileAttributes { relative_path: String, modified: Option<i64>, mode: Option<u32> },
+    FsBatch { ops: Vec<FsOp> },
+    FsLimits,
+    ShellHello { cwd: Option<String> },
+    TransferOptions { dereference: bool },
+    Symlink { relative_path: String, target: String },
+    FsDeleteRecursive { path: String },
+    SkipFiles { files: Vec<CompletedFile> },
+    SparseFiles,
+    FileHole { len: u64 },
+    CancelDownload,
+    FsWriteFile { path: String, data: Vec<u8> },
+    FsRename { from: String, to: String },
+    FsMkdir { path: String },
+    CompressedChunks,
+    CompressedChunk { data: Vec<u8> },
+    DownloadHashes,
+    ResumeUpload { offset: u64, hash: String },
+    Auth { secret: String },
+    FsMode,
+    Heartbeats,
+    ShellHelloEnv { cwd: Option<String>, term: Option<String> },
+    UdpOpen { stream_id: u32, destination_host: Option<String>, destination_port: u16 },
+    UdpData { stream_id: u32, data: Vec<u8> },
+    UdpClose { stream_id: u32 },
+    ReverseListen { port: u16 },
+    VersionRequest,
+    ExecHello { command: String },
 }
 
 #[derive(Debug, Archive, RkyvSerialize, RkyvDeserialize)]
@@ -182,9 +281,34 @@ pub enum ServerMessage {
     TcpCloseResponse { stream_id: u32, error: Option<String> },
     PingResponse { data: Vec<u8> },
     DnsResponse { query_id: u32, response_data: Vec<u8> },
+    FileAttributes { relative_path: String, modified: Option<i64>, mode: Option<u32> },
+    FsBatchResponse { results: Vec<FsOpResult> },
+    FsLimitsResponse { max_file_read: Option<u64>, max_upload: Option<u64> },
+    UploadComplete { success: bool, error: Option<String> },
+    Symlink { relative_path: String, target: String },
+    Preparing { entries_scanned: u64 },
+    FileHole { len: u64 },
+    FsWriteResponse { success: bool },
+    FsRenameResponse { success: bool },
+    FsMkdirResponse { success: bool },
+    CompressedChunk { data: Vec<u8> },
+    DownloadComplete { hash: String },
+    UploadResumed { offset: u64 },
+    AuthResult { ok: bool },
+    FsModeResponse { read_only: bool, root: Option<String> },
+    Heartbeat { interval_ms: u64 },
+    Exit { code: i32 },
+    UdpOpenResponse { stream_id: u32, success: bool, error: Option<String> },
+    UdpDataResponse { stream_id: u32, data: Vec<u8> },
+    UdpCloseResponse { stream_id: u32, error: Option<String> },
+    ReverseListenResponse { success: bool, error: Option<String> },
+    TcpIncoming { stream_id: u32, peer: String },
+    VersionResponse { version: String },
+    ErrorOutput { data: Vec<u8> },
 }
 
 // Helper to send envelope
+// Frame: u32 big-endian length + rkyv bytes - must match send_envelope/recv_envelope in the parent crate
 async fn send_envelope(
     send: &mut iroh::endpoint::SendStream,
     envelope: &MessageEnvelope,
@@ -224,3 +348,25 @@ async fn recv_envelope(
         .map_err(|e| KerrError::NetworkError(e.to_string()))?;
     Ok(envelope)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Same string and address as the parent crate's connection_string_round_trip test
+    const GOLDEN_CONNECTION_STRING: &str =
+        "H4sIAAAAAAAC_xWMQQrDIBAA_7LnYOK6KvqDXnstPay6oYWEhiSHFvHvNcxpYJgK7wIRhIlddkYwZIsTJ5mtnXzSBiXbOQRL3juW1PEpIKEWYRc0kSuIGQbgUvYD4qPCXRb-9enrPLcjjuN-uZIvr9siKn_WEdpQ4bb1RgdUk0KlI5Ex0J7tD7owfbCSAAAA";
+
+    fn golden_addr() -> iroh::EndpointAddr {
+        iroh::EndpointAddr::new(iroh::SecretKey::from_bytes(&[7; 32]).public())
+            .with_ip_addr("192.0.2.1:4433".parse().unwrap())
+            .with_relay_url("https://relay.example.com".parse().unwrap())
+    }
+
+    #[test]
+    fn connection_string_matches_parent_crate() {
+        let addr = golden_addr();
+        assert_eq!(encode_addr(&addr).unwrap(), GOLDEN_CONNECTION_STRING);
+        assert_eq!(decode_addr(GOLDEN_CONNECTION_STRING).unwrap(), addr);
+    }
+}
@@ -1,21 +1,80 @@
 use std::sync::Arc;
 use tokio::sync::Mutex;
-use crate::{KerrError, FileBrowser, ShellSession, ShellCallback, VpnTunnel};
+use crate::{KerrError, ConnectionPhase, Endpoint, FileBrowser, FileBrowserSession, ShellSession, ShellCallback, VpnTunnel};
+
+// Observer for connection progress - will be implemented in Swift
+pub trait ConnectionObserver: Send + Sync {
+    fn on_phase(&self, phase: ConnectionPhase);
+    fn on_error(&self, error: KerrError);
+}
+
+pub(crate) type Observer = Arc<Box<dyn ConnectionObserver>>;
+
+// Tell the observer (if any) about a phase
+pub(crate) fn report_phase(observer: Option<&Observer>, phase: ConnectionPhase) {
+    if let Some(observer) = observer {
+        observer.on_phase(phase);
+    }
+}
+
+// Tell the observer (if any) about an error and hand it back for `?`
+pub(crate) fn report_error(observer: Option<&Observer>, error: KerrError) -> KerrError {
+    if let Some(observer) = observer {
+        observer.on_error(error.clone());
+    }
+    error
+}
 
 pub struct Session {
     conn: Arc<iroh::endpoint::Connection>,
+    // Keeps the endpoint bound for as long as the session is in use
+    _endpoint: Arc<Endpoint>,
     file_browser: Arc<Mutex<Option<Arc<FileBrowser>>>>,
+    browser_session: Arc<Mutex<Option<Arc<FileBrowserSession>>>>,
     shell_session: Arc<Mutex<Option<Arc<ShellSession>>>>,
     connected: Arc<Mutex<bool>>,
+    observer: Option<Observer>,
+    close_watcher: tokio::task::JoinHandle<()>,
 }
 
 impl Session {
-    pub async fn new(conn: iroh::endpoint::Connection) -> Result<Arc<Self>, KerrError> {
+    pub(crate) async fn new(
+        conn: iroh::endpoint::Connection,
+        endpoint: Arc<Endpoint>,
+        observer: Option<Observer>,
+    ) -> Result<Arc<Self>, KerrError> {
+        let conn = Arc::new(conn);
+        let connected = Arc::new(Mutex::new(true));
+
+        // Notice when the connection goes away, whoever closed it
+        let close_watcher = {
+            let conn = Arc::clone(&conn);
+            let connected = Arc::clone(&connected);
+            let observer = observer.clone();
+            tokio::spawn(async move {
+                let reason = conn.closed().await;
+                *connected.lock().await = false;
+                let clean = matches!(
+                    reason,
+                    iroh::endpoint::ConnectionError::LocallyClosed
+                        | iroh::endpoint::ConnectionError::ApplicationClosed(_)
+                );
+                if !clean {
+                    report_error(observer.as_ref(), KerrError::NetworkError(reason.to_string()));
+                }
+                report_phase(observer.as_ref(), ConnectionPhase::Disconnected);
+            })
+        };
+
         Ok(Arc::new(Self {
-            conn: Arc::new(conn),
+            conn,
+            _endpoint: endpoint,
             file_browser: Arc::new(Mutex::new(None)),
+            browser_session: Arc::new(Mutex::new(None)),
             shell_session: Arc::new(Mutex::new(None)),
-            connected: Arc::new(Mutex::new(true)),
+            connected,
+            observer,
+            close_watcher,
         }))
     }
 
@@ -36,6 +95,24 @@ impl Session {
         })
     }
 
+    pub fn file_browser_session(self: Arc<Self>) -> Result<Arc<FileBrowserSession>, KerrError> {
+        let runtime = crate::get_runtime();
+        runtime.block_on(async {
+            let mut session_lock = self.browser_session.lock().await;
+
+            // Reuse the open one; replace it if its stream has gone away
+            if let Some(session) = session_lock.as_ref() {
+                if !session.is_closed() {
+                    return Ok(Arc::clone(session));
+                }
+            }
+
+            let session = FileBrowserSession::new(Arc::clone(&self.conn)).await?;
+            *session_lock = Some(Arc::clone(&session));
+            Ok(session)
+        })
+    }
+
     pub fn start_shell(
         self: Arc<Self>,
         callback: Box<dyn ShellCallback>,
@@ -50,7 +127,7 @@ impl Session {
             }
 
             // Create new shell session
-            let shell = ShellSession::new(Arc::clone(&self.conn), callback).await?;
+            let shell = ShellSession::new(Arc::clone(&self.conn), callback, self.observer.clone()).await?;
             *shell_lock = Some(Arc::clone(&shell));
             Ok(shell)
         })
@@ -95,3 +172,9 @@ impl Session {
         })
     }
 }
+
+impl Drop for Session {
+    fn drop(&mut self) {
+        self.close_watcher.abort();
+    }
+}
@@ -1,9 +1,10 @@
 use std::sync::Arc;
 use tokio::sync::Mutex;
 use crate::{
-    KerrError, MessageEnvelope, MessagePayload, ClientMessage, ServerMessage,
+    KerrError, ConnectionPhase, MessageEnvelope, MessagePayload, ClientMessage, ServerMessage,
     SessionType, send_envelope, recv_envelope,
 };
+use crate::session::{Observer, report_error, report_phase};
 
 // Callback trait for shell output - will be implemented in Swift
 pub trait ShellCallback: Send + Sync {
@@ -24,12 +25,13 @@ impl ShellSession {
     pub async fn new(
         conn: Arc<iroh::endpoint::Connection>,
         callback: Box<dyn ShellCallback>,
+        observer: Option<Observer>,
     ) -> Result<Arc<Self>, KerrError> {
         // Open a bidirectional stream
         let (mut send, recv) = conn
             .open_bi()
             .await
-            .map_err(|e| KerrError::ConnectionFailed(e.to_string()))?;
+            .map_err(|e| report_error(observer.as_ref(), KerrError::from_connection(e)))?;
 
         // Generate session ID
         let session_id = format!("shell_{}", std::process::id());
@@ -42,7 +44,10 @@ impl ShellSession {
             }),
         };
 
-        send_envelope(&mut send, &hello_envelope).await?;
+        send_envelope(&mut send, &hello_envelope)
+            .await
+            .map_err(|e| report_error(observer.as_ref(), e))?;
+        report_phase(observer.as_ref(), ConnectionPhase::HandshakeComplete);
 
         let callback = Arc::new(callback);
         let recv_shared = Arc::new(Mutex::new(recv));
@@ -54,7 +59,7 @@ impl ShellSession {
             let session_id_clone = session_id.clone();
 
             tokio::spawn(async move {
-                Self::receive_loop(recv_clone, callback_clone, session_id_clone).await;
+                Self::receive_loop(recv_clone, callback_clone, session_id_clone, observer).await;
             })
         };
 
@@ -71,7 +76,10 @@ impl ShellSession {
         recv: Arc<Mutex<iroh::endpoint::RecvStream>>,
         callback: Arc<Box<dyn ShellCallback>>,
         session_id: String,
+        observer: Option<Observer>,
     ) {
+        // The session counts as active once the shell has said something
+        let mut active = false;
         loop {
             let mut recv_guard = recv.lock().await;
             let result = recv_envelope(&mut *recv_guard).await;
@@ -85,10 +93,18 @@ impl ShellSession {
 
                     match envelope.payload {
                         MessagePayload::Server(ServerMessage::Output { data }) => {
+                            if !active {
+                                active = true;
+                                report_phase(observer.as_ref(), ConnectionPhase::SessionActive);
+                            }
                             let text = String::from_utf8_lossy(&data).to_string();
                             callback.on_output(text);
                         }
                         MessagePayload::Server(ServerMessage::Error { message }) => {
+                            // Before any output this means the shell never started
+                            if !active {
+                                report_error(observer.as_ref(), KerrError::ShellError(message.clone()));
+                            }
                             callback.on_error(message);
                         }
                         _ => {}
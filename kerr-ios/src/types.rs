@@ -1,6 +1,6 @@
 
 // Error type exposed to Swift via UniFFI
-#[derive(Debug, thiserror::Error)]
+#[derive(Debug, Clone, thiserror::Error)]
 pub enum KerrError {
     #[error("Connection failed: {0}")]
     ConnectionFailed(String),
@@ -14,6 +14,58 @@ pub enum KerrError {
     NetworkError(String),
     #[error("Operation timed out")]
     Timeout,
+    // No address or path to the host was found; it is probably offline
+    #[error("Host unreachable: {0}")]
+    HostUnreachable(String),
+    // The host answered but turned the connection down
+    #[error("Connection refused: {0}")]
+    ConnectionRefused(String),
+    // The connection was closed before it could be used
+    #[error("Connection closed: {0}")]
+    ConnectionClosed(String),
+}
+
+impl KerrError {
+    // Classify a failed connect so the app can tell an offline host from a refusal
+    pub(crate) fn from_connect(err: iroh::endpoint::ConnectError) -> Self {
+        use iroh::endpoint::{ConnectError, ConnectWithOptsError, ConnectingError};
+
+        let message = err.to_string();
+        match err {
+            ConnectError::Connect { source, .. } => match source {
+                ConnectWithOptsError::NoAddress { .. } => KerrError::HostUnreachable(message),
+                ConnectWithOptsError::LocallyRejected { .. } => KerrError::ConnectionRefused(message),
+                ConnectWithOptsError::EndpointClosed { .. } => KerrError::ConnectionClosed(message),
+                _ => KerrError::ConnectionFailed(message),
+            },
+            ConnectError::Connecting { source, .. } => match source {
+                ConnectingError::ConnectionError { source, .. } => Self::from_connection(source),
+                ConnectingError::HandshakeFailure { .. } | ConnectingError::LocallyRejected { .. } => {
+                    KerrError::ConnectionRefused(message)
+                }
+                _ => KerrError::ConnectionFailed(message),
+            },
+            ConnectError::Connection { source, .. } => Self::from_connection(source),
+            _ => KerrError::ConnectionFailed(message),
+        }
+    }
+
+    // Classify an error from an established connection, e.g. from open_bi
+    pub(crate) fn from_connection(err: iroh::endpoint::ConnectionError) -> Self {
+        use iroh::endpoint::ConnectionError;
+
+        let message = err.to_string();
+        match err {
+            ConnectionError::TimedOut => KerrError::Timeout,
+            ConnectionError::ConnectionClosed(_) | ConnectionError::VersionMismatch => {
+                KerrError::ConnectionRefused(message)
+            }
+            ConnectionError::ApplicationClosed(_)
+            | ConnectionError::Reset
+            | ConnectionError::LocallyClosed => KerrError::ConnectionClosed(message),
+            _ => KerrError::ConnectionFailed(message),
+        }
+    }
 }
 
 // Implement conversion from anyhow::Error
@@ -30,6 +82,18 @@ impl From<std::io::Error> for KerrError {
     }
 }
 
+// Connection lifecycle phases reported to a ConnectionObserver, in order.
+// Mirrors the desktop client: bind, dial, QUIC up, Hello sent, first shell output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectionPhase {
+    Binding,
+    Connecting,
+    Connected,
+    HandshakeComplete,
+    SessionActive,
+    Disconnected,
+}
+
 // File entry for Swift
 #[derive(Debug, Clone)]
 pub struct FileEntry {
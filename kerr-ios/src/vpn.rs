@@ -49,7 +49,7 @@ impl VpnTunnel {
         let (mut quic_send, mut quic_recv) = conn
             .open_bi()
             .await
-            .map_err(|e| KerrError::ConnectionFailed(format!("open_bi: {e}")))?;
+            .map_err(KerrError::from_connection)?;
 
         let session_id = format!("vpn_{}", std::process::id());
 
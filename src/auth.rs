@@ -68,6 +68,36 @@ pub struct ConnectionsListResponse {
     pub count: usize,
     #[serde(default)]
     pub from_cache: bool,
+    /// When the cache was last written (Unix seconds), set only when `from_cache` is true
+    #[serde(default)]
+    pub cached_at: Option<u64>,
+}
+
+impl ConnectionsListResponse {
+    /// Human-readable age of cached data, e.g. "updated 3h ago" (None for live data)
+    pub fn staleness(&self) -> Option<String> {
+        if !self.from_cache {
+            return None;
+        }
+        let Some(cached_at) = self.cached_at else {
+            return Some("last update time unknown".to_string());
+        };
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        let age = now.saturating_sub(cached_at);
+        let age = if age < 60 {
+            format!("{}s", age)
+        } else if age < 3600 {
+            format!("{}m", age / 60)
+        } else if age < 86400 {
+            format!("{}h", age / 3600)
+        } else {
+            format!("{}d", age / 86400)
+        };
+        Some(format!("updated {} ago", age))
+    }
 }
 
 /// Generate a random state token for CSRF protection
@@ -158,6 +188,12 @@ fn save_connections_cache(connections: &[Connection]) -> Result<()> {
     Ok(())
 }
 
+/// When the connections cache was last written (Unix seconds)
+fn connections_cache_time() -> Option<u64> {
+    let modified = fs::metadata(get_cache_file_path().ok()?).ok()?.modified().ok()?;
+    modified.duration_since(std::time::UNIX_EPOCH).ok().map(|d| d.as_secs())
+}
+
 /// Load connections from the local filesystem cache, returns None if not available
 fn load_connections_cache() -> Option<Vec<Connection>> {
     let cache_file = get_cache_file_path().ok()?;
@@ -490,7 +526,12 @@ pub async fn fetch_connections() -> Result<ConnectionsListResponse> {
     // Fall back to local filesystem cache
     if let Some(cached) = load_connections_cache() {
         let count = cached.len();
-        return Ok(ConnectionsListResponse { connections: cached, count, from_cache: true });
+        return Ok(ConnectionsListResponse {
+            connections: cached,
+            count,
+            from_cache: true,
+            cached_at: connections_cache_time(),
+        });
     }
 
     Err(n0_snafu::Error::anyhow(anyhow::anyhow!(
@@ -498,6 +539,37 @@ pub async fn fetch_connections() -> Result<ConnectionsListResponse> {
     )))
 }
 
+/// Resolve a command-line connection argument.
+/// A valid connection string is returned as-is; anything else is looked up as a registered alias.
+pub async fn resolve_connection_string(arg: String) -> Result<String> {
+    if crate::decode_connection_string(&arg).is_ok() {
+        return Ok(arg);
+    }
+
+    let response = fetch_connections().await.map_err(|e| {
+        n0_snafu::Error::anyhow(anyhow::anyhow!(
+            "'{}' is not a valid connection string, and aliases could not be looked up: {}", arg, e
+        ))
+    })?;
+
+    let staleness = response.staleness();
+    response
+        .connections
+        .into_iter()
+        .find(|c| c.alias.as_deref() == Some(arg.as_str()))
+        .map(|c| {
+            if let Some(staleness) = staleness {
+                eprintln!("Note: resolved '{}' from the offline cache ({})", arg, staleness);
+            }
+            c.connection_string
+        })
+        .ok_or_else(|| {
+            n0_snafu::Error::anyhow(anyhow::anyhow!(
+                "'{}' is neither a valid connection string nor a registered alias (see `kerr ls`)", arg
+            ))
+        })
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct LogoutResponse {
     pub message: String,
@@ -14,7 +14,7 @@ use ratatui::{
     Terminal,
 };
 use std::io;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use std::time::Instant;
 use tui_textarea::TextArea;
@@ -73,6 +73,22 @@ enum CopyMode {
     },
 }
 
+/// A one-line text prompt shown over the browser
+struct InputPrompt {
+    action: PromptAction,
+    /// Pane the prompt acts on
+    pane: FocusedPane,
+    input: String,
+}
+
+/// What an `InputPrompt` does with its input
+enum PromptAction {
+    /// Rename the given file or directory to the entered name
+    Rename(PathBuf),
+    /// Create a directory with the entered name in the given directory
+    Mkdir(PathBuf),
+}
+
 #[derive(Clone)]
 enum CopyDirection {
     LocalToRemote,
@@ -80,16 +96,36 @@ enum CopyDirection {
 }
 
 /// Run the interactive file browser with local filesystem
-pub fn run_browser() -> io::Result<()> {
+/// Starts in `start_dir` if given, otherwise the current directory.
+pub fn run_browser(start_dir: Option<PathBuf>) -> io::Result<()> {
+    // Validate before touching the terminal so the error prints normally
+    let start_dir = match start_dir {
+        Some(dir) => {
+            let dir = std::fs::canonicalize(&dir)?;
+            if !dir.is_dir() {
+                return Err(io::Error::new(
+                    io::ErrorKind::NotADirectory,
+                    format!("Not a directory: {}", dir.display()),
+                ));
+            }
+            Some(dir)
+        }
+        None => None,
+    };
+
     let filesystem = Arc::new(LocalFilesystem::new());
-    run_browser_with_fs(filesystem, None)
+    run_browser_with_fs(filesystem, None, start_dir, false)
 }
 
 /// Run the interactive file browser with a specific filesystem implementation
 /// If remote_fs is provided, it will be used for caching remote file access
+/// If local_start is provided, the local pane opens there.
+/// With remote_read_only, actions that would change the remote side are refused up front.
 pub fn run_browser_with_fs(
     _filesystem: Arc<dyn Filesystem>,
     remote_fs: Option<Arc<RemoteFilesystem>>,
+    local_start: Option<PathBuf>,
+    remote_read_only: bool,
 ) -> io::Result<()> {
     // Setup terminal
     enable_raw_mode()?;
@@ -109,12 +145,20 @@ pub fn run_browser_with_fs(
                 .add_modifier(Modifier::BOLD),
         );
     let mut local_explorer = FileExplorer::with_theme(local_theme, local_fs)?;
+    if let Some(ref dir) = local_start {
+        local_explorer.set_cwd(dir)?;
+    }
 
     // Create remote file explorer if remote_fs is provided
+    let remote_title = if remote_read_only { " Remote (read-only) " } else { " Remote " };
     let mut remote_explorer = if let Some(ref remote_fs) = remote_fs {
+        let mut remote_block = Block::default().borders(Borders::ALL).title(remote_title);
+        if remote_read_only {
+            remote_block = remote_block.border_style(Style::default().fg(Color::DarkGray));
+        }
         let remote_theme = Theme::default()
             .add_default_title()
-            .with_block(Block::default().borders(Borders::ALL).title(" Remote "))
+            .with_block(remote_block)
             .with_dir_style(
                 Style::default()
                     .fg(Color::Cyan)
@@ -175,6 +219,9 @@ pub fn run_browser_with_fs(
     // Copy mode state
     let mut copy_mode = CopyMode::None;
 
+    // Text prompt (rename, new directory), if one is open
+    let mut prompt: Option<InputPrompt> = None;
+
     // Initialize image picker for terminal
     let mut picker = Picker::from_query_stdio()
         .map_err(|e| io::Error::new(io::ErrorKind::Other, format!("{:?}", e)))?;
@@ -216,7 +263,7 @@ pub fn run_browser_with_fs(
                             let highlight_block = Block::default()
                                 .borders(Borders::ALL)
                                 .border_style(Style::default().fg(Color::Yellow).add_modifier(Modifier::BOLD))
-                                .title(" Remote ");
+                                .title(remote_title);
                             f.render_widget(highlight_block, browser_chunks[1]);
                         }
                     }
@@ -269,6 +316,10 @@ pub fn run_browser_with_fs(
                 }
                 CopyMode::None => {}
             }
+
+            if let Some(ref prompt) = prompt {
+                render_input_prompt(f, f.area(), prompt);
+            }
         })?;
 
         // Clear error messages after 3 seconds
@@ -283,6 +334,44 @@ pub fn run_browser_with_fs(
         // Handle input
         if event::poll(std::time::Duration::from_millis(100))? {
             if let Event::Key(key) = event::read()? {
+                // An open prompt takes all input until it's confirmed or cancelled
+                if let Some(mut current) = prompt.take() {
+                    match key.code {
+                        KeyCode::Enter => {
+                            let explorer = match current.pane {
+                                FocusedPane::Local => Some(&mut local_explorer),
+                                FocusedPane::Remote => remote_explorer.as_mut(),
+                            };
+                            let outcome = match (explorer, &current.action) {
+                                (Some(explorer), PromptAction::Rename(source)) => {
+                                    rename_entry(explorer, source, current.input.trim())
+                                }
+                                (Some(explorer), PromptAction::Mkdir(parent)) => {
+                                    create_directory(explorer, parent, current.input.trim())
+                                }
+                                (None, _) => Ok(String::new()),
+                            };
+                            let message = outcome.unwrap_or_else(|e| e.to_string());
+                            if !message.is_empty()
+                                && let Ok(mut error) = error_message.lock()
+                            {
+                                *error = Some(ErrorMessage { message, timestamp: Instant::now() });
+                            }
+                        }
+                        KeyCode::Esc => {}
+                        KeyCode::Backspace => {
+                            current.input.pop();
+                            prompt = Some(current);
+                        }
+                        KeyCode::Char(c) => {
+                            current.input.push(c);
+                            prompt = Some(current);
+                        }
+                        _ => prompt = Some(current),
+                    }
+                    continue;
+                }
+
                 // Handle copy mode first (highest priority)
                 match &copy_mode {
                     CopyMode::Error { .. } => {
@@ -408,7 +497,14 @@ pub fn run_browser_with_fs(
                             }
                             KeyCode::Char('c') => {
                                 // Copy file or directory: local->remote or remote->local
-                                if remote_explorer.is_some() {
+                                if remote_read_only && focused_pane == FocusedPane::Local {
+                                    if let Ok(mut error) = error_message.lock() {
+                                        *error = Some(ErrorMessage {
+                                            message: String::from("The server is read-only; copying to it is disabled"),
+                                            timestamp: Instant::now(),
+                                        });
+                                    }
+                                } else if remote_explorer.is_some() {
                                     let current = match focused_pane {
                                         FocusedPane::Local => local_explorer.current(),
                                         FocusedPane::Remote => remote_explorer.as_ref().unwrap().current(),
@@ -524,6 +620,46 @@ pub fn run_browser_with_fs(
                                     }
                                 }
                             }
+                            KeyCode::Char('r') | KeyCode::Char('m')
+                                if remote_read_only && focused_pane == FocusedPane::Remote =>
+                            {
+                                if let Ok(mut error) = error_message.lock() {
+                                    *error = Some(ErrorMessage {
+                                        message: String::from("The server is read-only; renaming and creating directories are disabled"),
+                                        timestamp: Instant::now(),
+                                    });
+                                }
+                            }
+                            KeyCode::Char('r') => {
+                                // Rename the selected entry (not the parent "..")
+                                let explorer = match focused_pane {
+                                    FocusedPane::Local => Some(&local_explorer),
+                                    FocusedPane::Remote => remote_explorer.as_ref(),
+                                };
+                                if let Some(current) = explorer.map(|e| e.current())
+                                    && current.metadata().is_some()
+                                {
+                                    prompt = Some(InputPrompt {
+                                        input: current.name().trim_end_matches('/').to_string(),
+                                        action: PromptAction::Rename(current.path().to_path_buf()),
+                                        pane: focused_pane,
+                                    });
+                                }
+                            }
+                            KeyCode::Char('m') => {
+                                // Create a directory in the focused pane's current directory
+                                let explorer = match focused_pane {
+                                    FocusedPane::Local => Some(&local_explorer),
+                                    FocusedPane::Remote => remote_explorer.as_ref(),
+                                };
+                                if let Some(explorer) = explorer {
+                                    prompt = Some(InputPrompt {
+                                        input: String::new(),
+                                        action: PromptAction::Mkdir(explorer.cwd().to_path_buf()),
+                                        pane: focused_pane,
+                                    });
+                                }
+                            }
                             KeyCode::Char('h') => {
                                 // Toggle hidden files on focused browser
                                 let toggle_event = Event::Key(
@@ -749,6 +885,82 @@ fn render_status_bar(
     frame.render_widget(paragraph, area);
 }
 
+/// Whether `name` can be used as a single entry name in a directory
+fn check_entry_name(name: &str) -> io::Result<()> {
+    if name.is_empty() || name.contains('/') || name == "." || name == ".." {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("Invalid name: {:?}", name)));
+    }
+    Ok(())
+}
+
+/// Rename `source` within its directory to `new_name`. Returns the status line to show.
+fn rename_entry(explorer: &mut FileExplorer, source: &Path, new_name: &str) -> io::Result<String> {
+    check_entry_name(new_name)?;
+    let target = source.with_file_name(new_name);
+    if target == source {
+        return Ok(String::new());
+    }
+    if explorer.files().iter().any(|f| f.path() == target) {
+        return Err(io::Error::new(io::ErrorKind::AlreadyExists, format!("{} already exists", new_name)));
+    }
+    explorer.rename(source, &target)?;
+    Ok(format!("✓ Renamed to {}", new_name))
+}
+
+/// Create directory `name` in `parent`. Returns the status line to show.
+fn create_directory(explorer: &mut FileExplorer, parent: &Path, name: &str) -> io::Result<String> {
+    check_entry_name(name)?;
+    let target = parent.join(name);
+    if explorer.files().iter().any(|f| f.path() == target) {
+        return Err(io::Error::new(io::ErrorKind::AlreadyExists, format!("{} already exists", name)));
+    }
+    explorer.create_dir(&target)?;
+    Ok(format!("✓ Created directory {}", name))
+}
+
+/// Render a one-line text prompt popup
+fn render_input_prompt(frame: &mut ratatui::Frame, area: Rect, prompt: &InputPrompt) {
+    let popup_width = 60.min(area.width.saturating_sub(4));
+    let popup_height = 7;
+    let popup_area = Rect {
+        x: (area.width.saturating_sub(popup_width)) / 2,
+        y: (area.height.saturating_sub(popup_height)) / 2,
+        width: popup_width,
+        height: popup_height,
+    };
+
+    frame.render_widget(Clear, popup_area);
+
+    let (title, label) = match &prompt.action {
+        PromptAction::Rename(source) => (
+            " Rename ",
+            format!("New name for {}:", source.file_name().unwrap_or_default().to_string_lossy()),
+        ),
+        PromptAction::Mkdir(parent) => (" New Directory ", format!("Create in {}:", parent.display())),
+    };
+    let block = Block::default()
+        .borders(Borders::ALL)
+        .title(title)
+        .border_style(Style::default().fg(Color::Yellow).add_modifier(Modifier::BOLD));
+
+    let text = vec![
+        Line::from(Span::raw(label)),
+        Line::from(vec![
+            Span::styled("> ", Style::default().fg(Color::Yellow)),
+            Span::raw(prompt.input.as_str()),
+            Span::styled("█", Style::default().fg(Color::Gray)),
+        ]),
+        Line::from(""),
+        Line::from(Span::styled("Enter to confirm, Esc to cancel", Style::default().fg(Color::Gray))),
+    ];
+
+    let paragraph = Paragraph::new(text)
+        .block(block)
+        .wrap(Wrap { trim: false });
+
+    frame.render_widget(paragraph, popup_area);
+}
+
 /// Check if a file is an image based on extension
 fn is_image_file(path: &Path) -> bool {
     if let Some(ext) = path.extension() {
@@ -1203,7 +1415,7 @@ fn perform_download(
 
             if metadata.is_dir {
                 // Download directory recursively
-                download_directory_recursive_sync(&source_path, &dest_path, &remote_fs).await
+                download_directory_recursive_sync(&source_path, &dest_path, &remote_fs, 0).await
             } else {
                 // Download single file
                 let data = remote_fs.read_file(&source_path).await?;
@@ -1217,15 +1429,28 @@ fn perform_download(
         .map_err(|_| io::Error::new(io::ErrorKind::Other, "Thread panicked"))?
 }
 
+/// Deepest directory nesting a browser download descends into. The server lists
+/// symlinked directories as directories, so a link to an ancestor would otherwise
+/// recurse forever.
+const MAX_DOWNLOAD_DEPTH: usize = 64;
+
 /// Recursively download a directory from remote to local (sync version for threading)
 fn download_directory_recursive_sync<'a>(
     source_dir: &'a Path,
     dest_dir: &'a Path,
     remote_fs: &'a Arc<RemoteFilesystem>,
+    depth: usize,
 ) -> std::pin::Pin<Box<dyn std::future::Future<Output = io::Result<()>> + 'a>> {
     Box::pin(async move {
         use crate::custom_explorer::Filesystem;
 
+        if depth > MAX_DOWNLOAD_DEPTH {
+            return Err(io::Error::other(format!(
+                "{} is nested more than {} directories deep (symlink loop?)",
+                source_dir.display(), MAX_DOWNLOAD_DEPTH
+            )));
+        }
+
         // Create destination directory
         std::fs::create_dir_all(dest_dir)?;
 
@@ -1240,7 +1465,7 @@ fn download_directory_recursive_sync<'a>(
 
             if entry.is_dir {
                 // Recursively copy subdirectory
-                download_directory_recursive_sync(source_path, &dest_path, remote_fs).await?;
+                download_directory_recursive_sync(source_path, &dest_path, remote_fs, depth + 1).await?;
             } else {
                 // Download file
                 let data = remote_fs.read_file(source_path).await?;
@@ -1,7 +1,6 @@
 //! Kerr client - connects to server and provides interactive terminal
 
-use iroh::Endpoint;
-use n0_snafu::{Result, ResultExt};
+use n0_snafu::Result;
 use std::io::{self, Write};
 use crossterm::{
     terminal::{self, ClearType},
@@ -9,33 +8,81 @@ use crossterm::{
 };
 use crate::{ClientMessage, ServerMessage, ALPN};
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::path::{Path, PathBuf};
 use std::fs;
 
-/// Resume metadata stored in .{filename}.resume_json
+/// Resume metadata stored in .{filename}.resume_json, or in a resume directory
 #[derive(Debug, Serialize, Deserialize)]
 struct ResumeMetadata {
     /// Number of bytes successfully received
+    /// (for a directory, the total size of `completed_files`)
     bytes_received: u64,
     /// Total file size expected
     total_size: u64,
     /// Remote path being downloaded
     remote_path: String,
+    /// Whether the download is a directory
+    #[serde(default)]
+    is_dir: bool,
+    /// Directory downloads: files received in full, by relative path, with their sizes
+    /// and the modification times the server reported for them
+    #[serde(default)]
+    completed_files: BTreeMap<String, (u64, Option<i64>)>,
 }
 
-/// Get the resume metadata file path for a given local file
-fn get_resume_metadata_path(local_path: &str) -> PathBuf {
+/// Upload resume metadata stored in .{filename}.upload_resume_json next to the file being sent
+#[derive(Debug, Serialize, Deserialize)]
+struct UploadResumeMetadata {
+    /// Number of bytes the server has acknowledged writing
+    bytes_sent: u64,
+    /// Size of the local file when it was sent
+    total_size: u64,
+    /// Modification time of the local file when it was sent, so an edited file starts over
+    modified: Option<i64>,
+    /// Remote path being uploaded to
+    remote_path: String,
+}
+
+/// Default directory for `kerr pull --resume-dir` without a value
+pub fn default_resume_dir() -> PathBuf {
+    directories::ProjectDirs::from("app", "freewill", "kerr")
+        .map(|dirs| dirs.data_local_dir().join("resume"))
+        .unwrap_or_else(|| std::env::temp_dir().join("kerr-resume"))
+}
+
+/// Get the resume metadata file path for a download of `remote_path` to `local_path`
+///
+/// Without `resume_dir` it's `.{filename}.resume_json` next to the file. With one, or
+/// when `local_path` has no file name, it's a file in that directory named after a
+/// hash of both paths.
+fn get_resume_metadata_path(local_path: &str, remote_path: &str, resume_dir: Option<&Path>) -> PathBuf {
     let path = Path::new(local_path);
-    let parent = path.parent().unwrap_or(Path::new("."));
-    let filename = path.file_name().unwrap().to_string_lossy();
-    parent.join(format!(".{}.resume_json", filename))
+    if resume_dir.is_none()
+        && let Some(filename) = path.file_name()
+    {
+        let parent = path.parent().filter(|p| !p.as_os_str().is_empty()).unwrap_or(Path::new("."));
+        return parent.join(format!(".{}.resume_json", filename.to_string_lossy()));
+    }
+
+    let dir = resume_dir.map(Path::to_path_buf).unwrap_or_else(default_resume_dir);
+    let absolute = std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf());
+    let key = blake3::hash(format!("{}\n{}", absolute.display(), remote_path).as_bytes());
+    dir.join(format!("{}.resume_json", &key.to_hex()[..32]))
+}
+
+/// Get the resume metadata file path for an upload of `local_path`:
+/// `.{filename}.upload_resume_json` next to the file
+fn get_upload_resume_path(local_path: &Path) -> PathBuf {
+    let filename = local_path.file_name().unwrap_or_default().to_string_lossy();
+    let parent = local_path.parent().filter(|p| !p.as_os_str().is_empty()).unwrap_or(Path::new("."));
+    parent.join(format!(".{}.upload_resume_json", filename))
 }
 
 /// Read resume metadata if it exists
-fn read_resume_metadata(local_path: &str) -> Option<ResumeMetadata> {
-    let metadata_path = get_resume_metadata_path(local_path);
+fn read_resume_metadata<T: serde::de::DeserializeOwned>(metadata_path: &Path) -> Option<T> {
     if metadata_path.exists() {
-        let content = fs::read_to_string(&metadata_path).ok()?;
+        let content = fs::read_to_string(metadata_path).ok()?;
         serde_json::from_str(&content).ok()
     } else {
         None
@@ -43,22 +90,500 @@ fn read_resume_metadata(local_path: &str) -> Option<ResumeMetadata> {
 }
 
 /// Write resume metadata
-fn write_resume_metadata(local_path: &str, metadata: &ResumeMetadata) -> std::io::Result<()> {
-    let metadata_path = get_resume_metadata_path(local_path);
+fn write_resume_metadata<T: Serialize>(metadata_path: &Path, metadata: &T) -> std::io::Result<()> {
+    if let Some(parent) = metadata_path.parent() {
+        fs::create_dir_all(parent)?;
+    }
     let json = serde_json::to_string_pretty(metadata)?;
-    fs::write(&metadata_path, json)?;
+    fs::write(metadata_path, json)?;
     Ok(())
 }
 
 /// Delete resume metadata file
-fn delete_resume_metadata(local_path: &str) -> std::io::Result<()> {
-    let metadata_path = get_resume_metadata_path(local_path);
+fn delete_resume_metadata(metadata_path: &Path) -> std::io::Result<()> {
     if metadata_path.exists() {
-        fs::remove_file(&metadata_path)?;
+        fs::remove_file(metadata_path)?;
     }
     Ok(())
 }
 
+/// Create a spinner for waits without a byte count, such as connecting
+fn connect_spinner() -> indicatif::ProgressBar {
+    use indicatif::{ProgressBar, ProgressStyle};
+
+    let spinner = ProgressBar::new_spinner();
+    spinner.set_style(
+        ProgressStyle::default_spinner()
+            .template("{spinner:.green} [{elapsed_precise}] {msg}")
+            .unwrap(),
+    );
+    spinner.enable_steady_tick(std::time::Duration::from_millis(100));
+    spinner
+}
+
+/// Error for a failed connection attempt, with advice on what to try next
+fn connect_error(err: iroh::endpoint::ConnectError) -> n0_snafu::Error {
+    let failure = crate::ConnectFailure::from_connect(&err);
+    failure_error(format!("Failed to connect: {}", err), failure)
+}
+
+/// Connect to the server, giving up once the `--connect-timeout` has passed
+async fn connect(endpoint: &iroh::Endpoint, addr: iroh::EndpointAddr) -> Result<iroh::endpoint::Connection> {
+    let timeout = crate::connect_timeout();
+    match tokio::time::timeout(timeout, endpoint.connect(addr, ALPN)).await {
+        Ok(result) => result.map_err(connect_error),
+        Err(_) => Err(failure_error(
+            format!("Failed to connect: no answer within {}s (see --connect-timeout)", timeout.as_secs()),
+            crate::ConnectFailure::Timeout,
+        )),
+    }
+}
+
+/// Error for a stream that couldn't be opened on an established connection
+fn open_stream_error(err: iroh::endpoint::ConnectionError) -> n0_snafu::Error {
+    let failure = crate::ConnectFailure::from_connection(&err);
+    failure_error(format!("Failed to open stream: {}", err), failure)
+}
+
+/// Everything but a refusal may clear up by itself, so those are marked retryable
+fn failure_error(message: String, failure: crate::ConnectFailure) -> n0_snafu::Error {
+    let message = match failure.hint() {
+        Some(hint) => format!("{}\n{}", message, hint),
+        None => message,
+    };
+    if failure == crate::ConnectFailure::Refused {
+        n0_snafu::Error::anyhow(anyhow::anyhow!("{}", message))
+    } else {
+        n0_snafu::Error::anyhow(anyhow::Error::new(LinkError { message, connecting: true }))
+    }
+}
+
+/// The connection failed underneath a transfer, so starting over on a fresh
+/// connection may get further
+#[derive(Debug)]
+struct LinkError {
+    message: String,
+    /// Failed while connecting, rather than on an established connection
+    connecting: bool,
+}
+
+impl std::fmt::Display for LinkError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for LinkError {}
+
+/// Error for a message that couldn't be sent or received on the connection
+fn link_error(err: impl std::fmt::Display) -> n0_snafu::Error {
+    n0_snafu::Error::anyhow(anyhow::Error::new(LinkError { message: err.to_string(), connecting: false }))
+}
+
+/// The server wants its `--password`, which wasn't entered or was wrong
+#[derive(Debug)]
+struct AuthError {
+    wrong: bool,
+}
+
+impl std::fmt::Display for AuthError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(if self.wrong { "Wrong password" } else { crate::AUTH_REQUIRED })
+    }
+}
+
+impl std::error::Error for AuthError {}
+
+/// Password for servers started with `--password`, asked for the first time one is
+/// needed and reused for the retries after that
+static SERVER_PASSWORD: std::sync::Mutex<Option<String>> = std::sync::Mutex::new(None);
+
+/// How many times to ask for a server's password before giving up
+const PASSWORD_ATTEMPTS: u32 = 3;
+
+/// Error for an `Error` reply from the server, recognising one that wants its password
+fn server_error(message: String) -> n0_snafu::Error {
+    if message.starts_with(crate::AUTH_REQUIRED) {
+        n0_snafu::Error::anyhow(anyhow::Error::new(AuthError { wrong: false }))
+    } else {
+        n0_snafu::Error::anyhow(anyhow::anyhow!("Server error: {}", message))
+    }
+}
+
+/// Send the password right after `Hello`, if one was entered, and wait for the server
+/// to accept it. Older servers reject `Auth` as unknown, which is fine: they have none.
+async fn authenticate(
+    send: &mut iroh::endpoint::SendStream,
+    recv: &mut iroh::endpoint::RecvStream,
+    session_id: &str,
+) -> Result<()> {
+    let Some(secret) = SERVER_PASSWORD.lock().ok().and_then(|password| password.clone()) else {
+        return Ok(());
+    };
+    let envelope = crate::MessageEnvelope {
+        session_id: session_id.to_string(),
+        payload: crate::MessagePayload::Client(ClientMessage::Auth { secret }),
+    };
+    crate::send_envelope(send, &envelope).await.map_err(link_error)?;
+
+    loop {
+        let envelope = crate::recv_envelope(recv).await.map_err(link_error)?;
+        if is_unsupported_reply(&envelope) {
+            return Ok(());
+        }
+        match envelope.payload {
+            crate::MessagePayload::Server(ServerMessage::AuthResult { ok: true }) => return Ok(()),
+            crate::MessagePayload::Server(ServerMessage::AuthResult { ok: false }) => {
+                return Err(n0_snafu::Error::anyhow(anyhow::Error::new(AuthError { wrong: true })));
+            }
+            // The server's answer to Hello, sent before it saw the password
+            crate::MessagePayload::Server(ServerMessage::Error { message }) if message.starts_with(crate::AUTH_REQUIRED) => {}
+            crate::MessagePayload::Server(ServerMessage::Error { message }) => return Err(server_error(message)),
+            _ => return Err(n0_snafu::Error::anyhow(anyhow::anyhow!("Unexpected server response to password"))),
+        }
+    }
+}
+
+/// If `err` means the server wants its password, ask for it and return true so the
+/// caller tries again; `asked` counts the prompts, up to `PASSWORD_ATTEMPTS`
+fn ask_password(err: &n0_snafu::Error, asked: &mut u32) -> bool {
+    let n0_snafu::Error::Anyhow { source, .. } = err else {
+        return false;
+    };
+    let Some(auth) = source.downcast_ref::<AuthError>() else {
+        return false;
+    };
+    if *asked >= PASSWORD_ATTEMPTS {
+        return false;
+    }
+    *asked += 1;
+    if auth.wrong {
+        eprintln!("Wrong password, try again.");
+    }
+    match read_password("Password: ") {
+        Ok(secret) => {
+            if let Ok(mut password) = SERVER_PASSWORD.lock() {
+                *password = Some(secret);
+            }
+            true
+        }
+        Err(e) => {
+            if e.kind() != io::ErrorKind::Interrupted {
+                eprintln!("{}", e);
+            }
+            false
+        }
+    }
+}
+
+/// Whether there is a terminal to ask for a password on. Stdin may carry data
+/// (`kerr send … -`, a piped script), so without one nothing is asked.
+fn has_terminal() -> bool {
+    use std::io::IsTerminal;
+
+    if io::stdin().is_terminal() {
+        return true;
+    }
+    #[cfg(unix)]
+    let terminal = "/dev/tty";
+    #[cfg(windows)]
+    let terminal = "CONIN$";
+    #[cfg(not(any(unix, windows)))]
+    return false;
+    #[cfg(any(unix, windows))]
+    std::fs::OpenOptions::new().read(true).write(true).open(terminal).is_ok()
+}
+
+/// Prompt on stderr and read a line from the controlling terminal without echoing it.
+/// Never reads stdin when it isn't the terminal, since it may be the data to send.
+fn read_password(prompt: &str) -> io::Result<String> {
+    use crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
+
+    if !has_terminal() {
+        return Err(io::Error::new(io::ErrorKind::NotFound, "password required; stdin is in use"));
+    }
+    eprint!("{}", prompt);
+    io::stderr().flush()?;
+
+    // crossterm reads keys from the terminal itself (/dev/tty, the console) when
+    // stdin is redirected
+    terminal::enable_raw_mode()?;
+    let mut secret = String::new();
+    let result = loop {
+        match event::read() {
+            Ok(Event::Key(key)) if key.kind == KeyEventKind::Press => match key.code {
+                KeyCode::Enter => break Ok(()),
+                KeyCode::Backspace => {
+                    secret.pop();
+                }
+                KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                    break Err(io::Error::new(io::ErrorKind::Interrupted, "cancelled"));
+                }
+                KeyCode::Char(c) => secret.push(c),
+                _ => {}
+            },
+            Ok(_) => {}
+            Err(e) => break Err(e),
+        }
+    };
+    let _ = terminal::disable_raw_mode();
+    eprintln!();
+    result.map(|()| secret)
+}
+
+/// Delay before the first retry of an interrupted transfer; doubled for each one after
+const RETRY_DELAY: std::time::Duration = std::time::Duration::from_secs(2);
+
+/// Whether to start a failed transfer again, at most `retries` times. Only a lost
+/// connection is retried, or one that can't be re-established once retrying (a first
+/// connection that fails is more likely a wrong or offline server than a blip).
+/// Waits out the backoff before returning true.
+async fn retry_transfer(err: &n0_snafu::Error, failures: &mut u32, retries: u32) -> bool {
+    let n0_snafu::Error::Anyhow { source, .. } = err else {
+        return false;
+    };
+    let Some(link) = source.downcast_ref::<LinkError>() else {
+        return false;
+    };
+    if *failures >= retries || (link.connecting && *failures == 0) {
+        return false;
+    }
+    *failures += 1;
+    let delay = RETRY_DELAY * 2u32.pow(*failures - 1);
+    eprintln!("Transfer interrupted: {}", link.message);
+    eprintln!("Retrying on a fresh connection in {}s ({}/{})...", delay.as_secs(), failures, retries);
+    tokio::time::sleep(delay).await;
+    true
+}
+
+/// How OSC 0/2 title sequences from the remote shell are handled
+#[derive(Debug, Clone, Default)]
+pub enum TitleMode {
+    /// Let remote programs set the local terminal title unchanged
+    #[default]
+    Passthrough,
+    /// Prepend a prefix (e.g. "kerr: ") to every remote title
+    Prefix(String),
+    /// Drop remote title changes entirely
+    Ignore,
+}
+
+/// Options for an interactive shell session
+#[derive(Debug, Clone)]
+pub struct ShellOptions {
+    /// Handling of remote terminal title changes
+    pub title_mode: TitleMode,
+    /// Directory on the server to start the shell in (None = server default)
+    pub cwd: Option<String>,
+    /// Start with local line editing on (toggle with Alt+L)
+    pub line_mode: bool,
+    /// Leave the terminal alone: pipe stdin to the shell and its output to stdout,
+    /// with status messages on stderr, so a session can be scripted and captured
+    pub no_raw: bool,
+    /// Most terminal resizes sent per second (0 = no limit)
+    pub max_resizes_per_sec: u32,
+    /// Record the session's output and resizes to this asciinema v2 file
+    pub record: Option<std::path::PathBuf>,
+    /// `TERM` for the remote shell, usually the local one (None = server default)
+    pub term: Option<String>,
+}
+
+impl Default for ShellOptions {
+    fn default() -> Self {
+        Self {
+            title_mode: TitleMode::default(),
+            cwd: None,
+            line_mode: false,
+            no_raw: false,
+            max_resizes_per_sec: crate::DEFAULT_MAX_RESIZES_PER_SEC,
+            record: None,
+            term: None,
+        }
+    }
+}
+
+/// Where a shell session's output is delivered. `run_client` writes to the terminal;
+/// embedders (another TUI, a GUI, tests) pass their own to `run_client_with_sink`.
+pub trait OutputSink: Send {
+    /// Shell output, after title sequences have been handled per `TitleMode`
+    fn output(&mut self, data: &[u8]);
+    /// An error reported by the server, such as the session ending
+    fn error(&mut self, message: &str);
+}
+
+/// The default sink: output to stdout, server errors to stderr
+pub struct StdoutSink {
+    stdout: io::Stdout,
+    no_raw: bool,
+}
+
+impl StdoutSink {
+    /// `no_raw` prints errors as plain lines, for a terminal not in raw mode
+    pub fn new(no_raw: bool) -> Self {
+        Self { stdout: io::stdout(), no_raw }
+    }
+}
+
+impl OutputSink for StdoutSink {
+    fn output(&mut self, data: &[u8]) {
+        let _ = self.stdout.write_all(data);
+        let _ = self.stdout.flush();
+    }
+
+    fn error(&mut self, message: &str) {
+        if self.no_raw {
+            eprintln!("{}", message);
+        } else {
+            eprintln!("\r\n{}\r\n", message);
+        }
+    }
+}
+
+/// Longest OSC sequence we will hold back waiting for its terminator
+const MAX_PENDING_OSC: usize = 4096;
+
+/// Rewrites OSC 0/2 (set title) sequences in PTY output according to a `TitleMode`
+/// Sequences split across output chunks are buffered until complete.
+struct TitleFilter {
+    mode: TitleMode,
+    pending: Vec<u8>,
+}
+
+impl TitleFilter {
+    fn new(mode: TitleMode) -> Self {
+        Self { mode, pending: Vec::new() }
+    }
+
+    fn filter(&mut self, data: &[u8]) -> Vec<u8> {
+        if matches!(self.mode, TitleMode::Passthrough) {
+            return data.to_vec();
+        }
+
+        let mut input = std::mem::take(&mut self.pending);
+        input.extend_from_slice(data);
+
+        let mut out = Vec::with_capacity(input.len());
+        let mut i = 0;
+        while i < input.len() {
+            if input[i] != 0x1b {
+                out.push(input[i]);
+                i += 1;
+                continue;
+            }
+
+            // Lone ESC at the end of the chunk might start an OSC
+            if i + 1 == input.len() {
+                self.pending = input[i..].to_vec();
+                break;
+            }
+
+            if input[i + 1] != b']' {
+                out.push(input[i]);
+                i += 1;
+                continue;
+            }
+
+            // OSC: ESC ] Ps ; Pt (BEL | ESC \)
+            let body_start = i + 2;
+            let terminator = (body_start..input.len()).find_map(|j| match input[j] {
+                0x07 => Some((j, 1)),
+                0x1b if input.get(j + 1) == Some(&b'\\') => Some((j, 2)),
+                _ => None,
+            });
+
+            let Some((body_end, term_len)) = terminator else {
+                if input.len() - i <= MAX_PENDING_OSC {
+                    self.pending = input[i..].to_vec();
+                } else {
+                    out.extend_from_slice(&input[i..]);
+                }
+                break;
+            };
+
+            let body = &input[body_start..body_end];
+            let is_title = body.starts_with(b"0;") || body.starts_with(b"2;");
+            match (&self.mode, is_title) {
+                (TitleMode::Ignore, true) => {}
+                (TitleMode::Prefix(prefix), true) => {
+                    out.extend_from_slice(&input[i..body_start + 2]);
+                    out.extend_from_slice(prefix.as_bytes());
+                    out.extend_from_slice(&input[body_start + 2..body_end + term_len]);
+                }
+                _ => out.extend_from_slice(&input[i..body_end + term_len]),
+            }
+            i = body_end + term_len;
+        }
+
+        out
+    }
+}
+
+/// Local line editing for `--line-mode`: printable keys are echoed locally and
+/// the line goes to the server in one `KeyEvent` when any other key is pressed,
+/// so typing on a laggy link doesn't wait a round trip per character.
+struct LineEditor {
+    enabled: bool,
+    line: String,
+}
+
+impl LineEditor {
+    fn new(enabled: bool) -> Self {
+        Self { enabled, line: String::new() }
+    }
+
+    /// Remove the local echo of the buffered line; the remote echoes it again
+    fn erase_echo(&self, out: &mut impl Write) {
+        let width = self.line.chars().count();
+        if width > 0 {
+            let _ = write!(out, "\x1b[{}D\x1b[K", width);
+            let _ = out.flush();
+        }
+    }
+
+    /// Take the buffered line followed by `tail` for sending
+    fn flush_with(&mut self, tail: &[u8], out: &mut impl Write) -> Vec<u8> {
+        self.erase_echo(out);
+        let mut data = std::mem::take(&mut self.line).into_bytes();
+        data.extend_from_slice(tail);
+        data
+    }
+
+    /// Bytes to send for `key`, if any. Enter, Tab, arrows and control keys
+    /// send the buffered line plus the key itself.
+    fn handle_key(&mut self, key: crossterm::event::KeyEvent, out: &mut impl Write) -> Option<Vec<u8>> {
+        use crossterm::event::{KeyCode, KeyModifiers};
+
+        if !self.enabled {
+            return Some(key_event_to_bytes(key));
+        }
+        match key.code {
+            KeyCode::Char(c) if !key.modifiers.intersects(KeyModifiers::CONTROL | KeyModifiers::ALT) => {
+                self.line.push(c);
+                let _ = write!(out, "{}", c);
+                let _ = out.flush();
+                None
+            }
+            KeyCode::Backspace if !self.line.is_empty() => {
+                self.line.pop();
+                let _ = out.write_all(b"\x08 \x08");
+                let _ = out.flush();
+                None
+            }
+            _ => Some(self.flush_with(&key_event_to_bytes(key), out)),
+        }
+    }
+
+    /// Switch line mode on/off; anything typed so far is sent (without Enter)
+    fn toggle(&mut self, out: &mut impl Write) -> Option<Vec<u8>> {
+        self.enabled = !self.enabled;
+        if self.line.is_empty() {
+            None
+        } else {
+            Some(self.flush_with(b"", out))
+        }
+    }
+}
+
 /// Convert a crossterm KeyEvent to raw terminal bytes
 fn key_event_to_bytes(event: crossterm::event::KeyEvent) -> Vec<u8> {
     use crossterm::event::{KeyCode, KeyModifiers};
@@ -125,45 +650,249 @@ fn key_event_to_bytes(event: crossterm::event::KeyEvent) -> Vec<u8> {
     bytes
 }
 
-pub async fn run_client(connection_string: String) -> Result<()> {
+/// How long the shell may take to send anything before the spinner says so
+const SESSION_START_NOTICE: std::time::Duration = std::time::Duration::from_secs(5);
+
+/// Heartbeats the server may miss before the shell warns that the connection looks stalled
+const MISSED_HEARTBEATS: u32 = 3;
+
+/// How often the shell checks whether the server has gone quiet for too long
+const STALL_CHECK_INTERVAL: std::time::Duration = std::time::Duration::from_secs(1);
+
+/// Shell output received so far, shared with the input task for the Ctrl+\ report
+struct OutputStats {
+    started: std::time::Instant,
+    bytes: std::sync::atomic::AtomicU64,
+    /// Milliseconds after `started` of the last output; 0 if none yet
+    last_output_ms: std::sync::atomic::AtomicU64,
+    /// Milliseconds after `started` of the last message of any kind
+    last_message_ms: std::sync::atomic::AtomicU64,
+    /// Interval announced by the server's heartbeats; 0 until the first one arrives
+    heartbeat_ms: std::sync::atomic::AtomicU64,
+}
+
+impl OutputStats {
+    fn new() -> Self {
+        Self {
+            started: std::time::Instant::now(),
+            bytes: std::sync::atomic::AtomicU64::new(0),
+            last_output_ms: std::sync::atomic::AtomicU64::new(0),
+            last_message_ms: std::sync::atomic::AtomicU64::new(0),
+            heartbeat_ms: std::sync::atomic::AtomicU64::new(0),
+        }
+    }
+
+    fn record(&self, len: usize) {
+        use std::sync::atomic::Ordering;
+        self.bytes.fetch_add(len as u64, Ordering::Relaxed);
+        let elapsed = self.started.elapsed().as_millis().max(1) as u64;
+        self.last_output_ms.store(elapsed, Ordering::Relaxed);
+    }
+
+    /// Note that a message arrived from the server
+    fn heard(&self) {
+        let elapsed = self.started.elapsed().as_millis() as u64;
+        self.last_message_ms.store(elapsed, std::sync::atomic::Ordering::Relaxed);
+    }
+
+    /// How long the server has been silent, once that's longer than its heartbeats allow
+    fn stalled_for(&self) -> Option<std::time::Duration> {
+        use std::sync::atomic::Ordering;
+
+        let interval = std::time::Duration::from_millis(self.heartbeat_ms.load(Ordering::Relaxed));
+        let last = std::time::Duration::from_millis(self.last_message_ms.load(Ordering::Relaxed));
+        let silence = self.started.elapsed().saturating_sub(last);
+        (!interval.is_zero() && silence > interval * MISSED_HEARTBEATS).then_some(silence)
+    }
+}
+
+/// Diagnostics printed by Ctrl+\: is shell output arriving, and is the connection itself alive
+fn recovery_report(stats: &OutputStats, conn: &iroh::endpoint::Connection) -> String {
+    use std::sync::atomic::Ordering;
+
+    let last_output = match stats.last_output_ms.load(Ordering::Relaxed) {
+        0 => "never".to_string(),
+        ms => {
+            let ago = stats.started.elapsed().saturating_sub(std::time::Duration::from_millis(ms));
+            format!("{:.1}s ago", ago.as_secs_f64())
+        }
+    };
+    let quic = conn.stats();
+    let closed = match conn.close_reason() {
+        Some(reason) => format!(", closed: {}", reason),
+        None => String::new(),
+    };
+    format!(
+        "\r\n[kerr] shell output: {} bytes, last {} | connection: {} bytes received, {} bytes sent, {} packets lost{}\r\n\
+         [kerr] asked the remote to redraw (resize + Ctrl+L)\r\n",
+        stats.bytes.load(Ordering::Relaxed),
+        last_output,
+        quic.udp_rx.bytes,
+        quic.udp_tx.bytes,
+        quic.lost_packets,
+        closed,
+    )
+}
+
+pub async fn run_client(connection_string: String, options: ShellOptions) -> Result<()> {
+    let sink = Box::new(StdoutSink::new(options.no_raw));
+    run_client_with_sink(connection_string, options, sink).await
+}
+
+/// `run_client` with the shell's output and server errors delivered to `sink`
+/// instead of the terminal
+pub async fn run_client_with_sink(connection_string: String, options: ShellOptions, sink: Box<dyn OutputSink>) -> Result<()> {
     use rand::RngExt;
 
     // Decode the compressed connection string (base64 -> gzip -> JSON -> NodeAddr)
     let addr = crate::decode_connection_string(&connection_string)
         .expect("Failed to decode connection string");
 
-    println!("Connecting to: {}", addr.id);
+    // With --no-raw, stdout carries only the shell's output
+    let status = |message: &str| {
+        if options.no_raw {
+            eprintln!("{}", message);
+        } else {
+            println!("{}", message);
+        }
+    };
+    status(&format!("Connecting to: {}", addr.id));
+
+    // Spinner so NAT traversal doesn't look like a hang
+    let spinner = connect_spinner();
+    spinner.set_message("Resolving relay and local endpoint...");
+
+    let endpoint = crate::bind_endpoint().await.map_err(|e| n0_snafu::Error::anyhow(anyhow::anyhow!("{}", e)))?;
+
+    // A server started with --password turns the session down until it's entered,
+    // after which a fresh connection is tried with it
+    let mut asked = 0;
+    // Set once the server turns down `ShellHelloEnv`, so retries use the old handshake
+    let mut legacy_hello = options.term.is_none();
+    let (conn, mut send, mut recv, session_id, first_envelope) = loop {
+        // Open a connection to the accepting node
+        spinner.set_message("Connecting to Kerr server (holepunching)...");
+        let conn = match connect(&endpoint, addr.clone()).await {
+            Ok(conn) => conn,
+            Err(e) => {
+                spinner.finish_with_message("Connection failed");
+                return Err(e);
+            }
+        };
 
-    let endpoint = Endpoint::bind(iroh::endpoint::presets::N0).await.map_err(|e| n0_snafu::Error::anyhow(anyhow::anyhow!("{}", e)))?;
+        // Open a bidirectional QUIC stream
+        spinner.set_message("Connected, session starting...");
+        let (mut send, mut recv) = conn.open_bi().await.map_err(open_stream_error)?;
 
-    // Open a connection to the accepting node
-    println!("Connecting to Kerr server...");
-    let conn = endpoint.connect(addr, ALPN).await.map_err(|e| n0_snafu::Error::anyhow(anyhow::anyhow!("{}", e)))?;
-    println!("Connected! Starting terminal session...");
-    println!("Press Ctrl+D to disconnect.");
+        // Generate a unique session ID for this shell session
+        let session_id = format!("shell_{}", rand::rng().random::<u64>());
 
-    // Open a bidirectional QUIC stream
-    let (mut send, mut recv) = conn.open_bi().await.e()?;
+        // Send Hello message using the multiplexed protocol
+        // (plain Hello unless options need it, so older servers still work)
+        let old_hello = || match options.cwd.clone() {
+            Some(cwd) => ClientMessage::ShellHello { cwd: Some(cwd) },
+            None => ClientMessage::Hello { session_type: crate::SessionType::Shell },
+        };
+        let env_hello = !legacy_hello;
+        let hello_msg = if env_hello {
+            ClientMessage::ShellHelloEnv { cwd: options.cwd.clone(), term: options.term.clone() }
+        } else {
+            old_hello()
+        };
+        let hello_envelope = crate::MessageEnvelope {
+            session_id: session_id.clone(),
+            payload: crate::MessagePayload::Client(hello_msg),
+        };
+        if let Err(e) = crate::send_envelope(&mut send, &hello_envelope).await {
+            spinner.finish_with_message("Session handshake failed");
+            return Err(n0_snafu::Error::anyhow(anyhow::anyhow!("{}", e)));
+        }
+        if let Err(e) = authenticate(&mut send, &mut recv, &session_id).await {
+            conn.close(0u32.into(), b"bye!");
+            if spinner.suspend(|| ask_password(&e, &mut asked)) {
+                continue;
+            }
+            spinner.finish_with_message("Session failed to start");
+            return Err(e);
+        }
 
-    // Generate a unique session ID for this shell session
-    let session_id = format!("shell_{}", rand::rng().random::<u64>());
+        // The session has only started once the server answers, usually with the prompt
+        let slow_notice = {
+            let spinner = spinner.clone();
+            tokio::spawn(async move {
+                tokio::time::sleep(SESSION_START_NOTICE).await;
+                spinner.set_message("Connected, still waiting for the remote shell to start...");
+            })
+        };
+        let mut first_reply = crate::recv_envelope(&mut recv).await;
+        if env_hello && matches!(&first_reply, Ok(envelope) if is_unsupported_reply(envelope)) {
+            // A server from before ShellHelloEnv; start the shell with its default TERM
+            legacy_hello = true;
+            let hello_envelope = crate::MessageEnvelope {
+                session_id: session_id.clone(),
+                payload: crate::MessagePayload::Client(old_hello()),
+            };
+            if let Err(e) = crate::send_envelope(&mut send, &hello_envelope).await {
+                slow_notice.abort();
+                spinner.finish_with_message("Session handshake failed");
+                return Err(n0_snafu::Error::anyhow(anyhow::anyhow!("{}", e)));
+            }
+            first_reply = crate::recv_envelope(&mut recv).await;
+        }
+        slow_notice.abort();
+
+        // Anything but an error is handled by the output loop like later replies
+        let first_envelope = match first_reply {
+            Ok(crate::MessageEnvelope { payload: crate::MessagePayload::Server(ServerMessage::Error { message }), .. }) => {
+                conn.close(0u32.into(), b"bye!");
+                let e = server_error(message);
+                if spinner.suspend(|| ask_password(&e, &mut asked)) {
+                    continue;
+                }
+                spinner.finish_with_message("Session failed to start");
+                return Err(e);
+            }
+            Ok(envelope) => envelope,
+            Err(e) => {
+                spinner.finish_with_message("Session handshake failed");
+                return Err(n0_snafu::Error::anyhow(anyhow::anyhow!("Server did not start the session: {}", e)));
+            }
+        };
+        break (conn, send, recv, session_id, first_envelope);
+    };
     let session_id_for_send = session_id.clone();
 
-    // Send Hello message using the multiplexed protocol
-    let hello_msg = ClientMessage::Hello { session_type: crate::SessionType::Shell };
-    let hello_envelope = crate::MessageEnvelope {
-        session_id: session_id.clone(),
-        payload: crate::MessagePayload::Client(hello_msg),
+    spinner.finish_with_message("Session started");
+
+    // Record from here on, starting at the local terminal's size
+    let recording = match &options.record {
+        Some(path) => {
+            let (cols, rows) = terminal::size().unwrap_or((80, 24));
+            match crate::recording::Recorder::start(path, cols, rows) {
+                Ok(recording) => Some(recording),
+                Err(e) => {
+                    conn.close(0u32.into(), b"bye!");
+                    return Err(n0_snafu::Error::anyhow(anyhow::anyhow!(
+                        "Failed to create recording {}: {}", path.display(), e
+                    )));
+                }
+            }
+        }
+        None => None,
     };
-    crate::send_envelope(&mut send, &hello_envelope).await.map_err(|e| n0_snafu::Error::anyhow(anyhow::anyhow!("{}", e)))?;
+    let recorder = recording.as_ref().map(|(recorder, _)| recorder.clone());
+
+    if !options.no_raw {
+        println!("Press Ctrl+D to disconnect, Alt+L to toggle local line editing, Ctrl+\\ if the screen seems stuck.");
 
-    // Enter raw mode
-    terminal::enable_raw_mode().expect("Failed to enable raw mode");
-    let mut stdout = io::stdout();
-    stdout.execute(terminal::Clear(ClearType::All)).unwrap();
+        // Enter raw mode
+        terminal::enable_raw_mode().expect("Failed to enable raw mode");
+        let mut stdout = io::stdout();
+        stdout.execute(terminal::Clear(ClearType::All)).unwrap();
+    }
 
     // Send initial terminal size using the multiplexed protocol
-    if let Ok((cols, rows)) = terminal::size() {
+    if !options.no_raw && let Ok((cols, rows)) = terminal::size() {
         let resize_msg = ClientMessage::Resize { cols, rows };
         let resize_envelope = crate::MessageEnvelope {
             session_id: session_id.clone(),
@@ -175,9 +904,25 @@ pub async fn run_client(connection_string: String) -> Result<()> {
     // Channel to send messages to the server
     let (msg_tx, mut msg_rx) = tokio::sync::mpsc::unbounded_channel::<ClientMessage>();
 
+    // Heartbeats let the stall warning below tell a stuck link from an idle shell
+    let _ = msg_tx.send(ClientMessage::Heartbeats);
+
     // Spawn task to write messages to send stream using the multiplexed protocol
+    let mut resizes = crate::ResizeLimiter::new(options.max_resizes_per_sec);
     let send_task = tokio::spawn(async move {
-        while let Some(msg) = msg_rx.recv().await {
+        loop {
+            let msg = tokio::select! {
+                msg = msg_rx.recv() => match msg {
+                    // Resizes past the cap wait their turn, and only the latest is sent
+                    Some(ClientMessage::Resize { cols, rows }) => match resizes.offer(cols, rows) {
+                        Some((cols, rows)) => ClientMessage::Resize { cols, rows },
+                        None => continue,
+                    },
+                    Some(msg) => msg,
+                    None => break,
+                },
+                (cols, rows) = resizes.next_due() => ClientMessage::Resize { cols, rows },
+            };
             let envelope = crate::MessageEnvelope {
                 session_id: session_id_for_send.clone(),
                 payload: crate::MessagePayload::Client(msg),
@@ -186,51 +931,140 @@ pub async fn run_client(connection_string: String) -> Result<()> {
                 break;
             }
         }
+        if resizes.dropped() > 0 {
+            tracing::debug!(dropped = resizes.dropped(), "Resizes superseded during the session");
+        }
     });
 
     // Spawn task to handle stdin input in raw mode using crossterm events
     // This handles both keyboard input and terminal resize events
     let msg_tx_clone = msg_tx.clone();
-    let input_task = tokio::spawn(async move {
-        use futures::StreamExt;
-        use crossterm::event::{EventStream, Event, KeyCode, KeyEvent, KeyModifiers};
-
-        let mut event_stream = EventStream::new();
-        while let Some(event_result) = event_stream.next().await {
-            match event_result {
-                Ok(Event::Key(KeyEvent { code: KeyCode::Char('d'), modifiers: KeyModifiers::CONTROL, .. })) => {
-                    // Ctrl+D - disconnect
-                    let _ = msg_tx_clone.send(ClientMessage::Disconnect);
-                    break;
-                }
-                Ok(Event::Key(key_event)) => {
-                    // Convert key event to raw bytes
-                    let data = key_event_to_bytes(key_event);
-                    if msg_tx_clone.send(ClientMessage::KeyEvent { data }).is_err() {
+    let mut line_editor = LineEditor::new(options.line_mode);
+    let output_stats = std::sync::Arc::new(OutputStats::new());
+    let output_stats_for_input = std::sync::Arc::clone(&output_stats);
+    let conn_for_input = conn.clone();
+    let recorder_for_input = recorder.clone();
+    let input_task = if options.no_raw {
+        tokio::spawn(forward_stdin(msg_tx_clone))
+    } else {
+        tokio::spawn(async move {
+            use futures::StreamExt;
+            use crossterm::event::{EventStream, Event, KeyCode, KeyEvent, KeyModifiers};
+
+            let mut echo = io::stdout();
+            let mut event_stream = EventStream::new();
+            while let Some(event_result) = event_stream.next().await {
+                match event_result {
+                    Ok(Event::Key(KeyEvent { code: KeyCode::Char('d'), modifiers: KeyModifiers::CONTROL, .. })) => {
+                        // Ctrl+D - disconnect
+                        let _ = msg_tx_clone.send(ClientMessage::Disconnect);
                         break;
                     }
+                    // Terminals report Ctrl+\ as either '\' or '4' with Ctrl
+                    Ok(Event::Key(KeyEvent { code: KeyCode::Char('\\' | '4'), modifiers: KeyModifiers::CONTROL, .. })) => {
+                        // Ctrl+\ - recover a frozen screen and show whether data is flowing
+                        let _ = echo.write_all(recovery_report(&output_stats_for_input, &conn_for_input).as_bytes());
+                        let _ = echo.flush();
+                        if let Ok((cols, rows)) = terminal::size() {
+                            // The shell only gets SIGWINCH if the size actually changes
+                            let _ = msg_tx_clone.send(ClientMessage::Resize { cols, rows: rows.saturating_sub(1).max(1) });
+                            let _ = msg_tx_clone.send(ClientMessage::Resize { cols, rows });
+                        }
+                        let _ = msg_tx_clone.send(ClientMessage::KeyEvent { data: vec![0x0c] });
+                    }
+                    Ok(Event::Key(KeyEvent { code: KeyCode::Char('l'), modifiers: KeyModifiers::ALT, .. })) => {
+                        // Alt+L - toggle local line editing
+                        if let Some(data) = line_editor.toggle(&mut echo)
+                            && msg_tx_clone.send(ClientMessage::KeyEvent { data }).is_err()
+                        {
+                            break;
+                        }
+                    }
+                    Ok(Event::Key(key_event)) => {
+                        // Convert key event to raw bytes (buffered locally in line mode)
+                        if let Some(data) = line_editor.handle_key(key_event, &mut echo)
+                            && msg_tx_clone.send(ClientMessage::KeyEvent { data }).is_err()
+                        {
+                            break;
+                        }
+                    }
+                    Ok(Event::Resize(cols, rows)) => {
+                        // Handle terminal resize
+                        let _ = msg_tx_clone.send(ClientMessage::Resize { cols, rows });
+                        if let Some(recorder) = &recorder_for_input {
+                            recorder.resize(cols, rows);
+                        }
+                    }
+                    Ok(_) => {
+                        // Ignore other events (mouse, focus, etc.)
+                    }
+                    Err(_) => break,
                 }
-                Ok(Event::Resize(cols, rows)) => {
-                    // Handle terminal resize
-                    let _ = msg_tx_clone.send(ClientMessage::Resize { cols, rows });
-                }
-                Ok(_) => {
-                    // Ignore other events (mouse, focus, etc.)
+            }
+        })
+    };
+
+    // Output and the stall warning both go to the sink
+    let sink = std::sync::Arc::new(std::sync::Mutex::new(sink));
+
+    // Warn once per stall when the server misses several heartbeats in a row
+    let stall_task = {
+        let stats = std::sync::Arc::clone(&output_stats);
+        let sink = std::sync::Arc::clone(&sink);
+        tokio::spawn(async move {
+            let mut warned = false;
+            loop {
+                tokio::time::sleep(STALL_CHECK_INTERVAL).await;
+                match stats.stalled_for() {
+                    Some(silence) if !warned => {
+                        warned = true;
+                        if let Ok(mut sink) = sink.lock() {
+                            sink.error(&format!(
+                                "[kerr] connection appears stalled: nothing from the server for {}s",
+                                silence.as_secs()
+                            ));
+                        }
+                    }
+                    Some(_) => {}
+                    None => warned = false,
                 }
-                Err(_) => break,
             }
-        }
-    });
+        })
+    };
 
     // Main task: receive output from server and display
+    let mut title_filter = TitleFilter::new(options.title_mode.clone());
+    let no_raw = options.no_raw;
     let output_task = tokio::spawn(async move {
-        let mut stdout = io::stdout();
+        // What reaches the sink is also what gets recorded
+        let mut show = |data: &[u8]| {
+            let data = title_filter.filter(data);
+            if let Some(recorder) = &recorder {
+                recorder.output(&data);
+            }
+            if let Ok(mut sink) = sink.lock() {
+                sink.output(&data);
+            }
+        };
+        let mut first_envelope = Some(first_envelope);
+        // Until a heartbeat arrives, an unsupported reply is an older server turning down `Heartbeats`
+        let mut heartbeats_pending = true;
         loop {
-            // Receive message using the multiplexed protocol
-            let envelope = match crate::recv_envelope(&mut recv).await {
-                Ok(env) => env,
-                Err(_) => break, // Connection closed
+            // Receive message using the multiplexed protocol, starting with the handshake's reply
+            let envelope = match first_envelope.take() {
+                Some(env) => env,
+                None => match crate::recv_envelope(&mut recv).await {
+                    Ok(env) => env,
+                    Err(e) if e.is::<crate::StreamClosed>() => break,
+                    // Anything else (truncated frame, reset, timeout) means the link died
+                    Err(e) => return Some(e.to_string()),
+                },
             };
+            output_stats.heard();
+            if heartbeats_pending && is_unsupported_reply(&envelope) {
+                heartbeats_pending = false;
+                continue;
+            }
 
             // Extract server message from envelope
             let msg = match envelope.payload {
@@ -240,13 +1074,13 @@ pub async fn run_client(connection_string: String) -> Result<()> {
 
             match msg {
                 ServerMessage::Output { data } => {
-                    // Write output to terminal
-                    let _ = stdout.write_all(&data);
-                    let _ = stdout.flush();
+                    output_stats.record(data.len());
+                    show(&data);
                 }
                 ServerMessage::Error { message } => {
-                    // Display error message
-                    eprintln!("\r\n{}\r\n", message);
+                    if let Ok(mut sink) = sink.lock() {
+                        sink.error(&message);
+                    }
 
                     // If this is a session end message, break the loop to exit
                     if message.contains("Session ended") || message.contains("bash exited") {
@@ -307,20 +1141,118 @@ pub async fn run_client(connection_string: String) -> Result<()> {
                 ServerMessage::DnsResponse { .. } => {
                     // DNS response - not used in run_client (only for dns proxy)
                 }
+                ServerMessage::FileAttributes { .. } => {
+                    // File attributes - not used in run_client (only for pull)
+                }
+                ServerMessage::FsBatchResponse { .. } => {
+                    // Batch results - not used in run_client (only for browse)
+                }
+                ServerMessage::FsLimitsResponse { .. } => {
+                    // File size limits - not used in run_client (only for browse)
+                }
+                ServerMessage::UploadComplete { .. } => {
+                    // Upload result - not used in run_client (only for send)
+                }
+                ServerMessage::Symlink { .. } => {
+                    // Symlink entry - not used in run_client (only for pull)
+                }
+                ServerMessage::Preparing { .. } => {
+                    // Enumeration keepalive - not used in run_client (only for pull)
+                }
+                ServerMessage::FileHole { .. } => {
+                    // Sparse download data - not used in run_client (only for pull)
+                }
+                ServerMessage::FsWriteResponse { .. } => {
+                    // File write confirmation - not used in run_client (only for browse)
+                }
+                ServerMessage::FsRenameResponse { .. } => {
+                    // Rename confirmation - not used in run_client (only for browse)
+                }
+                ServerMessage::FsMkdirResponse { .. } => {
+                    // Directory creation confirmation - not used in run_client (only for browse)
+                }
+                ServerMessage::CompressedChunk { .. } => {
+                    // Compressed download data - not used in run_client (only for pull)
+                }
+                ServerMessage::DownloadComplete { .. } => {
+                    // Downloaded file hash - not used in run_client (only for pull)
+                }
+                ServerMessage::UploadResumed { .. } => {
+                    // Upload resume offset - not used in run_client (only for send)
+                }
+                ServerMessage::AuthResult { .. } => {
+                    // Password answer - handled before the session starts
+                }
+                ServerMessage::FsModeResponse { .. } => {
+                    // Read-only flag - not used in run_client (only for browse)
+                }
+                ServerMessage::Heartbeat { interval_ms } => {
+                    heartbeats_pending = false;
+                    output_stats.heartbeat_ms.store(interval_ms, std::sync::atomic::Ordering::Relaxed);
+                }
+                ServerMessage::Exit { .. } => {
+                    // Shell exit code - not used in run_client (only for exec)
+                }
+                ServerMessage::UdpOpenResponse { .. } => {
+                    // UDP open response - not used in run_client (only for UDP relay)
+                }
+                ServerMessage::UdpDataResponse { .. } => {
+                    // UDP data response - not used in run_client (only for UDP relay)
+                }
+                ServerMessage::UdpCloseResponse { .. } => {
+                    // UDP close response - not used in run_client (only for UDP relay)
+                }
+                ServerMessage::ReverseListenResponse { .. } => {
+                    // Listen result - not used in run_client (only for reverse relay)
+                }
+                ServerMessage::TcpIncoming { .. } => {
+                    // Incoming connection - not used in run_client (only for reverse relay)
+                }
+                ServerMessage::VersionResponse { .. } => {
+                    // Server version - not used in run_client (only for doctor)
+                }
+                ServerMessage::ErrorOutput { .. } => {
+                    // Command stderr - not used in run_client (only for exec)
+                }
             }
         }
+        None
     });
 
     // Wait for either task to complete
+    let mut connection_lost = None;
     tokio::select! {
         _ = input_task => {},
-        _ = output_task => {},
+        result = output_task => connection_lost = result.ok().flatten(),
         _ = send_task => {},
     }
+    stall_task.abort();
 
     // Restore terminal
-    terminal::disable_raw_mode().expect("Failed to disable raw mode");
-    println!("\r\nDisconnected from server.");
+    if no_raw {
+        if let Some(reason) = connection_lost {
+            eprintln!("Connection lost: {}", reason);
+        }
+    } else {
+        terminal::disable_raw_mode().expect("Failed to disable raw mode");
+        match connection_lost {
+            Some(reason) => println!("\r\nConnection lost: {}", reason),
+            None => println!("\r\nDisconnected from server."),
+        }
+    }
+
+    if let Some((recorder, writer)) = recording {
+        recorder.finish();
+        match writer.join() {
+            Ok(Ok(())) => {
+                if let Some(path) = &options.record {
+                    eprintln!("Session recorded to {}", path.display());
+                }
+            }
+            Ok(Err(e)) => eprintln!("Recording failed: {}", e),
+            Err(_) => eprintln!("Recording failed: writer thread panicked"),
+        }
+    }
 
     // Explicitly close the connection
     conn.close(0u32.into(), b"bye!");
@@ -329,12 +1261,162 @@ pub async fn run_client(connection_string: String) -> Result<()> {
     Ok(())
 }
 
+/// Input for `--no-raw`: stdin goes to the shell byte for byte. At the end of input the
+/// shell gets Ctrl+D (after a newline, if the last line had none) so it exits, and the
+/// session stays open until the server ends it and all output has arrived.
+async fn forward_stdin(msg_tx: tokio::sync::mpsc::UnboundedSender<ClientMessage>) {
+    use tokio::io::AsyncReadExt;
+
+    let mut stdin = tokio::io::stdin();
+    let mut buf = vec![0u8; 4096];
+    let mut last = b'\n';
+    loop {
+        match stdin.read(&mut buf).await {
+            Ok(0) | Err(_) => break,
+            Ok(n) => {
+                last = buf[n - 1];
+                if msg_tx.send(ClientMessage::KeyEvent { data: buf[..n].to_vec() }).is_err() {
+                    return;
+                }
+            }
+        }
+    }
+    let end = if last == b'\n' { vec![0x04] } else { vec![b'\n', 0x04] };
+    let _ = msg_tx.send(ClientMessage::KeyEvent { data: end });
+    std::future::pending::<()>().await
+}
+
+/// Look up a remote path's metadata over a file transfer session
+/// Returns `None` if the path doesn't exist.
+async fn remote_metadata(
+    send: &mut iroh::endpoint::SendStream,
+    recv: &mut iroh::endpoint::RecvStream,
+    session_id: &str,
+    path: &str,
+) -> Result<Option<crate::custom_explorer::file_explorer::FileMetadata>> {
+    let envelope = crate::MessageEnvelope {
+        session_id: session_id.to_string(),
+        payload: crate::MessagePayload::Client(ClientMessage::FsMetadata { path: path.to_string() }),
+    };
+    crate::send_envelope(send, &envelope).await.map_err(link_error)?;
+
+    let response = crate::recv_envelope(recv).await.map_err(link_error)?;
+    match response.payload {
+        crate::MessagePayload::Server(ServerMessage::FsMetadataResponse { metadata_json }) => {
+            let metadata = serde_json::from_str(&metadata_json)
+                .map_err(|e| n0_snafu::Error::anyhow(anyhow::anyhow!("Invalid metadata from server: {}", e)))?;
+            Ok(Some(metadata))
+        }
+        crate::MessagePayload::Server(ServerMessage::FsError { .. }) => Ok(None),
+        crate::MessagePayload::Server(ServerMessage::Error { message }) => Err(server_error(message)),
+        _ => Err(n0_snafu::Error::anyhow(anyhow::anyhow!("Unexpected server response to metadata request"))),
+    }
+}
+
+/// Wait for the server's next upload acknowledgement
+/// Returns the total number of bytes the server has written so far.
+async fn recv_upload_ack(recv: &mut iroh::endpoint::RecvStream) -> Result<u64> {
+    let envelope = crate::recv_envelope(recv).await.map_err(link_error)?;
+    match envelope.payload {
+        crate::MessagePayload::Server(ServerMessage::Progress { bytes_transferred, .. }) => Ok(bytes_transferred),
+        crate::MessagePayload::Server(ServerMessage::Error { message }) => {
+            Err(n0_snafu::Error::anyhow(anyhow::anyhow!("Server error: {}", message)))
+        }
+        _ => Err(n0_snafu::Error::anyhow(anyhow::anyhow!("Unexpected server response during upload"))),
+    }
+}
+
+/// Wait for the server's `UploadComplete` after `EndUpload`, skipping leftover
+/// progress acks. Returns false if the server never answered (older servers don't).
+async fn recv_upload_complete(recv: &mut iroh::endpoint::RecvStream) -> Result<bool> {
+    let wait = async {
+        loop {
+            let envelope = crate::recv_envelope(&mut *recv).await.map_err(link_error)?;
+            match envelope.payload {
+                crate::MessagePayload::Server(ServerMessage::Progress { .. }) => {}
+                crate::MessagePayload::Server(ServerMessage::UploadComplete { success: true, .. }) => return Ok(()),
+                crate::MessagePayload::Server(ServerMessage::UploadComplete { success: false, error }) => {
+                    return Err(n0_snafu::Error::anyhow(anyhow::anyhow!(
+                        "Upload failed on the server: {}",
+                        error.unwrap_or_else(|| "unknown error".to_string())
+                    )));
+                }
+                crate::MessagePayload::Server(ServerMessage::Error { message }) => {
+                    return Err(n0_snafu::Error::anyhow(anyhow::anyhow!("Server error: {}", message)));
+                }
+                _ => return Err(n0_snafu::Error::anyhow(anyhow::anyhow!("Unexpected server response after upload"))),
+            }
+        }
+    };
+    match tokio::time::timeout(UPLOAD_DRAIN_TIMEOUT, wait).await {
+        Ok(result) => result.map(|()| true),
+        Err(_) => Ok(false),
+    }
+}
+
+/// Whether `envelope` is the error a server sends, on an empty session, for a
+/// message it can't decode - i.e. an older server that doesn't know it
+fn is_unsupported_reply(envelope: &crate::MessageEnvelope) -> bool {
+    envelope.session_id.is_empty()
+        && matches!(envelope.payload, crate::MessagePayload::Server(ServerMessage::Error { .. }))
+}
+
 /// Send a file or directory to the server
-pub async fn send_file(connection_string: String, local_path: String, remote_path: String, force: bool) -> Result<()> {
+///
+/// `preserve_times` defaults to on for directories and off for single files.
+/// A `local_path` of `-` uploads stdin to `remote_path`, which must name a file.
+/// Symlinks inside a directory are recreated as links unless `dereference` is set.
+/// If the connection drops, the upload starts over on a new one up to `retries` times
+/// (never for stdin, which can't be read twice).
+/// `limit` caps the upload at that many bytes per second (0 = unlimited).
+#[allow(clippy::too_many_arguments)]
+pub async fn send_file(
+    connection_string: String,
+    local_path: String,
+    remote_path: String,
+    force: bool,
+    preserve_times: Option<bool>,
+    preserve_perms: bool,
+    dereference: bool,
+    retries: u32,
+    limit: u64,
+) -> Result<()> {
+    let retries = if local_path == "-" { 0 } else { retries };
+    let mut force = force;
+    let mut failures = 0;
+    let mut asked = 0;
+    loop {
+        let mut started = false;
+        let result = send_file_attempt(
+            connection_string.clone(), local_path.clone(), remote_path.clone(),
+            force, preserve_times, preserve_perms, dereference, limit, &mut started,
+        ).await;
+        match result {
+            Err(e) if ask_password(&e, &mut asked) => {}
+            // Once the server accepted the upload, what's at the destination is our own partial copy
+            Err(e) if retry_transfer(&e, &mut failures, retries).await => force |= started,
+            result => return result,
+        }
+    }
+}
+
+/// One try at `send_file`; `started` is set once the server has accepted the upload
+#[allow(clippy::too_many_arguments)]
+async fn send_file_attempt(
+    connection_string: String,
+    local_path: String,
+    remote_path: String,
+    force: bool,
+    preserve_times: Option<bool>,
+    preserve_perms: bool,
+    dereference: bool,
+    limit: u64,
+    started: &mut bool,
+) -> Result<()> {
     use std::path::Path;
     use std::fs;
     use indicatif::{ProgressBar, ProgressStyle};
-    use crate::transfer::{calculate_size, get_files_recursive, CHUNK_SIZE};
+    use crate::transfer::{calculate_size, get_files_recursive, RateLimiter, SymlinkMode, CHUNK_SIZE, UPLOAD_WINDOW};
     use rand::RngExt;
 
     // Decode the compressed connection string (base64 -> gzip -> JSON)
@@ -342,9 +1424,9 @@ pub async fn send_file(connection_string: String, local_path: String, remote_pat
         .expect("Failed to decode connection string");
 
     println!("Connecting to server...");
-    let endpoint = Endpoint::bind(iroh::endpoint::presets::N0).await.map_err(|e| n0_snafu::Error::anyhow(anyhow::anyhow!("{}", e)))?;
-    let conn = endpoint.connect(addr, ALPN).await.map_err(|e| n0_snafu::Error::anyhow(anyhow::anyhow!("{}", e)))?;
-    let (mut send, mut recv) = conn.open_bi().await.e()?;
+    let endpoint = crate::bind_endpoint().await.map_err(|e| n0_snafu::Error::anyhow(anyhow::anyhow!("{}", e)))?;
+    let conn = connect(&endpoint, addr).await?;
+    let (mut send, mut recv) = conn.open_bi().await.map_err(open_stream_error)?;
 
     // Generate a unique session ID for this file transfer
     let session_id = format!("send_{}", rand::rng().random::<u64>());
@@ -355,16 +1437,33 @@ pub async fn send_file(connection_string: String, local_path: String, remote_pat
         session_id: session_id.clone(),
         payload: crate::MessagePayload::Client(hello_msg),
     };
-    crate::send_envelope(&mut send, &hello_envelope).await.map_err(|e| n0_snafu::Error::anyhow(anyhow::anyhow!("{}", e)))?;
+    crate::send_envelope(&mut send, &hello_envelope).await.map_err(link_error)?;
+    authenticate(&mut send, &mut recv, &session_id).await?;
 
     let local = Path::new(&local_path);
-    let is_dir = local.is_dir();
+    let from_stdin = local_path == "-";
+    let is_dir = !from_stdin && local.is_dir();
+    // stdin has no timestamps or permissions to carry over
+    let preserve_times = !from_stdin && preserve_times.unwrap_or(is_dir);
+    let preserve_perms = !from_stdin && preserve_perms;
+    let mut symlink_mode = if dereference { SymlinkMode::Follow } else { SymlinkMode::Preserve };
+
+    // Ask the server what already exists at the destination before streaming
+    let remote_meta = remote_metadata(&mut send, &mut recv, &session_id, &remote_path).await?;
 
     // Determine the actual remote file path
-    // If remote_path ends with / or is a directory name, append the local filename
+    // If remote_path ends with / or is an existing directory, append the local filename
     let actual_remote_path = if is_dir {
         // If sending a directory, use the remote_path as-is
         remote_path.clone()
+    } else if from_stdin {
+        // There's no local filename to append, so the destination must be a file
+        if remote_path.ends_with('/') || remote_meta.as_ref().is_some_and(|m| m.is_dir) {
+            return Err(n0_snafu::Error::anyhow(anyhow::anyhow!(
+                "{} is a directory; give a file name to upload stdin to", remote_path
+            )));
+        }
+        remote_path.clone()
     } else {
         // If sending a single file, determine the destination filename
         let local_filename = local.file_name()
@@ -372,18 +1471,84 @@ pub async fn send_file(connection_string: String, local_path: String, remote_pat
             .to_str()
             .expect("Invalid filename");
 
-        // If remote_path looks like a directory (ends with /), append the filename
+        let remote_is_dir = remote_meta.as_ref().is_some_and(|m| m.is_dir);
         if remote_path.ends_with('/') {
             format!("{}{}", remote_path, local_filename)
+        } else if remote_is_dir {
+            format!("{}/{}", remote_path, local_filename)
         } else {
             // Otherwise use remote_path as the exact filename
             remote_path.clone()
         }
     };
 
-    println!("Calculating size...");
-    let total_size = calculate_size(local)
-        .expect("Failed to calculate file size");
+    // A single file picks up where an earlier, interrupted send of it left off,
+    // as long as it hasn't changed since
+    let upload_resume_path = (!is_dir && !from_stdin).then(|| get_upload_resume_path(local));
+    let mut upload_resume = UploadResumeMetadata {
+        bytes_sent: 0,
+        total_size: fs::metadata(local).map(|m| m.len()).unwrap_or(0),
+        modified: crate::transfer::modified_secs(local),
+        remote_path: actual_remote_path.clone(),
+    };
+    let resume_offset = upload_resume_path.as_deref()
+        .and_then(read_resume_metadata::<UploadResumeMetadata>)
+        .filter(|m| {
+            m.remote_path == upload_resume.remote_path
+                && m.total_size == upload_resume.total_size
+                && m.modified == upload_resume.modified
+        })
+        .map_or(0, |m| m.bytes_sent);
+
+    if actual_remote_path != remote_path {
+        println!("Remote destination: {}", actual_remote_path);
+    } else if remote_meta.as_ref().is_some_and(|m| !m.is_dir) && !is_dir && !force && resume_offset == 0 {
+        println!("Warning: {} already exists on the server", actual_remote_path);
+    }
+
+    // The length of stdin isn't known up front; the server treats 0 as unknown
+    let total_size = if from_stdin {
+        0
+    } else {
+        println!("Calculating size...");
+        calculate_size(local, symlink_mode)
+            .expect("Failed to calculate file size")
+    };
+
+    // Preserved links need a server that can create them; older ones reject this
+    let probe_symlinks = is_dir && symlink_mode == SymlinkMode::Preserve;
+    if probe_symlinks {
+        let options_envelope = crate::MessageEnvelope {
+            session_id: session_id.clone(),
+            payload: crate::MessagePayload::Client(ClientMessage::TransferOptions { dereference }),
+        };
+        crate::send_envelope(&mut send, &options_envelope).await.map_err(link_error)?;
+    }
+
+    // Offer to send zero-filled chunks as holes and others compressed, and ask to resume
+    // an interrupted upload; older servers reject these
+    let mut options = vec![ClientMessage::SparseFiles, ClientMessage::CompressedChunks];
+    if resume_offset > 0 {
+        // Lets the server check that what it kept came from this file
+        use std::io::Read as _;
+        let mut hasher = blake3::Hasher::new();
+        let prefix = fs::File::open(local).and_then(|f| hasher.update_reader(f.take(resume_offset)).map(|_| ()));
+        match prefix {
+            Ok(()) => options.push(ClientMessage::ResumeUpload {
+                offset: resume_offset,
+                hash: hasher.finalize().to_hex().to_string(),
+            }),
+            Err(e) => println!("Warning: can't read {} to resume the upload: {}", local.display(), e),
+        }
+    }
+    let resume_requested = options.len() > 2;
+    for msg in options {
+        let envelope = crate::MessageEnvelope {
+            session_id: session_id.clone(),
+            payload: crate::MessagePayload::Client(msg),
+        };
+        crate::send_envelope(&mut send, &envelope).await.map_err(link_error)?;
+    }
 
     // Send upload start message using the multiplexed protocol
     let start_msg = ClientMessage::StartUpload {
@@ -396,17 +1561,50 @@ pub async fn send_file(connection_string: String, local_path: String, remote_pat
         session_id: session_id.clone(),
         payload: crate::MessagePayload::Client(start_msg),
     };
-    crate::send_envelope(&mut send, &start_envelope).await.map_err(|e| n0_snafu::Error::anyhow(anyhow::anyhow!("{}", e)))?;
+    crate::send_envelope(&mut send, &start_envelope).await.map_err(link_error)?;
 
     // Wait for ack or error
-    let response_envelope = crate::recv_envelope(&mut recv).await.map_err(|e| n0_snafu::Error::anyhow(anyhow::anyhow!("{}", e)))?;
+    // The options were added in the order they're sent (TransferOptions, SparseFiles,
+    // CompressedChunks, ResumeUpload), so n rejections mean the last n weren't understood.
+    // A server that can't resume just answers with UploadAck, so that needs no handling.
+    let mut response_envelope = crate::recv_envelope(&mut recv).await.map_err(link_error)?;
+    let mut rejected = 0u32;
+    while is_unsupported_reply(&response_envelope) {
+        rejected += 1;
+        response_envelope = crate::recv_envelope(&mut recv).await.map_err(link_error)?;
+    }
+    if resume_requested {
+        rejected = rejected.saturating_sub(1);
+    }
+    let compress = rejected == 0;
+    let sparse = rejected < 2;
+    if probe_symlinks && rejected >= 3 {
+        println!("Note: the server can't create symlinks (older kerr); symlinks will be skipped");
+        symlink_mode = SymlinkMode::Skip;
+    }
 
-    // Check if we got UploadAck, ConfirmPrompt, or Error
+    // Check if we got UploadAck, UploadResumed, ConfirmPrompt, or Error
+    let mut resumed_from = 0u64;
     match response_envelope.payload {
         crate::MessagePayload::Server(response) => match response {
         ServerMessage::UploadAck => {
             // Good to proceed
         }
+        ServerMessage::UploadResumed { offset } => {
+            println!("Found incomplete upload, resuming from {} bytes...", offset);
+            resumed_from = offset;
+        }
+        ServerMessage::ConfirmPrompt { message } if from_stdin => {
+            // stdin carries the file, so there's no way to ask
+            let decline_envelope = crate::MessageEnvelope {
+                session_id: session_id.clone(),
+                payload: crate::MessagePayload::Client(ClientMessage::ConfirmResponse { confirmed: false }),
+            };
+            let _ = crate::send_envelope(&mut send, &decline_envelope).await;
+            return Err(n0_snafu::Error::anyhow(anyhow::anyhow!(
+                "{} (use --force to overwrite when sending from stdin)", message
+            )));
+        }
         ServerMessage::ConfirmPrompt { message } => {
             // Ask user for confirmation
             use std::io::{stdin, stdout, Write as _};
@@ -423,7 +1621,7 @@ pub async fn send_file(connection_string: String, local_path: String, remote_pat
                 session_id: session_id.clone(),
                 payload: crate::MessagePayload::Client(confirm_msg),
             };
-            crate::send_envelope(&mut send, &confirm_envelope).await.map_err(|e| n0_snafu::Error::anyhow(anyhow::anyhow!("{}", e)))?;
+            crate::send_envelope(&mut send, &confirm_envelope).await.map_err(link_error)?;
 
             if !confirmed {
                 println!("Upload cancelled.");
@@ -431,12 +1629,16 @@ pub async fn send_file(connection_string: String, local_path: String, remote_pat
             }
 
             // Wait for final ack after confirmation
-            let final_envelope = crate::recv_envelope(&mut recv).await.map_err(|e| n0_snafu::Error::anyhow(anyhow::anyhow!("{}", e)))?;
+            let final_envelope = crate::recv_envelope(&mut recv).await.map_err(link_error)?;
 
             match final_envelope.payload {
                 crate::MessagePayload::Server(ServerMessage::UploadAck) => {
                     // Good to proceed
                 }
+                crate::MessagePayload::Server(ServerMessage::UploadResumed { offset }) => {
+                    println!("Found incomplete upload, resuming from {} bytes...", offset);
+                    resumed_from = offset;
+                }
                 crate::MessagePayload::Server(ServerMessage::Error { message }) => {
                     return Err(n0_snafu::Error::anyhow(anyhow::anyhow!("Server error: {}", message)));
                 }
@@ -456,25 +1658,69 @@ pub async fn send_file(connection_string: String, local_path: String, remote_pat
             return Err(n0_snafu::Error::anyhow(anyhow::anyhow!("Unexpected message type from server")));
         }
     }
+    *started = true;
+
+    // Create progress bar; stdin has no total, so count bytes instead
+    let pb = if from_stdin {
+        let pb = ProgressBar::new_spinner();
+        pb.set_style(ProgressStyle::default_spinner()
+            .template("{spinner:.green} [{elapsed_precise}] {bytes} ({bytes_per_sec})")
+            .unwrap());
+        pb
+    } else {
+        let pb = ProgressBar::new(total_size);
+        pb.set_style(ProgressStyle::default_bar()
+            .template("{spinner:.green} [{elapsed_precise}] [{bar:40.cyan/blue}] {bytes}/{total_bytes} ({eta})")
+            .unwrap()
+            .progress_chars("#>-"));
+        pb
+    };
 
-    // Create progress bar
-    let pb = ProgressBar::new(total_size);
-    pb.set_style(ProgressStyle::default_bar()
-        .template("{spinner:.green} [{elapsed_precise}] [{bar:40.cyan/blue}] {bytes}/{total_bytes} ({eta})")
-        .unwrap()
-        .progress_chars("#>-"));
-
-    // Send file data
-    let mut bytes_sent = 0u64;
-    let files = get_files_recursive(local)
-        .expect("Failed to get files");
+    // Send file data, keeping at most UPLOAD_WINDOW bytes unacknowledged
+    // Start from what the server kept of an earlier attempt, without counting it in the rate
+    let mut bytes_sent = resumed_from;
+    let mut limiter = RateLimiter::new(limit);
+    let mut bytes_acked = resumed_from;
+    let mut ack_count = 0u64;
+    pb.set_position(bytes_sent);
+    pb.reset_eta();
+    let files = if from_stdin {
+        vec![local.to_path_buf()]
+    } else {
+        get_files_recursive(local, symlink_mode)
+            .expect("Failed to get files")
+    };
 
     for file in &files {
+        let relative_str = if is_dir {
+            file.strip_prefix(local)
+                .expect("Failed to compute relative path")
+                .to_string_lossy()
+                .to_string()
+        } else {
+            String::new()
+        };
+
+        // Preserved links are sent as a link, with no data or attributes
+        if is_dir && symlink_mode == SymlinkMode::Preserve && file.is_symlink() {
+            let target = match crate::transfer::symlink_target(file) {
+                Ok(target) => target,
+                Err(e) => {
+                    pb.println(format!("Warning: {:#}", e));
+                    continue;
+                }
+            };
+            let link_envelope = crate::MessageEnvelope {
+                session_id: session_id.clone(),
+                payload: crate::MessagePayload::Client(ClientMessage::Symlink { relative_path: relative_str, target }),
+            };
+            crate::send_envelope(&mut send, &link_envelope).await.map_err(link_error)?;
+            continue;
+        }
+
         // For directory uploads, send FileStart with relative path for each file
         if is_dir {
-            let relative = file.strip_prefix(local)
-                .expect("Failed to compute relative path");
-            let relative_str = relative.to_string_lossy().to_string();
+            let relative_str = relative_str.clone();
             let file_size = fs::metadata(file)
                 .expect("Failed to get file metadata").len();
 
@@ -486,33 +1732,84 @@ pub async fn send_file(connection_string: String, local_path: String, remote_pat
                 session_id: session_id.clone(),
                 payload: crate::MessagePayload::Client(start_msg),
             };
-            crate::send_envelope(&mut send, &start_envelope).await.map_err(|e| n0_snafu::Error::anyhow(anyhow::anyhow!("{}", e)))?;
+            crate::send_envelope(&mut send, &start_envelope).await.map_err(link_error)?;
         }
 
-        let mut f = fs::File::open(file)
-            .expect("Failed to open file");
+        let mut f: Box<dyn std::io::Read + Send> = if from_stdin {
+            Box::new(io::stdin())
+        } else {
+            let mut f = fs::File::open(file)
+                .expect("Failed to open file");
+            if resumed_from > 0 {
+                use std::io::Seek;
+                f.seek(io::SeekFrom::Start(resumed_from))
+                    .expect("Failed to seek to resume offset");
+            }
+            Box::new(f)
+        };
         let mut buffer = vec![0u8; CHUNK_SIZE];
+        let mut try_compress = compress;
 
         loop {
-            use std::io::Read;
             let n = f.read(&mut buffer)
                 .expect("Failed to read file");
             if n == 0 {
                 break;
             }
 
-            // Send chunk using the multiplexed protocol
-            let chunk_msg = ClientMessage::FileChunk {
-                data: buffer[..n].to_vec(),
+            // Send chunk using the multiplexed protocol; all-zero chunks go as holes
+            // so sparse files stay sparse, and others compressed where that helps
+            let chunk = &buffer[..n];
+            let hole = sparse && crate::transfer::is_hole(chunk);
+            let chunk_msg = if hole {
+                ClientMessage::FileHole { len: n as u64 }
+            } else if let Some(data) = try_compress.then(|| crate::transfer::compress_chunk(chunk)).flatten() {
+                ClientMessage::CompressedChunk { data }
+            } else {
+                // Data that doesn't shrink is likely compressed already; stop trying for this file
+                try_compress = false;
+                ClientMessage::FileChunk { data: chunk.to_vec() }
             };
             let chunk_envelope = crate::MessageEnvelope {
                 session_id: session_id.clone(),
                 payload: crate::MessagePayload::Client(chunk_msg),
             };
-            crate::send_envelope(&mut send, &chunk_envelope).await.map_err(|e| n0_snafu::Error::anyhow(anyhow::anyhow!("{}", e)))?;
+            crate::send_envelope(&mut send, &chunk_envelope).await.map_err(link_error)?;
 
             bytes_sent += n as u64;
             pb.set_position(bytes_sent);
+            // Holes cost nothing to send, so only real data counts against the limit
+            if !hole {
+                limiter.consume(n as u64);
+                limiter.ready().await;
+            }
+
+            while bytes_sent - bytes_acked > UPLOAD_WINDOW {
+                bytes_acked = recv_upload_ack(&mut recv).await?;
+
+                // Update upload resume metadata every 10 acks (single-file only)
+                ack_count += 1;
+                if let Some(path) = &upload_resume_path
+                    && ack_count.is_multiple_of(10)
+                {
+                    upload_resume.bytes_sent = bytes_acked;
+                    let _ = write_resume_metadata(path, &upload_resume);
+                }
+            }
+        }
+
+        // Apply the original attributes once the file's data is on the server
+        if preserve_times || preserve_perms {
+            let attrs_msg = ClientMessage::FileAttributes {
+                relative_path: relative_str,
+                modified: if preserve_times { crate::transfer::modified_secs(file) } else { None },
+                mode: if preserve_perms { crate::transfer::mode_bits(file) } else { None },
+            };
+            let attrs_envelope = crate::MessageEnvelope {
+                session_id: session_id.clone(),
+                payload: crate::MessagePayload::Client(attrs_msg),
+            };
+            crate::send_envelope(&mut send, &attrs_envelope).await.map_err(link_error)?;
         }
     }
 
@@ -522,7 +1819,19 @@ pub async fn send_file(connection_string: String, local_path: String, remote_pat
         session_id: session_id.clone(),
         payload: crate::MessagePayload::Client(end_msg),
     };
-    crate::send_envelope(&mut send, &end_envelope).await.map_err(|e| n0_snafu::Error::anyhow(anyhow::anyhow!("{}", e)))?;
+    crate::send_envelope(&mut send, &end_envelope).await.map_err(link_error)?;
+
+    // Closing the connection straight away could drop data still in flight
+    pb.set_message("Waiting for server to receive everything...");
+    finish_and_drain(&mut send).await?;
+
+    // Only report success once the server has written and closed the file(s)
+    if !recv_upload_complete(&mut recv).await? {
+        pb.println("Warning: server did not confirm the upload (it may be running an older kerr)");
+    }
+    if let Some(path) = &upload_resume_path {
+        let _ = delete_resume_metadata(path);
+    }
 
     pb.finish_with_message("Upload complete!");
 
@@ -532,22 +1841,242 @@ pub async fn send_file(connection_string: String, local_path: String, remote_pat
     Ok(())
 }
 
+/// How long to wait for the server to acknowledge the tail of an upload
+const UPLOAD_DRAIN_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(30);
+
+/// Finish `send` and wait until the peer has acknowledged every byte on it,
+/// so a `conn.close()` right after can't cut off the end of the data.
+async fn finish_and_drain(send: &mut iroh::endpoint::SendStream) -> Result<()> {
+    send.finish()
+        .map_err(|e| n0_snafu::Error::anyhow(anyhow::anyhow!("Failed to finish upload stream: {}", e)))?;
+    match tokio::time::timeout(UPLOAD_DRAIN_TIMEOUT, send.stopped()).await {
+        Ok(Ok(None)) => Ok(()),
+        Ok(Ok(Some(code))) => Err(n0_snafu::Error::anyhow(anyhow::anyhow!(
+            "Server stopped the upload stream early (code {})", code
+        ))),
+        Ok(Err(e)) => Err(link_error(format_args!(
+            "Connection lost before the server received the whole upload: {}", e
+        ))),
+        Err(_) => Err(n0_snafu::Error::anyhow(anyhow::anyhow!(
+            "Timed out waiting for the server to receive the end of the upload"
+        ))),
+    }
+}
+
+/// What to do when a file in a directory pull already exists locally
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum ConflictPolicy {
+    /// Replace the local file
+    #[default]
+    Overwrite,
+    /// Keep the local file and discard the remote one
+    Skip,
+    /// Save the remote file next to it as "name (1).ext"
+    Rename,
+    /// Prompt for each file (skips when stdin isn't a terminal)
+    Ask,
+}
+
+/// Refuse a directory pull into `local` when that would mix with what's already there.
+/// A file of the same name is always an error; a non-empty directory needs `merge`.
+fn check_pull_destination(local: &Path, merge: bool) -> anyhow::Result<()> {
+    let Ok(metadata) = std::fs::metadata(local) else {
+        return Ok(());
+    };
+    if !metadata.is_dir() {
+        anyhow::bail!("{} exists and is not a directory", local.display());
+    }
+    let non_empty = std::fs::read_dir(local)
+        .map_err(|e| anyhow::anyhow!("Failed to read {}: {}", local.display(), e))?
+        .next()
+        .is_some();
+    if non_empty && !merge {
+        anyhow::bail!(
+            "{} already exists and is not empty; pass --merge to pull into it anyway",
+            local.display()
+        );
+    }
+    Ok(())
+}
+
+/// First "name (N).ext" next to `path` that doesn't exist yet
+fn conflict_free_path(path: &Path) -> PathBuf {
+    let stem = path.file_stem().map(|s| s.to_string_lossy().to_string()).unwrap_or_default();
+    let ext = path.extension().map(|e| format!(".{}", e.to_string_lossy())).unwrap_or_default();
+    let parent = path.parent().unwrap_or(Path::new(""));
+    (1..)
+        .map(|n| parent.join(format!("{} ({}){}", stem, n, ext)))
+        .find(|candidate| fs::symlink_metadata(candidate).is_err())
+        .expect("unbounded range always yields a free name")
+}
+
+/// Ask how to resolve a conflict; uppercase answers apply to all remaining files.
+/// Returns the decision for this file and whether it should become the new policy.
+fn ask_conflict(path: &Path) -> (ConflictPolicy, bool) {
+    use std::io::IsTerminal;
+
+    if !io::stdin().is_terminal() {
+        return (ConflictPolicy::Skip, false);
+    }
+    loop {
+        print!("{} exists. [o]verwrite, [s]kip, [r]ename (O/S/R = all): ", path.display());
+        let _ = io::stdout().flush();
+        let mut input = String::new();
+        if io::stdin().read_line(&mut input).is_err() {
+            return (ConflictPolicy::Skip, false);
+        }
+        match input.trim() {
+            "o" => return (ConflictPolicy::Overwrite, false),
+            "s" => return (ConflictPolicy::Skip, false),
+            "r" => return (ConflictPolicy::Rename, false),
+            "O" => return (ConflictPolicy::Overwrite, true),
+            "S" => return (ConflictPolicy::Skip, true),
+            "R" => return (ConflictPolicy::Rename, true),
+            _ => {}
+        }
+    }
+}
+
+/// Apply `on_conflict` to a directory entry about to be written at `path`.
+/// Returns where to write it, or `None` to skip it; "all" answers update `on_conflict`.
+fn resolve_conflict(path: PathBuf, on_conflict: &mut ConflictPolicy, pb: &indicatif::ProgressBar) -> Option<PathBuf> {
+    if fs::symlink_metadata(&path).is_err() {
+        return Some(path);
+    }
+    let decision = if *on_conflict == ConflictPolicy::Ask {
+        let (decision, for_all) = pb.suspend(|| ask_conflict(&path));
+        if for_all {
+            *on_conflict = decision;
+        }
+        decision
+    } else {
+        *on_conflict
+    };
+    match decision {
+        ConflictPolicy::Skip => {
+            pb.println(format!("Skipped existing {}", path.display()));
+            None
+        }
+        ConflictPolicy::Rename => {
+            let renamed = conflict_free_path(&path);
+            pb.println(format!("Saving as {}", renamed.display()));
+            Some(renamed)
+        }
+        ConflictPolicy::Overwrite | ConflictPolicy::Ask => Some(path),
+    }
+}
+
+/// Where a directory pull's entry goes under `local`, or None if the server's relative
+/// path could lead outside it: an absolute path, a `..`, or a path through (or onto) a
+/// symlink this pull created, in `links`
+fn pull_entry_path(local: &Path, relative_path: &str, links: &std::collections::HashSet<PathBuf>) -> Option<PathBuf> {
+    use std::path::Component;
+
+    let relative = Path::new(relative_path);
+    if !relative.components().all(|c| matches!(c, Component::Normal(_))) {
+        return None;
+    }
+    if relative.ancestors().any(|ancestor| links.contains(ancestor)) {
+        return None;
+    }
+    Some(local.join(relative))
+}
+
 /// Pull a file or directory from the server
-pub async fn pull_file(connection_string: String, remote_path: String, local_path: String) -> Result<()> {
+///
+/// `preserve_times` defaults to on for directories and off for single files.
+/// `on_conflict` applies to files that already exist inside a directory pull.
+/// A `local_path` of `-` writes the file to stdout, like `kerr cat`.
+/// `resume_dir` keeps resume metadata there instead of next to the file.
+/// Symlinks inside a directory are recreated as links unless `dereference` is set.
+/// A directory pull refuses a non-empty destination unless `merge` is set.
+/// If the connection drops, the pull resumes on a new one up to `retries` times.
+/// `limit` caps the download at that many bytes per second (0 = unlimited).
+#[allow(clippy::too_many_arguments)]
+pub async fn pull_file(
+    connection_string: String,
+    remote_path: String,
+    local_path: String,
+    preserve_times: Option<bool>,
+    preserve_perms: bool,
+    on_conflict: ConflictPolicy,
+    resume_dir: Option<PathBuf>,
+    dereference: bool,
+    merge: bool,
+    retries: u32,
+    limit: u64,
+) -> Result<()> {
+    if local_path == "-" {
+        return cat_file(connection_string, remote_path).await;
+    }
+
+    let mut merge = merge;
+    let mut failures = 0;
+    let mut asked = 0;
+    loop {
+        let mut started = false;
+        let result = pull_file_attempt(
+            connection_string.clone(), remote_path.clone(), local_path.clone(),
+            preserve_times, preserve_perms, on_conflict, resume_dir.clone(), dereference, merge, limit, &mut started,
+        ).await;
+        match result {
+            Err(e) if ask_password(&e, &mut asked) => {}
+            // Once a directory pull has begun, the destination holds our own partial copy
+            Err(e) if retry_transfer(&e, &mut failures, retries).await => merge |= started,
+            result => return result,
+        }
+    }
+}
+
+/// One try at `pull_file`, picking up from the resume metadata of earlier ones;
+/// `started` is set once the destination has been accepted
+#[allow(clippy::too_many_arguments)]
+async fn pull_file_attempt(
+    connection_string: String,
+    remote_path: String,
+    local_path: String,
+    preserve_times: Option<bool>,
+    preserve_perms: bool,
+    on_conflict: ConflictPolicy,
+    resume_dir: Option<PathBuf>,
+    dereference: bool,
+    merge: bool,
+    limit: u64,
+    started: &mut bool,
+) -> Result<()> {
     use std::path::Path;
     use std::fs;
-    use std::io::Write;
+    use std::io::{Read, Write};
     use indicatif::{ProgressBar, ProgressStyle};
     use rand::RngExt;
 
     // Check for existing resume metadata and validate before using
-    let resume_metadata = read_resume_metadata(&local_path);
+    let resume_path = get_resume_metadata_path(&local_path, &remote_path, resume_dir.as_deref());
+    let resume_metadata: Option<ResumeMetadata> = read_resume_metadata(&resume_path);
     let mut resume_offset = 0u64;
+    // Directory resume: files already received in full, which the server can skip
+    let mut completed_files: BTreeMap<String, (u64, Option<i64>)> = BTreeMap::new();
 
     if let Some(ref metadata) = resume_metadata {
         if metadata.remote_path != remote_path {
             println!("Warning: Resume metadata points to different remote file, starting fresh");
-            let _ = delete_resume_metadata(&local_path);
+            let _ = delete_resume_metadata(&resume_path);
+        } else if metadata.is_dir {
+            // Only trust files that are still there at the size they were received at
+            let local_dir = Path::new(&local_path);
+            completed_files = metadata.completed_files.iter()
+                .filter(|(relative, (size, _))| {
+                    fs::symlink_metadata(local_dir.join(relative)).is_ok_and(|m| m.is_file() && m.len() == *size)
+                })
+                .map(|(relative, completed)| (relative.clone(), *completed))
+                .collect();
+            if completed_files.is_empty() {
+                println!("Warning: No completed files found locally, starting fresh");
+                let _ = delete_resume_metadata(&resume_path);
+            } else {
+                println!("Found incomplete download, skipping {} completed files ({} bytes)...",
+                    completed_files.len(), completed_files.values().map(|(size, _)| size).sum::<u64>());
+            }
         } else {
             // Verify local file size matches the resume offset before using it
             let local_file = Path::new(&local_path);
@@ -556,14 +2085,17 @@ pub async fn pull_file(connection_string: String, remote_path: String, local_pat
                 if file_size == metadata.bytes_received {
                     println!("Found incomplete download, resuming from {} bytes...", metadata.bytes_received);
                     resume_offset = metadata.bytes_received;
+                } else if metadata.bytes_received == 0 {
+                    // Left by a download that failed its integrity check
+                    let _ = delete_resume_metadata(&resume_path);
                 } else {
                     println!("Warning: File size mismatch ({} vs expected {}), starting fresh",
                         file_size, metadata.bytes_received);
-                    let _ = delete_resume_metadata(&local_path);
+                    let _ = delete_resume_metadata(&resume_path);
                 }
             } else {
                 println!("Warning: Local file missing, starting fresh");
-                let _ = delete_resume_metadata(&local_path);
+                let _ = delete_resume_metadata(&resume_path);
             }
         }
     }
@@ -573,9 +2105,9 @@ pub async fn pull_file(connection_string: String, remote_path: String, local_pat
         .expect("Failed to decode connection string");
 
     println!("Connecting to server...");
-    let endpoint = Endpoint::bind(iroh::endpoint::presets::N0).await.map_err(|e| n0_snafu::Error::anyhow(anyhow::anyhow!("{}", e)))?;
-    let conn = endpoint.connect(addr, ALPN).await.map_err(|e| n0_snafu::Error::anyhow(anyhow::anyhow!("{}", e)))?;
-    let (mut send, mut recv) = conn.open_bi().await.e()?;
+    let endpoint = crate::bind_endpoint().await.map_err(|e| n0_snafu::Error::anyhow(anyhow::anyhow!("{}", e)))?;
+    let conn = connect(&endpoint, addr).await?;
+    let (mut send, mut recv) = conn.open_bi().await.map_err(open_stream_error)?;
 
     // Generate a unique session ID for this file transfer
     let session_id = format!("pull_{}", rand::rng().random::<u64>());
@@ -586,33 +2118,85 @@ pub async fn pull_file(connection_string: String, remote_path: String, local_pat
         session_id: session_id.clone(),
         payload: crate::MessagePayload::Client(hello_msg),
     };
-    crate::send_envelope(&mut send, &hello_envelope).await.map_err(|e| n0_snafu::Error::anyhow(anyhow::anyhow!("{}", e)))?;
-
-    // Send RequestDownload message with offset for resume support
-    let request_msg = ClientMessage::RequestDownload {
-        path: remote_path.clone(),
-        offset: resume_offset,
-    };
-    let request_envelope = crate::MessageEnvelope {
-        session_id: session_id.clone(),
-        payload: crate::MessagePayload::Client(request_msg),
-    };
-    crate::send_envelope(&mut send, &request_envelope).await.map_err(|e| n0_snafu::Error::anyhow(anyhow::anyhow!("{}", e)))?;
+    crate::send_envelope(&mut send, &hello_envelope).await.map_err(link_error)?;
+    authenticate(&mut send, &mut recv, &session_id).await?;
+
+    // Tell the server how to treat symlinks (older servers reject this and skip them),
+    // which files a resumed directory pull already has and that holes and compressed
+    // chunks are welcome, then send RequestDownload with offset for resume support
+    let mut requests = vec![ClientMessage::TransferOptions { dereference }];
+    if !completed_files.is_empty() {
+        let files = completed_files.iter()
+            .map(|(relative_path, &(size, modified))| crate::CompletedFile { relative_path: relative_path.clone(), size, modified })
+            .collect();
+        requests.push(ClientMessage::SkipFiles { files });
+    }
+    requests.push(ClientMessage::SparseFiles);
+    requests.push(ClientMessage::CompressedChunks);
+    requests.push(ClientMessage::DownloadHashes);
+    requests.push(ClientMessage::RequestDownload { path: remote_path.clone(), offset: resume_offset });
+    for msg in requests {
+        let envelope = crate::MessageEnvelope {
+            session_id: session_id.clone(),
+            payload: crate::MessagePayload::Client(msg),
+        };
+        crate::send_envelope(&mut send, &envelope).await.map_err(link_error)?;
+    }
 
     // Wait for StartDownload or Error
-    let response_envelope = crate::recv_envelope(&mut recv).await.map_err(|e| n0_snafu::Error::anyhow(anyhow::anyhow!("{}", e)))?;
+    // Older servers reject each option they don't know. The options were added in the
+    // order they're sent, so n rejections mean the last n weren't understood;
+    // DownloadHashes, CompressedChunks and SparseFiles need no handling, as such a
+    // server never sends hashes, compressed chunks or holes.
+    let mut response_envelope = crate::recv_envelope(&mut recv).await.map_err(link_error)?;
+    let mut rejected = 0u32;
+    while is_unsupported_reply(&response_envelope) {
+        rejected += 1;
+        response_envelope = crate::recv_envelope(&mut recv).await.map_err(link_error)?;
+    }
+    rejected = rejected.saturating_sub(3);
+    let resuming_dir = !completed_files.is_empty();
+    if resuming_dir && rejected > 0 {
+        println!("Note: the server can't skip completed files (older kerr); downloading everything again");
+        completed_files.clear();
+        rejected -= 1;
+    }
+    let symlinks_skipped = rejected > 0;
+
+    // A large directory takes the server a while to enumerate; it reports in meanwhile
+    let mut preparing: Option<ProgressBar> = None;
+    while let crate::MessagePayload::Server(ServerMessage::Preparing { entries_scanned }) = response_envelope.payload {
+        let spinner = preparing.get_or_insert_with(connect_spinner);
+        spinner.set_message(format!("Preparing transfer... {} entries scanned", entries_scanned));
+        response_envelope = crate::recv_envelope(&mut recv).await.map_err(link_error)?;
+    }
+    if let Some(spinner) = preparing {
+        spinner.finish_and_clear();
+    }
 
     let (total_size, is_dir) = match response_envelope.payload {
         crate::MessagePayload::Server(ServerMessage::StartDownload { size, is_dir }) => (size, is_dir),
         crate::MessagePayload::Server(ServerMessage::Error { message }) => {
-            return Err(n0_snafu::Error::anyhow(anyhow::anyhow!("Server error: {}", message)));
+            return Err(server_error(message));
         }
         _ => {
             return Err(n0_snafu::Error::anyhow(anyhow::anyhow!("Unexpected server response")));
         }
     };
 
+    // Picking a conflict policy other than the default only makes sense when merging.
+    // A resumed pull is writing into its own earlier, partial copy.
+    if is_dir && !resuming_dir {
+        check_pull_destination(Path::new(&local_path), merge || on_conflict != ConflictPolicy::Overwrite)
+            .map_err(n0_snafu::Error::anyhow)?;
+    }
+    *started = true;
+
     println!("Downloading {} ({} bytes)...", remote_path, total_size);
+    if symlinks_skipped && is_dir {
+        println!("Note: the server doesn't support symlink options (older kerr); symlinks will be skipped");
+    }
+    let preserve_times = preserve_times.unwrap_or(is_dir);
 
     // Prepare destination
     let local = Path::new(&local_path);
@@ -620,7 +2204,8 @@ pub async fn pull_file(connection_string: String, remote_path: String, local_pat
     // For directory downloads, create the base directory
     // For single file downloads, open the output file (with resume support)
     let mut output_file: Option<fs::File> = if is_dir {
-        fs::create_dir_all(&local_path).expect("Failed to create directory");
+        fs::create_dir_all(&local_path).map_err(|e| n0_snafu::Error::anyhow(
+            anyhow::anyhow!("Failed to create directory {}: {}", local_path, e)))?;
         None
     } else {
         crate::transfer::ensure_parent_dir(local)
@@ -643,70 +2228,284 @@ pub async fn pull_file(connection_string: String, remote_path: String, local_pat
         .unwrap()
         .progress_chars("#>-"));
 
-    let mut bytes_received = resume_offset;
+    // Start from what earlier attempts already received, without counting it in the rate
+    let mut bytes_received = resume_offset + completed_files.values().map(|(size, _)| size).sum::<u64>();
     pb.set_position(bytes_received);
+    pb.reset_eta();
+
+    let mut resume = ResumeMetadata {
+        bytes_received,
+        total_size,
+        remote_path: remote_path.clone(),
+        is_dir,
+        completed_files,
+    };
+    // Symlinks this pull created (relative paths), which later entries may not go through
+    let mut created_links: std::collections::HashSet<PathBuf> = std::collections::HashSet::new();
+    // The directory entry being received: relative path, size and bytes so far
+    let mut current_entry: Option<(String, u64, u64)> = None;
+
+    // Hash of the file being received, checked against the server's `DownloadComplete`.
+    // A resumed file's hash starts with the part already on disk.
+    let mut hasher = blake3::Hasher::new();
+    if resume_offset > 0 {
+        let prefix = fs::File::open(local).and_then(|f| hasher.update_reader(f.take(resume_offset)).map(|_| ()));
+        if let Err(e) = prefix {
+            pb.println(format!("Warning: can't read {} to verify it: {}", local_path, e));
+        }
+    }
+    // Directory entries that failed their integrity check
+    let mut corrupt_files: Vec<String> = Vec::new();
+
+    // Where the current directory entry is being written (None = skipped)
+    let mut current_target: Option<PathBuf> = None;
+    let mut on_conflict = on_conflict;
 
-    // Receive file chunks using the multiplexed protocol
+    // Ctrl+C stops the download, keeping what arrived so the same pull resumes it
+    let mut interrupted = std::pin::pin!(tokio::signal::ctrl_c());
+
+    // Receive file chunks using the multiplexed protocol. With a limit, the next chunk is
+    // only read once the rate allows, and QUIC flow control then slows the server down.
+    let mut limiter = crate::transfer::RateLimiter::new(limit);
     let mut chunk_count = 0u64;
     loop {
-        let envelope = crate::recv_envelope(&mut recv).await.map_err(|e| n0_snafu::Error::anyhow(anyhow::anyhow!("{}", e)))?;
+        let received = tokio::select! {
+            received = async {
+                limiter.ready().await;
+                crate::recv_envelope(&mut recv).await
+            } => received,
+            _ = &mut interrupted => {
+                pb.abandon_with_message("Download cancelled");
+                if !is_dir {
+                    resume.bytes_received = bytes_received;
+                }
+                let _ = write_resume_metadata(&resume_path, &resume);
+
+                // Tell the server to stop sending, so it doesn't stream the rest into a dead connection
+                for msg in [ClientMessage::CancelDownload, ClientMessage::Disconnect] {
+                    let envelope = crate::MessageEnvelope {
+                        session_id: session_id.clone(),
+                        payload: crate::MessagePayload::Client(msg),
+                    };
+                    let _ = crate::send_envelope(&mut send, &envelope).await;
+                }
+                let _ = send.finish();
+                conn.close(0u32.into(), b"cancelled");
+                endpoint.close().await;
+                return Err(n0_snafu::Error::anyhow(anyhow::anyhow!(
+                    "Download cancelled; run the same pull again to resume")));
+            }
+        };
+        let envelope = match received {
+            Ok(envelope) => envelope,
+            Err(e) => {
+                // Lost the connection: keep what we have for the next attempt
+                pb.abandon_with_message("Download interrupted");
+                if !is_dir {
+                    resume.bytes_received = bytes_received;
+                }
+                let _ = write_resume_metadata(&resume_path, &resume);
+                return Err(link_error(e));
+            }
+        };
 
         match envelope.payload {
-            crate::MessagePayload::Server(ServerMessage::FileStart { relative_path, size: _ }) => {
+            crate::MessagePayload::Server(ServerMessage::FileStart { relative_path, size }) => {
+                current_entry = Some((relative_path.clone(), size, 0));
+                hasher.reset();
+                // A file an earlier attempt completed that has changed on the server since;
+                // our old copy is replaced without asking
+                let stale = resume.completed_files.remove(&relative_path);
+                if let Some((old_size, _)) = stale {
+                    bytes_received = bytes_received.saturating_sub(old_size);
+                    resume.bytes_received = resume.bytes_received.saturating_sub(old_size);
+                    pb.set_position(bytes_received);
+                }
                 // Directory download: open a new file for this entry
-                let file_path = Path::new(&local_path).join(&relative_path);
+                let Some(entry_path) = pull_entry_path(local, &relative_path, &created_links) else {
+                    pb.println(format!("Warning: skipping {}, it would be written outside {}", relative_path, local_path));
+                    output_file = None;
+                    current_target = None;
+                    continue;
+                };
+                let resolved = if stale.is_some() { Some(entry_path) } else { resolve_conflict(entry_path, &mut on_conflict, &pb) };
+                let Some(file_path) = resolved else {
+                    output_file = None;
+                    current_target = None;
+                    continue;
+                };
                 if let Some(parent) = file_path.parent() {
                     fs::create_dir_all(parent).expect("Failed to create parent directory");
                 }
                 output_file = Some(fs::File::create(&file_path).expect("Failed to create file"));
+                current_target = Some(file_path);
             }
-            crate::MessagePayload::Server(ServerMessage::FileChunk { data }) => {
+            crate::MessagePayload::Server(ServerMessage::Symlink { relative_path, target }) => {
+                output_file = None;
+                current_target = None;
+                current_entry = None;
+                let Some(entry_path) = pull_entry_path(local, &relative_path, &created_links) else {
+                    pb.println(format!("Warning: skipping link {}, it would be created outside {}", relative_path, local_path));
+                    continue;
+                };
+                // Later entries must not be written through this link
+                created_links.insert(PathBuf::from(&relative_path));
+                if let Some(link_path) = resolve_conflict(entry_path, &mut on_conflict, &pb)
+                    && let Err(e) = crate::transfer::create_symlink(&link_path, &target)
+                {
+                    pb.println(format!("Warning: {:#}", e));
+                }
+            }
+            crate::MessagePayload::Server(
+                ServerMessage::FileChunk { .. } | ServerMessage::FileHole { .. } | ServerMessage::CompressedChunk { .. },
+            ) => {
+                // A hole stands for `len` zero bytes the server didn't send
+                let inflated;
+                let (data, hole) = match &envelope.payload {
+                    crate::MessagePayload::Server(ServerMessage::FileChunk { data }) => (data.as_slice(), 0),
+                    crate::MessagePayload::Server(ServerMessage::FileHole { len }) => match crate::transfer::hole_len(*len) {
+                        Ok(len) => (&[][..], len),
+                        Err(e) => {
+                            pb.abandon_with_message("Download failed");
+                            if !is_dir {
+                                resume.bytes_received = bytes_received;
+                            }
+                            let _ = write_resume_metadata(&resume_path, &resume);
+                            return Err(n0_snafu::Error::anyhow(e));
+                        }
+                    },
+                    crate::MessagePayload::Server(ServerMessage::CompressedChunk { data }) => {
+                        match crate::transfer::decompress_chunk(data) {
+                            Ok(data) => {
+                                inflated = data;
+                                (inflated.as_slice(), 0)
+                            }
+                            Err(e) => {
+                                pb.abandon_with_message("Download failed");
+                                if !is_dir {
+                                    resume.bytes_received = bytes_received;
+                                }
+                                let _ = write_resume_metadata(&resume_path, &resume);
+                                return Err(n0_snafu::Error::anyhow(e));
+                            }
+                        }
+                    }
+                    _ => unreachable!(),
+                };
+                let chunk_len = data.len() as u64 + hole;
+                if hole > 0 {
+                    hasher.update(&vec![0; hole as usize]);
+                } else {
+                    hasher.update(data);
+                }
                 if let Some(ref mut file) = output_file {
-                    file.write_all(&data)
-                        .expect("Failed to write to file");
+                    if hole > 0 {
+                        crate::transfer::write_hole(file, hole)
+                    } else {
+                        file.write_all(data)
+                    }
+                    .expect("Failed to write to file");
                 }
-                bytes_received += data.len() as u64;
+                bytes_received += chunk_len;
                 pb.set_position(bytes_received);
+                limiter.consume(data.len() as u64);
+                if let Some((_, _, received)) = current_entry.as_mut() {
+                    *received += chunk_len;
+                }
 
                 // Update resume metadata every 10 chunks (single-file only)
                 if !is_dir {
                     chunk_count += 1;
                     if chunk_count % 10 == 0 {
-                        let metadata = ResumeMetadata {
-                            bytes_received,
-                            total_size,
-                            remote_path: remote_path.clone(),
-                        };
-                        let _ = write_resume_metadata(&local_path, &metadata);
+                        resume.bytes_received = bytes_received;
+                        let _ = write_resume_metadata(&resume_path, &resume);
                     }
                 }
             }
-            crate::MessagePayload::Server(ServerMessage::EndDownload) => {
-                pb.finish_with_message("Download complete!");
-                let _ = delete_resume_metadata(&local_path);
+            crate::MessagePayload::Server(ServerMessage::DownloadComplete { hash }) => {
+                if hasher.finalize().to_hex().as_str() == hash {
+                    continue;
+                }
+                if !is_dir {
+                    // Resuming would keep the bad bytes, so the next pull starts over
+                    pb.abandon_with_message("Download failed");
+                    resume.bytes_received = 0;
+                    let _ = write_resume_metadata(&resume_path, &resume);
+                    return Err(n0_snafu::Error::anyhow(anyhow::anyhow!(
+                        "{} failed its integrity check (blake3 mismatch); run the same pull again to download it again",
+                        local_path)));
+                }
+                // Leave the entry out of the completed files so a resumed pull fetches it again
+                if let Some((entry, _, _)) = current_entry.take() {
+                    pb.println(format!("Error: {} failed its integrity check (blake3 mismatch)", entry));
+                    corrupt_files.push(entry);
+                }
+            }
+            crate::MessagePayload::Server(ServerMessage::EndDownload) => {
+                if !corrupt_files.is_empty() {
+                    pb.abandon_with_message("Download failed");
+                    let _ = write_resume_metadata(&resume_path, &resume);
+                    return Err(n0_snafu::Error::anyhow(anyhow::anyhow!(
+                        "{} file(s) failed their integrity check: {}; run the same pull again to download them again",
+                        corrupt_files.len(), corrupt_files.join(", "))));
+                }
+                pb.finish_with_message("Download complete!");
+                let _ = delete_resume_metadata(&resume_path);
                 break;
             }
+            crate::MessagePayload::Server(ServerMessage::FileAttributes { relative_path, modified, mode }) => {
+                // Attributes follow each directory entry's data, so the entry is done;
+                // record it if it arrived in full so a resumed pull can skip it
+                let finished = current_entry.take()
+                    .filter(|(entry, size, received)| *entry == relative_path && received == size);
+                if let Some((entry, size, _)) = finished {
+                    resume.completed_files.insert(entry, (size, modified));
+                    resume.bytes_received += size;
+                    if resume.completed_files.len().is_multiple_of(10) {
+                        let _ = write_resume_metadata(&resume_path, &resume);
+                    }
+                }
+
+                let modified = modified.filter(|_| preserve_times);
+                let mode = mode.filter(|_| preserve_perms);
+                if modified.is_some() || mode.is_some() {
+                    // Close the file first so no later write bumps the mtime again
+                    if let Some(mut file) = output_file.take() {
+                        let _ = file.flush();
+                    }
+                    // Directory entries may have been renamed or skipped on conflict
+                    let target = if relative_path.is_empty() {
+                        Some(local.to_path_buf())
+                    } else {
+                        current_target.clone()
+                    };
+                    if let Some(target) = target {
+                        if let Some(mode) = mode
+                            && let Err(e) = crate::transfer::set_mode_bits(&target, mode)
+                        {
+                            pb.println(format!("Warning: {}", e));
+                        }
+                        if let Some(modified) = modified
+                            && let Err(e) = crate::transfer::set_modified_secs(&target, modified)
+                        {
+                            pb.println(format!("Warning: {}", e));
+                        }
+                    }
+                }
+            }
             crate::MessagePayload::Server(ServerMessage::Error { message }) => {
                 pb.finish_with_message("Download failed");
                 if !is_dir {
-                    let metadata = ResumeMetadata {
-                        bytes_received,
-                        total_size,
-                        remote_path: remote_path.clone(),
-                    };
-                    let _ = write_resume_metadata(&local_path, &metadata);
+                    resume.bytes_received = bytes_received;
                 }
+                let _ = write_resume_metadata(&resume_path, &resume);
                 return Err(n0_snafu::Error::anyhow(anyhow::anyhow!("Server error: {}", message)));
             }
             _ => {
                 if !is_dir {
-                    let metadata = ResumeMetadata {
-                        bytes_received,
-                        total_size,
-                        remote_path: remote_path.clone(),
-                    };
-                    let _ = write_resume_metadata(&local_path, &metadata);
+                    resume.bytes_received = bytes_received;
                 }
+                let _ = write_resume_metadata(&resume_path, &resume);
                 return Err(n0_snafu::Error::anyhow(anyhow::anyhow!("Unexpected server message during download")));
             }
         }
@@ -720,18 +2519,373 @@ pub async fn pull_file(connection_string: String, remote_path: String, local_pat
     Ok(())
 }
 
+/// Stream a remote file to stdout (binary-safe, no progress output)
+pub async fn cat_file(connection_string: String, remote_path: String) -> Result<()> {
+    let mut asked = 0;
+    loop {
+        match cat_file_attempt(connection_string.clone(), remote_path.clone()).await {
+            Err(e) if ask_password(&e, &mut asked) => {}
+            result => return result,
+        }
+    }
+}
+
+/// One try at `cat_file`; nothing is written to stdout before the server accepts
+/// the password and the download starts
+async fn cat_file_attempt(connection_string: String, remote_path: String) -> Result<()> {
+    use rand::RngExt;
+
+    let addr = crate::decode_connection_string(&connection_string)
+        .map_err(|e| n0_snafu::Error::anyhow(anyhow::anyhow!("Failed to decode connection string: {}", e)))?;
+
+    // Status goes to stderr so stdout only carries file bytes
+    eprintln!("Connecting to server...");
+    let endpoint = crate::bind_endpoint().await.map_err(|e| n0_snafu::Error::anyhow(anyhow::anyhow!("{}", e)))?;
+    let conn = connect(&endpoint, addr).await?;
+    let (mut send, mut recv) = conn.open_bi().await.map_err(open_stream_error)?;
+
+    let session_id = format!("cat_{}", rand::rng().random::<u64>());
+
+    let send_msg = |msg: ClientMessage| crate::MessageEnvelope {
+        session_id: session_id.clone(),
+        payload: crate::MessagePayload::Client(msg),
+    };
+    let hello = send_msg(ClientMessage::Hello { session_type: crate::SessionType::FileTransfer });
+    crate::send_envelope(&mut send, &hello).await.map_err(|e| n0_snafu::Error::anyhow(anyhow::anyhow!("{}", e)))?;
+    authenticate(&mut send, &mut recv, &session_id).await?;
+    let request = send_msg(ClientMessage::RequestDownload { path: remote_path.clone(), offset: 0 });
+    crate::send_envelope(&mut send, &request).await.map_err(|e| n0_snafu::Error::anyhow(anyhow::anyhow!("{}", e)))?;
+
+    let response_envelope = crate::recv_envelope(&mut recv).await.map_err(|e| n0_snafu::Error::anyhow(anyhow::anyhow!("{}", e)))?;
+    match response_envelope.payload {
+        crate::MessagePayload::Server(ServerMessage::StartDownload { is_dir: false, .. }) => {}
+        crate::MessagePayload::Server(ServerMessage::StartDownload { is_dir: true, .. }) => {
+            return Err(n0_snafu::Error::anyhow(anyhow::anyhow!("{} is a directory", remote_path)));
+        }
+        crate::MessagePayload::Server(ServerMessage::Error { message }) => return Err(server_error(message)),
+        _ => {
+            return Err(n0_snafu::Error::anyhow(anyhow::anyhow!("Unexpected server response")));
+        }
+    }
+
+    // Write chunks straight through as they arrive
+    let mut stdout = io::stdout().lock();
+    loop {
+        let envelope = crate::recv_envelope(&mut recv).await.map_err(|e| n0_snafu::Error::anyhow(anyhow::anyhow!("{}", e)))?;
+        match envelope.payload {
+            crate::MessagePayload::Server(ServerMessage::FileChunk { data }) => {
+                if let Err(e) = stdout.write_all(&data) {
+                    // Reader went away (e.g. `| head`); stop quietly
+                    if e.kind() == io::ErrorKind::BrokenPipe {
+                        break;
+                    }
+                    return Err(n0_snafu::Error::anyhow(anyhow::anyhow!("Failed to write to stdout: {}", e)));
+                }
+            }
+            crate::MessagePayload::Server(ServerMessage::FileAttributes { .. }) => {}
+            crate::MessagePayload::Server(ServerMessage::EndDownload) => break,
+            crate::MessagePayload::Server(ServerMessage::Error { message }) => return Err(server_error(message)),
+            _ => {
+                return Err(n0_snafu::Error::anyhow(anyhow::anyhow!("Unexpected server message during download")));
+            }
+        }
+    }
+    let _ = stdout.flush();
+
+    conn.close(0u32.into(), b"done");
+    endpoint.close().await;
+
+    Ok(())
+}
+
+/// Run `command` on the server with `<shell> -c`, copying its stdout and stderr to ours
+/// as they arrive, and return its exit code. It runs without a terminal and with stdin
+/// closed, so its output is exactly what it wrote.
+pub async fn exec_command(connection_string: String, command: String) -> Result<i32> {
+    use rand::RngExt;
+
+    let addr = crate::decode_connection_string(&connection_string)
+        .map_err(|e| n0_snafu::Error::anyhow(anyhow::anyhow!("Failed to decode connection string: {}", e)))?;
+
+    // Status goes to stderr so stdout only carries the command's output
+    eprintln!("Connecting to server...");
+    let endpoint = crate::bind_endpoint().await.map_err(|e| n0_snafu::Error::anyhow(anyhow::anyhow!("{}", e)))?;
+
+    let mut asked = 0;
+    let (conn, mut recv) = loop {
+        let conn = connect(&endpoint, addr.clone()).await?;
+        let (mut send, mut recv) = conn.open_bi().await.map_err(open_stream_error)?;
+
+        let session_id = format!("exec_{}", rand::rng().random::<u64>());
+        let hello_envelope = crate::MessageEnvelope {
+            session_id: session_id.clone(),
+            payload: crate::MessagePayload::Client(ClientMessage::ExecHello { command: command.clone() }),
+        };
+        crate::send_envelope(&mut send, &hello_envelope).await.map_err(link_error)?;
+        if let Err(e) = authenticate(&mut send, &mut recv, &session_id).await {
+            conn.close(0u32.into(), b"bye!");
+            if ask_password(&e, &mut asked) {
+                continue;
+            }
+            return Err(e);
+        }
+        break (conn, recv);
+    };
+
+    // Bytes are written exactly as received; once stdout is closed (e.g. `| head`)
+    // the rest is dropped, but the exit code is still waited for
+    let mut stdout = io::stdout().lock();
+    let mut stdout_open = true;
+    let mut write_output = |data: &[u8]| -> Result<()> {
+        if !stdout_open {
+            return Ok(());
+        }
+        match stdout.write_all(data).and_then(|_| stdout.flush()) {
+            Ok(()) => Ok(()),
+            Err(e) if e.kind() == io::ErrorKind::BrokenPipe => {
+                stdout_open = false;
+                Ok(())
+            }
+            Err(e) => Err(n0_snafu::Error::anyhow(anyhow::anyhow!("Failed to write to stdout: {}", e))),
+        }
+    };
+
+    let code = loop {
+        let envelope = match crate::recv_envelope(&mut recv).await {
+            Ok(envelope) => envelope,
+            Err(e) if e.is::<crate::StreamClosed>() => break None,
+            Err(e) => return Err(link_error(e)),
+        };
+        if is_unsupported_reply(&envelope) {
+            return Err(n0_snafu::Error::anyhow(anyhow::anyhow!(
+                "The server is too old to run commands with kerr exec; update it to this version"
+            )));
+        }
+        match envelope.payload {
+            crate::MessagePayload::Server(ServerMessage::Output { data }) => write_output(&data)?,
+            crate::MessagePayload::Server(ServerMessage::ErrorOutput { data }) => {
+                let _ = io::stderr().write_all(&data);
+            }
+            crate::MessagePayload::Server(ServerMessage::Exit { code }) => break Some(code),
+            crate::MessagePayload::Server(ServerMessage::Error { message }) if message.starts_with("Session ended") => {
+                eprintln!("{}", message);
+                break None;
+            }
+            crate::MessagePayload::Server(ServerMessage::Error { message }) => return Err(server_error(message)),
+            _ => {}
+        }
+    };
+
+    conn.close(0u32.into(), b"done");
+    endpoint.close().await;
+
+    code.ok_or_else(|| n0_snafu::Error::anyhow(anyhow::anyhow!("The command ended without an exit code")))
+}
+
+/// Compare a local file with a remote one by blake3 hash, without transferring it
+pub async fn verify_file(connection_string: String, remote_path: String, local_path: String) -> Result<()> {
+    use rand::RngExt;
+
+    let local = PathBuf::from(&local_path);
+    if !local.is_file() {
+        return Err(n0_snafu::Error::anyhow(anyhow::anyhow!("{} is not a file", local_path)));
+    }
+
+    let addr = crate::decode_connection_string(&connection_string)
+        .map_err(|e| n0_snafu::Error::anyhow(anyhow::anyhow!("Failed to decode connection string: {}", e)))?;
+
+    println!("Connecting to server...");
+    let endpoint = crate::bind_endpoint().await.map_err(|e| n0_snafu::Error::anyhow(anyhow::anyhow!("{}", e)))?;
+    let conn = connect(&endpoint, addr).await?;
+    let (mut send, mut recv) = conn.open_bi().await.map_err(open_stream_error)?;
+
+    let session_id = format!("verify_{}", rand::rng().random::<u64>());
+
+    for msg in [
+        ClientMessage::Hello { session_type: crate::SessionType::FileBrowser },
+        ClientMessage::FsHashFile { path: remote_path.clone() },
+    ] {
+        let envelope = crate::MessageEnvelope {
+            session_id: session_id.clone(),
+            payload: crate::MessagePayload::Client(msg),
+        };
+        crate::send_envelope(&mut send, &envelope).await.map_err(|e| n0_snafu::Error::anyhow(anyhow::anyhow!("{}", e)))?;
+    }
+
+    // Hash the local copy while the server hashes its own
+    println!("Hashing {} and {}...", local_path, remote_path);
+    let local_task = tokio::task::spawn_blocking(move || -> io::Result<String> {
+        let mut hasher = blake3::Hasher::new();
+        hasher.update_reader(fs::File::open(&local)?)?;
+        Ok(hasher.finalize().to_hex().to_string())
+    });
+
+    let response = crate::recv_envelope(&mut recv).await.map_err(|e| n0_snafu::Error::anyhow(anyhow::anyhow!("{}", e)))?;
+    let remote_hash = match response.payload {
+        crate::MessagePayload::Server(ServerMessage::FsHashResponse { hash }) => hash,
+        crate::MessagePayload::Server(ServerMessage::FsError { message })
+        | crate::MessagePayload::Server(ServerMessage::Error { message }) => {
+            return Err(n0_snafu::Error::anyhow(anyhow::anyhow!("Server error: {}", message)));
+        }
+        _ => {
+            return Err(n0_snafu::Error::anyhow(anyhow::anyhow!("Unexpected server response to hash request")));
+        }
+    };
+
+    let local_hash = local_task
+        .await
+        .map_err(|e| n0_snafu::Error::anyhow(anyhow::anyhow!("Local hash task failed: {}", e)))?
+        .map_err(|e| n0_snafu::Error::anyhow(anyhow::anyhow!("Failed to hash {}: {}", local_path, e)))?;
+
+    conn.close(0u32.into(), b"done");
+    endpoint.close().await;
+
+    if local_hash == remote_hash {
+        println!("✓ Match (blake3 {})", local_hash);
+        Ok(())
+    } else {
+        println!("✗ Mismatch");
+        println!("  remote {}  {}", remote_hash, remote_path);
+        println!("  local  {}  {}", local_hash, local_path);
+        Err(n0_snafu::Error::anyhow(anyhow::anyhow!("{} differs from {}", local_path, remote_path)))
+    }
+}
+
+/// Default size limit for `kerr clip`; anything bigger belongs in a file transfer
+pub const CLIP_MAX_SIZE: u64 = 1024 * 1024;
+
+/// How long `kerr clip` keeps serving the clipboard on Linux, where the contents
+/// disappear with the process unless something else takes them over first
+#[cfg(target_os = "linux")]
+const CLIP_HOLD: std::time::Duration = std::time::Duration::from_secs(30);
+
+/// Copy a small remote text file to the local clipboard
+///
+/// Reads the file through the file browser's `FsReadFile`, after checking its size
+/// against `max_size` so a large file is never transferred.
+pub async fn clip_file(connection_string: String, remote_path: String, max_size: u64) -> Result<()> {
+    use rand::RngExt;
+    use crate::custom_explorer::file_explorer::FileMetadata;
+
+    let addr = crate::decode_connection_string(&connection_string)
+        .map_err(|e| n0_snafu::Error::anyhow(anyhow::anyhow!("Failed to decode connection string: {}", e)))?;
+
+    println!("Connecting to server...");
+    let endpoint = crate::bind_endpoint().await.map_err(|e| n0_snafu::Error::anyhow(anyhow::anyhow!("{}", e)))?;
+    let conn = connect(&endpoint, addr).await?;
+    let (mut send, mut recv) = conn.open_bi().await.map_err(open_stream_error)?;
+
+    let session_id = format!("clip_{}", rand::rng().random::<u64>());
+
+    for msg in [
+        ClientMessage::Hello { session_type: crate::SessionType::FileBrowser },
+        ClientMessage::FsMetadata { path: remote_path.clone() },
+    ] {
+        let envelope = crate::MessageEnvelope {
+            session_id: session_id.clone(),
+            payload: crate::MessagePayload::Client(msg),
+        };
+        crate::send_envelope(&mut send, &envelope).await.map_err(|e| n0_snafu::Error::anyhow(anyhow::anyhow!("{}", e)))?;
+    }
+
+    let response = crate::recv_envelope(&mut recv).await.map_err(|e| n0_snafu::Error::anyhow(anyhow::anyhow!("{}", e)))?;
+    let metadata: FileMetadata = match response.payload {
+        crate::MessagePayload::Server(ServerMessage::FsMetadataResponse { metadata_json }) => {
+            serde_json::from_str(&metadata_json)
+                .map_err(|e| n0_snafu::Error::anyhow(anyhow::anyhow!("Invalid metadata from server: {}", e)))?
+        }
+        crate::MessagePayload::Server(ServerMessage::FsError { message })
+        | crate::MessagePayload::Server(ServerMessage::Error { message }) => {
+            return Err(n0_snafu::Error::anyhow(anyhow::anyhow!("Server error: {}", message)));
+        }
+        _ => {
+            return Err(n0_snafu::Error::anyhow(anyhow::anyhow!("Unexpected server response to metadata request")));
+        }
+    };
+    if metadata.is_dir {
+        return Err(n0_snafu::Error::anyhow(anyhow::anyhow!("{} is a directory", remote_path)));
+    }
+    if metadata.size > max_size {
+        return Err(n0_snafu::Error::anyhow(anyhow::anyhow!(
+            "{} is {} bytes, over the {} byte clipboard limit; use kerr pull or --max-size",
+            remote_path, metadata.size, max_size
+        )));
+    }
+
+    let envelope = crate::MessageEnvelope {
+        session_id: session_id.clone(),
+        payload: crate::MessagePayload::Client(ClientMessage::FsReadFile { path: remote_path.clone() }),
+    };
+    crate::send_envelope(&mut send, &envelope).await.map_err(|e| n0_snafu::Error::anyhow(anyhow::anyhow!("{}", e)))?;
+
+    let response = crate::recv_envelope(&mut recv).await.map_err(|e| n0_snafu::Error::anyhow(anyhow::anyhow!("{}", e)))?;
+    let data = match response.payload {
+        crate::MessagePayload::Server(ServerMessage::FsFileContent { data }) => data,
+        crate::MessagePayload::Server(ServerMessage::FsError { message })
+        | crate::MessagePayload::Server(ServerMessage::Error { message }) => {
+            return Err(n0_snafu::Error::anyhow(anyhow::anyhow!("Server error: {}", message)));
+        }
+        _ => {
+            return Err(n0_snafu::Error::anyhow(anyhow::anyhow!("Unexpected server response to read request")));
+        }
+    };
+
+    conn.close(0u32.into(), b"done");
+    endpoint.close().await;
+
+    // The file may have grown between the two requests
+    if data.len() as u64 > max_size {
+        return Err(n0_snafu::Error::anyhow(anyhow::anyhow!(
+            "{} is over the {} byte clipboard limit", remote_path, max_size
+        )));
+    }
+    let text = String::from_utf8(data)
+        .map_err(|_| n0_snafu::Error::anyhow(anyhow::anyhow!("{} is not a text file", remote_path)))?;
+    let len = text.len();
+
+    #[cfg(target_os = "linux")]
+    println!("Serving the clipboard for up to {} s, until something else is copied (Ctrl+C to stop)...", CLIP_HOLD.as_secs());
+
+    tokio::task::spawn_blocking(move || -> std::result::Result<(), arboard::Error> {
+        let mut clipboard = arboard::Clipboard::new()?;
+
+        #[cfg(target_os = "linux")]
+        {
+            use arboard::SetExtLinux;
+            clipboard.set().wait_until(std::time::Instant::now() + CLIP_HOLD).text(text)
+        }
+
+        #[cfg(not(target_os = "linux"))]
+        clipboard.set_text(text)
+    })
+    .await
+    .map_err(|e| n0_snafu::Error::anyhow(anyhow::anyhow!("Clipboard task failed: {}", e)))?
+    .map_err(|e| n0_snafu::Error::anyhow(anyhow::anyhow!("Failed to access clipboard: {}", e)))?;
+
+    println!("✓ Copied {} bytes from {} to the clipboard", len, remote_path);
+    Ok(())
+}
+
 /// Test network performance with increasing payload sizes
-pub async fn ping_test(connection_string: String) -> Result<()> {
+///
+/// With `datagram`, payloads small enough for a QUIC datagram are also echoed that
+/// way, separating network latency from kerr's stream and serialization overhead.
+pub async fn ping_test(connection_string: String, format: Option<PingFormat>, datagram: bool) -> Result<()> {
+    use std::io::IsTerminal;
     use std::time::Instant;
 
+    // Scripts piping our output get JSON unless they asked for something else
+    let format = format.unwrap_or(if io::stdout().is_terminal() { PingFormat::Table } else { PingFormat::Json });
+    let table = format == PingFormat::Table;
+
     // Decode the compressed connection string (base64 -> gzip -> JSON)
     let addr = crate::decode_connection_string(&connection_string)
         .expect("Failed to decode connection string");
 
-    println!("Connecting to server...");
-    let endpoint = Endpoint::bind(iroh::endpoint::presets::N0).await.map_err(|e| n0_snafu::Error::anyhow(anyhow::anyhow!("{}", e)))?;
-    let conn = endpoint.connect(addr, ALPN).await.map_err(|e| n0_snafu::Error::anyhow(anyhow::anyhow!("{}", e)))?;
-    let (mut send, mut recv) = conn.open_bi().await.e()?;
+    eprintln!("Connecting to server...");
+    let endpoint = crate::bind_endpoint().await.map_err(|e| n0_snafu::Error::anyhow(anyhow::anyhow!("{}", e)))?;
+    let conn = connect(&endpoint, addr).await?;
+    let (mut send, mut recv) = conn.open_bi().await.map_err(open_stream_error)?;
 
     // Generate a unique session ID for this ping session
     use rand::RngExt;
@@ -745,11 +2899,30 @@ pub async fn ping_test(connection_string: String) -> Result<()> {
     };
     crate::send_envelope(&mut send, &hello_envelope).await.map_err(|e| n0_snafu::Error::anyhow(anyhow::anyhow!("{}", e)))?;
 
-    println!("\n╔══════════════════════════════════════════════════════════════════════╗");
-    println!("║                    Network Performance Test                          ║");
-    println!("╚══════════════════════════════════════════════════════════════════════╝\n");
-    println!("{:<12} {:<15} {:<15} {:<15}", "Payload Size", "Round-Trip", "Throughput", "Effective BW");
-    println!("{}", "─".repeat(70));
+    let max_datagram = if datagram {
+        let max = conn.max_datagram_size();
+        if max.is_none() {
+            eprintln!("Warning: the connection doesn't support datagrams, skipping datagram RTT");
+        }
+        max
+    } else {
+        None
+    };
+    let mut next_probe = 0u64;
+
+    if table {
+        println!("\n╔══════════════════════════════════════════════════════════════════════╗");
+        println!("║                    Network Performance Test                          ║");
+        println!("╚══════════════════════════════════════════════════════════════════════╝\n");
+        if datagram {
+            println!("{:<12} {:<15} {:<15} {:<15} {:<15}", "Payload Size", "Round-Trip", "Datagram RTT", "Throughput", "Effective BW");
+            println!("{}", "─".repeat(86));
+        } else {
+            println!("{:<12} {:<15} {:<15} {:<15}", "Payload Size", "Round-Trip", "Throughput", "Effective BW");
+            println!("{}", "─".repeat(70));
+        }
+    }
+    let mut results = Vec::new();
 
     // Test with exponentially growing payload sizes: 0, 1KB, 4KB, 16KB, 64KB, 256KB, 1MB
     let sizes = vec![0, 1024, 4096, 16384, 65536, 262144, 1048576];
@@ -806,6 +2979,23 @@ pub async fn ping_test(connection_string: String) -> Result<()> {
                     0.0
                 };
 
+                // Same payload as a datagram, if it fits in one
+                let datagram_rtt_ms = match max_datagram {
+                    Some(max) if size.max(8) <= max => datagram_rtt(&conn, &mut next_probe, size).await,
+                    _ => None,
+                };
+
+                results.push(PingResult {
+                    payload_bytes: size,
+                    rtt_ms,
+                    throughput_mb_s: throughput_mbps,
+                    effective_bw_mbps,
+                    datagram_rtt_ms,
+                });
+                if !table {
+                    continue;
+                }
+
                 // Format size nicely
                 let size_str = if size == 0 {
                     "0 B".to_string()
@@ -817,13 +3007,24 @@ pub async fn ping_test(connection_string: String) -> Result<()> {
                     format!("{} MB", size / 1048576)
                 };
 
-                println!(
-                    "{:<12} {:<15} {:<15} {:<15}",
-                    size_str,
-                    format!("{:.2} ms", rtt_ms),
-                    format!("{:.2} MB/s", throughput_mbps),
-                    format!("{:.2} Mbps", effective_bw_mbps)
-                );
+                if datagram {
+                    println!(
+                        "{:<12} {:<15} {:<15} {:<15} {:<15}",
+                        size_str,
+                        format!("{:.2} ms", rtt_ms),
+                        datagram_rtt_ms.map_or("-".to_string(), |ms| format!("{:.2} ms", ms)),
+                        format!("{:.2} MB/s", throughput_mbps),
+                        format!("{:.2} Mbps", effective_bw_mbps)
+                    );
+                } else {
+                    println!(
+                        "{:<12} {:<15} {:<15} {:<15}",
+                        size_str,
+                        format!("{:.2} ms", rtt_ms),
+                        format!("{:.2} MB/s", throughput_mbps),
+                        format!("{:.2} Mbps", effective_bw_mbps)
+                    );
+                }
             }
             _ => {
                 eprintln!("Unexpected server response");
@@ -832,8 +3033,74 @@ pub async fn ping_test(connection_string: String) -> Result<()> {
         }
     }
 
-    println!("\n{}", "─".repeat(70));
-    println!("Test complete!\n");
+    // Small pings in a row for the quality grade; loss comes from QUIC's own counters
+    let mut latency_samples = Vec::with_capacity(LATENCY_PROBES);
+    if table {
+        for _ in 0..LATENCY_PROBES {
+            let start = Instant::now();
+            let ping_envelope = crate::MessageEnvelope {
+                session_id: session_id.clone(),
+                payload: crate::MessagePayload::Client(ClientMessage::PingRequest { data: Vec::new() }),
+            };
+            crate::send_envelope(&mut send, &ping_envelope).await.map_err(|e| n0_snafu::Error::anyhow(anyhow::anyhow!("{}", e)))?;
+            let response = crate::recv_envelope(&mut recv).await.map_err(|e| n0_snafu::Error::anyhow(anyhow::anyhow!("{}", e)))?;
+            if !matches!(response.payload, crate::MessagePayload::Server(ServerMessage::PingResponse { .. })) {
+                break;
+            }
+            latency_samples.push(start.elapsed().as_secs_f64() * 1000.0);
+        }
+    }
+
+    match format {
+        PingFormat::Table => {
+            println!("\n{}", "─".repeat(if datagram { 86 } else { 70 }));
+            if let Some(r) = results.iter().find(|r| r.datagram_rtt_ms.is_some()) {
+                let network_ms = r.datagram_rtt_ms.unwrap_or_default();
+                println!(
+                    "Network RTT {:.2} ms, kerr stream overhead {:.2} ms (at {} B)",
+                    network_ms,
+                    (r.rtt_ms - network_ms).max(0.0),
+                    r.payload_bytes
+                );
+            } else if datagram {
+                println!("No datagram echoes came back (the server may be running an older kerr)");
+            }
+            if !latency_samples.is_empty() {
+                let (latency_ms, jitter_ms) = latency_and_jitter(&latency_samples);
+                let stats = conn.stats();
+                let loss_percent = if stats.udp_tx.datagrams > 0 {
+                    stats.lost_packets as f64 * 100.0 / stats.udp_tx.datagrams as f64
+                } else {
+                    0.0
+                };
+                let grade = ConnectionGrade::from_measurements(latency_ms, jitter_ms, loss_percent);
+                println!(
+                    "Quality: {} (latency {:.1} ms, jitter {:.1} ms, loss {:.1}%) - {}",
+                    grade, latency_ms, jitter_ms, loss_percent, grade.recommendation()
+                );
+            }
+            println!("Test complete!\n");
+        }
+        PingFormat::Json => {
+            let json = serde_json::to_string_pretty(&results)
+                .map_err(|e| n0_snafu::Error::anyhow(anyhow::anyhow!("{}", e)))?;
+            println!("{}", json);
+        }
+        PingFormat::Csv => {
+            if datagram {
+                println!("payload_bytes,rtt_ms,datagram_rtt_ms,throughput_mb_s,effective_bw_mbps");
+                for r in &results {
+                    let datagram_ms = r.datagram_rtt_ms.map(|ms| format!("{:.3}", ms)).unwrap_or_default();
+                    println!("{},{:.3},{},{:.3},{:.3}", r.payload_bytes, r.rtt_ms, datagram_ms, r.throughput_mb_s, r.effective_bw_mbps);
+                }
+            } else {
+                println!("payload_bytes,rtt_ms,throughput_mb_s,effective_bw_mbps");
+                for r in &results {
+                    println!("{},{:.3},{:.3},{:.3}", r.payload_bytes, r.rtt_ms, r.throughput_mb_s, r.effective_bw_mbps);
+                }
+            }
+        }
+    }
 
     // Send disconnect
     let disconnect_msg = ClientMessage::Disconnect;
@@ -849,63 +3116,632 @@ pub async fn ping_test(connection_string: String) -> Result<()> {
     Ok(())
 }
 
-/// Browse remote filesystem
-pub async fn browse_remote(connection_string: String) -> Result<()> {
-    use std::sync::Arc;
-    use std::path::PathBuf;
-    use rand::RngExt;
-
-    // Decode connection string
-    let addr = crate::decode_connection_string(&connection_string)
-        .expect("Failed to decode connection string");
+/// Output format for `kerr ping`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum PingFormat {
+    /// Human-readable table
+    Table,
+    /// JSON array with one object per payload size
+    Json,
+    /// CSV with a header row
+    Csv,
+}
 
-    println!("Connecting to server for file browsing...");
-    let endpoint = Endpoint::bind(iroh::endpoint::presets::N0).await.map_err(|e| n0_snafu::Error::anyhow(anyhow::anyhow!("{}", e)))?;
-    let conn = endpoint.connect(addr, ALPN).await.map_err(|e| n0_snafu::Error::anyhow(anyhow::anyhow!("{}", e)))?;
+/// Measurements for one payload size in `kerr ping`
+#[derive(Debug, Serialize)]
+struct PingResult {
+    payload_bytes: usize,
+    rtt_ms: f64,
+    throughput_mb_s: f64,
+    effective_bw_mbps: f64,
+    /// Best datagram echo round trip, with `--datagram` and payloads that fit in one
+    #[serde(skip_serializing_if = "Option::is_none")]
+    datagram_rtt_ms: Option<f64>,
+}
 
-    let (mut send, recv) = conn.open_bi().await.e()?;
+/// Datagram echoes sent per payload size; the fastest one is reported
+const DATAGRAM_PROBES: u64 = 3;
 
-    // Generate a unique session ID for this browser session
-    let session_id = format!("browser_{}", rand::rng().random::<u64>());
+/// Empty pings sent after the size sweep to measure latency and jitter for the grade
+const LATENCY_PROBES: usize = 10;
 
-    // Send Hello message using the multiplexed protocol
-    let hello = ClientMessage::Hello {
-        session_type: crate::SessionType::FileBrowser,
-    };
-    let hello_envelope = crate::MessageEnvelope {
-        session_id: session_id.clone(),
-        payload: crate::MessagePayload::Client(hello),
-    };
-    crate::send_envelope(&mut send, &hello_envelope).await.map_err(|e| n0_snafu::Error::anyhow(anyhow::anyhow!("{}", e)))?;
+/// Overall connection quality shown at the end of `kerr ping`, best first
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ConnectionGrade {
+    Excellent,
+    Good,
+    Fair,
+    Poor,
+}
 
-    println!("Connected! Starting file browser...");
+impl ConnectionGrade {
+    /// Grade a connection by its worst measurement. Latency and jitter are in
+    /// milliseconds, loss in percent of packets sent.
+    pub fn from_measurements(latency_ms: f64, jitter_ms: f64, loss_percent: f64) -> Self {
+        // Upper bounds for (latency, jitter, loss) at each grade but Poor
+        const LIMITS: [(ConnectionGrade, f64, f64, f64); 3] = [
+            (ConnectionGrade::Excellent, 50.0, 10.0, 0.5),
+            (ConnectionGrade::Good, 120.0, 30.0, 1.0),
+            (ConnectionGrade::Fair, 250.0, 60.0, 3.0),
+        ];
+        LIMITS
+            .iter()
+            .find(|(_, latency, jitter, loss)| {
+                latency_ms <= *latency && jitter_ms <= *jitter && loss_percent <= *loss
+            })
+            .map_or(ConnectionGrade::Poor, |(grade, ..)| *grade)
+    }
 
-    // Create RemoteFilesystem
-    use crate::custom_explorer::filesystem::RemoteFilesystem;
-    let remote_fs = Arc::new(RemoteFilesystem::new_with_session_id(
-        PathBuf::from("/"),
-        send,
-        recv,
-        session_id,
-    ));
+    /// What the grade means for using kerr interactively
+    pub fn recommendation(self) -> &'static str {
+        match self {
+            ConnectionGrade::Excellent => "suitable for interactive shells and full-screen apps",
+            ConnectionGrade::Good => "suitable for interactive shell use",
+            ConnectionGrade::Fair => "fine for commands, but expect lag in full-screen apps",
+            ConnectionGrade::Poor => "expect noticeable lag; prefer file transfers over interactive use",
+        }
+    }
+}
 
-    // Run the browser with remote filesystem
-    // Pass remote_fs as both the filesystem trait object and as the concrete type for caching
-    let filesystem: Arc<dyn crate::custom_explorer::Filesystem> = Arc::clone(&remote_fs) as Arc<dyn crate::custom_explorer::Filesystem>;
-    crate::browser::run_browser_with_fs(filesystem, Some(remote_fs))
-        .map_err(|e| n0_snafu::Error::anyhow(anyhow::anyhow!("Browser error: {}", e)))?;
+impl std::fmt::Display for ConnectionGrade {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        std::fmt::Debug::fmt(self, f)
+    }
+}
 
-    conn.close(0u32.into(), b"done");
+/// Median round trip and jitter (mean change between consecutive round trips), in ms
+fn latency_and_jitter(samples: &[f64]) -> (f64, f64) {
+    let mut sorted = samples.to_vec();
+    sorted.sort_by(f64::total_cmp);
+    let median = sorted.get(sorted.len() / 2).copied().unwrap_or_default();
+    let jitter = if samples.len() > 1 {
+        samples.windows(2).map(|w| (w[1] - w[0]).abs()).sum::<f64>() / (samples.len() - 1) as f64
+    } else {
+        0.0
+    };
+    (median, jitter)
+}
+
+/// How long to wait for an echoed datagram before counting it as lost
+const DATAGRAM_PROBE_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(1);
+
+/// Time datagram echoes of `size` bytes (at least 8, for the probe number).
+/// Datagrams skip streams and envelopes, so this is close to the bare network RTT.
+/// Returns `None` if every probe was lost, e.g. against a server without the echo.
+async fn datagram_rtt(conn: &iroh::endpoint::Connection, next_probe: &mut u64, size: usize) -> Option<f64> {
+    let mut best: Option<f64> = None;
+    for _ in 0..DATAGRAM_PROBES {
+        let probe_id = *next_probe;
+        *next_probe += 1;
+        let mut probe = vec![0u8; size.max(8)];
+        probe[..8].copy_from_slice(&probe_id.to_be_bytes());
+
+        let start = std::time::Instant::now();
+        if conn.send_datagram(probe.into()).is_err() {
+            return best;
+        }
+        // Skip late echoes of earlier probes
+        let echo = async {
+            loop {
+                let data = conn.read_datagram().await.ok()?;
+                if data.len() >= 8 && data[..8] == probe_id.to_be_bytes() {
+                    return Some(start.elapsed());
+                }
+            }
+        };
+        if let Ok(Some(elapsed)) = tokio::time::timeout(DATAGRAM_PROBE_TIMEOUT, echo).await {
+            let rtt_ms = elapsed.as_secs_f64() * 1000.0;
+            best = Some(best.map_or(rtt_ms, |b: f64| b.min(rtt_ms)));
+        }
+    }
+    best
+}
+
+/// Sessions `echo_test` runs side by side on one stream
+const ECHO_SESSIONS: usize = 2;
+
+/// Payload sizes each echo session sends, up to a multi-chunk 256 KiB message
+const ECHO_SIZES: [usize; 5] = [1, 256, 4096, 65536, 262144];
+
+/// End-to-end smoke test of the protocol for CI: opens `ECHO_SESSIONS` echo sessions on one
+/// stream, sends each payload size from every session in turn and checks that every byte
+/// comes back to the session that sent it. Needs no shell or filesystem on the server.
+pub async fn echo_test(connection_string: String) -> Result<()> {
+    use rand::RngExt;
+
+    let addr = crate::decode_connection_string(&connection_string)
+        .map_err(|e| n0_snafu::Error::anyhow(anyhow::anyhow!("Failed to decode connection string: {}", e)))?;
+    let start = std::time::Instant::now();
+    let endpoint = crate::bind_endpoint().await.map_err(|e| n0_snafu::Error::anyhow(anyhow::anyhow!("{}", e)))?;
+    let conn = connect(&endpoint, addr).await?;
+    let (mut send, mut recv) = conn.open_bi().await.map_err(open_stream_error)?;
+    println!("Connected in {:.1} ms", start.elapsed().as_secs_f64() * 1000.0);
+
+    let start = std::time::Instant::now();
+    let messages = echo_sessions(&mut send, &mut recv, rand::rng().random::<u64>()).await?;
+    let _ = send.finish();
+    conn.close(0u32.into(), b"done");
+    endpoint.close().await;
+
+    println!("Echo test passed: {} messages on {} sessions in {:.1} ms",
+        messages, ECHO_SESSIONS, start.elapsed().as_secs_f64() * 1000.0);
+    Ok(())
+}
+
+/// The exchange behind `echo_test`, on an already open stream. `run` keeps the session ids
+/// apart from other runs. Returns how many echoes came back.
+async fn echo_sessions(
+    send: &mut (impl crate::transport::EnvelopeWrite + ?Sized),
+    recv: &mut (impl crate::transport::EnvelopeRead + ?Sized),
+    run: u64,
+) -> Result<usize> {
+    let session_ids: Vec<String> = (0..ECHO_SESSIONS).map(|n| format!("echo_{}_{}", run, n)).collect();
+    let send_to = |session_id: &str, msg: ClientMessage| crate::MessageEnvelope {
+        session_id: session_id.to_string(),
+        payload: crate::MessagePayload::Client(msg),
+    };
+    for session_id in &session_ids {
+        let hello = send_to(session_id, ClientMessage::Hello { session_type: crate::SessionType::Echo });
+        crate::send_envelope(send, &hello).await.map_err(|e| n0_snafu::Error::anyhow(anyhow::anyhow!("{}", e)))?;
+    }
+
+    let mut messages = 0;
+    for size in ECHO_SIZES {
+        // Interleave the sessions, each with its own bytes, so misrouting can't go unnoticed
+        for (n, session_id) in session_ids.iter().enumerate() {
+            let data: Vec<u8> = (0..size).map(|i| (i + n * 97) as u8).collect();
+            let key = send_to(session_id, ClientMessage::KeyEvent { data: data.clone() });
+            crate::send_envelope(send, &key).await.map_err(|e| n0_snafu::Error::anyhow(anyhow::anyhow!("{}", e)))?;
+
+            let reply = crate::recv_envelope(recv).await.map_err(|e| n0_snafu::Error::anyhow(anyhow::anyhow!("{}", e)))?;
+            if is_unsupported_reply(&reply) {
+                return Err(n0_snafu::Error::anyhow(anyhow::anyhow!(
+                    "The server doesn't support echo sessions (it may be running an older kerr)"
+                )));
+            }
+            if reply.session_id != *session_id {
+                return Err(n0_snafu::Error::anyhow(anyhow::anyhow!(
+                    "Reply for {} arrived on session {}", session_id, reply.session_id
+                )));
+            }
+            match reply.payload {
+                crate::MessagePayload::Server(ServerMessage::Output { data: echoed }) if echoed == data => {}
+                crate::MessagePayload::Server(ServerMessage::Output { data: echoed }) => {
+                    return Err(n0_snafu::Error::anyhow(anyhow::anyhow!(
+                        "Echo of {} bytes on {} came back as {} different bytes", size, session_id, echoed.len()
+                    )));
+                }
+                other => {
+                    return Err(n0_snafu::Error::anyhow(anyhow::anyhow!(
+                        "Expected an echo on {}, got {:?}", session_id, other
+                    )));
+                }
+            }
+            messages += 1;
+        }
+    }
+
+    for session_id in &session_ids {
+        let _ = crate::send_envelope(send, &send_to(session_id, ClientMessage::Disconnect)).await;
+    }
+    Ok(messages)
+}
+
+/// Result of a single `kerr doctor` check
+struct DoctorCheck {
+    name: &'static str,
+    passed: bool,
+    detail: String,
+}
+
+/// Open a new stream on the connection and start a session of the given type
+async fn open_doctor_session(
+    conn: &iroh::endpoint::Connection,
+    session_type: crate::SessionType,
+    session_id: &str,
+) -> std::result::Result<(iroh::endpoint::SendStream, iroh::endpoint::RecvStream), Box<dyn std::error::Error>> {
+    let (mut send, recv) = conn.open_bi().await?;
+    let hello_envelope = crate::MessageEnvelope {
+        session_id: session_id.to_string(),
+        payload: crate::MessagePayload::Client(ClientMessage::Hello { session_type }),
+    };
+    crate::send_envelope(&mut send, &hello_envelope).await?;
+    Ok((send, recv))
+}
+
+/// Ask the server which kerr version it runs
+async fn doctor_version(conn: &iroh::endpoint::Connection) -> std::result::Result<String, Box<dyn std::error::Error>> {
+    let session_id = "doctor_version";
+    let (mut send, mut recv) = open_doctor_session(conn, crate::SessionType::Ping, session_id).await?;
+    let request = crate::MessageEnvelope {
+        session_id: session_id.to_string(),
+        payload: crate::MessagePayload::Client(ClientMessage::VersionRequest),
+    };
+    crate::send_envelope(&mut send, &request).await?;
+    let version = match crate::recv_envelope(&mut recv).await?.payload {
+        crate::MessagePayload::Server(ServerMessage::VersionResponse { version }) => version,
+        crate::MessagePayload::Server(ServerMessage::Error { .. }) => {
+            return Err(format!("server doesn't report its version (older than kerr {})", crate::VERSION).into());
+        }
+        _ => return Err("unexpected version response".into()),
+    };
+
+    let disconnect_envelope = crate::MessageEnvelope {
+        session_id: session_id.to_string(),
+        payload: crate::MessagePayload::Client(ClientMessage::Disconnect),
+    };
+    let _ = crate::send_envelope(&mut send, &disconnect_envelope).await;
+
+    Ok(version)
+}
+
+/// Ping the server a few times and report the average round-trip time
+async fn doctor_ping(conn: &iroh::endpoint::Connection) -> std::result::Result<String, Box<dyn std::error::Error>> {
+    use std::time::Instant;
+
+    let session_id = "doctor_ping";
+    let (mut send, mut recv) = open_doctor_session(conn, crate::SessionType::Ping, session_id).await?;
+
+    let mut total = std::time::Duration::ZERO;
+    let rounds = 5u32;
+    for _ in 0..rounds {
+        let start = Instant::now();
+        let ping_envelope = crate::MessageEnvelope {
+            session_id: session_id.to_string(),
+            payload: crate::MessagePayload::Client(ClientMessage::PingRequest { data: vec![0u8; 1024] }),
+        };
+        crate::send_envelope(&mut send, &ping_envelope).await?;
+        match crate::recv_envelope(&mut recv).await?.payload {
+            crate::MessagePayload::Server(ServerMessage::PingResponse { data }) if data.len() == 1024 => {
+                total += start.elapsed();
+            }
+            _ => return Err("unexpected ping response".into()),
+        }
+    }
+
+    let disconnect_envelope = crate::MessageEnvelope {
+        session_id: session_id.to_string(),
+        payload: crate::MessagePayload::Client(ClientMessage::Disconnect),
+    };
+    let _ = crate::send_envelope(&mut send, &disconnect_envelope).await;
+
+    Ok(format!("avg RTT {:.2} ms over {} pings", (total / rounds).as_secs_f64() * 1000.0, rounds))
+}
+
+/// Run `echo` in a shell session and wait for the marker to come back
+async fn doctor_shell(conn: &iroh::endpoint::Connection) -> std::result::Result<String, Box<dyn std::error::Error>> {
+    use rand::RngExt;
+
+    let session_id = format!("doctor_shell_{}", rand::rng().random::<u64>());
+    let (mut send, mut recv) = open_doctor_session(conn, crate::SessionType::Shell, &session_id).await?;
+
+    // Quote part of the marker so the echoed command line itself doesn't match
+    let token = rand::rng().random::<u32>();
+    let marker = format!("kerr-doctor-{}", token);
+    let command = format!("echo kerr-doctor-\"{}\"\r", token);
+
+    for msg in [
+        ClientMessage::Resize { cols: 80, rows: 24 },
+        ClientMessage::KeyEvent { data: command.into_bytes() },
+    ] {
+        let envelope = crate::MessageEnvelope {
+            session_id: session_id.clone(),
+            payload: crate::MessagePayload::Client(msg),
+        };
+        crate::send_envelope(&mut send, &envelope).await?;
+    }
+
+    let mut output = Vec::new();
+    let found = tokio::time::timeout(std::time::Duration::from_secs(10), async {
+        loop {
+            match crate::recv_envelope(&mut recv).await?.payload {
+                crate::MessagePayload::Server(ServerMessage::Output { data }) => {
+                    output.extend_from_slice(&data);
+                    if String::from_utf8_lossy(&output).contains(&marker) {
+                        return Ok::<bool, Box<dyn std::error::Error>>(true);
+                    }
+                }
+                crate::MessagePayload::Server(ServerMessage::Error { message }) => {
+                    return Err(format!("server error: {}", message).into());
+                }
+                _ => {}
+            }
+        }
+    })
+    .await
+    .map_err(|_| "timed out waiting for shell output")??;
+
+    let disconnect_envelope = crate::MessageEnvelope {
+        session_id: session_id.clone(),
+        payload: crate::MessagePayload::Client(ClientMessage::Disconnect),
+    };
+    let _ = crate::send_envelope(&mut send, &disconnect_envelope).await;
+
+    if found {
+        Ok("echo output received".to_string())
+    } else {
+        Err("echo output not received".into())
+    }
+}
+
+/// Upload a small file, read it back, compare it and delete it
+async fn doctor_file_roundtrip(conn: &iroh::endpoint::Connection) -> std::result::Result<String, Box<dyn std::error::Error>> {
+    use crate::custom_explorer::filesystem::RemoteFilesystem;
+    use crate::custom_explorer::Filesystem;
+    use rand::RngExt;
+
+    let session_id = format!("doctor_browser_{}", rand::rng().random::<u64>());
+    let (send, recv) = open_doctor_session(conn, crate::SessionType::FileBrowser, &session_id).await?;
+    let remote_fs = RemoteFilesystem::new_with_session_id(PathBuf::from("/"), send, recv, session_id);
+
+    // Relative path, so it lands in the server's working directory
+    let path = PathBuf::from(format!(".kerr_doctor_{}", rand::rng().random::<u64>()));
+    let data: Vec<u8> = (0..4096u32).map(|i| (i % 251) as u8).collect();
+
+    remote_fs.upload_file(&path, &data).await?;
+    let read_back = remote_fs.read_file(&path).await;
+    let _ = remote_fs.delete_file(&path).await;
+
+    if read_back? == data {
+        Ok(format!("{} bytes written, read back and deleted", data.len()))
+    } else {
+        Err("file content mismatch after round trip".into())
+    }
+}
+
+/// Run a battery of diagnostic checks against a server and print a report
+///
+/// Returns an error if any check fails so the exit code can be used by monitoring.
+pub async fn run_doctor(connection_string: String) -> Result<()> {
+    use std::time::Instant;
+
+    let mut checks = Vec::new();
+
+    let addr = match crate::decode_connection_string(&connection_string) {
+        Ok(addr) => {
+            checks.push(DoctorCheck { name: "Connection string", passed: true, detail: format!("node {}", addr.id) });
+            addr
+        }
+        Err(e) => {
+            checks.push(DoctorCheck { name: "Connection string", passed: false, detail: e.to_string() });
+            print_doctor_report(&checks);
+            return Err(n0_snafu::Error::anyhow(anyhow::anyhow!("Invalid connection string")));
+        }
+    };
+
+    let spinner = connect_spinner();
+    spinner.set_message("Connecting to server...");
+
+    let endpoint = crate::bind_endpoint().await.map_err(|e| n0_snafu::Error::anyhow(anyhow::anyhow!("{}", e)))?;
+    let start = Instant::now();
+    let timeout = crate::connect_timeout();
+    let connected = match tokio::time::timeout(timeout, endpoint.connect(addr, ALPN)).await {
+        Ok(Ok(conn)) => Ok(conn),
+        Ok(Err(e)) => Err((e.to_string(), crate::ConnectFailure::from_connect(&e))),
+        Err(_) => Err((format!("no answer within {}s", timeout.as_secs()), crate::ConnectFailure::Timeout)),
+    };
+    let conn = match connected {
+        Ok(conn) => {
+            checks.push(DoctorCheck {
+                name: "Connectivity",
+                passed: true,
+                detail: format!("connected in {:.2} s", start.elapsed().as_secs_f64()),
+            });
+            conn
+        }
+        Err((error, failure)) => {
+            spinner.finish_and_clear();
+            let detail = match failure.hint() {
+                Some(hint) => format!("{} ({})", error, hint),
+                None => error,
+            };
+            checks.push(DoctorCheck { name: "Connectivity", passed: false, detail });
+            print_doctor_report(&checks);
+            endpoint.close().await;
+            return Err(n0_snafu::Error::anyhow(anyhow::anyhow!("Doctor checks failed")));
+        }
+    };
+
+    spinner.set_message("Checking server version...");
+    checks.push(match doctor_version(&conn).await {
+        Ok(version) => DoctorCheck {
+            name: "Protocol version",
+            passed: version == crate::VERSION,
+            detail: format!("server runs kerr {}, this client kerr {}", version, crate::VERSION),
+        },
+        Err(e) => DoctorCheck { name: "Protocol version", passed: false, detail: e.to_string() },
+    });
+
+    spinner.set_message("Measuring latency...");
+    let result = doctor_ping(&conn).await;
+    checks.push(DoctorCheck {
+        name: "Latency",
+        passed: result.is_ok(),
+        detail: result.unwrap_or_else(|e| e.to_string()),
+    });
+
+    spinner.set_message("Testing shell session...");
+    let result = doctor_shell(&conn).await;
+    checks.push(DoctorCheck {
+        name: "Shell session",
+        passed: result.is_ok(),
+        detail: result.unwrap_or_else(|e| e.to_string()),
+    });
+
+    spinner.set_message("Testing file round trip...");
+    let result = doctor_file_roundtrip(&conn).await;
+    checks.push(DoctorCheck {
+        name: "File round trip",
+        passed: result.is_ok(),
+        detail: result.unwrap_or_else(|e| e.to_string()),
+    });
+
+    spinner.finish_and_clear();
+
+    conn.close(0u32.into(), b"done");
+    endpoint.close().await;
+
+    print_doctor_report(&checks);
+
+    if checks.iter().all(|c| c.passed) {
+        Ok(())
+    } else {
+        Err(n0_snafu::Error::anyhow(anyhow::anyhow!("Doctor checks failed")))
+    }
+}
+
+/// Print the pass/fail table for `kerr doctor`
+fn print_doctor_report(checks: &[DoctorCheck]) {
+    println!("\n{:<20} {:<6} Details", "Check", "Result");
+    println!("{}", "─".repeat(70));
+    for check in checks {
+        println!(
+            "{:<20} {:<6} {}",
+            check.name,
+            if check.passed { "PASS" } else { "FAIL" },
+            check.detail
+        );
+    }
+    println!("{}", "─".repeat(70));
+    let failed = checks.iter().filter(|c| !c.passed).count();
+    if failed == 0 {
+        println!("All {} checks passed.\n", checks.len());
+    } else {
+        println!("{} of {} checks failed.\n", failed, checks.len());
+    }
+}
+
+/// Browse remote filesystem
+pub async fn browse_remote(connection_string: String) -> Result<()> {
+    use std::sync::Arc;
+    use std::path::PathBuf;
+    use rand::RngExt;
+
+    // Decode connection string
+    let addr = crate::decode_connection_string(&connection_string)
+        .expect("Failed to decode connection string");
+
+    println!("Connecting to server for file browsing...");
+    let endpoint = crate::bind_endpoint().await.map_err(|e| n0_snafu::Error::anyhow(anyhow::anyhow!("{}", e)))?;
+    let conn = connect(&endpoint, addr).await?;
+
+    let (mut send, recv) = conn.open_bi().await.map_err(open_stream_error)?;
+
+    // Generate a unique session ID for this browser session
+    let session_id = format!("browser_{}", rand::rng().random::<u64>());
+
+    // Send Hello message using the multiplexed protocol
+    let hello = ClientMessage::Hello {
+        session_type: crate::SessionType::FileBrowser,
+    };
+    let hello_envelope = crate::MessageEnvelope {
+        session_id: session_id.clone(),
+        payload: crate::MessagePayload::Client(hello),
+    };
+    crate::send_envelope(&mut send, &hello_envelope).await.map_err(|e| n0_snafu::Error::anyhow(anyhow::anyhow!("{}", e)))?;
+
+    println!("Connected! Starting file browser...");
+
+    // Create RemoteFilesystem
+    use crate::custom_explorer::filesystem::RemoteFilesystem;
+    let remote_fs = RemoteFilesystem::new_with_session_id(
+        PathBuf::from("/"),
+        send,
+        recv,
+        session_id,
+    ).with_reconnect(conn.clone());
+
+    // Older servers don't know FsMode, always accept changes and have no root
+    let (read_only, root) = remote_fs.mode().await.unwrap_or((false, None));
+    if read_only {
+        println!("The server is read-only: uploads, renames and new directories are refused.");
+    }
+    let remote_fs = Arc::new(match root {
+        Some(root) => remote_fs.with_root(root),
+        None => remote_fs,
+    });
+
+    // Run the browser with remote filesystem
+    // Pass remote_fs as both the filesystem trait object and as the concrete type for caching
+    let filesystem: Arc<dyn crate::custom_explorer::Filesystem> = Arc::clone(&remote_fs) as Arc<dyn crate::custom_explorer::Filesystem>;
+    crate::browser::run_browser_with_fs(filesystem, Some(remote_fs), None, read_only)
+        .map_err(|e| n0_snafu::Error::anyhow(anyhow::anyhow!("Browser error: {}", e)))?;
+
+    conn.close(0u32.into(), b"done");
     endpoint.close().await;
 
     Ok(())
 }
 
-/// Run a TCP relay proxy that forwards local port to remote port
+/// How often a `--keep-alive-file` is checked
+const KEEP_ALIVE_POLL: std::time::Duration = std::time::Duration::from_millis(500);
+
+/// Fail early if the `--keep-alive-file` isn't there, since it would mean stopping right away
+fn check_keep_alive_file(path: &Path) -> Result<()> {
+    if !path.exists() {
+        return Err(n0_snafu::Error::anyhow(anyhow::anyhow!(
+            "Keep-alive file {} does not exist; create it before starting", path.display()
+        )));
+    }
+    Ok(())
+}
+
+/// Resolves once the keep-alive file is removed or `stop` is written to it
+async fn keep_alive_file_released(path: &Path) {
+    loop {
+        match tokio::fs::read_to_string(path).await {
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return,
+            Ok(contents) if contents.trim() == "stop" => return,
+            _ => {}
+        }
+        tokio::time::sleep(KEEP_ALIVE_POLL).await;
+    }
+}
+
+/// Warn before a relay or proxy listens on `bind`, when that lets other machines use it
+fn warn_exposed_bind(bind: std::net::IpAddr) {
+    if bind.is_unspecified() {
+        eprintln!("WARNING: listening on {}, so the forwarded port is reachable by anyone on the network.", bind);
+        eprintln!("         Anyone who can connect to it can reach the remote service through this machine.");
+    } else if !bind.is_loopback() {
+        eprintln!("WARNING: listening on {}, so other machines that can reach this address can use the forwarded port.", bind);
+    }
+}
+
+/// End a relay or proxy session: close each forwarded stream, then the session and the
+/// connection, so the server drops its side right away instead of waiting for a timeout
+async fn close_relay_session(
+    endpoint: &iroh::Endpoint,
+    conn: &iroh::endpoint::Connection,
+    send: &mut iroh::endpoint::SendStream,
+    session_id: &str,
+    stream_ids: Vec<u32>,
+) {
+    let closes = stream_ids.into_iter().map(|stream_id| ClientMessage::TcpClose { stream_id });
+    for msg in closes.chain(std::iter::once(ClientMessage::Disconnect)) {
+        let envelope = crate::MessageEnvelope {
+            session_id: session_id.to_string(),
+            payload: crate::MessagePayload::Client(msg),
+        };
+        if crate::send_envelope(send, &envelope).await.is_err() {
+            break;
+        }
+    }
+    let _ = send.finish();
+    conn.close(0u32.into(), b"bye!");
+    endpoint.close().await;
+}
+
+/// Run a TCP relay proxy that forwards local port (on the `bind` address) to remote port
+/// At most `max_streams` connections are forwarded at once; up to as many more wait for a slot.
+/// With a `keep_alive_file` there's no traffic UI, and the relay stops when the file is released.
 pub async fn run_tcp_relay(
     connection_string: &str,
+    bind: std::net::IpAddr,
     local_port: u16,
     remote_port: u16,
+    max_streams: usize,
+    keep_alive_file: Option<&Path>,
 ) -> Result<()> {
     use tokio::net::TcpListener;
     use tokio::io::{AsyncReadExt, AsyncWriteExt};
@@ -915,33 +3751,383 @@ pub async fn run_tcp_relay(
     use std::sync::atomic::{AtomicU64, Ordering};
     use rand::RngExt;
 
+    if let Some(path) = keep_alive_file {
+        check_keep_alive_file(path)?;
+    }
+
     // Decode connection string and connect to server
     let node_addr = crate::decode_connection_string(connection_string)
         .map_err(|e| n0_snafu::Error::anyhow(anyhow::anyhow!("Failed to decode connection string: {}", e)))?;
 
-    let endpoint = iroh::Endpoint::bind(iroh::endpoint::presets::N0)
+    let endpoint = crate::bind_endpoint()
         .await
         .map_err(|e| n0_snafu::Error::anyhow(anyhow::anyhow!("Failed to create endpoint: {}", e)))?;
 
-    let conn = endpoint.connect(node_addr, crate::ALPN)
-        .await
-        .map_err(|e| n0_snafu::Error::anyhow(anyhow::anyhow!("Failed to connect: {}", e)))?;
+    let conn = connect(&endpoint, node_addr).await?;
 
     let (mut send, mut recv) = conn.open_bi()
         .await
-        .map_err(|e| n0_snafu::Error::anyhow(anyhow::anyhow!("Failed to open stream: {}", e)))?;
+        .map_err(open_stream_error)?;
 
     // Generate a unique session ID for this relay session
     let session_id = format!("relay_{}", rand::rng().random::<u64>());
     let session_id_for_send = session_id.clone();
 
-    // Send Hello message using the multiplexed protocol
-    let hello = crate::ClientMessage::Hello {
-        session_type: crate::SessionType::TcpRelay,
-    };
+    // Send Hello message using the multiplexed protocol
+    let hello = crate::ClientMessage::Hello {
+        session_type: crate::SessionType::TcpRelay,
+    };
+    let hello_envelope = crate::MessageEnvelope {
+        session_id: session_id.clone(),
+        payload: crate::MessagePayload::Client(hello),
+    };
+    crate::send_envelope(&mut send, &hello_envelope).await
+        .map_err(|e| n0_snafu::Error::anyhow(anyhow::anyhow!("Failed to send hello: {}", e)))?;
+
+    // Traffic counters
+    let upload_bytes = Arc::new(AtomicU64::new(0));
+    let download_bytes = Arc::new(AtomicU64::new(0));
+
+    // Listen on local port
+    let local_addr = std::net::SocketAddr::new(bind, local_port);
+    warn_exposed_bind(bind);
+    let listener = TcpListener::bind(local_addr)
+        .await
+        .map_err(|e| n0_snafu::Error::anyhow(anyhow::anyhow!("Failed to bind to {}: {}", local_addr, e)))?;
+
+    // Start TUI in a blocking task
+    let upload_bytes_ui = Arc::clone(&upload_bytes);
+    let download_bytes_ui = Arc::clone(&download_bytes);
+    let (shutdown_tx, shutdown_rx) = tokio::sync::mpsc::channel::<()>(1);
+
+    // Per-stream stats for the UI's open streams view
+    let open_streams: crate::traffic_ui::OpenStreams = Arc::new(std::sync::Mutex::new(Default::default()));
+    let open_streams_ui = Arc::clone(&open_streams);
+    let forget_stream = |streams: &crate::traffic_ui::OpenStreams, stream_id: u32| {
+        if let Ok(mut streams) = streams.lock() {
+            streams.remove(&stream_id);
+        }
+    };
+
+    // Forwarded connections allowed at once, and those accepted but waiting for a slot
+    let stream_slots = Arc::new(tokio::sync::Semaphore::new(max_streams));
+    let queued_streams = Arc::new(AtomicU64::new(0));
+    let queued_streams_ui = Arc::clone(&queued_streams);
+
+    let ui_task = match keep_alive_file {
+        Some(path) => {
+            println!("Relaying {} to remote port {}", local_addr, remote_port);
+            println!("Remove {} to stop", path.display());
+            None
+        }
+        None => Some(tokio::task::spawn_blocking(move || {
+            crate::traffic_ui::run_traffic_ui("TCP", local_addr, remote_port, upload_bytes_ui, download_bytes_ui, shutdown_rx, open_streams_ui, queued_streams_ui)
+        })),
+    };
+
+    // Stop when the user quits the UI or the keep-alive file is released
+    let shutdown = async move {
+        match (keep_alive_file, ui_task) {
+            (Some(path), _) => keep_alive_file_released(path).await,
+            (None, Some(ui_task)) => {
+                let _ = ui_task.await;
+            }
+            (None, None) => {}
+        }
+    };
+    tokio::pin!(shutdown);
+    let mut stopped = false;
+
+    // Shared state for tracking TCP connections
+    let tcp_connections: Arc<Mutex<HashMap<u32, tokio::sync::mpsc::Sender<Vec<u8>>>>> = Arc::new(Mutex::new(HashMap::new()));
+    let next_stream_id = Arc::new(Mutex::new(1u32));
+
+    // Wrap send stream in Arc<Mutex> for sharing between tasks
+    let send = Arc::new(Mutex::new(send));
+    let send_clone = Arc::clone(&send);
+
+    // Task to handle incoming messages from server
+    let tcp_connections_clone = Arc::clone(&tcp_connections);
+    let download_bytes_recv = Arc::clone(&download_bytes);
+    let open_streams_recv = Arc::clone(&open_streams);
+    let recv_task = tokio::spawn(async move {
+        loop {
+            // Receive message using the multiplexed protocol
+            let envelope = match crate::recv_envelope(&mut recv).await {
+                Ok(env) => env,
+                Err(_) => break,
+            };
+
+            // Extract server message from envelope
+            let msg = match envelope.payload {
+                crate::MessagePayload::Server(server_msg) => server_msg,
+                _ => continue,
+            };
+
+            // Handle server messages
+            match msg {
+                crate::ServerMessage::TcpDataResponse { stream_id, data } => {
+                    // Track download bytes
+                    download_bytes_recv.fetch_add(data.len() as u64, Ordering::Relaxed);
+                    if let Ok(streams) = open_streams_recv.lock()
+                        && let Some(stream) = streams.get(&stream_id)
+                    {
+                        stream.download_bytes.fetch_add(data.len() as u64, Ordering::Relaxed);
+                    }
+
+                    // Forward data to local TCP connection
+                    let connections = tcp_connections_clone.lock().await;
+                    if let Some(tx) = connections.get(&stream_id) {
+                        let _ = tx.send(data).await;
+                    }
+                }
+                crate::ServerMessage::TcpCloseResponse { stream_id, error } => {
+                    if let Some(err) = error {
+                        eprintln!("Remote TCP connection {} closed with error: {}", stream_id, err);
+                    }
+                    // Remove connection from map (this will cause the local connection to close)
+                    tcp_connections_clone.lock().await.remove(&stream_id);
+                    forget_stream(&open_streams_recv, stream_id);
+                }
+                crate::ServerMessage::TcpOpenResponse { stream_id, success, error } => {
+                    if !success {
+                        eprintln!("Failed to open remote connection {}: {}", stream_id, error.unwrap_or_default());
+                        tcp_connections_clone.lock().await.remove(&stream_id);
+                        forget_stream(&open_streams_recv, stream_id);
+                    }
+                }
+                _ => {}
+            }
+        }
+    });
+
+    // Accept incoming TCP connections
+    loop {
+        let accepted = tokio::select! {
+            _ = &mut shutdown => {
+                stopped = true;
+                break;
+            }
+            accepted = listener.accept() => accepted,
+        };
+        let (tcp_stream, addr) = match accepted {
+            Ok(x) => x,
+            Err(e) => {
+                eprintln!("Failed to accept connection: {}", e);
+                continue;
+            }
+        };
+
+        // Streams are opened from the connection tasks, so notice a lost server here
+        if let Some(reason) = conn.close_reason() {
+            eprintln!("Connection to server lost: {}", reason);
+            break;
+        }
+
+        println!("New connection from {}", addr);
+
+        // Past the stream limit, connections wait for a free slot; past the queue
+        // limit as well, they're closed right away
+        let permit = match Arc::clone(&stream_slots).try_acquire_owned() {
+            Ok(permit) => Some(permit),
+            Err(_) if queued_streams.load(Ordering::Relaxed) >= max_streams as u64 => {
+                eprintln!("Rejecting connection from {}: {} streams open and {} waiting", addr, max_streams, max_streams);
+                continue;
+            }
+            Err(_) => {
+                queued_streams.fetch_add(1, Ordering::Relaxed);
+                None
+            }
+        };
+
+        // Get next stream ID
+        let stream_id = {
+            let mut id = next_stream_id.lock().await;
+            let current = *id;
+            *id += 1;
+            current
+        };
+
+        let stream_slots_for_task = Arc::clone(&stream_slots);
+        let queued_streams_for_task = Arc::clone(&queued_streams);
+        let open_streams_for_task = Arc::clone(&open_streams);
+        let send_for_task = Arc::clone(&send_clone);
+        let tcp_connections_for_task = Arc::clone(&tcp_connections);
+        let upload_bytes_task = Arc::clone(&upload_bytes);
+        let session_id_for_task = session_id_for_send.clone();
+
+        // Spawn task to handle this TCP connection
+        tokio::spawn(async move {
+            // Held until the connection closes
+            let _permit = match permit {
+                Some(permit) => permit,
+                None => {
+                    let permit = stream_slots_for_task.acquire_owned().await;
+                    queued_streams_for_task.fetch_sub(1, Ordering::Relaxed);
+                    match permit {
+                        Ok(permit) => permit,
+                        Err(_) => return,
+                    }
+                }
+            };
+
+            // Send TcpOpen message using the multiplexed protocol
+            let open_msg = crate::ClientMessage::TcpOpen {
+                stream_id,
+                destination_host: None,  // Connect to localhost on remote server
+                destination_port: remote_port,
+            };
+            let open_envelope = crate::MessageEnvelope {
+                session_id: session_id_for_task.clone(),
+                payload: crate::MessagePayload::Client(open_msg),
+            };
+
+            {
+                let mut send_locked = send_for_task.lock().await;
+                if let Err(e) = crate::send_envelope(&mut *send_locked, &open_envelope).await {
+                    eprintln!("Failed to send TcpOpen: {}", e);
+                    return;
+                }
+            }
+
+            // Create channel for receiving data from server
+            let (tx, mut rx) = tokio::sync::mpsc::channel::<Vec<u8>>(100);
+            tcp_connections_for_task.lock().await.insert(stream_id, tx);
+            let stream_stats = Arc::new(crate::traffic_ui::OpenStream::new(addr));
+            if let Ok(mut streams) = open_streams_for_task.lock() {
+                streams.insert(stream_id, Arc::clone(&stream_stats));
+            }
+
+            let (mut tcp_read, mut tcp_write) = tcp_stream.into_split();
+
+            // Task to read from local TCP and send to remote
+            let send_task = {
+                let send_for_read = Arc::clone(&send_for_task);
+                let upload_bytes_send = Arc::clone(&upload_bytes_task);
+                let session_id_for_read = session_id_for_task.clone();
+                let stream_stats = Arc::clone(&stream_stats);
+                tokio::spawn(async move {
+                    let mut buf = vec![0u8; 65536];
+                    loop {
+                        match tcp_read.read(&mut buf).await {
+                            Ok(0) => break, // EOF
+                            Ok(n) => {
+                                // Track upload bytes
+                                upload_bytes_send.fetch_add(n as u64, Ordering::Relaxed);
+                                stream_stats.upload_bytes.fetch_add(n as u64, Ordering::Relaxed);
+
+                                // Send data to remote using the multiplexed protocol
+                                let data_msg = crate::ClientMessage::TcpData {
+                                    stream_id,
+                                    data: buf[..n].to_vec(),
+                                };
+                                let data_envelope = crate::MessageEnvelope {
+                                    session_id: session_id_for_read.clone(),
+                                    payload: crate::MessagePayload::Client(data_msg),
+                                };
+
+                                let mut send_locked = send_for_read.lock().await;
+                                if crate::send_envelope(&mut *send_locked, &data_envelope).await.is_err() {
+                                    break;
+                                }
+                            }
+                            Err(_) => break,
+                        }
+                    }
+                })
+            };
+
+            // Task to receive from remote and write to local TCP
+            let write_task = tokio::spawn(async move {
+                while let Some(data) = rx.recv().await {
+                    if tcp_write.write_all(&data).await.is_err() {
+                        break;
+                    }
+                }
+            });
+
+            // Wait for either task to complete
+            tokio::select! {
+                _ = send_task => {}
+                _ = write_task => {}
+            }
+
+            // Send TcpClose message using the multiplexed protocol
+            let close_msg = crate::ClientMessage::TcpClose { stream_id };
+            let close_envelope = crate::MessageEnvelope {
+                session_id: session_id_for_task.clone(),
+                payload: crate::MessagePayload::Client(close_msg),
+            };
+            let mut send_locked = send_for_task.lock().await;
+            let _ = crate::send_envelope(&mut *send_locked, &close_envelope).await;
+
+            // Remove from connections map
+            tcp_connections_for_task.lock().await.remove(&stream_id);
+            forget_stream(&open_streams_for_task, stream_id);
+        });
+    }
+
+    // After losing the server, keep the UI up until the user quits it
+    if !stopped && keep_alive_file.is_none() {
+        shutdown.await;
+    }
+
+    // Send shutdown signal
+    let _ = shutdown_tx.send(()).await;
+
+    // Cleanup
+    recv_task.abort();
+    let stream_ids = tcp_connections.lock().await.drain().map(|(stream_id, _)| stream_id).collect();
+    close_relay_session(&endpoint, &conn, &mut *send.lock().await, &session_id, stream_ids).await;
+
+    Ok(())
+}
+
+/// How long a UDP relay keeps a local peer's stream open without traffic
+const UDP_IDLE_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(60);
+
+/// Run a UDP relay that forwards datagrams on a local port (on the `bind` address) to a remote port.
+/// UDP has no connections, so each local source address gets its own stream, closed
+/// after `UDP_IDLE_TIMEOUT` without traffic; datagrams from new peers are dropped while
+/// `max_streams` are open. With a `keep_alive_file` there's no traffic UI, and the relay
+/// stops when the file is released.
+pub async fn run_udp_relay(
+    connection_string: &str,
+    bind: std::net::IpAddr,
+    local_port: u16,
+    remote_port: u16,
+    max_streams: usize,
+    keep_alive_file: Option<&Path>,
+) -> Result<()> {
+    use std::collections::HashMap;
+    use std::net::SocketAddr;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use rand::RngExt;
+
+    if let Some(path) = keep_alive_file {
+        check_keep_alive_file(path)?;
+    }
+
+    // Decode connection string and connect to server
+    let node_addr = crate::decode_connection_string(connection_string)
+        .map_err(|e| n0_snafu::Error::anyhow(anyhow::anyhow!("Failed to decode connection string: {}", e)))?;
+
+    let endpoint = crate::bind_endpoint()
+        .await
+        .map_err(|e| n0_snafu::Error::anyhow(anyhow::anyhow!("Failed to create endpoint: {}", e)))?;
+
+    let conn = connect(&endpoint, node_addr).await?;
+
+    let (mut send, mut recv) = conn.open_bi()
+        .await
+        .map_err(open_stream_error)?;
+
+    let session_id = format!("udp_relay_{}", rand::rng().random::<u64>());
+
     let hello_envelope = crate::MessageEnvelope {
         session_id: session_id.clone(),
-        payload: crate::MessagePayload::Client(hello),
+        payload: crate::MessagePayload::Client(ClientMessage::Hello { session_type: crate::SessionType::UdpRelay }),
     };
     crate::send_envelope(&mut send, &hello_envelope).await
         .map_err(|e| n0_snafu::Error::anyhow(anyhow::anyhow!("Failed to send hello: {}", e)))?;
@@ -951,207 +4137,443 @@ pub async fn run_tcp_relay(
     let download_bytes = Arc::new(AtomicU64::new(0));
 
     // Listen on local port
-    let listener = TcpListener::bind(format!("127.0.0.1:{}", local_port))
-        .await
-        .map_err(|e| n0_snafu::Error::anyhow(anyhow::anyhow!("Failed to bind to port {}: {}", local_port, e)))?;
+    let local_addr = SocketAddr::new(bind, local_port);
+    warn_exposed_bind(bind);
+    let socket = Arc::new(
+        tokio::net::UdpSocket::bind(local_addr)
+            .await
+            .map_err(|e| n0_snafu::Error::anyhow(anyhow::anyhow!("Failed to bind to UDP {}: {}", local_addr, e)))?,
+    );
 
-    // Start TUI in a blocking task
     let upload_bytes_ui = Arc::clone(&upload_bytes);
     let download_bytes_ui = Arc::clone(&download_bytes);
     let (shutdown_tx, shutdown_rx) = tokio::sync::mpsc::channel::<()>(1);
 
-    let ui_task = tokio::task::spawn_blocking(move || {
-        crate::traffic_ui::run_traffic_ui(local_port, remote_port, upload_bytes_ui, download_bytes_ui, shutdown_rx)
-    });
+    // Per-peer stats for the UI's open streams view; nothing ever waits for a slot
+    let open_streams: crate::traffic_ui::OpenStreams = Arc::new(std::sync::Mutex::new(Default::default()));
+    let open_streams_ui = Arc::clone(&open_streams);
+    let queued_streams_ui = Arc::new(AtomicU64::new(0));
 
-    // Shared state for tracking TCP connections
-    let tcp_connections: Arc<Mutex<HashMap<u32, tokio::sync::mpsc::Sender<Vec<u8>>>>> = Arc::new(Mutex::new(HashMap::new()));
-    let next_stream_id = Arc::new(Mutex::new(1u32));
+    let ui_task = match keep_alive_file {
+        Some(path) => {
+            println!("Relaying UDP {} to remote port {}", local_addr, remote_port);
+            println!("Remove {} to stop", path.display());
+            None
+        }
+        None => Some(tokio::task::spawn_blocking(move || {
+            crate::traffic_ui::run_traffic_ui("UDP", local_addr, remote_port, upload_bytes_ui, download_bytes_ui, shutdown_rx, open_streams_ui, queued_streams_ui)
+        })),
+    };
 
-    // Wrap send stream in Arc<Mutex> for sharing between tasks
-    let send = Arc::new(Mutex::new(send));
-    let send_clone = Arc::clone(&send);
+    // Stop when the user quits the UI or the keep-alive file is released
+    let shutdown = async move {
+        match (keep_alive_file, ui_task) {
+            (Some(path), _) => keep_alive_file_released(path).await,
+            (None, Some(ui_task)) => {
+                let _ = ui_task.await;
+            }
+            (None, None) => {}
+        }
+    };
+    tokio::pin!(shutdown);
+    let mut stopped = false;
+
+    // Local peer of each open stream, where its replies are sent
+    let stream_peers: Arc<std::sync::Mutex<HashMap<u32, SocketAddr>>> = Arc::new(std::sync::Mutex::new(HashMap::new()));
+    let forget_stream = |stream_peers: &std::sync::Mutex<HashMap<u32, SocketAddr>>, streams: &crate::traffic_ui::OpenStreams, stream_id: u32| {
+        if let Ok(mut peers) = stream_peers.lock() {
+            peers.remove(&stream_id);
+        }
+        if let Ok(mut streams) = streams.lock() {
+            streams.remove(&stream_id);
+        }
+    };
 
     // Task to handle incoming messages from server
-    let tcp_connections_clone = Arc::clone(&tcp_connections);
+    let socket_recv = Arc::clone(&socket);
+    let stream_peers_recv = Arc::clone(&stream_peers);
     let download_bytes_recv = Arc::clone(&download_bytes);
+    let open_streams_recv = Arc::clone(&open_streams);
     let recv_task = tokio::spawn(async move {
         loop {
-            // Receive message using the multiplexed protocol
             let envelope = match crate::recv_envelope(&mut recv).await {
                 Ok(env) => env,
                 Err(_) => break,
             };
-
-            // Extract server message from envelope
             let msg = match envelope.payload {
                 crate::MessagePayload::Server(server_msg) => server_msg,
                 _ => continue,
             };
 
-            // Handle server messages
             match msg {
-                crate::ServerMessage::TcpDataResponse { stream_id, data } => {
-                    // Track download bytes
+                ServerMessage::UdpDataResponse { stream_id, data } => {
+                    let peer = stream_peers_recv.lock().ok().and_then(|peers| peers.get(&stream_id).copied());
+                    let Some(peer) = peer else {
+                        continue;
+                    };
                     download_bytes_recv.fetch_add(data.len() as u64, Ordering::Relaxed);
-
-                    // Forward data to local TCP connection
-                    let connections = tcp_connections_clone.lock().await;
-                    if let Some(tx) = connections.get(&stream_id) {
-                        let _ = tx.send(data).await;
+                    if let Ok(streams) = open_streams_recv.lock()
+                        && let Some(stream) = streams.get(&stream_id)
+                    {
+                        stream.download_bytes.fetch_add(data.len() as u64, Ordering::Relaxed);
                     }
+                    // Each reply goes back as the single datagram it arrived as
+                    let _ = socket_recv.send_to(&data, peer).await;
                 }
-                crate::ServerMessage::TcpCloseResponse { stream_id, error } => {
-                    if let Some(err) = error {
-                        eprintln!("Remote TCP connection {} closed with error: {}", stream_id, err);
-                    }
-                    // Remove connection from map (this will cause the local connection to close)
-                    tcp_connections_clone.lock().await.remove(&stream_id);
+                ServerMessage::UdpOpenResponse { stream_id, success: false, error } => {
+                    eprintln!("Failed to open remote UDP stream {}: {}", stream_id, error.unwrap_or_default());
+                    forget_stream(&stream_peers_recv, &open_streams_recv, stream_id);
                 }
-                crate::ServerMessage::TcpOpenResponse { stream_id, success, error } => {
-                    if !success {
-                        eprintln!("Failed to open remote connection {}: {}", stream_id, error.unwrap_or_default());
-                        tcp_connections_clone.lock().await.remove(&stream_id);
+                ServerMessage::UdpCloseResponse { stream_id, error } => {
+                    if let Some(err) = error {
+                        eprintln!("Remote UDP stream {} closed with error: {}", stream_id, err);
                     }
+                    forget_stream(&stream_peers_recv, &open_streams_recv, stream_id);
                 }
                 _ => {}
             }
         }
     });
 
-    // Accept incoming TCP connections
+    // Stream and last activity of each local peer
+    let mut peers: HashMap<SocketAddr, (u32, std::time::Instant)> = HashMap::new();
+    let mut next_stream_id = 1u32;
+    let mut sweep = tokio::time::interval(UDP_IDLE_TIMEOUT / 4);
+    let mut warned_full = false;
+    let mut buf = vec![0u8; 65536];
+
     loop {
-        let (tcp_stream, addr) = match listener.accept().await {
-            Ok(x) => x,
-            Err(e) => {
-                eprintln!("Failed to accept connection: {}", e);
+        let (n, peer) = tokio::select! {
+            _ = &mut shutdown => {
+                stopped = true;
+                break;
+            }
+            _ = sweep.tick() => {
+                // Close the streams of peers that have gone quiet
+                let now = std::time::Instant::now();
+                let mut idle = Vec::new();
+                peers.retain(|_, (stream_id, last_seen)| {
+                    let active = now.duration_since(*last_seen) < UDP_IDLE_TIMEOUT;
+                    if !active {
+                        idle.push(*stream_id);
+                    }
+                    active
+                });
+                for stream_id in idle {
+                    forget_stream(&stream_peers, &open_streams, stream_id);
+                    let close_envelope = crate::MessageEnvelope {
+                        session_id: session_id.clone(),
+                        payload: crate::MessagePayload::Client(ClientMessage::UdpClose { stream_id }),
+                    };
+                    let _ = crate::send_envelope(&mut send, &close_envelope).await;
+                }
                 continue;
             }
+            received = socket.recv_from(&mut buf) => match received {
+                Ok(received) => received,
+                // e.g. an ICMP error for an earlier reply to a peer that has gone away
+                Err(_) => continue,
+            },
         };
 
-        println!("New connection from {}", addr);
+        if let Some(reason) = conn.close_reason() {
+            eprintln!("Connection to server lost: {}", reason);
+            break;
+        }
 
-        // Get next stream ID
-        let stream_id = {
-            let mut id = next_stream_id.lock().await;
-            let current = *id;
-            *id += 1;
-            current
+        // A peer keeps its stream until it goes quiet or the server closes it
+        let open_stream = peers
+            .get(&peer)
+            .map(|(stream_id, _)| *stream_id)
+            .filter(|stream_id| stream_peers.lock().is_ok_and(|peers| peers.contains_key(stream_id)));
+        let stream_id = match open_stream {
+            Some(stream_id) => stream_id,
+            None => {
+                if stream_peers.lock().map(|peers| peers.len()).unwrap_or(0) >= max_streams {
+                    if !warned_full {
+                        eprintln!("Dropping datagrams from new peers like {}: {} streams open", peer, max_streams);
+                        warned_full = true;
+                    }
+                    continue;
+                }
+                let stream_id = next_stream_id;
+                next_stream_id += 1;
+                let open_envelope = crate::MessageEnvelope {
+                    session_id: session_id.clone(),
+                    payload: crate::MessagePayload::Client(ClientMessage::UdpOpen {
+                        stream_id,
+                        destination_host: None, // localhost on the remote server
+                        destination_port: remote_port,
+                    }),
+                };
+                if let Err(e) = crate::send_envelope(&mut send, &open_envelope).await {
+                    eprintln!("Failed to send UdpOpen: {}", e);
+                    break;
+                }
+                if let Ok(mut peers) = stream_peers.lock() {
+                    peers.insert(stream_id, peer);
+                }
+                if let Ok(mut streams) = open_streams.lock() {
+                    streams.insert(stream_id, Arc::new(crate::traffic_ui::OpenStream::new(peer)));
+                }
+                stream_id
+            }
         };
+        peers.insert(peer, (stream_id, std::time::Instant::now()));
 
-        // Send TcpOpen message using the multiplexed protocol
-        let open_msg = crate::ClientMessage::TcpOpen {
-            stream_id,
-            destination_host: None,  // Connect to localhost on remote server
-            destination_port: remote_port,
+        upload_bytes.fetch_add(n as u64, Ordering::Relaxed);
+        if let Ok(streams) = open_streams.lock()
+            && let Some(stream) = streams.get(&stream_id)
+        {
+            stream.upload_bytes.fetch_add(n as u64, Ordering::Relaxed);
+        }
+        let data_envelope = crate::MessageEnvelope {
+            session_id: session_id.clone(),
+            payload: crate::MessagePayload::Client(ClientMessage::UdpData { stream_id, data: buf[..n].to_vec() }),
         };
-        let open_envelope = crate::MessageEnvelope {
-            session_id: session_id_for_send.clone(),
-            payload: crate::MessagePayload::Client(open_msg),
+        if let Err(e) = crate::send_envelope(&mut send, &data_envelope).await {
+            eprintln!("Failed to send UdpData: {}", e);
+            break;
+        }
+    }
+
+    // After losing the server, keep the UI up until the user quits it
+    if !stopped && keep_alive_file.is_none() {
+        shutdown.await;
+    }
+
+    // Send shutdown signal
+    let _ = shutdown_tx.send(()).await;
+
+    // The server drops every stream's socket along with the session
+    recv_task.abort();
+    close_relay_session(&endpoint, &conn, &mut send, &session_id, Vec::new()).await;
+
+    Ok(())
+}
+
+/// Run a reverse TCP relay: the server listens on `remote_port` of its loopback interface,
+/// and each connection made to it there is forwarded to `local_port` on this machine.
+/// At most `max_streams` are forwarded at once; the server's connection is closed for more.
+/// With a `keep_alive_file` the relay stops when the file is released.
+pub async fn run_reverse_relay(
+    connection_string: &str,
+    remote_port: u16,
+    local_port: u16,
+    max_streams: usize,
+    keep_alive_file: Option<&Path>,
+) -> Result<()> {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use std::collections::HashMap;
+    use std::sync::Arc;
+    use tokio::sync::Mutex;
+    use rand::RngExt;
+
+    if let Some(path) = keep_alive_file {
+        check_keep_alive_file(path)?;
+    }
+
+    // Decode connection string and connect to server
+    let node_addr = crate::decode_connection_string(connection_string)
+        .map_err(|e| n0_snafu::Error::anyhow(anyhow::anyhow!("Failed to decode connection string: {}", e)))?;
+
+    let endpoint = crate::bind_endpoint()
+        .await
+        .map_err(|e| n0_snafu::Error::anyhow(anyhow::anyhow!("Failed to create endpoint: {}", e)))?;
+
+    let conn = connect(&endpoint, node_addr).await?;
+
+    let (mut send, mut recv) = conn.open_bi()
+        .await
+        .map_err(open_stream_error)?;
+
+    let session_id = format!("reverse_{}", rand::rng().random::<u64>());
+
+    for msg in [
+        ClientMessage::Hello { session_type: crate::SessionType::ReverseRelay },
+        ClientMessage::ReverseListen { port: remote_port },
+    ] {
+        let envelope = crate::MessageEnvelope {
+            session_id: session_id.clone(),
+            payload: crate::MessagePayload::Client(msg),
         };
+        crate::send_envelope(&mut send, &envelope).await
+            .map_err(|e| n0_snafu::Error::anyhow(anyhow::anyhow!("Failed to send hello: {}", e)))?;
+    }
 
-        {
-            let mut send_locked = send_clone.lock().await;
-            if let Err(e) = crate::send_envelope(&mut *send_locked, &open_envelope).await {
-                eprintln!("Failed to send TcpOpen: {}", e);
-                break;
-            }
+    // Nothing is forwarded until the server is listening
+    let envelope = crate::recv_envelope(&mut recv).await.map_err(link_error)?;
+    if is_unsupported_reply(&envelope) {
+        return Err(n0_snafu::Error::anyhow(anyhow::anyhow!(
+            "The server doesn't support reverse relays; it needs a newer kerr"
+        )));
+    }
+    match envelope.payload {
+        crate::MessagePayload::Server(ServerMessage::ReverseListenResponse { success: true, .. }) => {}
+        crate::MessagePayload::Server(ServerMessage::ReverseListenResponse { success: false, error }) => {
+            return Err(n0_snafu::Error::anyhow(anyhow::anyhow!(
+                "Server could not listen on port {}: {}", remote_port, error.unwrap_or_default()
+            )));
+        }
+        crate::MessagePayload::Server(ServerMessage::Error { message }) => return Err(server_error(message)),
+        _ => {
+            return Err(n0_snafu::Error::anyhow(anyhow::anyhow!("Unexpected server response")));
         }
+    }
 
-        // Create channel for receiving data from server
-        let (tx, mut rx) = tokio::sync::mpsc::channel::<Vec<u8>>(100);
-        tcp_connections.lock().await.insert(stream_id, tx);
+    println!("Server port 127.0.0.1:{} now forwards to local port {}", remote_port, local_port);
+    println!("Anything on the server that connects to that port reaches your local service");
+    match keep_alive_file {
+        Some(path) => println!("Remove {} to stop", path.display()),
+        None => println!("Press Ctrl+C to stop"),
+    }
 
-        let send_for_task = Arc::clone(&send_clone);
-        let tcp_connections_for_task = Arc::clone(&tcp_connections);
-        let upload_bytes_task = Arc::clone(&upload_bytes);
-        let session_id_for_task = session_id_for_send.clone();
+    // Shared state for tracking TCP connections
+    let tcp_connections: Arc<Mutex<HashMap<u32, tokio::sync::mpsc::Sender<Vec<u8>>>>> = Arc::new(Mutex::new(HashMap::new()));
 
-        // Spawn task to handle this TCP connection
-        tokio::spawn(async move {
-            let (mut tcp_read, mut tcp_write) = tcp_stream.into_split();
+    // Wrap send stream in Arc<Mutex> for sharing between tasks
+    let send = Arc::new(Mutex::new(send));
 
-            // Task to read from local TCP and send to remote
-            let send_task = {
-                let send_for_read = Arc::clone(&send_for_task);
-                let upload_bytes_send = Arc::clone(&upload_bytes_task);
-                let session_id_for_read = session_id_for_task.clone();
+    // Without a keep-alive file the relay runs until it's interrupted
+    let shutdown = async move {
+        match keep_alive_file {
+            Some(path) => keep_alive_file_released(path).await,
+            None => std::future::pending().await,
+        }
+    };
+    tokio::pin!(shutdown);
+
+    loop {
+        let envelope = tokio::select! {
+            _ = &mut shutdown => break,
+            envelope = crate::recv_envelope(&mut recv) => match envelope {
+                Ok(envelope) => envelope,
+                Err(e) => {
+                    eprintln!("Connection to server lost: {}", e);
+                    break;
+                }
+            },
+        };
+        let msg = match envelope.payload {
+            crate::MessagePayload::Server(server_msg) => server_msg,
+            _ => continue,
+        };
+
+        match msg {
+            ServerMessage::TcpIncoming { stream_id, peer } => {
+                // Past the stream limit, the server's connection is closed right away
+                if tcp_connections.lock().await.len() >= max_streams {
+                    eprintln!("Rejecting connection from {} on the server: {} streams open", peer, max_streams);
+                    let close_envelope = crate::MessageEnvelope {
+                        session_id: session_id.clone(),
+                        payload: crate::MessagePayload::Client(ClientMessage::TcpClose { stream_id }),
+                    };
+                    let _ = crate::send_envelope(&mut *send.lock().await, &close_envelope).await;
+                    continue;
+                }
+                println!("New connection from {} on the server", peer);
+
+                // Registered now, so data arriving while the local connect is underway waits for it
+                let (tx, mut rx) = tokio::sync::mpsc::channel::<Vec<u8>>(100);
+                tcp_connections.lock().await.insert(stream_id, tx);
+
+                let send_for_task = Arc::clone(&send);
+                let tcp_connections_for_task = Arc::clone(&tcp_connections);
+                let session_id_for_task = session_id.clone();
                 tokio::spawn(async move {
-                    let mut buf = vec![0u8; 65536];
-                    loop {
-                        match tcp_read.read(&mut buf).await {
-                            Ok(0) => break, // EOF
-                            Ok(n) => {
-                                // Track upload bytes
-                                upload_bytes_send.fetch_add(n as u64, Ordering::Relaxed);
+                    let tcp_stream = match tokio::net::TcpStream::connect(("127.0.0.1", local_port)).await {
+                        Ok(tcp_stream) => Some(tcp_stream),
+                        Err(e) => {
+                            eprintln!("Failed to connect to local port {}: {}", local_port, e);
+                            None
+                        }
+                    };
 
-                                // Send data to remote using the multiplexed protocol
-                                let data_msg = crate::ClientMessage::TcpData {
-                                    stream_id,
-                                    data: buf[..n].to_vec(),
-                                };
-                                let data_envelope = crate::MessageEnvelope {
-                                    session_id: session_id_for_read.clone(),
-                                    payload: crate::MessagePayload::Client(data_msg),
-                                };
+                    if let Some(tcp_stream) = tcp_stream {
+                        let (mut tcp_read, mut tcp_write) = tcp_stream.into_split();
+
+                        // Task to read from the local service and send to the server
+                        let send_task = {
+                            let send_for_read = Arc::clone(&send_for_task);
+                            let session_id_for_read = session_id_for_task.clone();
+                            tokio::spawn(async move {
+                                let mut buf = vec![0u8; 65536];
+                                loop {
+                                    match tcp_read.read(&mut buf).await {
+                                        Ok(0) | Err(_) => break,
+                                        Ok(n) => {
+                                            let data_envelope = crate::MessageEnvelope {
+                                                session_id: session_id_for_read.clone(),
+                                                payload: crate::MessagePayload::Client(ClientMessage::TcpData {
+                                                    stream_id,
+                                                    data: buf[..n].to_vec(),
+                                                }),
+                                            };
+                                            let mut send_locked = send_for_read.lock().await;
+                                            if crate::send_envelope(&mut *send_locked, &data_envelope).await.is_err() {
+                                                break;
+                                            }
+                                        }
+                                    }
+                                }
+                            })
+                        };
 
-                                let mut send_locked = send_for_read.lock().await;
-                                if crate::send_envelope(&mut *send_locked, &data_envelope).await.is_err() {
+                        // Task to receive from the server and write to the local service
+                        let write_task = tokio::spawn(async move {
+                            while let Some(data) = rx.recv().await {
+                                if tcp_write.write_all(&data).await.is_err() {
                                     break;
                                 }
                             }
-                            Err(_) => break,
+                        });
+
+                        tokio::select! {
+                            _ = send_task => {}
+                            _ = write_task => {}
                         }
                     }
-                })
-            };
 
-            // Task to receive from remote and write to local TCP
-            let write_task = tokio::spawn(async move {
-                while let Some(data) = rx.recv().await {
-                    if tcp_write.write_all(&data).await.is_err() {
-                        break;
-                    }
+                    let close_envelope = crate::MessageEnvelope {
+                        session_id: session_id_for_task.clone(),
+                        payload: crate::MessagePayload::Client(ClientMessage::TcpClose { stream_id }),
+                    };
+                    let _ = crate::send_envelope(&mut *send_for_task.lock().await, &close_envelope).await;
+                    tcp_connections_for_task.lock().await.remove(&stream_id);
+                });
+            }
+            ServerMessage::TcpDataResponse { stream_id, data } => {
+                let connections = tcp_connections.lock().await;
+                if let Some(tx) = connections.get(&stream_id) {
+                    let _ = tx.send(data).await;
                 }
-            });
-
-            // Wait for either task to complete
-            tokio::select! {
-                _ = send_task => {}
-                _ = write_task => {}
             }
-
-            // Send TcpClose message using the multiplexed protocol
-            let close_msg = crate::ClientMessage::TcpClose { stream_id };
-            let close_envelope = crate::MessageEnvelope {
-                session_id: session_id_for_task.clone(),
-                payload: crate::MessagePayload::Client(close_msg),
-            };
-            let mut send_locked = send_for_task.lock().await;
-            let _ = crate::send_envelope(&mut *send_locked, &close_envelope).await;
-
-            // Remove from connections map
-            tcp_connections_for_task.lock().await.remove(&stream_id);
-        });
+            ServerMessage::TcpCloseResponse { stream_id, error } => {
+                if let Some(err) = error {
+                    eprintln!("Remote TCP connection {} closed with error: {}", stream_id, err);
+                }
+                // Removing it ends the local connection
+                tcp_connections.lock().await.remove(&stream_id);
+            }
+            _ => {}
+        }
     }
 
-    // Wait for UI to exit (when user presses 'q')
-    let _ = ui_task.await;
-
-    // Send shutdown signal
-    let _ = shutdown_tx.send(()).await;
-
     // Cleanup
-    recv_task.abort();
+    let stream_ids = tcp_connections.lock().await.drain().map(|(stream_id, _)| stream_id).collect();
+    close_relay_session(&endpoint, &conn, &mut *send.lock().await, &session_id, stream_ids).await;
 
     Ok(())
 }
 
 /// Run an HTTP/HTTPS proxy that relays traffic through the Kerr connection
+/// With a `keep_alive_file` the proxy stops when the file is released.
 pub async fn run_proxy(
     connection_string: &str,
+    bind: std::net::IpAddr,
     port: u16,
     enable_dns: bool,
+    keep_alive_file: Option<&Path>,
 ) -> Result<()> {
     use tokio::net::TcpListener;
     use tokio::io::{AsyncReadExt, AsyncWriteExt};
@@ -1161,20 +4583,22 @@ pub async fn run_proxy(
     use std::sync::atomic::{AtomicU32, Ordering};
     use rand::RngExt;
 
+    if let Some(path) = keep_alive_file {
+        check_keep_alive_file(path)?;
+    }
+
     // Decode connection string and connect to server
     let node_addr = crate::decode_connection_string(connection_string)
         .map_err(|e| n0_snafu::Error::anyhow(anyhow::anyhow!("Failed to decode connection string: {}", e)))?;
 
-    let endpoint = iroh::Endpoint::bind(iroh::endpoint::presets::N0)
+    let endpoint = crate::bind_endpoint()
         .await
         .map_err(|e| n0_snafu::Error::anyhow(anyhow::anyhow!("Failed to create endpoint: {}", e)))?;
 
-    let conn = endpoint.connect(node_addr, crate::ALPN)
-        .await
-        .map_err(|e| n0_snafu::Error::anyhow(anyhow::anyhow!("Failed to connect: {}", e)))?;
+    let conn = connect(&endpoint, node_addr).await?;
 
     // Start DNS proxy if requested
-    let _dns_task = if enable_dns {
+    let dns_task = if enable_dns {
         let conn_clone = conn.clone();
         Some(tokio::spawn(async move {
             if let Err(e) = start_dns_proxy_task(conn_clone).await {
@@ -1187,7 +4611,7 @@ pub async fn run_proxy(
 
     let (mut send, mut recv) = conn.open_bi()
         .await
-        .map_err(|e| n0_snafu::Error::anyhow(anyhow::anyhow!("Failed to open stream: {}", e)))?;
+        .map_err(open_stream_error)?;
 
     // Generate a unique session ID for this proxy session
     let session_id = format!("proxy_{}", rand::rng().random::<u64>());
@@ -1205,16 +4629,21 @@ pub async fn run_proxy(
         .map_err(|e| n0_snafu::Error::anyhow(anyhow::anyhow!("Failed to send hello: {}", e)))?;
 
     // Listen on local port
-    let listener = TcpListener::bind(format!("127.0.0.1:{}", port))
+    let local_addr = std::net::SocketAddr::new(bind, port);
+    warn_exposed_bind(bind);
+    let listener = TcpListener::bind(local_addr)
         .await
-        .map_err(|e| n0_snafu::Error::anyhow(anyhow::anyhow!("Failed to bind to port {}: {}", port, e)))?;
+        .map_err(|e| n0_snafu::Error::anyhow(anyhow::anyhow!("Failed to bind to {}: {}", local_addr, e)))?;
 
-    println!("HTTP/HTTPS proxy listening on 127.0.0.1:{}", port);
+    println!("HTTP/HTTPS proxy listening on {}", local_addr);
     println!("Configure your browser to use this as an HTTP proxy");
     if enable_dns {
-        println!("DNS proxy also running on 127.0.0.1:53");
+        println!("DNS proxy also running on 127.0.0.1:53 (or {} without privileges)", DNS_FALLBACK_PORT);
+    }
+    match keep_alive_file {
+        Some(path) => println!("Remove {} to stop", path.display()),
+        None => println!("Press Ctrl+C to stop"),
     }
-    println!("Press Ctrl+C to stop");
 
     // Shared state for tracking TCP connections
     let tcp_connections: Arc<Mutex<HashMap<u32, tokio::sync::mpsc::Sender<Vec<u8>>>>> = Arc::new(Mutex::new(HashMap::new()));
@@ -1226,7 +4655,7 @@ pub async fn run_proxy(
 
     // Task to handle incoming messages from server
     let tcp_connections_clone = Arc::clone(&tcp_connections);
-    let _recv_task = tokio::spawn(async move {
+    let recv_task = tokio::spawn(async move {
         loop {
             // Receive message using the multiplexed protocol
             let envelope = match crate::recv_envelope(&mut recv).await {
@@ -1267,9 +4696,22 @@ pub async fn run_proxy(
         }
     });
 
+    // Without a keep-alive file the proxy runs until it's interrupted
+    let shutdown = async move {
+        match keep_alive_file {
+            Some(path) => keep_alive_file_released(path).await,
+            None => std::future::pending().await,
+        }
+    };
+    tokio::pin!(shutdown);
+
     // Accept incoming HTTP/HTTPS connections
     loop {
-        let (mut client_socket, client_addr) = match listener.accept().await {
+        let accepted = tokio::select! {
+            _ = &mut shutdown => break,
+            accepted = listener.accept() => accepted,
+        };
+        let (mut client_socket, client_addr) = match accepted {
             Ok(x) => x,
             Err(e) => {
                 eprintln!("Failed to accept connection: {}", e);
@@ -1461,11 +4903,53 @@ pub async fn run_proxy(
             println!("Connection closed for stream {}", stream_id);
         });
     }
+
+    println!("Keep-alive file released, stopping proxy");
+    if let Some(dns_task) = dns_task {
+        dns_task.abort();
+    }
+    recv_task.abort();
+    let stream_ids = tcp_connections.lock().await.drain().map(|(stream_id, _)| stream_id).collect();
+    close_relay_session(&endpoint, &conn, &mut *send.lock().await, &session_id, stream_ids).await;
+
+    Ok(())
+}
+
+/// Port the DNS proxy falls back to when binding port 53 needs privileges we don't have
+const DNS_FALLBACK_PORT: u16 = 5353;
+
+/// Bind the local DNS proxy socket on `port`. If that is port 53 and binding is
+/// refused, bind `DNS_FALLBACK_PORT` instead and explain how to redirect DNS to it,
+/// so the proxy still works for users who can redirect at the OS level.
+async fn bind_dns_socket(port: u16) -> Result<tokio::net::UdpSocket> {
+    use tokio::net::UdpSocket;
+
+    let err = match UdpSocket::bind(("127.0.0.1", port)).await {
+        Ok(socket) => return Ok(socket),
+        Err(e) => e,
+    };
+    if port != 53 || err.kind() != io::ErrorKind::PermissionDenied {
+        return Err(n0_snafu::Error::anyhow(anyhow::anyhow!(
+            "Failed to bind to UDP port {}: {}. You may need sudo/admin privileges.", port, err
+        )));
+    }
+
+    let socket = UdpSocket::bind(("127.0.0.1", DNS_FALLBACK_PORT))
+        .await
+        .map_err(|e| n0_snafu::Error::anyhow(anyhow::anyhow!(
+            "Failed to bind to UDP port 53 ({}) or fallback port {}: {}", err, DNS_FALLBACK_PORT, e
+        )))?;
+
+    eprintln!("Binding UDP port 53 needs sudo/admin privileges; using port {} instead.", DNS_FALLBACK_PORT);
+    eprintln!("Redirect DNS to it at the OS level, for example:");
+    eprintln!("  Linux:  sudo iptables -t nat -A OUTPUT -p udp -d 127.0.0.1 --dport 53 -j REDIRECT --to-ports {}", DNS_FALLBACK_PORT);
+    eprintln!("          or set DNS=127.0.0.1:{} in /etc/systemd/resolved.conf", DNS_FALLBACK_PORT);
+    eprintln!("  macOS:  echo \"rdr pass on lo0 inet proto udp from any to 127.0.0.1 port 53 -> 127.0.0.1 port {}\" | sudo pfctl -ef -", DNS_FALLBACK_PORT);
+    Ok(socket)
 }
 
 /// Helper function to start DNS proxy using an existing connection
 async fn start_dns_proxy_task(conn: iroh::endpoint::Connection) -> Result<()> {
-    use tokio::net::UdpSocket;
     use std::collections::HashMap;
     use std::sync::Arc;
     use tokio::sync::Mutex;
@@ -1474,7 +4958,7 @@ async fn start_dns_proxy_task(conn: iroh::endpoint::Connection) -> Result<()> {
 
     let (mut send, mut recv) = conn.open_bi()
         .await
-        .map_err(|e| n0_snafu::Error::anyhow(anyhow::anyhow!("Failed to open stream: {}", e)))?;
+        .map_err(open_stream_error)?;
 
     // Send Hello message with Dns session type
     let hello = crate::ClientMessage::Hello {
@@ -1488,12 +4972,12 @@ async fn start_dns_proxy_task(conn: iroh::endpoint::Connection) -> Result<()> {
     send.write_all(&encoded).await
         .map_err(|e| n0_snafu::Error::anyhow(anyhow::anyhow!("Failed to send hello: {}", e)))?;
 
-    // Bind UDP socket for DNS (port 53)
-    let socket = Arc::new(UdpSocket::bind("127.0.0.1:53")
-        .await
-        .map_err(|e| n0_snafu::Error::anyhow(anyhow::anyhow!("Failed to bind to UDP port 53: {}. You may need sudo/admin privileges.", e)))?);
+    // Bind UDP socket for DNS (port 53, or the fallback port without privileges)
+    let socket = Arc::new(bind_dns_socket(53).await?);
+    let local_addr = socket.local_addr()
+        .map_err(|e| n0_snafu::Error::anyhow(anyhow::anyhow!("Failed to read DNS socket address: {}", e)))?;
 
-    println!("DNS server listening on 127.0.0.1:53");
+    println!("DNS server listening on {}", local_addr);
 
     // Track pending queries: query_id -> (client_addr, original_transaction_id)
     let pending_queries: Arc<Mutex<HashMap<u32, (SocketAddr, u16)>>> = Arc::new(Mutex::new(HashMap::new()));
@@ -1624,7 +5108,6 @@ pub async fn run_dns_proxy(
     connection_string: &str,
     port: u16,
 ) -> Result<()> {
-    use tokio::net::UdpSocket;
     use std::collections::HashMap;
     use std::sync::Arc;
     use tokio::sync::Mutex;
@@ -1635,17 +5118,15 @@ pub async fn run_dns_proxy(
     let node_addr = crate::decode_connection_string(connection_string)
         .map_err(|e| n0_snafu::Error::anyhow(anyhow::anyhow!("Failed to decode connection string: {}", e)))?;
 
-    let endpoint = iroh::Endpoint::bind(iroh::endpoint::presets::N0)
+    let endpoint = crate::bind_endpoint()
         .await
         .map_err(|e| n0_snafu::Error::anyhow(anyhow::anyhow!("Failed to create endpoint: {}", e)))?;
 
-    let conn = endpoint.connect(node_addr, crate::ALPN)
-        .await
-        .map_err(|e| n0_snafu::Error::anyhow(anyhow::anyhow!("Failed to connect: {}", e)))?;
+    let conn = connect(&endpoint, node_addr).await?;
 
     let (mut send, mut recv) = conn.open_bi()
         .await
-        .map_err(|e| n0_snafu::Error::anyhow(anyhow::anyhow!("Failed to open stream: {}", e)))?;
+        .map_err(open_stream_error)?;
 
     // Send Hello message with Dns session type
     let hello = crate::ClientMessage::Hello {
@@ -1660,11 +5141,11 @@ pub async fn run_dns_proxy(
         .map_err(|e| n0_snafu::Error::anyhow(anyhow::anyhow!("Failed to send hello: {}", e)))?;
 
     // Bind UDP socket for DNS
-    let socket = Arc::new(UdpSocket::bind(format!("127.0.0.1:{}", port))
-        .await
-        .map_err(|e| n0_snafu::Error::anyhow(anyhow::anyhow!("Failed to bind to UDP port {}: {}. You may need sudo/admin privileges.", port, e)))?);
+    let socket = Arc::new(bind_dns_socket(port).await?);
+    let local_addr = socket.local_addr()
+        .map_err(|e| n0_snafu::Error::anyhow(anyhow::anyhow!("Failed to read DNS socket address: {}", e)))?;
 
-    println!("DNS server listening on 127.0.0.1:{}", port);
+    println!("DNS server listening on {}", local_addr);
     println!("Configure your system to use this as DNS server (127.0.0.1)");
     println!("Press Ctrl+C to stop");
 
@@ -1796,3 +5277,58 @@ pub async fn run_dns_proxy(
     #[allow(unreachable_code)]
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    #[test]
+    fn connection_grade_follows_the_worst_measurement() {
+        assert_eq!(ConnectionGrade::from_measurements(20.0, 2.0, 0.0), ConnectionGrade::Excellent);
+        assert_eq!(ConnectionGrade::from_measurements(50.0, 10.0, 0.5), ConnectionGrade::Excellent);
+        assert_eq!(ConnectionGrade::from_measurements(90.0, 5.0, 0.0), ConnectionGrade::Good);
+        assert_eq!(ConnectionGrade::from_measurements(200.0, 40.0, 2.0), ConnectionGrade::Fair);
+        assert_eq!(ConnectionGrade::from_measurements(400.0, 20.0, 0.0), ConnectionGrade::Poor);
+        // One bad number is enough to pull the grade down
+        assert_eq!(ConnectionGrade::from_measurements(20.0, 45.0, 0.0), ConnectionGrade::Fair);
+        assert_eq!(ConnectionGrade::from_measurements(20.0, 2.0, 10.0), ConnectionGrade::Poor);
+    }
+
+    #[test]
+    fn latency_is_the_median_and_jitter_the_mean_change() {
+        assert_eq!(latency_and_jitter(&[]), (0.0, 0.0));
+        assert_eq!(latency_and_jitter(&[30.0]), (30.0, 0.0));
+        assert_eq!(latency_and_jitter(&[10.0, 50.0, 20.0]), (20.0, 35.0));
+    }
+
+    #[test]
+    fn pull_entry_path_stays_in_the_destination() {
+        let local = Path::new("/dest");
+        let links = HashSet::new();
+        assert_eq!(pull_entry_path(local, "a/b.txt", &links), Some(PathBuf::from("/dest/a/b.txt")));
+        assert_eq!(pull_entry_path(local, "../escape", &links), None);
+        assert_eq!(pull_entry_path(local, "a/../../escape", &links), None);
+        assert_eq!(pull_entry_path(local, "/etc/passwd", &links), None);
+    }
+
+    #[test]
+    fn pull_entry_path_refuses_paths_through_created_links() {
+        let local = Path::new("/dest");
+        let links: HashSet<PathBuf> = [PathBuf::from("a")].into_iter().collect();
+        assert_eq!(pull_entry_path(local, "a/.bashrc", &links), None);
+        assert_eq!(pull_entry_path(local, "a//deeper/file", &links), None);
+        assert_eq!(pull_entry_path(local, "a", &links), None);
+        assert_eq!(pull_entry_path(local, "ab/file", &links), Some(PathBuf::from("/dest/ab/file")));
+    }
+
+    #[tokio::test]
+    async fn echo_sessions_round_trip_in_memory() {
+        let ((mut send, mut recv), (server_send, server_recv)) = crate::transport::memory_pair(1 << 20);
+        let server = crate::server::ServerConfig::default();
+        tokio::spawn(crate::server::serve_stream(crate::test_util::node_id(), server_send, server_recv, server));
+
+        let messages = echo_sessions(&mut send, &mut recv, 1).await.unwrap();
+        assert_eq!(messages, ECHO_SESSIONS * ECHO_SIZES.len());
+    }
+}
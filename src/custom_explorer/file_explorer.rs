@@ -302,6 +302,37 @@ impl FileExplorer {
         &self.filesystem
     }
 
+    /// Rename or move a file or directory, then reload the listing
+    /// (async operation, blocking wrapper)
+    pub fn rename(&mut self, from: &Path, to: &Path) -> io::Result<()> {
+        let (from, to) = (from.to_path_buf(), to.to_path_buf());
+        let filesystem = Arc::clone(&self.filesystem);
+
+        std::thread::spawn(move || {
+            let rt = tokio::runtime::Runtime::new().map_err(io::Error::other)?;
+            rt.block_on(filesystem.rename(&from, &to))
+        })
+        .join()
+        .map_err(|e| io::Error::other(format!("Thread panicked: {:?}", e)))??;
+
+        self.refresh_sync()
+    }
+
+    /// Create a directory, then reload the listing (async operation, blocking wrapper)
+    pub fn create_dir(&mut self, path: &Path) -> io::Result<()> {
+        let path = path.to_path_buf();
+        let filesystem = Arc::clone(&self.filesystem);
+
+        std::thread::spawn(move || {
+            let rt = tokio::runtime::Runtime::new().map_err(io::Error::other)?;
+            rt.block_on(filesystem.create_dir(&path))
+        })
+        .join()
+        .map_err(|e| io::Error::other(format!("Thread panicked: {:?}", e)))??;
+
+        self.refresh_sync()
+    }
+
     /// Read file content (async operation, blocking wrapper)
     pub fn read_file(&self, path: &Path) -> io::Result<Vec<u8>> {
         let path = path.to_path_buf();
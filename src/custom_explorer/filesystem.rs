@@ -16,6 +16,62 @@ pub struct FileEntry {
     pub metadata: Option<FileMetadata>,
 }
 
+/// Rename or move `from` to `to`. Where `rename` can't (a different filesystem) the
+/// tree is copied and the original deleted. A non-empty directory at `to` is refused
+/// rather than merged into.
+pub fn move_path(from: &Path, to: &Path) -> io::Result<()> {
+    if std::fs::symlink_metadata(to).is_ok_and(|m| m.is_dir())
+        && std::fs::read_dir(to)?.next().is_some()
+    {
+        return Err(io::Error::new(
+            io::ErrorKind::DirectoryNotEmpty,
+            format!("{} is a directory that isn't empty", to.display()),
+        ));
+    }
+
+    match std::fs::rename(from, to) {
+        Err(e) if e.kind() == io::ErrorKind::CrossesDevices => {
+            copy_tree(from, to)?;
+            if std::fs::symlink_metadata(from)?.is_dir() {
+                std::fs::remove_dir_all(from)
+            } else {
+                std::fs::remove_file(from)
+            }
+        }
+        result => result,
+    }
+}
+
+/// Create `path` and any missing parents. Succeeds if the directory is already there,
+/// but not if something else (e.g. a file) has its name.
+pub fn make_dir(path: &Path) -> io::Result<()> {
+    if std::fs::metadata(path).is_ok_and(|m| !m.is_dir()) {
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("{} already exists and is not a directory", path.display()),
+        ));
+    }
+    std::fs::create_dir_all(path)
+}
+
+/// Copy a file, symlink or whole directory for `move_path`
+fn copy_tree(from: &Path, to: &Path) -> io::Result<()> {
+    let metadata = std::fs::symlink_metadata(from)?;
+    if metadata.is_symlink() {
+        let target = crate::transfer::symlink_target(from).map_err(io::Error::other)?;
+        crate::transfer::create_symlink(to, &target).map_err(io::Error::other)
+    } else if metadata.is_dir() {
+        std::fs::create_dir_all(to)?;
+        for entry in std::fs::read_dir(from)? {
+            let entry = entry?;
+            copy_tree(&entry.path(), &to.join(entry.file_name()))?;
+        }
+        std::fs::set_permissions(to, metadata.permissions())
+    } else {
+        std::fs::copy(from, to).map(|_| ())
+    }
+}
+
 /// Trait for filesystem operations that can be implemented for local or remote filesystems
 #[async_trait::async_trait]
 pub trait Filesystem: Send + Sync {
@@ -49,6 +105,12 @@ pub trait Filesystem: Send + Sync {
     /// Get the current working directory (for local filesystem)
     /// For remote filesystem, this might return a default root path
     fn current_dir(&self) -> io::Result<PathBuf>;
+
+    /// Rename or move a file or directory (see `move_path`)
+    async fn rename(&self, from: &Path, to: &Path) -> io::Result<()>;
+
+    /// Create a directory and any missing parents (see `make_dir`)
+    async fn create_dir(&self, path: &Path) -> io::Result<()>;
 }
 
 /// Local filesystem implementation
@@ -145,38 +207,196 @@ impl Filesystem for LocalFilesystem {
     fn current_dir(&self) -> io::Result<PathBuf> {
         std::env::current_dir()
     }
+
+    async fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        move_path(from, to)
+    }
+
+    async fn create_dir(&self, path: &Path) -> io::Result<()> {
+        make_dir(path)
+    }
 }
 
 /// Remote filesystem implementation via p2p connection
 pub struct RemoteFilesystem {
     root_path: PathBuf,
-    send: Arc<tokio::sync::Mutex<iroh::endpoint::SendStream>>,
-    recv: Arc<tokio::sync::Mutex<iroh::endpoint::RecvStream>>,
+    send: Arc<tokio::sync::Mutex<crate::transport::BoxedWrite>>,
+    recv: Arc<tokio::sync::Mutex<crate::transport::BoxedRead>>,
     session_id: String,
     error_callback: Arc<std::sync::Mutex<Option<Box<dyn Fn(String) + Send + Sync>>>>,
+    /// Connection used to re-open the session if the stream dies (None = no reconnect)
+    connection: Option<iroh::endpoint::Connection>,
+    health: Arc<std::sync::Mutex<FsHealth>>,
+}
+
+/// Stream health of a `RemoteFilesystem`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FsHealth {
+    /// Requests are going through
+    Healthy,
+    /// The stream dropped and a new session is being opened
+    Reconnecting,
+    /// The stream dropped and could not be re-opened
+    Failed,
+}
+
+/// Map a failure to send a request's first frame. The server never got a whole frame,
+/// so the request didn't run and can be sent again on a new stream.
+fn unsent_error(e: impl std::fmt::Display) -> io::Error {
+    io::Error::new(io::ErrorKind::NotConnected, e.to_string())
+}
+
+/// Map a stream failure after a request's first frame went out; it may have run
+fn stream_error(e: impl std::fmt::Display) -> io::Error {
+    io::Error::new(io::ErrorKind::ConnectionAborted, e.to_string())
+}
+
+/// Map a failure to receive a reply. A frame that arrived whole but didn't decode
+/// leaves the stream usable, so it isn't treated as a dead stream.
+fn recv_error(e: Box<dyn std::error::Error>) -> io::Error {
+    if e.is::<crate::EnvelopeDecodeError>() {
+        io::Error::new(io::ErrorKind::InvalidData, e.to_string())
+    } else {
+        stream_error(e)
+    }
+}
+
+/// Whether `e` came from the stream dying rather than from the request itself
+fn is_stream_failure(e: &io::Error) -> bool {
+    matches!(e.kind(), io::ErrorKind::NotConnected | io::ErrorKind::ConnectionAborted)
+}
+
+/// Requests that only read, so running them twice is harmless
+fn is_idempotent(msg: &crate::ClientMessage) -> bool {
+    matches!(
+        msg,
+        crate::ClientMessage::FsReadDir { .. }
+            | crate::ClientMessage::FsMetadata { .. }
+            | crate::ClientMessage::FsReadFile { .. }
+            | crate::ClientMessage::FsHashFile { .. }
+            | crate::ClientMessage::FsLimits
+            | crate::ClientMessage::FsMode
+    )
 }
 
 impl RemoteFilesystem {
+    /// `send`/`recv` are normally an iroh bi stream; `transport::memory_pair` works too
     pub fn new(
         root_path: PathBuf,
-        send: iroh::endpoint::SendStream,
-        recv: iroh::endpoint::RecvStream,
+        send: impl crate::transport::EnvelopeWrite + 'static,
+        recv: impl crate::transport::EnvelopeRead + 'static,
     ) -> Self {
         Self::new_with_session_id(root_path, send, recv, "browser_1".to_string())
     }
 
     pub fn new_with_session_id(
         root_path: PathBuf,
-        send: iroh::endpoint::SendStream,
-        recv: iroh::endpoint::RecvStream,
+        send: impl crate::transport::EnvelopeWrite + 'static,
+        recv: impl crate::transport::EnvelopeRead + 'static,
         session_id: String,
     ) -> Self {
         Self {
             root_path,
-            send: Arc::new(tokio::sync::Mutex::new(send)),
-            recv: Arc::new(tokio::sync::Mutex::new(recv)),
+            send: Arc::new(tokio::sync::Mutex::new(Box::new(send))),
+            recv: Arc::new(tokio::sync::Mutex::new(Box::new(recv))),
             session_id,
             error_callback: Arc::new(std::sync::Mutex::new(None)),
+            connection: None,
+            health: Arc::new(std::sync::Mutex::new(FsHealth::Healthy)),
+        }
+    }
+
+    /// Re-open the FileBrowser session over `connection` when the stream fails.
+    /// The failed request is retried once on the new stream if it only reads or
+    /// never left; otherwise its error is returned, as it may already have run.
+    pub fn with_reconnect(mut self, connection: iroh::endpoint::Connection) -> Self {
+        self.connection = Some(connection);
+        self
+    }
+
+    /// Start browsing at `root_path` instead of the one given to the constructor
+    pub fn with_root(mut self, root_path: PathBuf) -> Self {
+        self.root_path = root_path;
+        self
+    }
+
+    /// Current stream health, so callers can tell a transient reconnect from a hard failure
+    pub fn health(&self) -> FsHealth {
+        self.health.lock().map(|h| *h).unwrap_or(FsHealth::Failed)
+    }
+
+    fn set_health(&self, health: FsHealth) {
+        if let Ok(mut h) = self.health.lock() {
+            *h = health;
+        }
+    }
+
+    /// Open a fresh bi stream, say Hello with the same session_id and swap it in
+    async fn reconnect(&self) -> io::Result<()> {
+        let Some(conn) = self.connection.as_ref() else {
+            self.set_health(FsHealth::Failed);
+            return Err(io::Error::other("Stream closed and reconnect is not enabled"));
+        };
+
+        self.set_health(FsHealth::Reconnecting);
+        tracing::warn!(session_id = %self.session_id, "File browser stream lost, reconnecting");
+
+        // Hold both locks so no request can use a half-swapped pair
+        let mut send = self.send.lock().await;
+        let mut recv = self.recv.lock().await;
+
+        let result = async {
+            let (mut new_send, new_recv) = conn.open_bi().await.map_err(io::Error::other)?;
+            let hello = crate::MessageEnvelope {
+                session_id: self.session_id.clone(),
+                payload: crate::MessagePayload::Client(crate::ClientMessage::Hello {
+                    session_type: crate::SessionType::FileBrowser,
+                }),
+            };
+            crate::send_envelope(&mut new_send, &hello)
+                .await
+                .map_err(|e| io::Error::other(e.to_string()))?;
+            Ok::<_, io::Error>((new_send, new_recv))
+        }
+        .await;
+
+        match result {
+            Ok((new_send, new_recv)) => {
+                *send = Box::new(new_send);
+                *recv = Box::new(new_recv);
+                self.set_health(FsHealth::Healthy);
+                Ok(())
+            }
+            Err(e) => {
+                self.set_health(FsHealth::Failed);
+                Err(e)
+            }
+        }
+    }
+
+    /// Run `op`, reconnecting if the stream itself failed. `op` runs again on the new
+    /// stream only if it is `idempotent` or its request never left.
+    async fn with_retry<T, F, Fut>(&self, idempotent: bool, op: F) -> io::Result<T>
+    where
+        F: Fn() -> Fut,
+        Fut: std::future::Future<Output = io::Result<T>>,
+    {
+        match op().await {
+            Err(e) if is_stream_failure(&e) => {
+                self.reconnect().await?;
+                if !idempotent && e.kind() != io::ErrorKind::NotConnected {
+                    return Err(e);
+                }
+                let result = op().await;
+                if let Err(e) = &result
+                    && is_stream_failure(e)
+                {
+                    self.set_health(FsHealth::Failed);
+                }
+                result
+            }
+            result => result,
         }
     }
 
@@ -190,6 +410,10 @@ impl RemoteFilesystem {
     }
 
     async fn send_request(&self, msg: crate::ClientMessage) -> io::Result<crate::ServerMessage> {
+        self.with_retry(is_idempotent(&msg), || self.send_request_once(msg.clone())).await
+    }
+
+    async fn send_request_once(&self, msg: crate::ClientMessage) -> io::Result<crate::ServerMessage> {
         // Wrap in envelope with session_id
         let envelope = crate::MessageEnvelope {
             session_id: self.session_id.clone(),
@@ -200,14 +424,14 @@ impl RemoteFilesystem {
         let mut send = self.send.lock().await;
         crate::send_envelope(&mut *send, &envelope)
             .await
-            .map_err(|e| io::Error::new(io::ErrorKind::Other, e.to_string()))?;
+            .map_err(unsent_error)?;
         drop(send);
 
         // Receive response envelope
         let mut recv = self.recv.lock().await;
         let response_envelope = crate::recv_envelope(&mut *recv)
             .await
-            .map_err(|e| io::Error::new(io::ErrorKind::Other, e.to_string()))?;
+            .map_err(recv_error)?;
         drop(recv);
 
         // Extract server message from envelope
@@ -321,6 +545,51 @@ impl Filesystem for RemoteFilesystem {
     fn current_dir(&self) -> io::Result<PathBuf> {
         Ok(self.root_path.clone())
     }
+
+    async fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        let msg = crate::ClientMessage::FsRename {
+            from: from.display().to_string(),
+            to: to.display().to_string(),
+        };
+
+        match self.send_request(msg).await? {
+            crate::ServerMessage::FsRenameResponse { success: true } => Ok(()),
+            crate::ServerMessage::FsRenameResponse { success: false } => Err(io::Error::other("Rename failed")),
+            crate::ServerMessage::FsError { message } => {
+                // Call error callback if set
+                if let Ok(cb_guard) = self.error_callback.lock()
+                    && let Some(cb) = cb_guard.as_ref()
+                {
+                    cb(message.clone());
+                }
+                Err(io::Error::other(message))
+            }
+            crate::ServerMessage::Error { message } => Err(io::Error::other(message)),
+            _ => Err(io::Error::other("Unexpected response type")),
+        }
+    }
+
+    async fn create_dir(&self, path: &Path) -> io::Result<()> {
+        let msg = crate::ClientMessage::FsMkdir {
+            path: path.display().to_string(),
+        };
+
+        match self.send_request(msg).await? {
+            crate::ServerMessage::FsMkdirResponse { success: true } => Ok(()),
+            crate::ServerMessage::FsMkdirResponse { success: false } => Err(io::Error::other("Create directory failed")),
+            crate::ServerMessage::FsError { message } => {
+                // Call error callback if set
+                if let Ok(cb_guard) = self.error_callback.lock()
+                    && let Some(cb) = cb_guard.as_ref()
+                {
+                    cb(message.clone());
+                }
+                Err(io::Error::other(message))
+            }
+            crate::ServerMessage::Error { message } => Err(io::Error::other(message)),
+            _ => Err(io::Error::other("Unexpected response type")),
+        }
+    }
 }
 
 impl RemoteFilesystem {
@@ -355,6 +624,20 @@ impl RemoteFilesystem {
     /// Sends StartUpload + FileChunks + EndUpload through the shared multiplexed stream,
     /// then reads a single UploadAck response.
     pub async fn upload_file(&self, path: &Path, data: &[u8]) -> io::Result<()> {
+        self.upload_file_with_progress(path, data, &|_| {}).await
+    }
+
+    /// `upload_file`, calling `progress` with the size of each chunk as it is sent
+    pub async fn upload_file_with_progress(
+        &self,
+        path: &Path,
+        data: &[u8],
+        progress: &(dyn Fn(u64) + Send + Sync),
+    ) -> io::Result<()> {
+        self.with_retry(false, || self.upload_file_once(path, data, progress)).await
+    }
+
+    async fn upload_file_once(&self, path: &Path, data: &[u8], progress: &(dyn Fn(u64) + Send + Sync)) -> io::Result<()> {
         const CHUNK_SIZE: usize = 65536; // 64KB chunks
 
         // Hold both locks for the entire upload sequence to prevent
@@ -374,7 +657,7 @@ impl RemoteFilesystem {
         };
         crate::send_envelope(&mut *send, &start_envelope)
             .await
-            .map_err(|e| io::Error::new(io::ErrorKind::Other, e.to_string()))?;
+            .map_err(unsent_error)?;
 
         // Send file data in chunks
         for chunk in data.chunks(CHUNK_SIZE) {
@@ -386,7 +669,8 @@ impl RemoteFilesystem {
             };
             crate::send_envelope(&mut *send, &chunk_envelope)
                 .await
-                .map_err(|e| io::Error::new(io::ErrorKind::Other, e.to_string()))?;
+                .map_err(stream_error)?;
+            progress(chunk.len() as u64);
         }
 
         // Send EndUpload
@@ -396,12 +680,12 @@ impl RemoteFilesystem {
         };
         crate::send_envelope(&mut *send, &end_envelope)
             .await
-            .map_err(|e| io::Error::new(io::ErrorKind::Other, e.to_string()))?;
+            .map_err(stream_error)?;
 
         // Read the single ack response
         let response_envelope = crate::recv_envelope(&mut *recv)
             .await
-            .map_err(|e| io::Error::new(io::ErrorKind::Other, e.to_string()))?;
+            .map_err(recv_error)?;
 
         match response_envelope.payload {
             crate::MessagePayload::Server(crate::ServerMessage::UploadAck) => Ok(()),
@@ -412,12 +696,89 @@ impl RemoteFilesystem {
         }
     }
 
-    /// Delete a file or directory on the remote filesystem
-    pub async fn delete_file(&self, path: &Path) -> io::Result<()> {
-        let msg = crate::ClientMessage::FsDelete {
+    /// Replace a remote file's contents, creating missing parent directories.
+    /// The server writes a temporary file and renames it into place.
+    pub async fn write_file(&self, path: &Path, data: &[u8]) -> io::Result<()> {
+        let msg = crate::ClientMessage::FsWriteFile {
             path: path.display().to_string(),
+            data: data.to_vec(),
         };
 
+        match self.send_request(msg).await? {
+            crate::ServerMessage::FsWriteResponse { success: true } => Ok(()),
+            crate::ServerMessage::FsWriteResponse { success: false } => Err(io::Error::other("Write failed")),
+            crate::ServerMessage::FsError { message } => {
+                // Call error callback if set
+                if let Ok(cb_guard) = self.error_callback.lock()
+                    && let Some(cb) = cb_guard.as_ref()
+                {
+                    cb(message.clone());
+                }
+                Err(io::Error::other(message))
+            }
+            crate::ServerMessage::Error { message } => Err(io::Error::other(message)),
+            _ => Err(io::Error::other("Unexpected response type")),
+        }
+    }
+
+    /// Run several filesystem operations in a single round trip
+    /// Returns one result per operation, in request order.
+    pub async fn batch(&self, ops: Vec<crate::FsOp>) -> io::Result<Vec<crate::FsOpResult>> {
+        let msg = crate::ClientMessage::FsBatch { ops };
+
+        match self.send_request(msg).await? {
+            crate::ServerMessage::FsBatchResponse { results } => Ok(results),
+            crate::ServerMessage::FsError { message } => {
+                // Call error callback if set
+                if let Ok(cb_guard) = self.error_callback.lock()
+                    && let Some(cb) = cb_guard.as_ref()
+                {
+                    cb(message.clone());
+                }
+                Err(io::Error::other(message))
+            }
+            crate::ServerMessage::Error { message } => Err(io::Error::other(message)),
+            _ => Err(io::Error::other("Unexpected response type")),
+        }
+    }
+
+    /// Get the server's file size limits as `(max_file_read, max_upload)`, None = unlimited
+    pub async fn limits(&self) -> io::Result<(Option<u64>, Option<u64>)> {
+        match self.send_request(crate::ClientMessage::FsLimits).await? {
+            crate::ServerMessage::FsLimitsResponse { max_file_read, max_upload } => Ok((max_file_read, max_upload)),
+            crate::ServerMessage::Error { message } => Err(io::Error::other(message)),
+            _ => Err(io::Error::other("Unexpected response type")),
+        }
+    }
+
+    /// Get the server's file access mode as `(read_only, root)`: whether it refuses
+    /// changes, and the directory it confines paths to (None = everywhere)
+    pub async fn mode(&self) -> io::Result<(bool, Option<PathBuf>)> {
+        match self.send_request(crate::ClientMessage::FsMode).await? {
+            crate::ServerMessage::FsModeResponse { read_only, root } => Ok((read_only, root.map(PathBuf::from))),
+            crate::ServerMessage::Error { message } => Err(io::Error::other(message)),
+            _ => Err(io::Error::other("Unexpected response type")),
+        }
+    }
+
+    /// Delete a file or empty directory on the remote filesystem
+    pub async fn delete_file(&self, path: &Path) -> io::Result<()> {
+        self.delete(crate::ClientMessage::FsDelete {
+            path: path.display().to_string(),
+        })
+        .await
+    }
+
+    /// Delete a directory and everything in it on the remote filesystem
+    pub async fn delete_recursive(&self, path: &Path) -> io::Result<()> {
+        self.delete(crate::ClientMessage::FsDeleteRecursive {
+            path: path.display().to_string(),
+        })
+        .await
+    }
+
+    async fn delete(&self, msg: crate::ClientMessage) -> io::Result<()> {
+
         match self.send_request(msg).await? {
             crate::ServerMessage::FsDeleteResponse { success } => {
                 if success {
@@ -517,3 +878,66 @@ impl FileCache {
         self.store(&hash, &data, remote_path)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::AsyncWriteExt;
+
+    #[test]
+    fn only_reads_are_idempotent() {
+        assert!(is_idempotent(&crate::ClientMessage::FsReadDir { path: "/".to_string() }));
+        assert!(is_idempotent(&crate::ClientMessage::FsHashFile { path: "/f".to_string() }));
+        assert!(!is_idempotent(&crate::ClientMessage::FsDelete { path: "/f".to_string() }));
+        assert!(!is_idempotent(&crate::ClientMessage::FsRename { from: "/a".to_string(), to: "/b".to_string() }));
+        assert!(!is_idempotent(&crate::ClientMessage::FsBatch { ops: Vec::new() }));
+    }
+
+    #[tokio::test]
+    async fn undecodable_reply_is_not_a_dead_stream() {
+        let ((send, recv), (mut server_send, mut server_recv)) = crate::transport::memory_pair(4096);
+        let fs = RemoteFilesystem::new(PathBuf::from("/"), send, recv);
+        let server = tokio::spawn(async move {
+            crate::recv_envelope(&mut server_recv).await.unwrap();
+            server_send.write_all(&[0, 0, 0, 3, 1, 2, 3]).await.unwrap();
+            let request = crate::recv_envelope(&mut server_recv).await.unwrap();
+            let reply = crate::MessageEnvelope {
+                session_id: request.session_id,
+                payload: crate::MessagePayload::Server(crate::ServerMessage::FsDirListing {
+                    entries_json: "[]".to_string(),
+                }),
+            };
+            crate::send_envelope(&mut server_send, &reply).await.unwrap();
+        });
+
+        let err = fs.read_dir(Path::new("/")).await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(fs.health(), FsHealth::Healthy);
+        assert!(fs.read_dir(Path::new("/")).await.unwrap().is_empty());
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn upload_reports_progress_per_chunk() {
+        let ((mut send, recv), (server_send, server_recv)) = crate::transport::memory_pair(1 << 20);
+        let config = crate::server::ServerConfig::default();
+        tokio::spawn(crate::server::serve_stream(crate::test_util::node_id(), server_send, server_recv, config));
+        let hello = crate::MessageEnvelope {
+            session_id: "browser_1".to_string(),
+            payload: crate::MessagePayload::Client(crate::ClientMessage::Hello {
+                session_type: crate::SessionType::FileBrowser,
+            }),
+        };
+        crate::send_envelope(&mut send, &hello).await.unwrap();
+        let fs = RemoteFilesystem::new(PathBuf::from("/"), send, recv);
+
+        let dir = crate::test_util::TempDir::new("browser-upload");
+        let path = dir.path().join("file");
+        let data = vec![0x5a; 2 * 65536 + 5];
+        let chunks = std::sync::Mutex::new(Vec::new());
+        fs.upload_file_with_progress(&path, &data, &|n| chunks.lock().unwrap().push(n)).await.unwrap();
+
+        assert_eq!(chunks.into_inner().unwrap(), vec![65536, 65536, 5]);
+        assert_eq!(std::fs::read(&path).unwrap(), data);
+    }
+}
@@ -4,4 +4,4 @@ pub mod filesystem;
 
 pub use file_explorer::{File, FileExplorer, FileMetadata};
 pub use widget::{Renderer, Theme};
-pub use filesystem::{Filesystem, LocalFilesystem, RemoteFilesystem, FileEntry, FileCache};
+pub use filesystem::{Filesystem, LocalFilesystem, RemoteFilesystem, FsHealth, FileEntry, FileCache};
@@ -12,9 +12,13 @@ pub mod traffic_ui;
 pub mod debug_log;
 pub mod web_ui;
 pub mod logging;
+pub mod transport;
+pub mod recording;
+#[cfg(test)]
+mod test_util;
 
 /// Session type for initial handshake
-#[derive(Debug, Clone, Archive, RkyvSerialize, RkyvDeserialize)]
+#[derive(Debug, Clone, PartialEq, Archive, RkyvSerialize, RkyvDeserialize)]
 #[rkyv(derive(Debug))]
 pub enum SessionType {
     /// Interactive shell session
@@ -31,11 +35,17 @@ pub enum SessionType {
     HttpProxy,
     /// DNS-over-P2P session
     Dns,
+    /// Test session that sends every `KeyEvent` back as `Output`
+    Echo,
+    /// UDP relay session
+    UdpRelay,
+    /// Reverse TCP relay session: the server listens and the client connects out
+    ReverseRelay,
 }
 
 /// Message envelope for multiplexing multiple sessions over a single stream
 /// Each message includes a session_id to route it to the correct handler
-#[derive(Debug, Clone, Archive, RkyvSerialize, RkyvDeserialize)]
+#[derive(Debug, Clone, PartialEq, Archive, RkyvSerialize, RkyvDeserialize)]
 #[rkyv(derive(Debug))]
 pub struct MessageEnvelope {
     /// Unique identifier for this session (e.g., "shell_1", "browser_1")
@@ -45,7 +55,7 @@ pub struct MessageEnvelope {
 }
 
 /// Wrapper for client/server messages to enable bidirectional multiplexing
-#[derive(Debug, Clone, Archive, RkyvSerialize, RkyvDeserialize)]
+#[derive(Debug, Clone, PartialEq, Archive, RkyvSerialize, RkyvDeserialize)]
 #[rkyv(derive(Debug))]
 pub enum MessagePayload {
     /// Message from client to server
@@ -54,8 +64,45 @@ pub enum MessagePayload {
     Server(ServerMessage),
 }
 
+/// A single filesystem operation inside an `FsBatch` request
+#[derive(Debug, Clone, PartialEq, Archive, RkyvSerialize, RkyvDeserialize, serde::Serialize, serde::Deserialize)]
+#[rkyv(derive(Debug))]
+#[serde(tag = "op", rename_all = "lowercase")]
+pub enum FsOp {
+    /// Create a directory (and any missing parents)
+    Mkdir { path: String },
+    /// Delete a file, symlink or empty directory
+    Delete { path: String },
+    /// Rename or move a file or directory
+    Rename { from: String, to: String },
+    /// Set Unix permission bits (rwx only; setuid, setgid and sticky are ignored)
+    Chmod { path: String, mode: u32 },
+}
+
+/// Outcome of one `FsOp`, in the same order as the request
+#[derive(Debug, Clone, PartialEq, Archive, RkyvSerialize, RkyvDeserialize, serde::Serialize, serde::Deserialize)]
+#[rkyv(derive(Debug))]
+pub struct FsOpResult {
+    /// Whether the operation succeeded
+    pub success: bool,
+    /// Error message if it failed
+    pub error: Option<String>,
+}
+
+/// A file a resumed directory pull already has, as the server described it when it was sent
+#[derive(Debug, Clone, PartialEq, Archive, RkyvSerialize, RkyvDeserialize)]
+#[rkyv(derive(Debug))]
+pub struct CompletedFile {
+    /// Path relative to the directory being pulled
+    pub relative_path: String,
+    /// Size of the file
+    pub size: u64,
+    /// Modification time (seconds since the Unix epoch) from its `FileAttributes`
+    pub modified: Option<i64>,
+}
+
 /// Messages sent from client to server
-#[derive(Debug, Clone, Archive, RkyvSerialize, RkyvDeserialize)]
+#[derive(Debug, Clone, PartialEq, Archive, RkyvSerialize, RkyvDeserialize)]
 #[rkyv(derive(Debug))]
 pub enum ClientMessage {
     /// Initial handshake with session type
@@ -86,7 +133,7 @@ pub enum ClientMessage {
     FsReadFile { path: String },
     /// Request file hash (for file browser caching)
     FsHashFile { path: String },
-    /// Request to delete a file or directory (for file browser)
+    /// Request to delete a file or an empty directory (for file browser)
     FsDelete { path: String },
     /// Open a new TCP connection on the remote server
     TcpOpen { stream_id: u32, destination_host: Option<String>, destination_port: u16 },
@@ -98,10 +145,82 @@ pub enum ClientMessage {
     PingRequest { data: Vec<u8> },
     /// DNS query request
     DnsQuery { query_id: u32, query_data: Vec<u8> },
+    /// Attributes to apply to a file after its data has been sent
+    /// (empty relative_path = the single file being transferred)
+    FileAttributes { relative_path: String, modified: Option<i64>, mode: Option<u32> },
+    /// Run several filesystem operations in one round trip (for file browser)
+    FsBatch { ops: Vec<FsOp> },
+    /// Ask for the server's file size limits (for file browser)
+    FsLimits,
+    /// Handshake for a shell session that should start in a specific directory
+    ShellHello { cwd: Option<String> },
+    /// How the rest of this transfer session treats symlinks in directories
+    /// (without it the server skips them, as older clients expect)
+    TransferOptions { dereference: bool },
+    /// A symlink within a directory upload, recreated rather than copied
+    Symlink { relative_path: String, target: String },
+    /// Delete a directory and everything in it. `FsDelete` only removes empty directories.
+    FsDeleteRecursive { path: String },
+    /// Files to leave out of the next directory download, because a resumed pull
+    /// already has them. The server still sends any whose size or modification
+    /// time has changed since.
+    SkipFiles { files: Vec<CompletedFile> },
+    /// Either side of this transfer session may send `FileHole` in place of zero-filled
+    /// chunks (older servers reject this)
+    SparseFiles,
+    /// A run of zero bytes in the file being uploaded, to leave as a hole
+    FileHole { len: u64 },
+    /// Stop the download in progress; the server sends nothing more for it
+    /// (older servers ignore this and keep sending until the connection closes)
+    CancelDownload,
+    /// Replace a file's contents in one go, creating missing parent directories (for file browser)
+    FsWriteFile { path: String, data: Vec<u8> },
+    /// Rename or move a file or directory, across filesystems if need be (for file browser)
+    FsRename { from: String, to: String },
+    /// Create a directory and any missing parents (for file browser)
+    FsMkdir { path: String },
+    /// Either side of this transfer session may send `CompressedChunk` in place of
+    /// `FileChunk` (older servers reject this)
+    CompressedChunks,
+    /// A gzip-compressed chunk of the file being uploaded
+    CompressedChunk { data: Vec<u8> },
+    /// Follow each downloaded file's data with `DownloadComplete` carrying its blake3
+    /// hash, so the client can check what it wrote (older servers reject this)
+    DownloadHashes,
+    /// The next single-file `StartUpload` continues an interrupted upload, of which the
+    /// server may already have the first `offset` bytes. `hash` is the hex blake3 hash of
+    /// those bytes in the local file; the server starts over if its copy doesn't match.
+    /// (older servers reject this)
+    ResumeUpload { offset: u64, hash: String },
+    /// The secret of a server started with `--password`, sent right after `Hello`.
+    /// Such a server answers `Hello` with the `AUTH_REQUIRED` error and starts no
+    /// session before this arrives.
+    Auth { secret: String },
+    /// Ask whether the server accepts changes to its files (for file browser)
+    FsMode,
+    /// Ask for a `Heartbeat` on this session every few seconds, so a stalled
+    /// connection can be told apart from a quiet one (older servers reject this)
+    Heartbeats,
+    /// Handshake for a shell session with a starting directory and the client's `TERM`
+    /// (older servers reject this, and the client falls back to `ShellHello`)
+    ShellHelloEnv { cwd: Option<String>, term: Option<String> },
+    /// Open a UDP stream to a port on the remote server, for one local peer
+    UdpOpen { stream_id: u32, destination_host: Option<String>, destination_port: u16 },
+    /// Send one datagram on a UDP stream
+    UdpData { stream_id: u32, data: Vec<u8> },
+    /// Close a UDP stream
+    UdpClose { stream_id: u32 },
+    /// Ask the server to listen on this port of its loopback interface (for reverse relay)
+    ReverseListen { port: u16 },
+    /// Ask a ping session which kerr version the server runs (older servers reject this)
+    VersionRequest,
+    /// Handshake for a shell session that runs one command with `<shell> -c`, without a
+    /// terminal and with stdin closed, then ends with `Exit` (older servers reject this)
+    ExecHello { command: String },
 }
 
 /// Messages sent from server to client
-#[derive(Debug, Clone, Archive, RkyvSerialize, RkyvDeserialize)]
+#[derive(Debug, Clone, PartialEq, Archive, RkyvSerialize, RkyvDeserialize)]
 #[rkyv(derive(Debug))]
 pub enum ServerMessage {
     /// Output from the PTY
@@ -144,21 +263,284 @@ pub enum ServerMessage {
     PingResponse { data: Vec<u8> },
     /// DNS query response
     DnsResponse { query_id: u32, response_data: Vec<u8> },
+    /// Attributes to apply to a file after its data has been sent
+    /// (empty relative_path = the single file being transferred)
+    FileAttributes { relative_path: String, modified: Option<i64>, mode: Option<u32> },
+    /// Per-operation results for an `FsBatch` request (for file browser)
+    FsBatchResponse { results: Vec<FsOpResult> },
+    /// Server file size limits in bytes (None = unlimited)
+    FsLimitsResponse { max_file_read: Option<u64>, max_upload: Option<u64> },
+    /// Sent after `EndUpload` once the server has flushed and closed the upload
+    UploadComplete { success: bool, error: Option<String> },
+    /// A symlink within a directory download, recreated rather than copied
+    Symlink { relative_path: String, target: String },
+    /// Sent periodically while a download is still being enumerated, before `StartDownload`.
+    /// Only sent to clients that announced themselves with `TransferOptions`.
+    Preparing { entries_scanned: u64 },
+    /// A run of zero bytes in the file being downloaded, to leave as a hole
+    /// (only sent to clients that sent `SparseFiles`)
+    FileHole { len: u64 },
+    /// File write confirmation response (for file browser)
+    FsWriteResponse { success: bool },
+    /// Rename confirmation response (for file browser)
+    FsRenameResponse { success: bool },
+    /// Directory creation confirmation response (for file browser)
+    FsMkdirResponse { success: bool },
+    /// A gzip-compressed chunk of the file being downloaded
+    /// (only sent to clients that sent `CompressedChunks`)
+    CompressedChunk { data: Vec<u8> },
+    /// Hex blake3 hash of the whole file whose data was just sent, ahead of its
+    /// `FileAttributes` (only sent to clients that sent `DownloadHashes`)
+    DownloadComplete { hash: String },
+    /// Sent instead of `UploadAck` when a `ResumeUpload` was honoured: the server kept the
+    /// first `offset` bytes of the file and appends what follows
+    UploadResumed { offset: u64 },
+    /// Answer to `Auth`. On failure the server closes the connection after sending it.
+    AuthResult { ok: bool },
+    /// Answer to `FsMode`; a server started with `--read-only` refuses uploads and edits,
+    /// and one started with `--root` only allows paths inside `root`
+    FsModeResponse { read_only: bool, root: Option<String> },
+    /// Liveness signal for a session that asked for `Heartbeats`; the next one is
+    /// due `interval_ms` later
+    Heartbeat { interval_ms: u64 },
+    /// The shell of a `Shell` session ended with exit code `code`; sent just before
+    /// the session-ended error
+    Exit { code: i32 },
+    /// UDP stream opened successfully (or failed)
+    UdpOpenResponse { stream_id: u32, success: bool, error: Option<String> },
+    /// One datagram received from the remote service
+    UdpDataResponse { stream_id: u32, data: Vec<u8> },
+    /// UDP stream closed by the server, or an error occurred
+    UdpCloseResponse { stream_id: u32, error: Option<String> },
+    /// Whether the server is now listening for a reverse relay
+    ReverseListenResponse { success: bool, error: Option<String> },
+    /// A connection from `peer` arrived on the reverse relay's port. The client connects
+    /// to its local service for it, and the stream carries `TcpData` and `TcpDataResponse`.
+    TcpIncoming { stream_id: u32, peer: String },
+    /// Answer to `VersionRequest`: the server's `VERSION`
+    VersionResponse { version: String },
+    /// Output a command started with `ExecHello` wrote to stderr; its stdout comes as `Output`
+    ErrorOutput { data: Vec<u8> },
 }
 
+/// Version of this kerr build
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// Start of the error a server started with `--password` answers `Hello` with until the
+/// client sends `Auth`, so clients can tell it apart and ask for the password
+pub const AUTH_REQUIRED: &str = "This server requires a password";
+
 /// ALPN for the Kerr protocol
 pub const ALPN: &[u8] = b"kerr/0";
 
+/// QUIC liveness and path settings applied to every endpoint kerr binds
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TransportSettings {
+    /// Send a keep-alive after this long without traffic
+    pub keep_alive: std::time::Duration,
+    /// Drop the connection after hearing nothing from the peer for this long.
+    /// Both sides propose a value and QUIC uses the smaller one.
+    pub max_idle_timeout: std::time::Duration,
+    /// Use this relay server instead of the n0 defaults
+    pub relay_url: Option<iroh::RelayUrl>,
+    /// Bind UDP sockets for direct paths. Off means every packet goes through the relay.
+    pub direct: bool,
+    /// Give up on connecting to a peer that hasn't answered after this long
+    pub connect_timeout: std::time::Duration,
+}
+
+impl Default for TransportSettings {
+    /// iroh's own defaults
+    fn default() -> Self {
+        Self {
+            keep_alive: std::time::Duration::from_secs(5),
+            max_idle_timeout: std::time::Duration::from_secs(30),
+            relay_url: None,
+            direct: true,
+            connect_timeout: std::time::Duration::from_secs(30),
+        }
+    }
+}
+
+static TRANSPORT_SETTINGS: std::sync::OnceLock<TransportSettings> = std::sync::OnceLock::new();
+
+/// Set the transport settings for this process; call before binding any endpoint
+pub fn set_transport_settings(settings: TransportSettings) {
+    let _ = TRANSPORT_SETTINGS.set(settings);
+}
+
+/// How long to wait for a peer to answer a connection attempt
+pub fn connect_timeout() -> std::time::Duration {
+    TRANSPORT_SETTINGS.get().cloned().unwrap_or_default().connect_timeout
+}
+
+/// Bind an iroh endpoint using the process's `TransportSettings`
+pub async fn bind_endpoint() -> Result<iroh::Endpoint, iroh::endpoint::BindError> {
+    let settings = TRANSPORT_SETTINGS.get().cloned().unwrap_or_default();
+    // Durations too large for QUIC's varint mean "never time out"
+    let idle = iroh::endpoint::IdleTimeout::try_from(settings.max_idle_timeout).ok();
+    let transport = iroh::endpoint::QuicTransportConfig::builder()
+        .keep_alive_interval(settings.keep_alive)
+        .max_idle_timeout(idle)
+        .build();
+    let mut builder = iroh::Endpoint::builder(iroh::endpoint::presets::N0)
+        .transport_config(transport);
+    if let Some(relay_url) = settings.relay_url {
+        builder = builder.relay_mode(iroh::RelayMode::custom([relay_url]));
+    }
+    if !settings.direct {
+        builder = builder.clear_ip_transports();
+    }
+    builder.bind().await
+}
+
+/// Default cap on terminal resizes passed on per second, by the client and the server
+pub const DEFAULT_MAX_RESIZES_PER_SEC: u32 = 10;
+
+/// Caps how often terminal resizes are passed on, so a terminal that emits a storm of
+/// them can't crowd out the session's input and output. Sizes that come too soon are
+/// held back, each replacing the last, and the latest is passed on once allowed.
+#[derive(Debug)]
+pub struct ResizeLimiter {
+    interval: std::time::Duration,
+    last: Option<tokio::time::Instant>,
+    pending: Option<(u16, u16)>,
+    dropped: u64,
+}
+
+impl ResizeLimiter {
+    /// Pass on at most `max_per_sec` resizes a second (0 = no limit)
+    pub fn new(max_per_sec: u32) -> Self {
+        let interval = match max_per_sec {
+            0 => std::time::Duration::ZERO,
+            n => std::time::Duration::from_secs(1) / n,
+        };
+        Self { interval, last: None, pending: None, dropped: 0 }
+    }
+
+    /// A new size: returns it if it can be applied now, or holds it back
+    pub fn offer(&mut self, cols: u16, rows: u16) -> Option<(u16, u16)> {
+        let now = tokio::time::Instant::now();
+        if self.pending.is_none() && self.last.is_none_or(|last| now >= last + self.interval) {
+            self.last = Some(now);
+            return Some((cols, rows));
+        }
+        if self.pending.replace((cols, rows)).is_some() {
+            self.dropped += 1;
+            tracing::debug!(cols, rows, dropped = self.dropped, "Resize superseded before it could be applied");
+        }
+        None
+    }
+
+    /// Waits until the held-back size may be applied and returns it; never
+    /// returns while nothing is held back. Safe to cancel, e.g. in `select!`.
+    pub async fn next_due(&mut self) -> (u16, u16) {
+        let (Some(last), Some(size)) = (self.last, self.pending) else {
+            return std::future::pending().await;
+        };
+        tokio::time::sleep_until(last + self.interval).await;
+        self.last = Some(tokio::time::Instant::now());
+        self.pending = None;
+        size
+    }
+
+    /// Sizes that were replaced by a later one before being applied
+    pub fn dropped(&self) -> u64 {
+        self.dropped
+    }
+}
+
+/// Why connecting to a peer (or opening a stream on the connection) failed,
+/// coarse enough to tell the user what to try next
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectFailure {
+    /// Nothing answered before the handshake timed out
+    Timeout,
+    /// No address or path to the peer could be found
+    NoPath,
+    /// The peer answered but turned the connection down
+    Refused,
+    /// The connection was closed, by either side, before it could be used
+    Closed,
+    /// Anything else, e.g. a local misconfiguration
+    Other,
+}
+
+impl ConnectFailure {
+    /// Classify an error from `Endpoint::connect`
+    pub fn from_connect(err: &iroh::endpoint::ConnectError) -> Self {
+        use iroh::endpoint::{ConnectError, ConnectWithOptsError, ConnectingError};
+
+        match err {
+            ConnectError::Connect { source, .. } => match source {
+                ConnectWithOptsError::NoAddress { .. } => Self::NoPath,
+                ConnectWithOptsError::LocallyRejected { .. } => Self::Refused,
+                ConnectWithOptsError::EndpointClosed { .. } => Self::Closed,
+                _ => Self::Other,
+            },
+            ConnectError::Connecting { source, .. } => match source {
+                ConnectingError::ConnectionError { source, .. } => Self::from_connection(source),
+                ConnectingError::HandshakeFailure { .. } | ConnectingError::LocallyRejected { .. } => Self::Refused,
+                _ => Self::Other,
+            },
+            ConnectError::Connection { source, .. } => Self::from_connection(source),
+            _ => Self::Other,
+        }
+    }
+
+    /// Classify an error from an established connection, e.g. from `open_bi`
+    pub fn from_connection(err: &iroh::endpoint::ConnectionError) -> Self {
+        use iroh::endpoint::ConnectionError;
+
+        match err {
+            ConnectionError::TimedOut => Self::Timeout,
+            ConnectionError::ConnectionClosed(_) | ConnectionError::VersionMismatch => Self::Refused,
+            ConnectionError::ApplicationClosed(_) | ConnectionError::Reset | ConnectionError::LocallyClosed => {
+                Self::Closed
+            }
+            _ => Self::Other,
+        }
+    }
+
+    /// What the user should check or try, or None when there is nothing useful to say
+    pub fn hint(self) -> Option<&'static str> {
+        match self {
+            Self::Timeout => Some(
+                "The host appears to be offline, or a firewall is blocking the connection. \
+                 Check that `kerr serve` is still running there and try again.",
+            ),
+            Self::NoPath => Some(
+                "No path to the host was found. Check that the connection string is current; \
+                 if the host is up, a firewall may be blocking direct connections, so try again.",
+            ),
+            Self::Refused => Some(
+                "The host refused the connection. It may be running an incompatible kerr version.",
+            ),
+            Self::Closed => Some("The connection was closed. The host may have restarted; try again."),
+            Self::Other => None,
+        }
+    }
+}
+
+/// Gzip level used for connection strings. Kept in sync with `kerr-ios` so
+/// both crates produce byte-identical strings for the same address.
+pub const CONNECTION_STRING_COMPRESSION: u32 = 9;
+
 /// Encode an EndpointAddr as a compressed connection string (JSON -> gzip -> base64)
 pub fn encode_connection_string(addr: &iroh::EndpointAddr) -> String {
+    encode_connection_string_with_level(addr, flate2::Compression::new(CONNECTION_STRING_COMPRESSION))
+}
+
+/// Encode an EndpointAddr with an explicit gzip level.
+/// Use `Compression::fast()` when encoding often, `Compression::best()` for the shortest string.
+pub fn encode_connection_string_with_level(addr: &iroh::EndpointAddr, level: flate2::Compression) -> String {
     use flate2::write::GzEncoder;
-    use flate2::Compression;
     use std::io::Write;
 
     let addr_json = serde_json::to_string(addr).unwrap();
 
     // Compress with gzip
-    let mut encoder = GzEncoder::new(Vec::new(), Compression::best());
+    let mut encoder = GzEncoder::new(Vec::new(), level);
     encoder.write_all(addr_json.as_bytes()).unwrap();
     let compressed = encoder.finish().unwrap();
 
@@ -166,11 +548,41 @@ pub fn encode_connection_string(addr: &iroh::EndpointAddr) -> String {
     base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(&compressed)
 }
 
+/// Marker for the uncompressed connection string form (`kerr-raw1:<json>`).
+/// `:` never appears in URL-safe base64, so decoders can tell the two forms apart.
+pub const RAW_CONNECTION_STRING_PREFIX: &str = "kerr-raw1:";
+
+/// Encode an EndpointAddr as plain JSON behind `RAW_CONNECTION_STRING_PREFIX`.
+/// Longer than the gzip form, but readable and immune to base64 mangling.
+pub fn encode_raw_connection_string(addr: &iroh::EndpointAddr) -> String {
+    format!("{}{}", RAW_CONNECTION_STRING_PREFIX, serde_json::to_string(addr).unwrap())
+}
+
+/// URL prefix used to deep-link a connection string into the app
+pub const CONNECTION_URL_PREFIX: &str = "kerr://connect/";
+
+/// Wrap a connection string as a `kerr://connect/<string>` URL
+pub fn connection_url(connection_string: &str) -> String {
+    format!("{}{}", CONNECTION_URL_PREFIX, connection_string)
+}
+
 /// Decode a compressed connection string to EndpointAddr (base64 -> gzip -> JSON)
+/// Also accepts the `kerr://connect/<string>` URL form and the raw `kerr-raw1:<json>` form.
 pub fn decode_connection_string(connection_string: &str) -> Result<iroh::EndpointAddr, Box<dyn std::error::Error>> {
     use flate2::read::GzDecoder;
     use std::io::Read;
 
+    let connection_string = connection_string.trim();
+    let connection_string = connection_string
+        .strip_prefix(CONNECTION_URL_PREFIX)
+        .unwrap_or(connection_string);
+
+    // Uncompressed form: the JSON follows the marker directly
+    if let Some(addr_json) = connection_string.strip_prefix(RAW_CONNECTION_STRING_PREFIX) {
+        let addr: iroh::EndpointAddr = serde_json::from_str(addr_json)?;
+        return Ok(addr);
+    }
+
     // Base64 decode
     let compressed = base64::engine::general_purpose::URL_SAFE_NO_PAD
         .decode(connection_string.as_bytes())?;
@@ -185,12 +597,105 @@ pub fn decode_connection_string(connection_string: &str) -> Result<iroh::Endpoin
     Ok(addr)
 }
 
-/// Helper to send an enveloped message over a QUIC stream
+/// Size of the length prefix in front of every `MessageEnvelope` frame.
+/// Frame layout (shared with `kerr-ios`):
+///
+/// ```text
+/// +----------------------+-----------------------------------+
+/// | len: u32 big-endian  | rkyv bytes of MessageEnvelope     |
+/// +----------------------+-----------------------------------+
+/// ```
+///
+/// `ClientMessage`/`ServerMessage` variants are encoded by declaration order,
+/// so new variants must only ever be appended in both crates.
+pub const ENVELOPE_LENGTH_PREFIX_BYTES: usize = 4;
+
+/// A frame was read in full but could not be decoded, usually a message or
+/// session type from a newer kerr build. The stream itself is still in sync.
+#[derive(Debug)]
+pub struct EnvelopeDecodeError(pub String);
+
+impl std::fmt::Display for EnvelopeDecodeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "failed to decode message: {}", self.0)
+    }
+}
+
+impl std::error::Error for EnvelopeDecodeError {}
+
+/// The peer finished the stream cleanly, between two frames
+#[derive(Debug)]
+pub struct StreamClosed;
+
+impl std::fmt::Display for StreamClosed {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "stream closed by peer")
+    }
+}
+
+impl std::error::Error for StreamClosed {}
+
+/// The stream ended partway through a frame, i.e. the connection was lost
+/// rather than closed. Sizes include the length prefix.
+#[derive(Debug)]
+pub struct FrameTruncated {
+    pub expected: usize,
+    pub received: usize,
+}
+
+impl std::fmt::Display for FrameTruncated {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "connection lost mid-message ({} of {} bytes received)",
+            self.received, self.expected
+        )
+    }
+}
+
+impl std::error::Error for FrameTruncated {}
+
+/// Whether a read failed because the QUIC connection was closed on purpose
+/// (by either side) rather than dropped, which counts as EOF.
+fn is_graceful_close(e: &std::io::Error) -> bool {
+    use iroh::endpoint::{ConnectionError, ReadError};
+
+    matches!(
+        e.get_ref().and_then(|inner| inner.downcast_ref::<ReadError>()),
+        Some(ReadError::ConnectionLost(ConnectionError::ApplicationClosed(_) | ConnectionError::LocallyClosed))
+    )
+}
+
+/// Fill `buf` from `recv`, stopping early at EOF. Returns the bytes read.
+async fn read_until_eof(
+    recv: &mut (impl transport::EnvelopeRead + ?Sized),
+    buf: &mut [u8],
+) -> std::io::Result<usize> {
+    use tokio::io::AsyncReadExt;
+
+    let mut filled = 0;
+    while filled < buf.len() {
+        let n = match recv.read(&mut buf[filled..]).await {
+            Ok(n) => n,
+            Err(e) if is_graceful_close(&e) => 0,
+            Err(e) => return Err(e),
+        };
+        if n == 0 {
+            break;
+        }
+        filled += n;
+    }
+    Ok(filled)
+}
+
+/// Helper to send an enveloped message over a QUIC (or in-memory) stream
 /// Format: 4-byte length prefix + rkyv-encoded MessageEnvelope
 pub async fn send_envelope(
-    send: &mut iroh::endpoint::SendStream,
+    send: &mut (impl transport::EnvelopeWrite + ?Sized),
     envelope: &MessageEnvelope,
 ) -> Result<(), Box<dyn std::error::Error>> {
+    use tokio::io::AsyncWriteExt;
+
     let encoded = rkyv::to_bytes::<rkyv::rancor::Error>(envelope)
         .map_err(|e| Box::new(e) as Box<dyn std::error::Error>)?;
     let len = (encoded.len() as u32).to_be_bytes();
@@ -201,25 +706,355 @@ pub async fn send_envelope(
     Ok(())
 }
 
-/// Helper to receive an enveloped message from a QUIC stream
+/// Helper to receive an enveloped message from a QUIC (or in-memory) stream
 /// Format: 4-byte length prefix + rkyv-encoded MessageEnvelope
+///
+/// EOF before a frame starts is `StreamClosed`; EOF inside one is `FrameTruncated`.
 pub async fn recv_envelope(
-    recv: &mut iroh::endpoint::RecvStream,
+    recv: &mut (impl transport::EnvelopeRead + ?Sized),
 ) -> Result<MessageEnvelope, Box<dyn std::error::Error>> {
     // Read length prefix
-    let mut len_bytes = [0u8; 4];
-    recv.read_exact(&mut len_bytes).await?;
+    let mut len_bytes = [0u8; ENVELOPE_LENGTH_PREFIX_BYTES];
+    match read_until_eof(recv, &mut len_bytes).await? {
+        0 => return Err(Box::new(StreamClosed)),
+        n if n < ENVELOPE_LENGTH_PREFIX_BYTES => {
+            return Err(Box::new(FrameTruncated { expected: ENVELOPE_LENGTH_PREFIX_BYTES, received: n }));
+        }
+        _ => {}
+    }
     let len = u32::from_be_bytes(len_bytes) as usize;
 
     // Read message body
     let mut msg_bytes = vec![0u8; len];
-    recv.read_exact(&mut msg_bytes).await?;
+    let n = read_until_eof(recv, &mut msg_bytes).await?;
+    if n < len {
+        return Err(Box::new(FrameTruncated {
+            expected: ENVELOPE_LENGTH_PREFIX_BYTES + len,
+            received: ENVELOPE_LENGTH_PREFIX_BYTES + n,
+        }));
+    }
 
     // Decode envelope
     let archived = rkyv::access::<rkyv::Archived<MessageEnvelope>, rkyv::rancor::Error>(&msg_bytes)
-        .map_err(|e| Box::new(e) as Box<dyn std::error::Error>)?;
+        .map_err(|e| Box::new(EnvelopeDecodeError(e.to_string())) as Box<dyn std::error::Error>)?;
     let envelope: MessageEnvelope = rkyv::deserialize::<MessageEnvelope, rkyv::rancor::Error>(archived)
-        .map_err(|e| Box::new(e) as Box<dyn std::error::Error>)?;
+        .map_err(|e| Box::new(EnvelopeDecodeError(e.to_string())) as Box<dyn std::error::Error>)?;
 
     Ok(envelope)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    /// Number of `ClientMessage` variants. `client_variant` stops compiling when one is
+    /// added, as a reminder to add it to `client_messages` and count it here.
+    const CLIENT_VARIANTS: usize = 48;
+    /// Number of `ServerMessage` variants, kept the same way as `CLIENT_VARIANTS`
+    const SERVER_VARIANTS: usize = 44;
+
+    fn client_variant(msg: &ClientMessage) -> std::mem::Discriminant<ClientMessage> {
+        match msg {
+            ClientMessage::Hello { .. }
+            | ClientMessage::KeyEvent { .. }
+            | ClientMessage::Resize { .. }
+            | ClientMessage::Disconnect
+            | ClientMessage::StartUpload { .. }
+            | ClientMessage::FileChunk { .. }
+            | ClientMessage::EndUpload
+            | ClientMessage::FileStart { .. }
+            | ClientMessage::ConfirmResponse { .. }
+            | ClientMessage::RequestDownload { .. }
+            | ClientMessage::FsReadDir { .. }
+            | ClientMessage::FsMetadata { .. }
+            | ClientMessage::FsReadFile { .. }
+            | ClientMessage::FsHashFile { .. }
+            | ClientMessage::FsDelete { .. }
+            | ClientMessage::TcpOpen { .. }
+            | ClientMessage::TcpData { .. }
+            | ClientMessage::TcpClose { .. }
+            | ClientMessage::PingRequest { .. }
+            | ClientMessage::DnsQuery { .. }
+            | ClientMessage::FileAttributes { .. }
+            | ClientMessage::FsBatch { .. }
+            | ClientMessage::FsLimits
+            | ClientMessage::ShellHello { .. }
+            | ClientMessage::TransferOptions { .. }
+            | ClientMessage::Symlink { .. }
+            | ClientMessage::FsDeleteRecursive { .. }
+            | ClientMessage::SkipFiles { .. }
+            | ClientMessage::SparseFiles
+            | ClientMessage::FileHole { .. }
+            | ClientMessage::CancelDownload
+            | ClientMessage::FsWriteFile { .. }
+            | ClientMessage::FsRename { .. }
+            | ClientMessage::FsMkdir { .. }
+            | ClientMessage::CompressedChunks
+            | ClientMessage::CompressedChunk { .. }
+            | ClientMessage::DownloadHashes
+            | ClientMessage::ResumeUpload { .. }
+            | ClientMessage::Auth { .. }
+            | ClientMessage::FsMode
+            | ClientMessage::Heartbeats
+            | ClientMessage::ShellHelloEnv { .. }
+            | ClientMessage::UdpOpen { .. }
+            | ClientMessage::UdpData { .. }
+            | ClientMessage::UdpClose { .. }
+            | ClientMessage::ReverseListen { .. }
+            | ClientMessage::VersionRequest
+            | ClientMessage::ExecHello { .. } => std::mem::discriminant(msg),
+        }
+    }
+
+    fn server_variant(msg: &ServerMessage) -> std::mem::Discriminant<ServerMessage> {
+        match msg {
+            ServerMessage::Output { .. }
+            | ServerMessage::Error { .. }
+            | ServerMessage::UploadAck
+            | ServerMessage::ConfirmPrompt { .. }
+            | ServerMessage::StartDownload { .. }
+            | ServerMessage::FileChunk { .. }
+            | ServerMessage::EndDownload
+            | ServerMessage::FileStart { .. }
+            | ServerMessage::Progress { .. }
+            | ServerMessage::FsDirListing { .. }
+            | ServerMessage::FsMetadataResponse { .. }
+            | ServerMessage::FsFileContent { .. }
+            | ServerMessage::FsHashResponse { .. }
+            | ServerMessage::FsDeleteResponse { .. }
+            | ServerMessage::FsError { .. }
+            | ServerMessage::TcpOpenResponse { .. }
+            | ServerMessage::TcpDataResponse { .. }
+            | ServerMessage::TcpCloseResponse { .. }
+            | ServerMessage::PingResponse { .. }
+            | ServerMessage::DnsResponse { .. }
+            | ServerMessage::FileAttributes { .. }
+            | ServerMessage::FsBatchResponse { .. }
+            | ServerMessage::FsLimitsResponse { .. }
+            | ServerMessage::UploadComplete { .. }
+            | ServerMessage::Symlink { .. }
+            | ServerMessage::Preparing { .. }
+            | ServerMessage::FileHole { .. }
+            | ServerMessage::FsWriteResponse { .. }
+            | ServerMessage::FsRenameResponse { .. }
+            | ServerMessage::FsMkdirResponse { .. }
+            | ServerMessage::CompressedChunk { .. }
+            | ServerMessage::DownloadComplete { .. }
+            | ServerMessage::UploadResumed { .. }
+            | ServerMessage::AuthResult { .. }
+            | ServerMessage::FsModeResponse { .. }
+            | ServerMessage::Heartbeat { .. }
+            | ServerMessage::Exit { .. }
+            | ServerMessage::UdpOpenResponse { .. }
+            | ServerMessage::UdpDataResponse { .. }
+            | ServerMessage::UdpCloseResponse { .. }
+            | ServerMessage::ReverseListenResponse { .. }
+            | ServerMessage::TcpIncoming { .. }
+            | ServerMessage::VersionResponse { .. }
+            | ServerMessage::ErrorOutput { .. } => std::mem::discriminant(msg),
+        }
+    }
+
+    /// One of every `ClientMessage`, with fields set away from their defaults
+    fn client_messages() -> Vec<ClientMessage> {
+        vec![
+            ClientMessage::Hello { session_type: SessionType::ReverseRelay },
+            ClientMessage::KeyEvent { data: vec![0, 1, 0xfe, 0xff] },
+            ClientMessage::Resize { cols: 8080, rows: 8080 },
+            ClientMessage::Disconnect,
+            ClientMessage::StartUpload { path: "path".to_string(), size: u64::MAX - 1, is_dir: true, force: true },
+            ClientMessage::FileChunk { data: vec![0, 1, 0xfe, 0xff] },
+            ClientMessage::EndUpload,
+            ClientMessage::FileStart { relative_path: "relative_path".to_string(), size: u64::MAX - 1 },
+            ClientMessage::ConfirmResponse { confirmed: true },
+            ClientMessage::RequestDownload { path: "path".to_string(), offset: u64::MAX - 1 },
+            ClientMessage::FsReadDir { path: "path".to_string() },
+            ClientMessage::FsMetadata { path: "path".to_string() },
+            ClientMessage::FsReadFile { path: "path".to_string() },
+            ClientMessage::FsHashFile { path: "path".to_string() },
+            ClientMessage::FsDelete { path: "path".to_string() },
+            ClientMessage::TcpOpen { stream_id: 0x1234_5678, destination_host: Some("destination_host".to_string()), destination_port: 8080 },
+            ClientMessage::TcpData { stream_id: 0x1234_5678, data: vec![0, 1, 0xfe, 0xff] },
+            ClientMessage::TcpClose { stream_id: 0x1234_5678 },
+            ClientMessage::PingRequest { data: vec![0, 1, 0xfe, 0xff] },
+            ClientMessage::DnsQuery { query_id: 0x1234_5678, query_data: vec![0, 1, 0xfe, 0xff] },
+            ClientMessage::FileAttributes { relative_path: "relative_path".to_string(), modified: Some(1_700_000_000), mode: Some(0o644) },
+            ClientMessage::FsBatch { ops: vec![FsOp::Mkdir { path: "d".to_string() }, FsOp::Chmod { path: "f".to_string(), mode: 0o755 }] },
+            ClientMessage::FsLimits,
+            ClientMessage::ShellHello { cwd: Some("cwd".to_string()) },
+            ClientMessage::TransferOptions { dereference: true },
+            ClientMessage::Symlink { relative_path: "relative_path".to_string(), target: "target".to_string() },
+            ClientMessage::FsDeleteRecursive { path: "path".to_string() },
+            ClientMessage::SkipFiles {
+                files: vec![CompletedFile { relative_path: "b/c".to_string(), size: u64::MAX - 1, modified: Some(1_700_000_000) }],
+            },
+            ClientMessage::SparseFiles,
+            ClientMessage::FileHole { len: u64::MAX - 1 },
+            ClientMessage::CancelDownload,
+            ClientMessage::FsWriteFile { path: "path".to_string(), data: vec![0, 1, 0xfe, 0xff] },
+            ClientMessage::FsRename { from: "from".to_string(), to: "to".to_string() },
+            ClientMessage::FsMkdir { path: "path".to_string() },
+            ClientMessage::CompressedChunks,
+            ClientMessage::CompressedChunk { data: vec![0, 1, 0xfe, 0xff] },
+            ClientMessage::DownloadHashes,
+            ClientMessage::ResumeUpload { offset: u64::MAX - 1, hash: "hash".to_string() },
+            ClientMessage::Auth { secret: "secret".to_string() },
+            ClientMessage::FsMode,
+            ClientMessage::Heartbeats,
+            ClientMessage::ShellHelloEnv { cwd: Some("cwd".to_string()), term: Some("term".to_string()) },
+            ClientMessage::UdpOpen { stream_id: 0x1234_5678, destination_host: Some("destination_host".to_string()), destination_port: 8080 },
+            ClientMessage::UdpData { stream_id: 0x1234_5678, data: vec![0, 1, 0xfe, 0xff] },
+            ClientMessage::UdpClose { stream_id: 0x1234_5678 },
+            ClientMessage::ReverseListen { port: 8080 },
+            ClientMessage::VersionRequest,
+            ClientMessage::ExecHello { command: "command".to_string() },
+        ]
+    }
+
+    /// One of every `ServerMessage`, with fields set away from their defaults
+    fn server_messages() -> Vec<ServerMessage> {
+        vec![
+            ServerMessage::Output { data: vec![0, 1, 0xfe, 0xff] },
+            ServerMessage::Error { message: "message".to_string() },
+            ServerMessage::UploadAck,
+            ServerMessage::ConfirmPrompt { message: "message".to_string() },
+            ServerMessage::StartDownload { size: u64::MAX - 1, is_dir: true },
+            ServerMessage::FileChunk { data: vec![0, 1, 0xfe, 0xff] },
+            ServerMessage::EndDownload,
+            ServerMessage::FileStart { relative_path: "relative_path".to_string(), size: u64::MAX - 1 },
+            ServerMessage::Progress { bytes_transferred: u64::MAX - 1, total_bytes: u64::MAX - 1 },
+            ServerMessage::FsDirListing { entries_json: "entries_json".to_string() },
+            ServerMessage::FsMetadataResponse { metadata_json: "metadata_json".to_string() },
+            ServerMessage::FsFileContent { data: vec![0, 1, 0xfe, 0xff] },
+            ServerMessage::FsHashResponse { hash: "hash".to_string() },
+            ServerMessage::FsDeleteResponse { success: true },
+            ServerMessage::FsError { message: "message".to_string() },
+            ServerMessage::TcpOpenResponse { stream_id: 0x1234_5678, success: true, error: Some("error".to_string()) },
+            ServerMessage::TcpDataResponse { stream_id: 0x1234_5678, data: vec![0, 1, 0xfe, 0xff] },
+            ServerMessage::TcpCloseResponse { stream_id: 0x1234_5678, error: Some("error".to_string()) },
+            ServerMessage::PingResponse { data: vec![0, 1, 0xfe, 0xff] },
+            ServerMessage::DnsResponse { query_id: 0x1234_5678, response_data: vec![0, 1, 0xfe, 0xff] },
+            ServerMessage::FileAttributes { relative_path: "relative_path".to_string(), modified: Some(1_700_000_000), mode: Some(0o644) },
+            ServerMessage::FsBatchResponse { results: vec![FsOpResult { success: false, error: Some("denied".to_string()) }] },
+            ServerMessage::FsLimitsResponse { max_file_read: Some(1 << 40), max_upload: Some(1 << 40) },
+            ServerMessage::UploadComplete { success: true, error: Some("error".to_string()) },
+            ServerMessage::Symlink { relative_path: "relative_path".to_string(), target: "target".to_string() },
+            ServerMessage::Preparing { entries_scanned: u64::MAX - 1 },
+            ServerMessage::FileHole { len: u64::MAX - 1 },
+            ServerMessage::FsWriteResponse { success: true },
+            ServerMessage::FsRenameResponse { success: true },
+            ServerMessage::FsMkdirResponse { success: true },
+            ServerMessage::CompressedChunk { data: vec![0, 1, 0xfe, 0xff] },
+            ServerMessage::DownloadComplete { hash: "hash".to_string() },
+            ServerMessage::UploadResumed { offset: u64::MAX - 1 },
+            ServerMessage::AuthResult { ok: true },
+            ServerMessage::FsModeResponse { read_only: true, root: Some("root".to_string()) },
+            ServerMessage::Heartbeat { interval_ms: u64::MAX - 1 },
+            ServerMessage::Exit { code: -3 },
+            ServerMessage::UdpOpenResponse { stream_id: 0x1234_5678, success: true, error: Some("error".to_string()) },
+            ServerMessage::UdpDataResponse { stream_id: 0x1234_5678, data: vec![0, 1, 0xfe, 0xff] },
+            ServerMessage::UdpCloseResponse { stream_id: 0x1234_5678, error: Some("error".to_string()) },
+            ServerMessage::ReverseListenResponse { success: true, error: Some("error".to_string()) },
+            ServerMessage::TcpIncoming { stream_id: 0x1234_5678, peer: "peer".to_string() },
+            ServerMessage::VersionResponse { version: "version".to_string() },
+            ServerMessage::ErrorOutput { data: vec![0, 1, 0xfe, 0xff] },
+        ]
+    }
+
+    /// A connection string `kerr-ios` must encode and decode the same way (its tests
+    /// hold the same string)
+    const GOLDEN_CONNECTION_STRING: &str =
+        "H4sIAAAAAAAC_xWMQQrDIBAA_7LnYOK6KvqDXnstPay6oYWEhiSHFvHvNcxpYJgK7wIRhIlddkYwZIsTJ5mtnXzSBiXbOQRL3juW1PEpIKEWYRc0kSuIGQbgUvYD4qPCXRb-9enrPLcjjuN-uZIvr9siKn_WEdpQ4bb1RgdUk0KlI5Ex0J7tD7owfbCSAAAA";
+
+    fn golden_addr() -> iroh::EndpointAddr {
+        iroh::EndpointAddr::new(iroh::SecretKey::from_bytes(&[7; 32]).public())
+            .with_ip_addr("192.0.2.1:4433".parse().unwrap())
+            .with_relay_url("https://relay.example.com".parse().unwrap())
+    }
+
+    #[test]
+    fn connection_string_round_trip() {
+        let addr = golden_addr();
+        let encoded = encode_connection_string(&addr);
+        assert_eq!(encoded, GOLDEN_CONNECTION_STRING);
+        assert_eq!(decode_connection_string(&encoded).unwrap(), addr);
+        assert_eq!(decode_connection_string(&connection_url(&encoded)).unwrap(), addr);
+        assert_eq!(decode_connection_string(&format!("  {}\n", encoded)).unwrap(), addr);
+        assert_eq!(decode_connection_string(&encode_raw_connection_string(&addr)).unwrap(), addr);
+
+        // Any gzip level decodes to the same address
+        let fast = encode_connection_string_with_level(&addr, flate2::Compression::fast());
+        assert_eq!(decode_connection_string(&fast).unwrap(), addr);
+    }
+
+    #[test]
+    fn every_variant_has_a_sample() {
+        let client: HashSet<_> = client_messages().iter().map(client_variant).collect();
+        assert_eq!(client.len(), CLIENT_VARIANTS);
+        assert_eq!(client_messages().len(), CLIENT_VARIANTS);
+        let server: HashSet<_> = server_messages().iter().map(server_variant).collect();
+        assert_eq!(server.len(), SERVER_VARIANTS);
+        assert_eq!(server_messages().len(), SERVER_VARIANTS);
+    }
+
+    /// Every message arrives through `send_envelope`/`recv_envelope` exactly as sent,
+    /// in order, and the end of the stream is `StreamClosed`
+    #[tokio::test]
+    async fn envelopes_round_trip() {
+        let envelopes: Vec<MessageEnvelope> = client_messages()
+            .into_iter()
+            .map(MessagePayload::Client)
+            .chain(server_messages().into_iter().map(MessagePayload::Server))
+            .enumerate()
+            .map(|(i, payload)| MessageEnvelope { session_id: format!("session_{}", i), payload })
+            .collect();
+
+        let ((mut send, _), (_, mut recv)) = transport::memory_pair(4096);
+        let sent = envelopes.clone();
+        let writer = tokio::spawn(async move {
+            for envelope in &sent {
+                send_envelope(&mut send, envelope).await.unwrap();
+            }
+        });
+
+        for expected in &envelopes {
+            let received = recv_envelope(&mut recv).await.unwrap();
+            assert_eq!(&received, expected);
+        }
+        writer.await.unwrap();
+        let end = recv_envelope(&mut recv).await.unwrap_err();
+        assert!(end.is::<StreamClosed>(), "{}", end);
+    }
+
+    /// A frame built by hand the way the session loops build theirs: big-endian length
+    /// then the rkyv bytes
+    fn hand_framed(envelope: &MessageEnvelope) -> Vec<u8> {
+        let encoded = rkyv::to_bytes::<rkyv::rancor::Error>(envelope).unwrap();
+        let mut frame = (encoded.len() as u32).to_be_bytes().to_vec();
+        frame.extend_from_slice(&encoded);
+        frame
+    }
+
+    #[tokio::test]
+    async fn hand_framed_envelopes_match_the_helpers() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let envelope = MessageEnvelope {
+            session_id: "session".to_string(),
+            payload: MessagePayload::Client(ClientMessage::KeyEvent { data: b"ls\n".to_vec() }),
+        };
+        let ((mut send, _), (_, mut recv)) = transport::memory_pair(4096);
+
+        // A hand-written frame decodes with recv_envelope
+        send.write_all(&hand_framed(&envelope)).await.unwrap();
+        assert_eq!(recv_envelope(&mut recv).await.unwrap(), envelope);
+
+        // And send_envelope writes exactly the bytes the hand-written path would
+        send_envelope(&mut send, &envelope).await.unwrap();
+        let expected = hand_framed(&envelope);
+        let mut frame = vec![0u8; expected.len()];
+        recv.read_exact(&mut frame).await.unwrap();
+        assert_eq!(frame, expected);
+    }
+}
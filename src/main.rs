@@ -13,6 +13,22 @@ use n0_snafu::Result;
 struct Cli {
     #[command(subcommand)]
     command: Commands,
+    /// Seconds between QUIC keep-alives on an idle connection
+    #[arg(long, global = true, default_value = "5")]
+    keep_alive: u64,
+    /// Seconds without hearing from the peer before the connection is dropped
+    /// (the lower of client and server values wins)
+    #[arg(long, global = true, default_value = "30")]
+    idle_timeout: u64,
+    /// Relay server to use instead of the public n0 relays
+    #[arg(long, global = true, value_name = "URL")]
+    relay_url: Option<iroh::RelayUrl>,
+    /// Never connect directly; send all traffic through the relay
+    #[arg(long, global = true)]
+    no_direct: bool,
+    /// Seconds to wait for the server to answer before giving up on connecting
+    #[arg(long, global = true, default_value = "30")]
+    connect_timeout: u64,
 }
 
 #[derive(Subcommand)]
@@ -28,15 +44,96 @@ enum Commands {
         /// Path to log file (logs will be appended with timestamps)
         #[arg(long)]
         log: Option<String>,
+        /// Also print the connection string as a kerr://connect/ URL for the app
+        #[arg(long)]
+        url: bool,
+        /// PTY read buffer size in bytes
+        #[arg(long, default_value = "8192")]
+        pty_buffer: usize,
+        /// Milliseconds to coalesce shell output before sending (0 disables)
+        #[arg(long, default_value = "3")]
+        pty_coalesce_ms: u64,
+        /// Log command lines typed in shell sessions (privacy-sensitive; clients are notified)
+        #[arg(long)]
+        log_commands: bool,
+        /// Print an uncompressed (kerr-raw1:<json>) connection string for debugging
+        #[arg(long)]
+        no_compression: bool,
+        /// Largest file (bytes) the file browser may open
+        #[arg(long)]
+        max_file_read: Option<u64>,
+        /// Largest upload (bytes) accepted from clients
+        #[arg(long)]
+        max_upload: Option<u64>,
+        /// Largest file (bytes) the file browser may ask to hash for its cache
+        #[arg(long)]
+        max_hash_size: Option<u64>,
+        /// JSON file with runtime setting overrides (reloaded on SIGHUP)
+        #[arg(long)]
+        config: Option<std::path::PathBuf>,
+        /// Shell to start for shell sessions (default: $SHELL, then /bin/bash, then /bin/sh)
+        #[arg(long, value_name = "PATH")]
+        shell: Option<std::path::PathBuf>,
+        /// Start shells as login shells (-l) so .profile/.bashrc run
+        #[arg(long)]
+        login_shell: bool,
+        /// Don't force kerr's `user@kerr` prompt; start the shell with its rc files so your own prompt is kept
+        #[arg(long)]
+        no_prompt_override: bool,
+        /// Text file shown to clients when a shell session starts (message of the day)
+        #[arg(long, value_name = "PATH")]
+        motd_file: Option<std::path::PathBuf>,
+        /// New connections allowed per client per minute (0 = unlimited)
+        #[arg(long, default_value_t = kerr::server::DEFAULT_MAX_CONNECTIONS_PER_MINUTE)]
+        max_connections_per_minute: u32,
+        /// Print throughput, queue depth and backpressure stats every N seconds
+        #[arg(long, value_name = "SECS", num_args = 0..=1, default_missing_value = "10")]
+        profile: Option<u64>,
+        /// Which of this server's addresses to put in the connection string
+        #[arg(long, value_enum, default_value_t)]
+        advertise: kerr::server::AdvertiseMode,
+        /// Most client resizes applied to a shell per second (0 = no limit)
+        #[arg(long, default_value_t = kerr::DEFAULT_MAX_RESIZES_PER_SEC)]
+        max_resizes_per_sec: u32,
+        /// Require clients to enter this password or PIN before starting any session
+        #[arg(long, value_name = "PIN")]
+        password: Option<String>,
+        /// Only let clients browse and download: no shells, uploads or file changes
+        #[arg(long)]
+        read_only: bool,
+        /// Confine file browsing and transfers to this directory
+        #[arg(long, value_name = "DIR")]
+        root: Option<std::path::PathBuf>,
     },
     /// Connect to a Kerr server
     Connect {
-        /// Connection string from the server
+        /// Connection string from the server, or a registered alias
         connection_string: String,
+        /// Prefix prepended to window titles set by the remote shell (e.g. "kerr: ")
+        #[arg(long, conflicts_with = "ignore_remote_title")]
+        title_prefix: Option<String>,
+        /// Don't let the remote shell change the local window title
+        #[arg(long)]
+        ignore_remote_title: bool,
+        /// Directory on the server to start the shell in
+        #[arg(long)]
+        cwd: Option<String>,
+        /// Edit lines locally and send them on Enter, for laggy links (toggle with Alt+L)
+        #[arg(long)]
+        line_mode: bool,
+        /// Don't take over the terminal: pipe stdin to the shell and its output to stdout
+        #[arg(long, conflicts_with = "line_mode")]
+        no_raw: bool,
+        /// Most terminal resizes sent per second; later ones wait and only the last is sent (0 = no limit)
+        #[arg(long, default_value_t = kerr::DEFAULT_MAX_RESIZES_PER_SEC)]
+        max_resizes_per_sec: u32,
+        /// Record the session to an asciinema v2 file, playable with `asciinema play`
+        #[arg(long, value_name = "FILE.cast")]
+        record: Option<std::path::PathBuf>,
     },
     /// Send a file or directory to the server
     Send {
-        /// Connection string from the server
+        /// Connection string from the server, or a registered alias
         connection_string: String,
         /// Local file or directory path
         local_path: String,
@@ -45,38 +142,152 @@ enum Commands {
         /// Force overwrite without confirmation
         #[arg(short, long)]
         force: bool,
+        /// Preserve modification times (default: true for directories, false for files)
+        #[arg(long)]
+        preserve_times: Option<bool>,
+        /// Preserve Unix permission bits (ignored on platforms without them)
+        #[arg(long)]
+        preserve_perms: bool,
+        /// Copy the files that symlinks point to instead of recreating the links
+        #[arg(long, overrides_with = "no_dereference")]
+        dereference: bool,
+        /// Recreate symlinks as symlinks (the default)
+        #[arg(long, overrides_with = "dereference")]
+        no_dereference: bool,
+        /// Times to start over on a fresh connection if the connection drops (not for stdin)
+        #[arg(long, default_value = "3")]
+        retries: u32,
+        /// Cap the upload at this many bytes per second, e.g. 500k or 2M (0 = unlimited)
+        #[arg(long, value_name = "BYTES_PER_SEC", value_parser = kerr::transfer::parse_rate, default_value = "0")]
+        limit: u64,
     },
     /// Pull a file or directory from the server
     Pull {
-        /// Connection string from the server
+        /// Connection string from the server, or a registered alias
         connection_string: String,
         /// Remote file or directory path
         remote_path: String,
         /// Local destination path
         local_path: String,
+        /// Preserve modification times (default: true for directories, false for files)
+        #[arg(long)]
+        preserve_times: Option<bool>,
+        /// Preserve Unix permission bits (ignored on platforms without them)
+        #[arg(long)]
+        preserve_perms: bool,
+        /// What to do with files that already exist locally in a directory pull
+        #[arg(long, value_enum, default_value_t)]
+        on_conflict: kerr::client::ConflictPolicy,
+        /// Keep resume metadata in this directory (kerr's state directory if no value)
+        /// instead of next to the downloaded file
+        #[arg(long, value_name = "DIR")]
+        resume_dir: Option<Option<std::path::PathBuf>>,
+        /// Copy the files that symlinks point to instead of recreating the links
+        #[arg(long, overrides_with = "no_dereference")]
+        dereference: bool,
+        /// Recreate symlinks as symlinks (the default)
+        #[arg(long, overrides_with = "dereference")]
+        no_dereference: bool,
+        /// Pull a directory into a local directory that isn't empty
+        #[arg(long, alias = "force")]
+        merge: bool,
+        /// Times to resume on a fresh connection if the connection drops
+        #[arg(long, default_value = "3")]
+        retries: u32,
+        /// Cap the download at this many bytes per second, e.g. 500k or 2M (0 = unlimited)
+        #[arg(long, value_name = "BYTES_PER_SEC", value_parser = kerr::transfer::parse_rate, default_value = "0")]
+        limit: u64,
+    },
+    /// Run a command in a remote shell and exit with its exit code
+    Exec {
+        /// Connection string from the server, or a registered alias
+        connection_string: String,
+        /// Command line to run, e.g. "make test"
+        command: String,
+    },
+    /// Print a remote file to stdout (for piping into local tools)
+    Cat {
+        /// Connection string from the server, or a registered alias
+        connection_string: String,
+        /// Remote file path
+        remote_path: String,
+    },
+    /// Copy a small remote text file to the local clipboard
+    Clip {
+        /// Connection string from the server, or a registered alias
+        connection_string: String,
+        /// Remote file path
+        remote_path: String,
+        /// Largest file (bytes) to copy
+        #[arg(long, default_value_t = kerr::client::CLIP_MAX_SIZE)]
+        max_size: u64,
+    },
+    /// Check that a local file matches a remote one by comparing blake3 hashes
+    Verify {
+        /// Connection string from the server, or a registered alias
+        connection_string: String,
+        /// Remote file path
+        remote_path: String,
+        /// Local file path
+        local_path: String,
     },
     /// Browse the filesystem with an interactive TUI
     Browse {
-        /// Optional connection string to browse remote filesystem
+        /// Optional connection string (or registered alias) to browse remote filesystem
         connection_string: Option<String>,
+        /// Directory to start in when browsing the local filesystem
+        #[arg(long, conflicts_with = "connection_string")]
+        path: Option<std::path::PathBuf>,
     },
     /// Create a TCP relay proxy to forward local port to remote port
     Relay {
-        /// Connection string from the server
+        /// Connection string from the server, or a registered alias
         connection_string: String,
-        /// Local port to listen on
+        /// Local port to listen on (with --reverse: the server's port to listen on)
         local_port: u16,
-        /// Remote port to forward to
+        /// Remote port to forward to (with --reverse: the local port to forward to)
         remote_port: u16,
+        /// Most connections forwarded at once; as many more wait, the rest are refused
+        #[arg(long, default_value = "64", value_parser = clap::value_parser!(u64).range(1..))]
+        max_streams: u64,
+        /// Run without the traffic UI and stop once this file is removed (or `stop` is written to it)
+        #[arg(long, value_name = "PATH")]
+        keep_alive_file: Option<std::path::PathBuf>,
+        /// Relay UDP datagrams instead of TCP connections (each local peer counts as one stream)
+        #[arg(long)]
+        udp: bool,
+        /// Local address to listen on; anything but loopback lets other machines use the forward
+        #[arg(long, value_name = "ADDR", default_value = "127.0.0.1")]
+        bind: std::net::IpAddr,
+        /// Reverse the relay: the server listens on the first port and forwards to the second one here
+        #[arg(long, conflicts_with_all = ["udp", "bind"])]
+        reverse: bool,
     },
     /// Test network performance with increasing payload sizes
     Ping {
-        /// Connection string from the server
+        /// Connection string from the server, or a registered alias
+        connection_string: String,
+        /// Output format (default: table on a terminal, json when piped)
+        #[arg(long, value_enum)]
+        format: Option<kerr::client::PingFormat>,
+        /// Also time datagram echoes, to separate network latency from kerr's overhead
+        #[arg(long)]
+        datagram: bool,
+    },
+    /// Run diagnostic checks against a server and print a pass/fail report
+    Doctor {
+        /// Connection string from the server, or a registered alias
+        connection_string: String,
+    },
+    /// Check that bytes sent through an echo session come back unchanged (for CI)
+    #[command(hide = true)]
+    EchoTest {
+        /// Connection string from the server, or a registered alias
         connection_string: String,
     },
     /// Start a local HTTP/HTTPS proxy that relays traffic through the Kerr connection
     Proxy {
-        /// Connection string from the server
+        /// Connection string from the server, or a registered alias
         connection_string: String,
         /// Local port to listen on (default: 8080)
         #[arg(short, long, default_value = "8080")]
@@ -84,6 +295,12 @@ enum Commands {
         /// Also start a DNS server on port 53 (requires sudo/admin)
         #[arg(long)]
         dns: bool,
+        /// Stop once this file is removed (or `stop` is written to it)
+        #[arg(long, value_name = "PATH")]
+        keep_alive_file: Option<std::path::PathBuf>,
+        /// Local address to listen on; anything but loopback lets other machines use the proxy
+        #[arg(long, value_name = "ADDR", default_value = "127.0.0.1")]
+        bind: std::net::IpAddr,
     },
     /// Login with Google OAuth2
     Login,
@@ -91,6 +308,8 @@ enum Commands {
     Logout,
     /// List all registered connections
     Ls,
+    /// Print version and build information
+    Version,
     /// Start a web-based UI for remote file browsing and editing
     Ui {
         /// Optional connection string from the server (if not provided, will show connection selector)
@@ -105,8 +324,25 @@ enum Commands {
 async fn main() -> Result<()> {
     let cli = Cli::parse();
 
+    if cli.keep_alive == 0 || cli.keep_alive >= cli.idle_timeout {
+        return Err(n0_snafu::Error::anyhow(anyhow::anyhow!(
+            "--keep-alive must be at least 1 and less than --idle-timeout ({}s)",
+            cli.idle_timeout
+        )));
+    }
+    if cli.connect_timeout == 0 {
+        return Err(n0_snafu::Error::anyhow(anyhow::anyhow!("--connect-timeout must be at least 1")));
+    }
+    kerr::set_transport_settings(kerr::TransportSettings {
+        keep_alive: std::time::Duration::from_secs(cli.keep_alive),
+        max_idle_timeout: std::time::Duration::from_secs(cli.idle_timeout),
+        relay_url: cli.relay_url,
+        direct: !cli.no_direct,
+        connect_timeout: std::time::Duration::from_secs(cli.connect_timeout),
+    });
+
     match cli.command {
-        Commands::Serve { register, session, log } => {
+        Commands::Serve { register, session, log, url, pty_buffer, pty_coalesce_ms, log_commands, no_compression, max_file_read, max_upload, max_hash_size, config, shell, login_shell, no_prompt_override, motd_file, max_connections_per_minute, profile, advertise, max_resizes_per_sec, password, read_only, root } => {
             // Initialize logging if log file is specified
             // IMPORTANT: Keep _guard alive for the entire server lifetime
             let _guard = if let Some(log_file) = &log {
@@ -124,35 +360,120 @@ async fn main() -> Result<()> {
                 None
             };
 
-            kerr::server::run_server(register, session).await?;
+            // Client paths are checked against where the root really is
+            let root = root
+                .map(|dir| {
+                    std::fs::canonicalize(&dir)
+                        .ok()
+                        .filter(|real| real.is_dir())
+                        .ok_or_else(|| n0_snafu::Error::anyhow(anyhow::anyhow!("--root {} is not a directory", dir.display())))
+                })
+                .transpose()?;
+
+            let config = kerr::server::ServerConfig {
+                print_url: url,
+                pty_read_buffer: pty_buffer,
+                pty_coalesce: std::time::Duration::from_millis(pty_coalesce_ms),
+                log_commands,
+                raw_connection_string: no_compression,
+                max_file_read,
+                max_upload,
+                max_hash_size,
+                config_file: config,
+                shell,
+                login_shell,
+                no_prompt_override,
+                motd_file,
+                max_connections_per_minute: Some(max_connections_per_minute).filter(|&n| n > 0),
+                profile: profile.map(|secs| std::time::Duration::from_secs(secs.max(1))),
+                advertise,
+                max_resizes_per_sec,
+                password: password.map(|secret| kerr::server::PasswordGate::new(&secret)),
+                read_only,
+                root,
+            };
+            kerr::server::run_server(register, session, config).await?;
         }
-        Commands::Connect { connection_string } => {
-            kerr::client::run_client(connection_string).await?;
+        Commands::Connect { connection_string, title_prefix, ignore_remote_title, cwd, line_mode, no_raw, max_resizes_per_sec, record } => {
+            let connection_string = kerr::auth::resolve_connection_string(connection_string).await?;
+            let title_mode = if ignore_remote_title {
+                kerr::client::TitleMode::Ignore
+            } else if let Some(prefix) = title_prefix {
+                kerr::client::TitleMode::Prefix(prefix)
+            } else {
+                kerr::client::TitleMode::Passthrough
+            };
+            // The remote shell gets the local TERM so colours and keys match
+            let term = std::env::var("TERM").ok().filter(|term| !term.is_empty());
+            let options = kerr::client::ShellOptions { title_mode, cwd, line_mode, no_raw, max_resizes_per_sec, record, term };
+            kerr::client::run_client(connection_string, options).await?;
+        }
+        Commands::Send { connection_string, local_path, remote_path, force, preserve_times, preserve_perms, dereference, no_dereference: _, retries, limit } => {
+            let connection_string = kerr::auth::resolve_connection_string(connection_string).await?;
+            kerr::client::send_file(connection_string, local_path, remote_path, force, preserve_times, preserve_perms, dereference, retries, limit).await?;
+        }
+        Commands::Pull { connection_string, remote_path, local_path, preserve_times, preserve_perms, on_conflict, resume_dir, dereference, no_dereference: _, merge, retries, limit } => {
+            let connection_string = kerr::auth::resolve_connection_string(connection_string).await?;
+            let resume_dir = resume_dir.map(|dir| dir.unwrap_or_else(kerr::client::default_resume_dir));
+            kerr::client::pull_file(connection_string, remote_path, local_path, preserve_times, preserve_perms, on_conflict, resume_dir, dereference, merge, retries, limit).await?;
+        }
+        Commands::Exec { connection_string, command } => {
+            let connection_string = kerr::auth::resolve_connection_string(connection_string).await?;
+            let code = kerr::client::exec_command(connection_string, command).await?;
+            if code != 0 {
+                std::process::exit(code);
+            }
+        }
+        Commands::Cat { connection_string, remote_path } => {
+            let connection_string = kerr::auth::resolve_connection_string(connection_string).await?;
+            kerr::client::cat_file(connection_string, remote_path).await?;
         }
-        Commands::Send { connection_string, local_path, remote_path, force } => {
-            kerr::client::send_file(connection_string, local_path, remote_path, force).await?;
+        Commands::Clip { connection_string, remote_path, max_size } => {
+            let connection_string = kerr::auth::resolve_connection_string(connection_string).await?;
+            kerr::client::clip_file(connection_string, remote_path, max_size).await?;
         }
-        Commands::Pull { connection_string, remote_path, local_path } => {
-            kerr::client::pull_file(connection_string, remote_path, local_path).await?;
+        Commands::Verify { connection_string, remote_path, local_path } => {
+            let connection_string = kerr::auth::resolve_connection_string(connection_string).await?;
+            kerr::client::verify_file(connection_string, remote_path, local_path).await?;
         }
-        Commands::Browse { connection_string } => {
+        Commands::Browse { connection_string, path } => {
             if let Some(conn_str) = connection_string {
                 // Browse remote filesystem
+                let conn_str = kerr::auth::resolve_connection_string(conn_str).await?;
                 kerr::client::browse_remote(conn_str).await?;
             } else {
                 // Browse local filesystem
-                kerr::browser::run_browser()
+                kerr::browser::run_browser(path)
                     .map_err(|e| n0_snafu::Error::anyhow(anyhow::anyhow!("Browser error: {}", e)))?;
             }
         }
-        Commands::Relay { connection_string, local_port, remote_port } => {
-            kerr::client::run_tcp_relay(&connection_string, local_port, remote_port).await?;
+        Commands::Relay { connection_string, local_port, remote_port, max_streams, keep_alive_file, udp, bind, reverse } => {
+            let connection_string = kerr::auth::resolve_connection_string(connection_string).await?;
+            if reverse {
+                // The listening port comes first either way
+                let (listen_port, target_port) = (local_port, remote_port);
+                kerr::client::run_reverse_relay(&connection_string, listen_port, target_port, max_streams as usize, keep_alive_file.as_deref()).await?;
+            } else if udp {
+                kerr::client::run_udp_relay(&connection_string, bind, local_port, remote_port, max_streams as usize, keep_alive_file.as_deref()).await?;
+            } else {
+                kerr::client::run_tcp_relay(&connection_string, bind, local_port, remote_port, max_streams as usize, keep_alive_file.as_deref()).await?;
+            }
+        }
+        Commands::Ping { connection_string, format, datagram } => {
+            let connection_string = kerr::auth::resolve_connection_string(connection_string).await?;
+            kerr::client::ping_test(connection_string, format, datagram).await?;
         }
-        Commands::Ping { connection_string } => {
-            kerr::client::ping_test(connection_string).await?;
+        Commands::Doctor { connection_string } => {
+            let connection_string = kerr::auth::resolve_connection_string(connection_string).await?;
+            kerr::client::run_doctor(connection_string).await?;
         }
-        Commands::Proxy { connection_string, port, dns } => {
-            kerr::client::run_proxy(&connection_string, port, dns).await?;
+        Commands::EchoTest { connection_string } => {
+            let connection_string = kerr::auth::resolve_connection_string(connection_string).await?;
+            kerr::client::echo_test(connection_string).await?;
+        }
+        Commands::Proxy { connection_string, port, dns, keep_alive_file, bind } => {
+            let connection_string = kerr::auth::resolve_connection_string(connection_string).await?;
+            kerr::client::run_proxy(&connection_string, bind, port, dns, keep_alive_file.as_deref()).await?;
         }
         Commands::Login => {
             kerr::auth::login().await?;
@@ -163,6 +484,9 @@ async fn main() -> Result<()> {
         Commands::Ls => {
             // Fetch connections from backend
             let connections_response = kerr::auth::fetch_connections().await?;
+            if let Some(staleness) = connections_response.staleness() {
+                eprintln!("Registry unreachable - showing cached connections ({})", staleness);
+            }
 
             // Show the interactive list
             match kerr::connections_list::run_connections_list(connections_response.connections)? {
@@ -193,6 +517,12 @@ async fn main() -> Result<()> {
                 }
             }
         }
+        Commands::Version => {
+            println!("kerr {}", kerr::VERSION);
+            println!("  Target:   {}-{}", std::env::consts::OS, std::env::consts::ARCH);
+            println!("  Profile:  {}", if cfg!(debug_assertions) { "debug" } else { "release" });
+            println!("  Protocol: {}", String::from_utf8_lossy(kerr::ALPN));
+        }
         Commands::Ui { connection_string, port } => {
             kerr::web_ui::run_web_ui(connection_string, port).await
                 .map_err(|e| n0_snafu::Error::anyhow(anyhow::anyhow!("Web UI error: {}", e)))?;
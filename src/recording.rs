@@ -0,0 +1,132 @@
+//! Shell session recordings in asciinema's v2 format (`kerr connect --record`)
+//!
+//! The session only queues events; a writer thread turns them into JSON lines and
+//! writes them out, so a slow disk never holds up the terminal.
+
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::sync::mpsc;
+use std::time::Instant;
+
+enum Event {
+    Output(f64, Vec<u8>),
+    Resize(f64, u16, u16),
+    Finish,
+}
+
+/// Handle for adding events to a recording. Clones share the same file.
+#[derive(Clone)]
+pub struct Recorder {
+    tx: mpsc::Sender<Event>,
+    started: Instant,
+}
+
+impl Recorder {
+    /// Create `path` and write the header for a `cols`x`rows` terminal. The returned
+    /// thread writes the events and ends, with the file flushed, after `finish`.
+    pub fn start(path: &Path, cols: u16, rows: u16) -> io::Result<(Self, std::thread::JoinHandle<io::Result<()>>)> {
+        let mut file = BufWriter::new(File::create(path)?);
+        let timestamp = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        let header = serde_json::json!({
+            "version": 2,
+            "width": cols,
+            "height": rows,
+            "timestamp": timestamp,
+            "env": { "TERM": std::env::var("TERM").unwrap_or_else(|_| "xterm-256color".to_string()) },
+        });
+        writeln!(file, "{}", header)?;
+        file.flush()?;
+
+        let (tx, rx) = mpsc::channel();
+        let writer = std::thread::spawn(move || write_events(file, rx));
+        Ok((Self { tx, started: Instant::now() }, writer))
+    }
+
+    /// Record shell output as it arrived from the server
+    pub fn output(&self, data: &[u8]) {
+        let _ = self.tx.send(Event::Output(self.elapsed(), data.to_vec()));
+    }
+
+    /// Record a change of the local terminal size
+    pub fn resize(&self, cols: u16, rows: u16) {
+        let _ = self.tx.send(Event::Resize(self.elapsed(), cols, rows));
+    }
+
+    /// Write out everything recorded so far and close the file
+    pub fn finish(&self) {
+        let _ = self.tx.send(Event::Finish);
+    }
+
+    fn elapsed(&self) -> f64 {
+        self.started.elapsed().as_secs_f64()
+    }
+}
+
+/// Writer thread: one JSON line per event, flushed whenever the queue runs dry
+fn write_events(mut file: BufWriter<File>, rx: mpsc::Receiver<Event>) -> io::Result<()> {
+    // Output is written as text, so a UTF-8 sequence split between two chunks
+    // waits here for the rest of it
+    let mut pending: Vec<u8> = Vec::new();
+    while let Ok(event) = rx.recv() {
+        let mut next = Some(event);
+        while let Some(event) = next {
+            let line = match event {
+                Event::Output(time, data) => {
+                    pending.extend_from_slice(&data);
+                    let text = take_utf8(&mut pending);
+                    if text.is_empty() {
+                        None
+                    } else {
+                        Some(serde_json::json!([(time * 1e6).round() / 1e6, "o", text]))
+                    }
+                }
+                Event::Resize(time, cols, rows) => {
+                    Some(serde_json::json!([(time * 1e6).round() / 1e6, "r", format!("{}x{}", cols, rows)]))
+                }
+                Event::Finish => return file.flush(),
+            };
+            if let Some(line) = line {
+                writeln!(file, "{}", line)?;
+            }
+            next = rx.try_recv().ok();
+        }
+        file.flush()?;
+    }
+    file.flush()
+}
+
+/// Take the decodable text off the front of `bytes`, replacing invalid sequences and
+/// leaving an incomplete one at the end in place
+fn take_utf8(bytes: &mut Vec<u8>) -> String {
+    let mut text = String::new();
+    let mut start = 0;
+    loop {
+        match std::str::from_utf8(&bytes[start..]) {
+            Ok(valid) => {
+                text.push_str(valid);
+                start = bytes.len();
+                break;
+            }
+            Err(e) => {
+                let valid_end = start + e.valid_up_to();
+                text.push_str(std::str::from_utf8(&bytes[start..valid_end]).unwrap_or_default());
+                match e.error_len() {
+                    Some(len) => {
+                        text.push(char::REPLACEMENT_CHARACTER);
+                        start = valid_end + len;
+                    }
+                    None => {
+                        start = valid_end;
+                        break;
+                    }
+                }
+            }
+        }
+    }
+    bytes.drain(..start);
+    text
+}
@@ -1,14 +1,14 @@
 //! Kerr server - accepts incoming connections, creates PTY, and spawns bash
 
 use iroh::{
-    Endpoint,
     endpoint::Connection,
     protocol::{AcceptError, ProtocolHandler, Router},
 };
 use n0_snafu::{Result, ResultExt};
 use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
 use std::io::{IsTerminal, Write as IoWrite};
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use portable_pty::{native_pty_system, CommandBuilder, PtySize};
 use crate::{ClientMessage, ServerMessage, ALPN};
 use crate::debug_log;
@@ -54,22 +54,1251 @@ async fn unregister_from_backend(alias: String) -> Result<()> {
     crate::auth::unregister_connection(alias).await
 }
 
-pub async fn run_server(register_alias: Option<String>, session_path: Option<String>) -> Result<()> {
+/// Options for `kerr serve`
+#[derive(Debug, Clone)]
+pub struct ServerConfig {
+    /// Also print the connection string as a `kerr://connect/...` URL
+    pub print_url: bool,
+    /// Size of each PTY read
+    pub pty_read_buffer: usize,
+    /// How long to accumulate PTY output before sending one `Output` message
+    /// (zero disables coalescing)
+    pub pty_coalesce: std::time::Duration,
+    /// Log command lines typed in shell sessions (privacy-sensitive, off by default)
+    pub log_commands: bool,
+    /// Print the uncompressed `kerr-raw1:<json>` connection string instead of base64-of-gzip
+    pub raw_connection_string: bool,
+    /// Largest file the browser may read into memory (None = unlimited)
+    pub max_file_read: Option<u64>,
+    /// Largest upload accepted by browser and transfer sessions (None = unlimited)
+    pub max_upload: Option<u64>,
+    /// Largest file the browser may ask to hash (None = unlimited)
+    pub max_hash_size: Option<u64>,
+    /// JSON file overriding the runtime settings above; re-read on SIGHUP
+    pub config_file: Option<std::path::PathBuf>,
+    /// Shell started in PTYs (None = `$SHELL`, then `/bin/bash`, then `/bin/sh`)
+    pub shell: Option<std::path::PathBuf>,
+    /// Start the shell with `-l` so the user's startup files run, instead of a bare
+    /// `bash --norc --noprofile` with kerr's prompt
+    pub login_shell: bool,
+    /// Start a plain interactive shell that reads `~/.bashrc` and keeps the user's
+    /// prompt, instead of forcing kerr's. Login shells never get kerr's prompt.
+    pub no_prompt_override: bool,
+    /// Text file shown to clients when a shell session starts, re-read for every session
+    pub motd_file: Option<std::path::PathBuf>,
+    /// New connections allowed per remote node per minute, with bursts up to
+    /// the same number (None = unlimited)
+    pub max_connections_per_minute: Option<u32>,
+    /// Print PTY, queue and QUIC send stats this often (None = off)
+    pub profile: Option<std::time::Duration>,
+    /// Which of the endpoint's addresses go into the connection string
+    pub advertise: AdvertiseMode,
+    /// Most client resizes applied to a PTY per second (0 = no limit)
+    pub max_resizes_per_sec: u32,
+    /// Secret clients must send with `Auth` before any session starts (None = open)
+    pub password: Option<PasswordGate>,
+    /// Serve files for reading only: no shells, uploads or changes through the browser
+    pub read_only: bool,
+    /// Directory that browser and transfer sessions are confined to, already
+    /// canonicalized (None = the whole filesystem)
+    pub root: Option<std::path::PathBuf>,
+}
+
+/// Salted hash of the `--password` secret, so the server doesn't keep it in plain text
+#[derive(Clone)]
+pub struct PasswordGate {
+    salt: [u8; 32],
+    hash: blake3::Hash,
+}
+
+impl PasswordGate {
+    pub fn new(secret: &str) -> Self {
+        use rand::RngExt;
+
+        let salt: [u8; 32] = rand::rng().random();
+        Self { salt, hash: blake3::keyed_hash(&salt, secret.as_bytes()) }
+    }
+
+    /// Whether `secret` is the password (compared in constant time)
+    pub fn check(&self, secret: &str) -> bool {
+        blake3::keyed_hash(&self.salt, secret.as_bytes()) == self.hash
+    }
+}
+
+impl std::fmt::Debug for PasswordGate {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("PasswordGate(..)")
+    }
+}
+
+/// How long a wrong password waits before being answered, to slow down guessing
+const AUTH_FAILURE_DELAY: std::time::Duration = std::time::Duration::from_secs(1);
+
+/// Wrong passwords the server answers per minute across all connections, with bursts
+/// up to the same number
+const AUTH_FAILURES_PER_MINUTE: u32 = 10;
+
+/// Token bucket of wrong passwords shared by every connection. Node ids cost nothing
+/// to make, so limits per connection or per node don't stop guessing in parallel.
+/// Once the bucket is empty, every attempt waits for a token before it is checked.
+#[derive(Debug)]
+struct AuthThrottle {
+    per_minute: u32,
+    /// Tokens left and when they were last counted
+    bucket: tokio::sync::Mutex<(f64, std::time::Instant)>,
+}
+
+impl AuthThrottle {
+    fn new(per_minute: u32) -> Self {
+        Self {
+            per_minute,
+            bucket: tokio::sync::Mutex::new((per_minute as f64, std::time::Instant::now())),
+        }
+    }
+
+    /// Run `check` once a wrong answer is within budget; a wrong answer takes a token.
+    /// Attempts wait in turn, so a queue of guesses can't overdraw the bucket.
+    async fn check(&self, check: impl FnOnce() -> bool) -> bool {
+        let capacity = self.per_minute as f64;
+        let refill_per_sec = capacity / 60.0;
+
+        let mut bucket = self.bucket.lock().await;
+        loop {
+            let (tokens, updated) = &mut *bucket;
+            let now = std::time::Instant::now();
+            *tokens = (*tokens + now.duration_since(*updated).as_secs_f64() * refill_per_sec).min(capacity);
+            *updated = now;
+            if *tokens >= 1.0 {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_secs_f64((1.0 - *tokens) / refill_per_sec)).await;
+        }
+
+        let ok = check();
+        if !ok {
+            bucket.0 -= 1.0;
+        }
+        ok
+    }
+}
+
+impl Default for AuthThrottle {
+    fn default() -> Self {
+        Self::new(AUTH_FAILURES_PER_MINUTE)
+    }
+}
+
+/// Password attempts made on one connection. Attempts on its streams are checked
+/// one at a time, and after a wrong one every later attempt fails and the connection
+/// is closed, so a client can't try secrets in parallel on many streams.
+#[derive(Debug, Default)]
+struct AuthAttempts {
+    failed: tokio::sync::Mutex<bool>,
+    /// The server-wide budget of wrong passwords
+    throttle: Arc<AuthThrottle>,
+}
+
+impl AuthAttempts {
+    fn new(throttle: Arc<AuthThrottle>) -> Self {
+        Self { failed: tokio::sync::Mutex::default(), throttle }
+    }
+
+    /// Check `secret`, waiting `AUTH_FAILURE_DELAY` before answering a wrong one
+    async fn check(&self, gate: &PasswordGate, secret: &str) -> bool {
+        let mut failed = self.failed.lock().await;
+        let ok = !*failed && self.throttle.check(|| gate.check(secret)).await;
+        if !ok {
+            *failed = true;
+            tokio::time::sleep(AUTH_FAILURE_DELAY).await;
+        }
+        ok
+    }
+
+    fn has_failed(&self) -> bool {
+        self.failed.try_lock().is_ok_and(|failed| *failed)
+    }
+}
+
+/// Which of the server's addresses are put in its connection string.
+/// This only changes what clients are told up front; use `--no-direct` to keep
+/// the endpoint itself off direct paths.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum AdvertiseMode {
+    /// The relay and every IP address the endpoint found
+    #[default]
+    All,
+    /// The relay and public IP addresses, leaving out LAN and loopback ones
+    Public,
+    /// Only the relay, so the string reveals no IP address of this machine
+    Relay,
+    /// Only IP addresses, for clients that should connect directly
+    Direct,
+}
+
+impl AdvertiseMode {
+    /// The part of `addr` to advertise
+    pub fn filter(self, addr: iroh::EndpointAddr) -> iroh::EndpointAddr {
+        use iroh::TransportAddr;
+
+        let addrs = addr.addrs.into_iter().filter(|transport| match (self, transport) {
+            (AdvertiseMode::All, _) => true,
+            (AdvertiseMode::Public, TransportAddr::Ip(ip)) => is_public_ip(ip.ip()),
+            (AdvertiseMode::Public, _) => true,
+            (AdvertiseMode::Relay, transport) => transport.is_relay(),
+            (AdvertiseMode::Direct, transport) => transport.is_ip(),
+        });
+        iroh::EndpointAddr::from_parts(addr.id, addrs)
+    }
+}
+
+/// How long `--advertise relay` waits for the endpoint to reach its relay
+const ADVERTISE_RELAY_WAIT: std::time::Duration = std::time::Duration::from_secs(10);
+
+/// Whether `ip` can be reached from outside the local network
+fn is_public_ip(ip: std::net::IpAddr) -> bool {
+    match ip {
+        std::net::IpAddr::V4(v4) => {
+            !(v4.is_private() || v4.is_loopback() || v4.is_link_local() || v4.is_unspecified())
+        }
+        std::net::IpAddr::V6(v6) => match v6.to_ipv4_mapped() {
+            Some(v4) => is_public_ip(v4.into()),
+            None => !(v6.is_loopback() || v6.is_unique_local() || v6.is_unicast_link_local() || v6.is_unspecified()),
+        },
+    }
+}
+
+/// Runtime settings that can be set in the `--config` file.
+/// Anything left out keeps the value given on the command line.
+#[derive(Debug, Default, serde::Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct ServerConfigFile {
+    pty_buffer: Option<usize>,
+    pty_coalesce_ms: Option<u64>,
+    log_commands: Option<bool>,
+    max_file_read: Option<u64>,
+    max_upload: Option<u64>,
+    max_hash_size: Option<u64>,
+    login_shell: Option<bool>,
+    no_prompt_override: Option<bool>,
+    motd_file: Option<std::path::PathBuf>,
+    max_connections_per_minute: Option<u32>,
+    max_resizes_per_sec: Option<u32>,
+    read_only: Option<bool>,
+    shell: Option<std::path::PathBuf>,
+}
+
+impl ServerConfig {
+    /// Apply the overrides from `config_file`, if any, on top of these settings
+    fn with_config_file(&self) -> anyhow::Result<ServerConfig> {
+        let mut config = self.clone();
+        let Some(path) = &self.config_file else {
+            return Ok(config);
+        };
+
+        let data = std::fs::read_to_string(path)
+            .map_err(|e| anyhow::anyhow!("Failed to read config file {}: {}", path.display(), e))?;
+        let file: ServerConfigFile = serde_json::from_str(&data)
+            .map_err(|e| anyhow::anyhow!("Invalid config file {}: {}", path.display(), e))?;
+
+        if let Some(pty_buffer) = file.pty_buffer {
+            config.pty_read_buffer = pty_buffer;
+        }
+        if let Some(ms) = file.pty_coalesce_ms {
+            config.pty_coalesce = std::time::Duration::from_millis(ms);
+        }
+        if let Some(log_commands) = file.log_commands {
+            config.log_commands = log_commands;
+        }
+        if file.max_file_read.is_some() {
+            config.max_file_read = file.max_file_read;
+        }
+        if file.max_upload.is_some() {
+            config.max_upload = file.max_upload;
+        }
+        if file.max_hash_size.is_some() {
+            config.max_hash_size = file.max_hash_size;
+        }
+        if let Some(login_shell) = file.login_shell {
+            config.login_shell = login_shell;
+        }
+        if let Some(no_prompt_override) = file.no_prompt_override {
+            config.no_prompt_override = no_prompt_override;
+        }
+        if file.motd_file.is_some() {
+            config.motd_file = file.motd_file;
+        }
+        if let Some(per_minute) = file.max_connections_per_minute {
+            config.max_connections_per_minute = Some(per_minute).filter(|&n| n > 0);
+        }
+        if let Some(per_sec) = file.max_resizes_per_sec {
+            config.max_resizes_per_sec = per_sec;
+        }
+        if let Some(read_only) = file.read_only {
+            config.read_only = read_only;
+        }
+        if file.shell.is_some() {
+            config.shell = file.shell;
+        }
+        Ok(config)
+    }
+}
+
+/// Re-read the config file into the shared settings used for new sessions.
+/// Running sessions keep the settings they started with.
+fn reload_config(base: &ServerConfig, shared: &std::sync::RwLock<Arc<ServerConfig>>) {
+    let Some(path) = &base.config_file else {
+        tracing::info!("Received SIGHUP, but no --config file was given; nothing to reload");
+        return;
+    };
+
+    match base.with_config_file() {
+        Ok(config) => {
+            if config.log_commands {
+                tracing::warn!("Shell command logging is enabled");
+            }
+            tracing::info!(path = %path.display(), ?config, "Reloaded configuration");
+            if let Ok(mut shared) = shared.write() {
+                *shared = Arc::new(config);
+            }
+        }
+        Err(e) => {
+            tracing::error!(path = %path.display(), error = %e, "Failed to reload configuration, keeping the previous one");
+        }
+    }
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            print_url: false,
+            pty_read_buffer: 8192,
+            pty_coalesce: std::time::Duration::from_millis(3),
+            log_commands: false,
+            raw_connection_string: false,
+            max_file_read: None,
+            max_upload: None,
+            max_hash_size: None,
+            config_file: None,
+            shell: None,
+            login_shell: false,
+            no_prompt_override: false,
+            motd_file: None,
+            max_connections_per_minute: Some(DEFAULT_MAX_CONNECTIONS_PER_MINUTE),
+            profile: None,
+            advertise: AdvertiseMode::All,
+            max_resizes_per_sec: crate::DEFAULT_MAX_RESIZES_PER_SEC,
+            password: None,
+            read_only: false,
+            root: None,
+        }
+    }
+}
+
+/// Default for `--max-connections-per-minute`: generous for people, tight for reconnect loops
+pub const DEFAULT_MAX_CONNECTIONS_PER_MINUTE: u32 = 60;
+
+/// Stop remembering idle nodes once this many are tracked
+const MAX_TRACKED_NODES: usize = 4096;
+
+/// Token bucket per remote node, limiting how fast it may open connections
+#[derive(Debug, Default)]
+struct ConnectionLimiter {
+    buckets: std::collections::HashMap<iroh::PublicKey, ConnectionBucket>,
+}
+
+#[derive(Debug)]
+struct ConnectionBucket {
+    tokens: f64,
+    updated: std::time::Instant,
+    /// Whether the last attempt was rejected, so only the first one is logged
+    limited: bool,
+}
+
+/// Outcome of `ConnectionLimiter::check`
+enum RateDecision {
+    Allow,
+    /// `first` is true for the first rejection of a burst
+    Reject { first: bool },
+}
+
+impl ConnectionLimiter {
+    fn check(&mut self, node: iroh::PublicKey, per_minute: u32) -> RateDecision {
+        let now = std::time::Instant::now();
+        let capacity = per_minute as f64;
+        let refill_per_sec = capacity / 60.0;
+
+        if self.buckets.len() >= MAX_TRACKED_NODES {
+            // Full buckets carry no state worth keeping
+            self.buckets.retain(|_, b| {
+                b.tokens + now.duration_since(b.updated).as_secs_f64() * refill_per_sec < capacity
+            });
+        }
+
+        let bucket = self.buckets.entry(node).or_insert(ConnectionBucket {
+            tokens: capacity,
+            updated: now,
+            limited: false,
+        });
+        let elapsed = now.duration_since(bucket.updated).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * refill_per_sec).min(capacity);
+        bucket.updated = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            bucket.limited = false;
+            RateDecision::Allow
+        } else {
+            let first = !bucket.limited;
+            bucket.limited = true;
+            RateDecision::Reject { first }
+        }
+    }
+}
+
+/// The message of the day as terminal output (LF turned into CRLF), if one is configured
+fn motd_output(config: &ServerConfig) -> Option<Vec<u8>> {
+    let path = config.motd_file.as_ref()?;
+    let text = match std::fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(e) => {
+            tracing::warn!(path = %path.display(), error = %e, "Failed to read MOTD file");
+            return None;
+        }
+    };
+    if text.trim().is_empty() {
+        return None;
+    }
+
+    let mut data = text.trim_end().replace("\r\n", "\n").replace('\n', "\r\n");
+    data.push_str("\r\n\r\n");
+    Some(data.into_bytes())
+}
+
+/// Exit code to report for an exec'd command; one killed by a signal reports
+/// 128 + the signal number, as shells do
+fn exit_status_code(status: std::process::ExitStatus) -> i32 {
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::ExitStatusExt;
+        if let Some(signal) = status.signal() {
+            return 128 + signal;
+        }
+    }
+    status.code().unwrap_or(-1)
+}
+
+/// What to tell the client when its input can't be written to the PTY, and whether
+/// the session has to end. A closed PTY (the shell is gone) ends it; other errors
+/// only lose that piece of input.
+fn pty_write_failure(e: &std::io::Error) -> (String, bool) {
+    // The master side reports EIO once nothing holds the other end open
+    let closed = e.kind() == std::io::ErrorKind::BrokenPipe || (cfg!(unix) && e.raw_os_error() == Some(5));
+    if closed {
+        ("Session ended: failed to write to shell, it has exited".to_string(), true)
+    } else {
+        (format!("failed to write to shell: {}", e), false)
+    }
+}
+
+/// The shell to start: `--shell`, else `$SHELL`, else bash or sh from /bin
+fn shell_path(config: &ServerConfig) -> PathBuf {
+    if let Some(shell) = &config.shell {
+        return shell.clone();
+    }
+    if let Some(shell) = std::env::var_os("SHELL").filter(|shell| !shell.is_empty()) {
+        return PathBuf::from(shell);
+    }
+    if Path::new("/bin/bash").exists() {
+        PathBuf::from("/bin/bash")
+    } else {
+        PathBuf::from("/bin/sh")
+    }
+}
+
+/// Command for a new shell session's PTY
+fn shell_command(config: &ServerConfig) -> CommandBuilder {
+    let shell = shell_path(config);
+    let mut cmd = CommandBuilder::new(&shell);
+    if config.login_shell {
+        // The user's own startup files decide PATH, aliases and prompt
+        cmd.arg("-l");
+        return cmd;
+    }
+    // Other shells have their own ways of setting the prompt, so they keep theirs
+    if config.no_prompt_override || shell.file_name() != Some(std::ffi::OsStr::new("bash")) {
+        return cmd;
+    }
+
+    // Bash keeps a PS1 it inherits when no startup file replaces it
+    let username = std::env::var("USER").unwrap_or_else(|_| "user".to_string());
+    cmd.env("PS1", format!("{}@kerr \\w> ", username));
+    cmd.arg("--norc");
+    cmd.arg("--noprofile");
+    cmd
+}
+
+/// Datagrams queued for one UDP relay stream; past this, new ones are dropped
+/// the way a busy network would drop them
+const UDP_STREAM_QUEUE: usize = 256;
+
+/// A UDP socket connected to `host:port`, bound on a matching address family
+async fn connect_udp(host: &str, port: u16) -> std::io::Result<tokio::net::UdpSocket> {
+    let target = tokio::net::lookup_host((host, port))
+        .await?
+        .next()
+        .ok_or_else(|| std::io::Error::other(format!("no address found for {}", host)))?;
+    let bind_addr: std::net::SocketAddr = if target.is_ipv4() {
+        (std::net::Ipv4Addr::UNSPECIFIED, 0).into()
+    } else {
+        (std::net::Ipv6Addr::UNSPECIFIED, 0).into()
+    };
+    let socket = tokio::net::UdpSocket::bind(bind_addr).await?;
+    socket.connect(target).await?;
+    Ok(socket)
+}
+
+/// Checked between download chunks: whether the client has cancelled the download
+/// or gone away. Anything else it sent meanwhile is kept in `deferred` for later.
+fn download_cancelled(
+    incoming: &mut tokio::sync::mpsc::UnboundedReceiver<crate::ClientMessage>,
+    deferred: &mut std::collections::VecDeque<crate::ClientMessage>,
+) -> bool {
+    loop {
+        match incoming.try_recv() {
+            Ok(crate::ClientMessage::CancelDownload) => return true,
+            Ok(crate::ClientMessage::Disconnect) => {
+                deferred.push_back(crate::ClientMessage::Disconnect);
+                return true;
+            }
+            Ok(msg) => deferred.push_back(msg),
+            Err(tokio::sync::mpsc::error::TryRecvError::Empty) => return false,
+            Err(tokio::sync::mpsc::error::TryRecvError::Disconnected) => return true,
+        }
+    }
+}
+
+/// Called after queueing a download chunk: wait for the stream's send task to write
+/// another envelope, and keep waiting while its queue is longer than
+/// `DOWNLOAD_QUEUE_LIMIT`. False once the send task has ended.
+async fn wait_for_send_queue(queue_depth: &mut tokio::sync::watch::Receiver<usize>) -> bool {
+    loop {
+        if queue_depth.changed().await.is_err() {
+            return false;
+        }
+        if *queue_depth.borrow_and_update() <= DOWNLOAD_QUEUE_LIMIT {
+            return true;
+        }
+    }
+}
+
+/// Flush coalesced PTY output once this many bytes are buffered
+const PTY_COALESCE_MAX_BYTES: usize = 64 * 1024;
+
+/// Reopen a partly uploaded file for appending, keeping its first `offset` bytes
+/// (anything past what the client counted is dropped). Returns `None` when the file
+/// is shorter than that or its first `offset` bytes don't hash to `hash`, i.e. they
+/// aren't from the upload being resumed.
+fn reopen_partial_upload(path: &Path, offset: u64, hash: &str) -> std::io::Result<Option<std::fs::File>> {
+    use std::io::Read;
+
+    let mut file = std::fs::OpenOptions::new().read(true).append(true).open(path)?;
+    if file.metadata()?.len() < offset {
+        return Ok(None);
+    }
+    let mut hasher = blake3::Hasher::new();
+    hasher.update_reader((&mut file).take(offset))?;
+    if hasher.finalize().to_hex().as_str() != hash {
+        return Ok(None);
+    }
+    file.set_len(offset)?;
+    Ok(Some(file))
+}
+
+/// Try to continue an interrupted upload of `path` (see `reopen_partial_upload`),
+/// logging why not when it has to start over instead
+fn resume_partial_upload(session_id: &str, path: &str, offset: u64, hash: &str) -> Option<std::fs::File> {
+    match reopen_partial_upload(Path::new(path), offset, hash) {
+        Ok(Some(file)) => {
+            tracing::info!(session_id = %session_id, path = %path, offset = offset, "Resuming upload");
+            Some(file)
+        }
+        Ok(None) => {
+            tracing::info!(session_id = %session_id, path = %path,
+                "Partial upload doesn't match the client's file, starting over");
+            None
+        }
+        Err(e) => {
+            tracing::warn!(session_id = %session_id, path = %path, error = %e,
+                "Can't resume upload, starting over");
+            None
+        }
+    }
+}
+
+/// Flush and close an upload's file, if one is open. Data written with
+/// `write_all` can still fail to reach the disk (e.g. quota or NFS), which
+/// only shows up here.
+fn close_upload_file(file: &mut Option<std::fs::File>) -> std::result::Result<(), String> {
+    match file.take() {
+        Some(file) => file.sync_all().map_err(|e| format!("Failed to flush file to disk: {}", e)),
+        None => Ok(()),
+    }
+}
+
+/// Error message for a file that exceeds a configured size limit
+fn size_limit_message(what: &str, size: u64, limit: u64) -> String {
+    format!("{} is too large ({} bytes, server limit is {} bytes)", what, size, limit)
+}
+
+/// Why a `--read-only` server turned a request down
+fn read_only_message(what: &str) -> String {
+    format!("{} refused: this server is read-only", what)
+}
+
+/// Resolve a path from a client against the `--root` jail. Relative paths start at
+/// `root` (which must be canonical), and the result, with `..` and symlinks resolved,
+/// has to stay inside it. The missing part of a path that doesn't exist yet may not
+/// contain `..` or start at a dangling symlink. Without `follow_last` the final
+/// component is kept as is, so deleting or renaming a symlink acts on the link; such a
+/// path may not be the root itself.
+fn confine_path(root: &Path, path: &Path, follow_last: bool) -> Result<std::path::PathBuf, String> {
+    use std::path::Component;
+
+    let outside = || format!("Path is outside the server root: {}", path.display());
+    let requested = root.join(path);
+    let (dir, name) = if follow_last {
+        (requested.as_path(), None)
+    } else {
+        match (requested.parent(), requested.file_name()) {
+            (Some(dir), Some(name)) => (dir, Some(name)),
+            _ => return Err(outside()),
+        }
+    };
+
+    // Resolve the longest part of the path that exists
+    let mut existing = dir;
+    let real = loop {
+        match std::fs::canonicalize(existing) {
+            Ok(real) => break real,
+            Err(_) => existing = existing.parent().ok_or_else(outside)?,
+        }
+    };
+    let missing = dir.strip_prefix(existing).map_err(|_| outside())?;
+    if missing.components().any(|c| !matches!(c, Component::Normal(_)))
+        || missing.components().next().is_some_and(|first| real.join(first).symlink_metadata().is_ok())
+    {
+        return Err(outside());
+    }
+
+    let mut resolved = real;
+    if !missing.as_os_str().is_empty() {
+        resolved.push(missing);
+    }
+    if let Some(name) = name {
+        resolved.push(name);
+    }
+    if !resolved.starts_with(root) || (name.is_some() && resolved == root) {
+        return Err(outside());
+    }
+    Ok(resolved)
+}
+
+/// With a `--root`, swap the paths in a browser or transfer request for their real
+/// location inside it, or say why the request is refused. Paths within a directory
+/// upload are checked as their files arrive.
+fn confine_request(root: Option<&Path>, msg: crate::ClientMessage) -> Result<crate::ClientMessage, String> {
+    use crate::{ClientMessage, FsOp};
+
+    let Some(root) = root else {
+        return Ok(msg);
+    };
+    let confine = |path: &str, follow_last: bool| {
+        confine_path(root, Path::new(path), follow_last).map(|p| p.to_string_lossy().to_string())
+    };
+
+    Ok(match msg {
+        ClientMessage::FsReadDir { path } => ClientMessage::FsReadDir { path: confine(&path, true)? },
+        ClientMessage::FsMetadata { path } => ClientMessage::FsMetadata { path: confine(&path, true)? },
+        ClientMessage::FsReadFile { path } => ClientMessage::FsReadFile { path: confine(&path, true)? },
+        ClientMessage::FsHashFile { path } => ClientMessage::FsHashFile { path: confine(&path, true)? },
+        ClientMessage::FsMkdir { path } => ClientMessage::FsMkdir { path: confine(&path, true)? },
+        ClientMessage::FsWriteFile { path, data } => ClientMessage::FsWriteFile { path: confine(&path, true)?, data },
+        ClientMessage::FsDelete { path } => ClientMessage::FsDelete { path: confine(&path, false)? },
+        ClientMessage::FsDeleteRecursive { path } => ClientMessage::FsDeleteRecursive { path: confine(&path, false)? },
+        ClientMessage::FsRename { from, to } => ClientMessage::FsRename {
+            from: confine(&from, false)?,
+            to: confine(&to, false)?,
+        },
+        ClientMessage::FsBatch { ops } => ClientMessage::FsBatch {
+            ops: ops
+                .into_iter()
+                .map(|op| {
+                    Ok(match op {
+                        FsOp::Mkdir { path } => FsOp::Mkdir { path: confine(&path, true)? },
+                        FsOp::Delete { path } => FsOp::Delete { path: confine(&path, false)? },
+                        FsOp::Rename { from, to } => FsOp::Rename { from: confine(&from, false)?, to: confine(&to, false)? },
+                        FsOp::Chmod { path, mode } => FsOp::Chmod { path: confine(&path, true)?, mode },
+                    })
+                })
+                .collect::<Result<_, String>>()?,
+        },
+        ClientMessage::StartUpload { path, size, is_dir, force } => {
+            ClientMessage::StartUpload { path: confine(&path, true)?, size, is_dir, force }
+        }
+        ClientMessage::RequestDownload { path, offset } => {
+            ClientMessage::RequestDownload { path: confine(&path, true)?, offset }
+        }
+        msg => msg,
+    })
+}
+
+/// Where an entry of a directory upload goes, kept inside the `--root` if there is one
+fn upload_entry_path(
+    root: Option<&Path>,
+    base_path: &str,
+    relative_path: &str,
+    follow_last: bool,
+) -> Result<std::path::PathBuf, String> {
+    let full_path = Path::new(base_path).join(relative_path);
+    match root {
+        Some(root) => confine_path(root, &full_path, follow_last),
+        None => Ok(full_path),
+    }
+}
+
+/// Build the response to an `FsHashFile` request, streaming the file through the
+/// hasher so memory use doesn't grow with the file. Blocks; run it off the runtime.
+fn hash_response(path: &str, limit: Option<u64>) -> crate::ServerMessage {
+    let hash = std::fs::File::open(Path::new(path)).and_then(|file| {
+        if let Some(limit) = limit {
+            let size = file.metadata()?.len();
+            if size > limit {
+                return Err(std::io::Error::other(size_limit_message("File", size, limit)));
+            }
+        }
+        let mut hasher = blake3::Hasher::new();
+        hasher.update_reader(file)?;
+        Ok(hasher.finalize())
+    });
+
+    match hash {
+        Ok(hash) => crate::ServerMessage::FsHashResponse { hash: hash.to_hex().to_string() },
+        Err(e) => crate::ServerMessage::FsError {
+            message: format!("Failed to hash file: {}", e),
+        },
+    }
+}
+
+/// Build the response to an `FsMetadata` request
+/// A missing path yields `FsError`, which clients use as the "does not exist" answer.
+fn metadata_response(path: &str) -> crate::ServerMessage {
+    use crate::custom_explorer::file_explorer::FileMetadata;
+
+    match std::fs::metadata(Path::new(path)) {
+        Ok(metadata) => {
+            let file_metadata = FileMetadata {
+                size: metadata.len(),
+                created: metadata.created().ok(),
+                modified: metadata.modified().ok(),
+                is_dir: metadata.is_dir(),
+            };
+
+            let metadata_json = serde_json::to_string(&file_metadata).unwrap();
+            crate::ServerMessage::FsMetadataResponse { metadata_json }
+        }
+        Err(e) => crate::ServerMessage::FsError {
+            message: format!("Failed to get metadata: {}", e),
+        },
+    }
+}
+
+/// Delete a file or symlink, or a directory. Without `recursive` only an empty
+/// directory is removed, so a stray request can't take a whole tree with it.
+fn delete_path(path: &Path, recursive: bool) -> std::io::Result<()> {
+    let is_dir = std::fs::symlink_metadata(path).is_ok_and(|m| m.is_dir());
+    if !is_dir {
+        std::fs::remove_file(path)
+    } else if recursive {
+        std::fs::remove_dir_all(path)
+    } else {
+        std::fs::remove_dir(path)
+    }
+}
+
+/// Replace the file at `path` with `data` for an `FsWriteFile` request. The data goes to
+/// a temporary file next to it first and is renamed over it, so readers never see a
+/// half-written file. An existing file keeps its permissions.
+fn write_file_atomic(path: &Path, data: &[u8]) -> std::io::Result<()> {
+    use std::io::Write;
+
+    let existing = std::fs::metadata(path).ok();
+    if existing.as_ref().is_some_and(|m| m.is_dir()) {
+        return Err(std::io::Error::other(format!("{} is a directory", path.display())));
+    }
+    let parent = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    std::fs::create_dir_all(parent)?;
+
+    let name = path.file_name().map(|n| n.to_string_lossy()).unwrap_or_default();
+    let temp = parent.join(format!(".{}.kerr-{:08x}.tmp", name, rand::random::<u32>()));
+    let result = (|| {
+        let mut file = std::fs::File::create(&temp)?;
+        file.write_all(data)?;
+        file.sync_all()?;
+        if let Some(metadata) = &existing {
+            std::fs::set_permissions(&temp, metadata.permissions())?;
+        }
+        std::fs::rename(&temp, path)
+    })();
+    if result.is_err() {
+        let _ = std::fs::remove_file(&temp);
+    }
+    result
+}
+
+/// Execute one operation from an `FsBatch` request
+fn apply_fs_op(op: &crate::FsOp) -> crate::FsOpResult {
+    let result = match op {
+        crate::FsOp::Mkdir { path } => crate::custom_explorer::filesystem::make_dir(Path::new(path)),
+        // Like `FsDelete`: a directory tree needs `FsDeleteRecursive` and its confirmation
+        crate::FsOp::Delete { path } => delete_path(Path::new(path), false),
+        crate::FsOp::Rename { from, to } => crate::custom_explorer::filesystem::move_path(Path::new(from), Path::new(to)),
+        crate::FsOp::Chmod { path, mode } => {
+            crate::transfer::set_mode_bits(Path::new(path), *mode)
+                .map_err(|e| std::io::Error::other(e.to_string()))
+        }
+    };
+
+    match result {
+        Ok(()) => crate::FsOpResult { success: true, error: None },
+        Err(e) => crate::FsOpResult { success: false, error: Some(e.to_string()) },
+    }
+}
+
+/// Reconstructs typed command lines from raw shell keystrokes for `--log-commands`
+///
+/// This is a heuristic: it understands backspace, Ctrl+U/Ctrl+W/Ctrl+C and skips
+/// escape sequences, but can't see tab completion or history recall.
+#[derive(Default)]
+struct CommandLineBuffer {
+    line: Vec<u8>,
+    in_escape: bool,
+    in_csi: bool,
+}
+
+impl CommandLineBuffer {
+    /// Feed keystrokes and return any lines completed by Enter
+    fn feed(&mut self, data: &[u8]) -> Vec<String> {
+        let mut lines = Vec::new();
+
+        for &b in data {
+            if self.in_csi {
+                // CSI sequences end with a byte in 0x40..=0x7e
+                if (0x40..=0x7e).contains(&b) {
+                    self.in_csi = false;
+                }
+                continue;
+            }
+            if self.in_escape {
+                self.in_escape = false;
+                if b == b'[' || b == b'O' {
+                    self.in_csi = true;
+                }
+                continue;
+            }
+
+            match b {
+                0x1b => self.in_escape = true,
+                b'\r' | b'\n' => {
+                    let line = String::from_utf8_lossy(&self.line).trim().to_string();
+                    self.line.clear();
+                    if !line.is_empty() {
+                        lines.push(line);
+                    }
+                }
+                // Backspace / DEL: drop the last (possibly multi-byte) character
+                0x7f | 0x08 => {
+                    while let Some(last) = self.line.pop() {
+                        if last & 0xc0 != 0x80 {
+                            break;
+                        }
+                    }
+                }
+                // Ctrl+C and Ctrl+U discard the line
+                0x03 | 0x15 => self.line.clear(),
+                // Ctrl+W deletes the previous word
+                0x17 => {
+                    while self.line.last() == Some(&b' ') {
+                        self.line.pop();
+                    }
+                    while matches!(self.line.last(), Some(c) if *c != b' ') {
+                        self.line.pop();
+                    }
+                }
+                b if b < 0x20 => {}
+                b => self.line.push(b),
+            }
+        }
+
+        lines
+    }
+}
+
+/// What a client's handshake asked of a new shell session
+#[derive(Default)]
+struct ShellStart {
+    /// Directory to start in (None = the server's working directory)
+    cwd: Option<String>,
+    /// `TERM` for the shell (None = xterm-256color)
+    term: Option<String>,
+    /// Run this with `<shell> -c` on pipes instead of starting an interactive shell
+    command: Option<String>,
+}
+
+/// `TERM` for a shell whose client didn't send a usable one
+const DEFAULT_TERM: &str = "xterm-256color";
+
+/// Whether `term` looks like a terminal type name, so odd values never reach the
+/// shell's environment
+fn valid_term(term: &str) -> bool {
+    !term.is_empty()
+        && term.len() <= 64
+        && term.chars().all(|c| c.is_ascii_alphanumeric() || "-_.+".contains(c))
+}
+
+/// Output read from the PTY by the blocking reader thread
+enum PtyOutput {
+    Data(Vec<u8>),
+    /// The shell closed the PTY
+    Eof,
+    /// The shell's exit code, sent just before `Eof`
+    Exit(i32),
+}
+
+/// How often a download still being enumerated reports `Preparing` to the client
+const PREPARING_INTERVAL: std::time::Duration = std::time::Duration::from_secs(1);
+
+/// A send that blocks this long is counted as QUIC backpressure
+const BACKPRESSURE_THRESHOLD: std::time::Duration = std::time::Duration::from_millis(20);
+
+/// Most envelopes a download lets pile up in its stream's send queue. Past that it
+/// waits for the wire, so a big file isn't read into memory ahead of the network
+/// and a `CancelDownload` is seen while there's still something left to stop.
+const DOWNLOAD_QUEUE_LIMIT: usize = 8;
+
+/// Process-wide counters reported by `--profile`. Always updated; they are
+/// cheap relaxed atomics, so the flag only controls the printing.
+struct ServerStats {
+    pty_bytes_read: AtomicU64,
+    quic_bytes_written: AtomicU64,
+    /// Envelope sends that took longer than `BACKPRESSURE_THRESHOLD`
+    backpressure_events: AtomicU64,
+    next_stream_id: AtomicU64,
+    streams: std::sync::Mutex<std::collections::BTreeMap<u64, Arc<StreamStats>>>,
+}
+
+static STATS: ServerStats = ServerStats {
+    pty_bytes_read: AtomicU64::new(0),
+    quic_bytes_written: AtomicU64::new(0),
+    backpressure_events: AtomicU64::new(0),
+    next_stream_id: AtomicU64::new(0),
+    streams: std::sync::Mutex::new(std::collections::BTreeMap::new()),
+};
+
+type SessionMap = Arc<tokio::sync::Mutex<std::collections::HashMap<
+    String,
+    tokio::sync::mpsc::UnboundedSender<crate::ClientMessage>
+>>>;
+
+/// Heartbeat interval used when `KERR_HEARTBEAT_SECS` isn't set
+const DEFAULT_HEARTBEAT_INTERVAL: std::time::Duration = std::time::Duration::from_secs(10);
+
+/// How often a session that asked for `Heartbeats` gets one: `KERR_HEARTBEAT_SECS`
+/// seconds, or `DEFAULT_HEARTBEAT_INTERVAL`. Zero turns heartbeats off.
+fn heartbeat_interval() -> Option<std::time::Duration> {
+    match std::env::var("KERR_HEARTBEAT_SECS").ok().and_then(|secs| secs.trim().parse::<u64>().ok()) {
+        Some(0) => None,
+        Some(secs) => Some(std::time::Duration::from_secs(secs)),
+        None => Some(DEFAULT_HEARTBEAT_INTERVAL),
+    }
+}
+
+/// Queue a `Heartbeat` for `session_id` every `interval` until the session ends.
+/// They share the stream's outgoing queue, so a stuck stream holds them back too.
+async fn send_heartbeats(
+    session_id: String,
+    interval: std::time::Duration,
+    sessions: SessionMap,
+    outgoing: tokio::sync::mpsc::UnboundedSender<crate::MessageEnvelope>,
+) {
+    let mut ticker = tokio::time::interval(interval);
+    ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    ticker.tick().await;
+    loop {
+        ticker.tick().await;
+        if !sessions.lock().await.contains_key(&session_id) {
+            break;
+        }
+        let heartbeat = crate::MessageEnvelope {
+            session_id: session_id.clone(),
+            payload: crate::MessagePayload::Server(crate::ServerMessage::Heartbeat {
+                interval_ms: interval.as_millis() as u64,
+            }),
+        };
+        if outgoing.send(heartbeat).is_err() {
+            break;
+        }
+    }
+}
+
+/// Outgoing queue of one envelope stream, shared by the sessions on it
+struct StreamStats {
+    node_id: iroh::PublicKey,
+    sessions: SessionMap,
+    queue_depth: AtomicUsize,
+    /// Deepest queue since the last report
+    max_queue_depth: AtomicUsize,
+}
+
+impl StreamStats {
+    fn note_queue_depth(&self, depth: usize) {
+        self.queue_depth.store(depth, Ordering::Relaxed);
+        self.max_queue_depth.fetch_max(depth, Ordering::Relaxed);
+    }
+}
+
+/// Removes a stream from `STATS` when its handler returns
+struct StreamStatsGuard {
+    id: u64,
+    stats: Arc<StreamStats>,
+}
+
+impl StreamStatsGuard {
+    fn register(node_id: iroh::PublicKey, sessions: SessionMap) -> Self {
+        let id = STATS.next_stream_id.fetch_add(1, Ordering::Relaxed);
+        let stats = Arc::new(StreamStats {
+            node_id,
+            sessions,
+            queue_depth: AtomicUsize::new(0),
+            max_queue_depth: AtomicUsize::new(0),
+        });
+        if let Ok(mut streams) = STATS.streams.lock() {
+            streams.insert(id, stats.clone());
+        }
+        Self { id, stats }
+    }
+}
+
+impl Drop for StreamStatsGuard {
+    fn drop(&mut self) {
+        if let Ok(mut streams) = STATS.streams.lock() {
+            streams.remove(&self.id);
+        }
+    }
+}
+
+/// Counts the bytes written through to the QUIC stream
+struct CountingWrite<W> {
+    inner: W,
+    bytes: &'static AtomicU64,
+}
+
+impl<W: tokio::io::AsyncWrite + Unpin> tokio::io::AsyncWrite for CountingWrite<W> {
+    fn poll_write(
+        mut self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+        buf: &[u8],
+    ) -> std::task::Poll<std::io::Result<usize>> {
+        let poll = std::pin::Pin::new(&mut self.inner).poll_write(cx, buf);
+        if let std::task::Poll::Ready(Ok(n)) = &poll {
+            self.bytes.fetch_add(*n as u64, Ordering::Relaxed);
+        }
+        poll
+    }
+
+    fn poll_flush(mut self: std::pin::Pin<&mut Self>, cx: &mut std::task::Context<'_>) -> std::task::Poll<std::io::Result<()>> {
+        std::pin::Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: std::pin::Pin<&mut Self>, cx: &mut std::task::Context<'_>) -> std::task::Poll<std::io::Result<()>> {
+        std::pin::Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+fn format_rate(bytes: u64, elapsed: std::time::Duration) -> String {
+    let per_sec = bytes as f64 / elapsed.as_secs_f64().max(0.001);
+    if per_sec >= 1024.0 * 1024.0 {
+        format!("{:.2} MB/s", per_sec / (1024.0 * 1024.0))
+    } else {
+        format!("{:.1} KB/s", per_sec / 1024.0)
+    }
+}
+
+/// Print the `--profile` stats every `interval`, until the server shuts down.
+/// Lines go to stdout and, at info level, to the log under `kerr::profile`.
+async fn report_stats(interval: std::time::Duration) {
+    let mut ticker = tokio::time::interval(interval);
+    ticker.tick().await;
+    let mut last_pty = STATS.pty_bytes_read.load(Ordering::Relaxed);
+    let mut last_quic = STATS.quic_bytes_written.load(Ordering::Relaxed);
+    let mut last_backpressure = STATS.backpressure_events.load(Ordering::Relaxed);
+    let mut last_tick = std::time::Instant::now();
+
+    loop {
+        ticker.tick().await;
+        let elapsed = last_tick.elapsed();
+        last_tick = std::time::Instant::now();
+
+        let pty = STATS.pty_bytes_read.load(Ordering::Relaxed);
+        let quic = STATS.quic_bytes_written.load(Ordering::Relaxed);
+        let backpressure = STATS.backpressure_events.load(Ordering::Relaxed);
+
+        println!(
+            "[PROFILE] pty read {} ({}), quic written {} ({}), backpressure events {} (+{})\r",
+            pty, format_rate(pty - last_pty, elapsed),
+            quic, format_rate(quic - last_quic, elapsed),
+            backpressure, backpressure - last_backpressure,
+        );
+        tracing::info!(target: "kerr::profile", pty_bytes_read = pty, quic_bytes_written = quic,
+            backpressure_events = backpressure, "Server stats");
+        last_pty = pty;
+        last_quic = quic;
+        last_backpressure = backpressure;
+
+        let streams: Vec<Arc<StreamStats>> = match STATS.streams.lock() {
+            Ok(streams) => streams.values().cloned().collect(),
+            Err(_) => Vec::new(),
+        };
+        for stream in streams {
+            let sessions = match stream.sessions.try_lock() {
+                Ok(sessions) => sessions
+                    .keys()
+                    .map(|id| id.chars().take(8).collect::<String>())
+                    .collect::<Vec<_>>()
+                    .join(","),
+                Err(_) => "?".to_string(),
+            };
+            let depth = stream.queue_depth.load(Ordering::Relaxed);
+            let max_depth = stream.max_queue_depth.swap(0, Ordering::Relaxed);
+            println!(
+                "[PROFILE]   node {} sessions [{}]: queue depth {} (max {})\r",
+                stream.node_id.fmt_short(), sessions, depth, max_depth,
+            );
+            tracing::info!(target: "kerr::profile", node_id = %stream.node_id, sessions = %sessions,
+                queue_depth = depth, max_queue_depth = max_depth, "Stream queue");
+        }
+    }
+}
+
+/// A client command listed in the server banner; its key copies it to the clipboard
+struct BannerCommand {
+    key: char,
+    name: &'static str,
+    /// Copied as is; `usage` is only shown after it
+    command: String,
+    usage: &'static str,
+    about: &'static str,
+}
+
+impl BannerCommand {
+    fn new(key: char, name: &'static str, connection_string: &str, usage: &'static str, about: &'static str) -> Self {
+        let command = format!("kerr {} {}", name.to_lowercase(), connection_string);
+        Self { key, name, command, usage, about }
+    }
+}
+
+/// The banner's command list. Keys are only listed when `interactive`, since
+/// without a terminal there's no keyboard to press them on.
+fn print_banner(commands: &[BannerCommand], url: Option<&str>, interactive: bool) {
+    println!("\n╔══════════════════════════════════════════════════════════════╗");
+    println!("║                    Kerr Server Online                        ║");
+    println!("╚══════════════════════════════════════════════════════════════╝\n");
+    println!("Run these on another machine to reach this one:\n");
+    for command in commands {
+        let key = if interactive { format!("[{}]", command.key) } else { "   ".to_string() };
+        println!("  {} {:<8} {}", key, command.name, command.about);
+        println!("               {}{}", command.command, command.usage);
+    }
+    if let Some(url) = url {
+        println!("\n  App URL:     {}", url);
+    }
+    if interactive {
+        println!("\n─────────────────────────────────────────────────────────────────");
+        println!("Press a command's key to copy it to the clipboard, Ctrl+C to stop");
+        println!("─────────────────────────────────────────────────────────────────\n");
+    } else {
+        println!();
+    }
+}
+
+pub async fn run_server(register_alias: Option<String>, session_path: Option<String>, base_config: ServerConfig) -> Result<()> {
     // Print session status
     crate::auth::print_session_status(session_path);
     println!();
 
-    let endpoint = Endpoint::bind(iroh::endpoint::presets::N0).await.map_err(|e| n0_snafu::Error::anyhow(anyhow::anyhow!("{}", e)))?;
+    // Command-line settings plus any --config overrides
+    let config = base_config
+        .with_config_file()
+        .map_err(n0_snafu::Error::anyhow)?;
+
+    if config.log_commands {
+        println!("⚠ WARNING: --log-commands is enabled. Commands typed in shell sessions will be");
+        println!("  written to the server log, and connecting clients are notified of this.\n");
+        tracing::warn!("Shell command logging is enabled");
+    }
+    if config.password.is_some() {
+        println!("Clients must enter the --password to start a session.\n");
+        tracing::info!("Password required for new sessions");
+    }
+    if config.read_only {
+        println!("Read-only mode: shells, uploads and file changes are refused.\n");
+        tracing::info!("Serving in read-only mode");
+    }
+    if let Some(root) = &config.root {
+        println!("File access is confined to {}.\n", root.display());
+        tracing::info!(root = %root.display(), "File access confined to root");
+    }
+
+    let endpoint = crate::bind_endpoint().await.map_err(|e| n0_snafu::Error::anyhow(anyhow::anyhow!("{}", e)))?;
 
     // Build our protocol handler and add our protocol, identified by its ALPN, and spawn the node.
-    let router = Router::builder(endpoint).accept(ALPN.to_vec(), KerrServer).spawn();
+    let shared_config = Arc::new(std::sync::RwLock::new(Arc::new(config.clone())));
+    let server = KerrServer {
+        config: shared_config.clone(),
+        limiter: Arc::new(std::sync::Mutex::new(ConnectionLimiter::default())),
+        auth_throttle: Arc::default(),
+    };
+    let router = Router::builder(endpoint).accept(ALPN.to_vec(), server).spawn();
+
+    let profile_task = config.profile.map(|interval| tokio::spawn(report_stats(interval)));
 
-    // Get the node address from the router's endpoint
+    // Get the node address from the router's endpoint, trimmed to what should be advertised
     let _node_id = router.endpoint().id();
-    let addr = router.endpoint().addr();
+    if config.advertise == AdvertiseMode::Relay {
+        // The relay only shows up in the address once the endpoint has reached it
+        let _ = tokio::time::timeout(ADVERTISE_RELAY_WAIT, router.endpoint().online()).await;
+    }
+    let addr = config.advertise.filter(router.endpoint().addr());
+    if addr.is_empty() {
+        println!("⚠ None of this server's addresses match --advertise yet, so clients will have to");
+        println!("  find it through address lookup, which can take longer.\n");
+    }
 
-    // Encode the address as a compressed connection string (JSON -> gzip -> base64)
-    let connection_string = crate::encode_connection_string(&addr);
+    // Encode the address as a compressed connection string (JSON -> gzip -> base64),
+    // or as marked plain JSON when compression is disabled
+    let connection_string = if config.raw_connection_string {
+        crate::encode_raw_connection_string(&addr)
+    } else {
+        crate::encode_connection_string(&addr)
+    };
+    // Raw JSON contains quotes, so quote it for copy-pasted shell commands
+    let shell_connection_string = if config.raw_connection_string {
+        format!("'{}'", connection_string)
+    } else {
+        connection_string.clone()
+    };
 
     // Register with backend if alias was provided
     let registered_alias = if let Some(alias) = register_alias {
@@ -89,42 +1318,26 @@ pub async fn run_server(register_alias: Option<String>, session_path: Option<Str
     };
 
     // Build the connection commands
-    let connect_command = format!("kerr connect {}", connection_string);
-    let send_command = format!("kerr send {}", connection_string);
-    let pull_command = format!("kerr pull {}", connection_string);
-    let browse_command = format!("kerr browse {}", connection_string);
-    let relay_command = format!("kerr relay {}", connection_string);
-    let ping_command = format!("kerr ping {}", connection_string);
-
-    println!("\n╔══════════════════════════════════════════════════════════════╗");
-    println!("║                    Kerr Server Online                        ║");
-    println!("╚══════════════════════════════════════════════════════════════╝\n");
-    println!("Commands:");
-    println!("  Connect: {}", connect_command);
-    println!("  Send:    {} <local> <remote>", send_command);
-    println!("  Pull:    {} <remote> <local>", pull_command);
-    println!("  Browse:  {}", browse_command);
-    println!("  Relay:   {} <local_port> <remote_port>", relay_command);
-    println!("  Ping:    {}", ping_command);
-    println!("\n─────────────────────────────────────────────────────────────────");
-    println!("Keys: [c]onnect | [s]end | [p]ull | [b]rowse | [r]elay | p[i]ng | Ctrl+C");
-    println!("─────────────────────────────────────────────────────────────────\n");
+    let commands = vec![
+        BannerCommand::new('c', "Connect", &shell_connection_string, "", "open a shell on this machine"),
+        BannerCommand::new('s', "Send", &shell_connection_string, " <local> <remote>", "upload a file or directory here"),
+        BannerCommand::new('p', "Pull", &shell_connection_string, " <remote> <local>", "download a file or directory from here"),
+        BannerCommand::new('b', "Browse", &shell_connection_string, "", "browse and edit files here in a TUI"),
+        BannerCommand::new('r', "Relay", &shell_connection_string, " <local_port> <remote_port>", "forward a local port to a port here"),
+        BannerCommand::new('i', "Ping", &shell_connection_string, "", "measure latency and throughput to here"),
+    ];
+    let url = config.print_url.then(|| crate::connection_url(&connection_string));
+    let interactive = std::io::stdin().is_terminal();
+    print_banner(&commands, url.as_deref(), interactive);
 
     // When stdin is not a TTY (e.g. launched as a systemd service), skip all
     // keyboard/clipboard interaction — the EventStream would spin on EOF and
     // the raw-mode calls have no meaning without a terminal.
-    if std::io::stdin().is_terminal() {
+    if interactive {
         // Enable raw mode for keyboard event handling
         enable_raw_mode().unwrap_or_else(|err| eprintln!("Failed to enable raw mode: {err}"));
 
         // Spawn task to handle keyboard events
-        let connect_clone = connect_command.clone();
-        let send_clone = send_command.clone();
-        let pull_clone = pull_command.clone();
-        let browse_clone = browse_command.clone();
-        let relay_clone = relay_command.clone();
-        let ping_clone = ping_command.clone();
-
         let keyboard_task = tokio::task::spawn(async move {
             let mut event_stream = EventStream::new();
 
@@ -133,88 +1346,15 @@ pub async fn run_server(register_alias: Option<String>, session_path: Option<Str
                     match event_result {
                         Ok(Event::Key(key_event)) => {
                             match (key_event.code, key_event.modifiers, key_event.kind) {
-                                // Handle 'c' key press to copy connect command
-                                (KeyCode::Char('c'), KeyModifiers::NONE, KeyEventKind::Press) => {
-                                    match Clipboard::new() {
-                                        Ok(mut clipboard) => {
-                                            if clipboard.set_text(&connect_clone).is_ok() {
-                                                println!("\r\n✓ Connect command copied to clipboard!\r\n");
-                                            } else {
-                                                eprintln!("\r\n✗ Failed to copy to clipboard\r\n");
-                                            }
-                                        }
-                                        Err(e) => {
-                                            eprintln!("\r\n✗ Failed to access clipboard: {}\r\n", e);
-                                        }
-                                    }
-                                }
-                                // Handle 's' key press to copy send command
-                                (KeyCode::Char('s'), KeyModifiers::NONE, KeyEventKind::Press) => {
-                                    match Clipboard::new() {
-                                        Ok(mut clipboard) => {
-                                            if clipboard.set_text(&send_clone).is_ok() {
-                                                println!("\r\n✓ Send command copied to clipboard!\r\n");
-                                            } else {
-                                                eprintln!("\r\n✗ Failed to copy to clipboard\r\n");
-                                            }
-                                        }
-                                        Err(e) => {
-                                            eprintln!("\r\n✗ Failed to access clipboard: {}\r\n", e);
-                                        }
-                                    }
-                                }
-                                // Handle 'p' key press to copy pull command
-                                (KeyCode::Char('p'), KeyModifiers::NONE, KeyEventKind::Press) => {
-                                    match Clipboard::new() {
-                                        Ok(mut clipboard) => {
-                                            if clipboard.set_text(&pull_clone).is_ok() {
-                                                println!("\r\n✓ Pull command copied to clipboard!\r\n");
-                                            } else {
-                                                eprintln!("\r\n✗ Failed to copy to clipboard\r\n");
-                                            }
-                                        }
-                                        Err(e) => {
-                                            eprintln!("\r\n✗ Failed to access clipboard: {}\r\n", e);
-                                        }
-                                    }
-                                }
-                                // Handle 'b' key press to copy browse command
-                                (KeyCode::Char('b'), KeyModifiers::NONE, KeyEventKind::Press) => {
-                                    match Clipboard::new() {
-                                        Ok(mut clipboard) => {
-                                            if clipboard.set_text(&browse_clone).is_ok() {
-                                                println!("\r\n✓ Browse command copied to clipboard!\r\n");
-                                            } else {
-                                                eprintln!("\r\n✗ Failed to copy to clipboard\r\n");
-                                            }
-                                        }
-                                        Err(e) => {
-                                            eprintln!("\r\n✗ Failed to access clipboard: {}\r\n", e);
-                                        }
-                                    }
-                                }
-                                // Handle 'r' key press to copy relay command
-                                (KeyCode::Char('r'), KeyModifiers::NONE, KeyEventKind::Press) => {
-                                    match Clipboard::new() {
-                                        Ok(mut clipboard) => {
-                                            if clipboard.set_text(&relay_clone).is_ok() {
-                                                println!("\r\n✓ Relay command copied to clipboard!\r\n");
-                                            }
-                                            else {
-                                                eprintln!("\r\n✗ Failed to copy to clipboard\r\n");
-                                            }
-                                        }
-                                        Err(e) => {
-                                            eprintln!("\r\n✗ Failed to access clipboard: {}\r\n", e);
-                                        }
-                                    }
-                                }
-                                // Handle 'i' key press to copy ping command
-                                (KeyCode::Char('i'), KeyModifiers::NONE, KeyEventKind::Press) => {
+                                // A command's key copies it to the clipboard
+                                (KeyCode::Char(key), KeyModifiers::NONE, KeyEventKind::Press) => {
+                                    let Some(command) = commands.iter().find(|command| command.key == key) else {
+                                        continue;
+                                    };
                                     match Clipboard::new() {
                                         Ok(mut clipboard) => {
-                                            if clipboard.set_text(&ping_clone).is_ok() {
-                                                println!("\r\n✓ Ping command copied to clipboard!\r\n");
+                                            if clipboard.set_text(&command.command).is_ok() {
+                                                println!("\r\n✓ {} command copied to clipboard!\r\n", command.name);
                                             } else {
                                                 eprintln!("\r\n✗ Failed to copy to clipboard\r\n");
                                             }
@@ -240,9 +1380,10 @@ pub async fn run_server(register_alias: Option<String>, session_path: Option<Str
             }
         });
 
-        // Wait for either Ctrl+C signal or keyboard task to complete
+        // Wait for a shutdown signal or the keyboard task to complete
         tokio::select! {
-            _ = tokio::signal::ctrl_c() => {
+            signal = shutdown_signal(|| reload_config(&base_config, &shared_config)) => {
+                tracing::info!(signal = signal, "Shutdown signal received");
                 println!("\r\nShutting down...");
             }
             _ = keyboard_task => {
@@ -253,9 +1394,10 @@ pub async fn run_server(register_alias: Option<String>, session_path: Option<Str
         disable_raw_mode().unwrap_or_else(|e| eprintln!("Failed to disable raw mode: {}", e));
     } else {
         // Headless mode (no TTY): running as a systemd service or piped process.
-        // Just wait for SIGINT; keyboard shortcuts are not available.
-        tracing::info!(pid = std::process::id(), "Running headless — waiting for SIGINT to stop");
-        tokio::signal::ctrl_c().await.ok();
+        // Wait for SIGINT/SIGTERM; keyboard shortcuts are not available.
+        tracing::info!(pid = std::process::id(), "Running headless — waiting for SIGINT or SIGTERM to stop");
+        let signal = shutdown_signal(|| reload_config(&base_config, &shared_config)).await;
+        tracing::info!(signal = signal, "Shutdown signal received");
         println!("Shutting down...");
     }
 
@@ -271,24 +1413,108 @@ pub async fn run_server(register_alias: Option<String>, session_path: Option<Str
         }
     }
 
+    if let Some(task) = profile_task {
+        task.abort();
+    }
+
     // Shutdown the router
     router.shutdown().await.e()?;
 
     Ok(())
 }
 
+/// Serve a single envelope stream with a fresh session table, as the server does
+/// for every QUIC bi stream. With `transport::memory_pair` this runs shell, browser
+/// and transfer sessions end to end without an endpoint.
+pub async fn serve_stream(
+    node_id: iroh::PublicKey,
+    send: impl crate::transport::EnvelopeWrite + 'static,
+    recv: impl crate::transport::EnvelopeRead + 'static,
+    config: ServerConfig,
+) {
+    KerrServer::handle_stream(node_id, send, recv, Arc::new(config), Arc::default()).await;
+}
+
+/// Wait until the server should shut down and return the signal's name.
+/// SIGTERM (sent by systemd, supervisord, ...) is treated like Ctrl+C so the
+/// backend unregistration and router shutdown still run. SIGHUP calls `on_reload`
+/// instead of killing the server.
+async fn shutdown_signal(on_reload: impl Fn()) -> &'static str {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        let (mut term, mut hup) = match (signal(SignalKind::terminate()), signal(SignalKind::hangup())) {
+            (Ok(term), Ok(hup)) => (term, hup),
+            _ => {
+                tracing::warn!("Failed to install SIGTERM/SIGHUP handlers, only Ctrl+C will stop the server");
+                let _ = tokio::signal::ctrl_c().await;
+                return "SIGINT";
+            }
+        };
+
+        loop {
+            tokio::select! {
+                _ = tokio::signal::ctrl_c() => return "SIGINT",
+                _ = term.recv() => return "SIGTERM",
+                _ = hup.recv() => on_reload(),
+            }
+        }
+    }
+
+    #[cfg(not(unix))]
+    {
+        let _ = on_reload;
+        let _ = tokio::signal::ctrl_c().await;
+        "Ctrl+C"
+    }
+}
+
 #[derive(Debug, Clone)]
-struct KerrServer;
+struct KerrServer {
+    /// Settings for new sessions, swapped out on config reload
+    config: Arc<std::sync::RwLock<Arc<ServerConfig>>>,
+    limiter: Arc<std::sync::Mutex<ConnectionLimiter>>,
+    auth_throttle: Arc<AuthThrottle>,
+}
 
 impl ProtocolHandler for KerrServer {
     async fn accept(&self, connection: Connection) -> Result<(), AcceptError> {
         let node_id = connection.remote_id();
+
+        let per_minute = match self.config.read() {
+            Ok(config) => config.max_connections_per_minute,
+            Err(poisoned) => poisoned.into_inner().max_connections_per_minute,
+        };
+        if let Some(per_minute) = per_minute {
+            let decision = match self.limiter.lock() {
+                Ok(mut limiter) => limiter.check(node_id, per_minute),
+                Err(poisoned) => poisoned.into_inner().check(node_id, per_minute),
+            };
+            if let RateDecision::Reject { first } = decision {
+                if first {
+                    tracing::warn!(node_id = %node_id, per_minute, "Rejecting connections over the rate limit");
+                }
+                connection.close(1u32.into(), b"rate limited");
+                return Ok(());
+            }
+        }
+
         tracing::info!(node_id = %node_id, "Accepted connection - envelope-based multiplexing");
 
+        // Echo datagrams straight back, so `kerr ping --datagram` can time the network alone
+        let echo_connection = connection.clone();
+        tokio::spawn(async move {
+            while let Ok(data) = echo_connection.read_datagram().await {
+                let _ = echo_connection.send_datagram(data);
+            }
+        });
+
         // Accept multiple bidirectional streams from the client
         // Each stream uses envelopes for session identification
+        let auth = Arc::new(AuthAttempts::new(self.auth_throttle.clone()));
         loop {
-            let (mut send, mut recv) = match connection.accept_bi().await {
+            let (send, recv) = match connection.accept_bi().await {
                 Ok(streams) => streams,
                 Err(_) => {
                     tracing::info!(node_id = %node_id, "Connection closed");
@@ -296,195 +1522,405 @@ impl ProtocolHandler for KerrServer {
                 }
             };
 
-            let node_id_clone = node_id;
+            let config = match self.config.read() {
+                Ok(config) => config.clone(),
+                Err(poisoned) => poisoned.into_inner().clone(),
+            };
 
-            // Spawn handler for this stream
+            // Spawn handler for this stream; a wrong password ends the whole connection
+            let auth = auth.clone();
+            let connection = connection.clone();
             tokio::spawn(async move {
-                tracing::debug!(node_id = %node_id_clone, "New stream accepted");
+                Self::handle_stream(node_id, send, recv, config, auth.clone()).await;
+                if auth.has_failed() {
+                    // Give the client a moment to read the answer and hang up itself;
+                    // any further attempts fail meanwhile
+                    let _ = tokio::time::timeout(AUTH_FAILURE_DELAY, connection.closed()).await;
+                    tracing::warn!(node_id = %node_id, "Closing connection after a wrong password");
+                    connection.close(1u32.into(), b"authentication failed");
+                }
+            });
+        }
+
+        Ok(())
+    }
+}
+
+impl KerrServer {
+    /// Run one envelope stream until it closes, routing each session's messages
+    /// to its handler. Generic over the transport so it also runs in memory.
+    async fn handle_stream(
+        node_id: iroh::PublicKey,
+        send: impl crate::transport::EnvelopeWrite + 'static,
+        mut recv: impl crate::transport::EnvelopeRead + 'static,
+        config: Arc<ServerConfig>,
+        auth: Arc<AuthAttempts>,
+    ) {
+        tracing::debug!(node_id = %node_id, "New stream accepted");
+
+        // Create channels for sessions on this stream
+        let sessions: SessionMap = Arc::new(tokio::sync::Mutex::new(std::collections::HashMap::new()));
+        let stream_stats = StreamStatsGuard::register(node_id, sessions.clone());
+        let queue_stats = stream_stats.stats.clone();
+
+        let (outgoing_tx, mut outgoing_rx) = tokio::sync::mpsc::unbounded_channel::<crate::MessageEnvelope>();
+        // Queue length after each write, for downloads to pace themselves against
+        let (queue_depth_tx, queue_depth) = tokio::sync::watch::channel(0usize);
+
+        // Spawn task to send outgoing messages
+        let send_task = tokio::spawn(async move {
+            let mut send = CountingWrite { inner: send, bytes: &STATS.quic_bytes_written };
+            while let Some(envelope) = outgoing_rx.recv().await {
+                queue_stats.note_queue_depth(outgoing_rx.len());
+                let started = std::time::Instant::now();
+                if let Err(e) = crate::send_envelope(&mut send, &envelope).await {
+                    tracing::error!("Failed to send envelope: {}", e);
+                    break;
+                }
+                queue_depth_tx.send_replace(outgoing_rx.len());
+                if started.elapsed() >= BACKPRESSURE_THRESHOLD {
+                    STATS.backpressure_events.fetch_add(1, Ordering::Relaxed);
+                }
+            }
+            tracing::debug!("Send task ended");
+        });
 
-                // Create channels for sessions on this stream
-                let sessions: Arc<tokio::sync::Mutex<std::collections::HashMap<
-                    String,
-                    tokio::sync::mpsc::UnboundedSender<crate::ClientMessage>
-                >>> = Arc::new(tokio::sync::Mutex::new(std::collections::HashMap::new()));
+        // With --password, no session starts on this stream until the client has sent it.
+        // A Hello is answered with the AUTH_REQUIRED error and waits here for the Auth
+        // that follows it; clients that don't send one see that error and can ask for it.
+        let mut authenticated = config.password.is_none();
+        let mut pending_hello: Option<(String, crate::SessionType, ShellStart)> = None;
 
-                let (outgoing_tx, mut outgoing_rx) = tokio::sync::mpsc::unbounded_channel::<crate::MessageEnvelope>();
+        // Main message loop for this stream
+        let sessions_clone = sessions.clone();
+        loop {
+            let envelope = match crate::recv_envelope(&mut recv).await {
+                Ok(env) => {
+                    tracing::debug!(node_id = %node_id, "Received envelope");
+                    env
+                },
+                Err(e) if e.is::<crate::EnvelopeDecodeError>() => {
+                    // Most likely a Hello for a session type this build doesn't know.
+                    // The session_id is inside the undecodable frame, so reply on an
+                    // empty one; clients show errors regardless of session.
+                    tracing::warn!(node_id = %node_id, error = %e, "Unsupported message from client");
+                    let response = crate::MessageEnvelope {
+                        session_id: String::new(),
+                        payload: crate::MessagePayload::Server(crate::ServerMessage::Error {
+                            message: format!(
+                                "Request not supported by this server (kerr {}); the client may be newer. Details: {}",
+                                crate::VERSION, e
+                            ),
+                        }),
+                    };
+                    let _ = outgoing_tx.send(response);
+                    continue;
+                }
+                Err(e) if e.is::<crate::StreamClosed>() => {
+                    tracing::info!(node_id = %node_id, "Stream closed by client");
+                    break;
+                }
+                Err(e) => {
+                    tracing::warn!(node_id = %node_id, error = %e, "Stream lost");
+                    break;
+                }
+            };
 
-                // Spawn task to send outgoing messages
-                let send_task = tokio::spawn(async move {
-                    while let Some(envelope) = outgoing_rx.recv().await {
-                        if let Err(e) = crate::send_envelope(&mut send, &envelope).await {
-                            tracing::error!("Failed to send envelope: {}", e);
-                            break;
+            let session_id = envelope.session_id.clone();
+            let session_id_short = if session_id.len() >= 8 { &session_id[..8] } else { &session_id };
+            tracing::debug!(node_id = %node_id, session_id = %session_id, "Processing envelope for session");
+
+            match envelope.payload {
+                crate::MessagePayload::Client(client_msg) => {
+                    // Check if this is a Hello message
+                    let mut hello = match &client_msg {
+                        crate::ClientMessage::Hello { session_type } => Some((session_type.clone(), ShellStart::default())),
+                        crate::ClientMessage::ShellHello { cwd } => {
+                            Some((crate::SessionType::Shell, ShellStart { cwd: cwd.clone(), ..ShellStart::default() }))
                         }
-                    }
-                    tracing::debug!("Send task ended");
-                });
+                        crate::ClientMessage::ShellHelloEnv { cwd, term } => {
+                            Some((crate::SessionType::Shell, ShellStart { cwd: cwd.clone(), term: term.clone(), command: None }))
+                        }
+                        crate::ClientMessage::ExecHello { command } => {
+                            Some((crate::SessionType::Shell, ShellStart { command: Some(command.clone()), ..ShellStart::default() }))
+                        }
+                        _ => None,
+                    };
 
-                // Main message loop for this stream
-                let sessions_clone = sessions.clone();
-                loop {
-                    let envelope = match crate::recv_envelope(&mut recv).await {
-                        Ok(env) => {
-                            tracing::debug!(node_id = %node_id_clone, "Received envelope");
-                            env
-                        },
-                        Err(e) => {
-                            tracing::info!(node_id = %node_id_clone, error = %e, "Stream closed or error");
+                    if let crate::ClientMessage::Auth { secret } = &client_msg {
+                        let ok = authenticated || match &config.password {
+                            Some(gate) => auth.check(gate, secret).await,
+                            None => false,
+                        };
+                        if !ok {
+                            tracing::warn!(node_id = %node_id, session_id = %session_id, "Wrong password, closing stream");
+                        } else if !authenticated {
+                            tracing::info!(node_id = %node_id, session_id = %session_id, "Client authenticated");
+                            authenticated = true;
+                            hello = pending_hello.take()
+                                .filter(|(id, _, _)| *id == session_id)
+                                .map(|(_, session_type, start)| (session_type, start));
+                        }
+                        let response = crate::MessageEnvelope {
+                            session_id: session_id.clone(),
+                            payload: crate::MessagePayload::Server(crate::ServerMessage::AuthResult { ok }),
+                        };
+                        let _ = outgoing_tx.send(response);
+                        if !ok {
                             break;
                         }
+                        if hello.is_none() {
+                            continue;
+                        }
+                    } else if !authenticated {
+                        if pending_hello.is_none() {
+                            let response = crate::MessageEnvelope {
+                                session_id: session_id.clone(),
+                                payload: crate::MessagePayload::Server(crate::ServerMessage::Error {
+                                    message: format!("{}; kerr connect, send and pull ask for it", crate::AUTH_REQUIRED),
+                                }),
+                            };
+                            let _ = outgoing_tx.send(response);
+                        }
+                        if let Some((session_type, shell_start)) = hello
+                            && pending_hello.is_none()
+                        {
+                            pending_hello = Some((session_id.clone(), session_type, shell_start));
+                            continue;
+                        }
+                        tracing::warn!(node_id = %node_id, session_id = %session_id, "Client sent no password, closing stream");
+                        break;
+                    }
+                    // Shells can change anything, and reverse relays open ports on this machine
+                    let refused_when_read_only = match &hello {
+                        Some((crate::SessionType::Shell, _)) => Some("Shell session"),
+                        Some((crate::SessionType::ReverseRelay, _)) => Some("Reverse relay"),
+                        _ => None,
                     };
-
-                    let session_id = envelope.session_id.clone();
-                    let session_id_short = if session_id.len() >= 8 { &session_id[..8] } else { &session_id };
-                    tracing::debug!(node_id = %node_id_clone, session_id = %session_id, "Processing envelope for session");
-
-                    match envelope.payload {
-                        crate::MessagePayload::Client(client_msg) => {
-                            // Check if this is a Hello message
-                            if let crate::ClientMessage::Hello { session_type } = &client_msg {
-                                debug_log::log_new_session_separator(session_id_short, &format!("{:?}", session_type));
-                                tracing::info!(node_id = %node_id_clone, session_id = %session_id, session_type = ?session_type, "Creating new session");
-
-                                let (session_tx, session_rx) = tokio::sync::mpsc::unbounded_channel();
-                                sessions_clone.lock().await.insert(session_id.clone(), session_tx);
-
-                                let outgoing_tx_clone = outgoing_tx.clone();
-                                let session_id_clone = session_id.clone();
-                                let sessions_for_cleanup = sessions_clone.clone();
-
-                                match session_type {
-                                    crate::SessionType::Shell => {
-                                        tokio::spawn(async move {
-                                            if let Err(e) = Self::handle_shell_session_mux(
-                                                node_id_clone,
-                                                session_id_clone.clone(),
-                                                session_rx,
-                                                outgoing_tx_clone,
-                                            ).await {
-                                                tracing::error!(session_id = %session_id_clone, error = ?e, "Shell session error");
-                                            }
-                                            sessions_for_cleanup.lock().await.remove(&session_id_clone);
-                                        });
+                    if let Some(what) = refused_when_read_only
+                        && config.read_only
+                    {
+                        tracing::warn!(node_id = %node_id, session_id = %session_id, session = what,
+                            "Refusing session in read-only mode");
+                        let response = crate::MessageEnvelope {
+                            session_id: session_id.clone(),
+                            payload: crate::MessagePayload::Server(crate::ServerMessage::Error {
+                                message: read_only_message(what),
+                            }),
+                        };
+                        let _ = outgoing_tx.send(response);
+                        continue;
+                    }
+                    if let Some((session_type, shell_start)) = hello {
+                        debug_log::log_new_session_separator(session_id_short, &format!("{:?}", session_type));
+                        tracing::info!(node_id = %node_id, session_id = %session_id, session_type = ?session_type, "Creating new session");
+
+                        let (session_tx, session_rx) = tokio::sync::mpsc::unbounded_channel();
+                        sessions_clone.lock().await.insert(session_id.clone(), session_tx);
+
+                        let outgoing_tx_clone = outgoing_tx.clone();
+                        let session_id_clone = session_id.clone();
+                        let sessions_for_cleanup = sessions_clone.clone();
+
+                        match session_type {
+                            crate::SessionType::Shell if shell_start.command.is_some() => {
+                                let config = config.clone();
+                                let command = shell_start.command.unwrap_or_default();
+                                tokio::spawn(async move {
+                                    if let Err(e) = Self::handle_exec_session_mux(
+                                        node_id,
+                                        session_id_clone.clone(),
+                                        session_rx,
+                                        outgoing_tx_clone,
+                                        config,
+                                        command,
+                                    ).await {
+                                        tracing::error!(session_id = %session_id_clone, error = ?e, "Exec session error");
                                     }
-                                    crate::SessionType::FileBrowser => {
-                                        tokio::spawn(async move {
-                                            if let Err(e) = Self::handle_file_browser_session_mux(
-                                                node_id_clone,
-                                                session_id_clone.clone(),
-                                                session_rx,
-                                                outgoing_tx_clone,
-                                            ).await {
-                                                tracing::error!(session_id = %session_id_clone, error = ?e, "FileBrowser session error");
-                                            }
-                                            sessions_for_cleanup.lock().await.remove(&session_id_clone);
-                                        });
+                                    sessions_for_cleanup.lock().await.remove(&session_id_clone);
+                                });
+                            }
+                            crate::SessionType::Shell => {
+                                let config = config.clone();
+                                tokio::spawn(async move {
+                                    if let Err(e) = Self::handle_shell_session_mux(
+                                        node_id,
+                                        session_id_clone.clone(),
+                                        session_rx,
+                                        outgoing_tx_clone,
+                                        config,
+                                        shell_start,
+                                    ).await {
+                                        tracing::error!(session_id = %session_id_clone, error = ?e, "Shell session error");
                                     }
-                                    crate::SessionType::FileTransfer => {
-                                        tokio::spawn(async move {
-                                            if let Err(e) = Self::handle_file_transfer_session_mux(
-                                                node_id_clone,
-                                                session_id_clone.clone(),
-                                                session_rx,
-                                                outgoing_tx_clone,
-                                            ).await {
-                                                tracing::error!(session_id = %session_id_clone, error = ?e, "FileTransfer session error");
-                                            }
-                                            sessions_for_cleanup.lock().await.remove(&session_id_clone);
-                                        });
+                                    sessions_for_cleanup.lock().await.remove(&session_id_clone);
+                                });
+                            }
+                            crate::SessionType::FileBrowser => {
+                                let config = config.clone();
+                                tokio::spawn(async move {
+                                    if let Err(e) = Self::handle_file_browser_session_mux(
+                                        node_id,
+                                        session_id_clone.clone(),
+                                        session_rx,
+                                        outgoing_tx_clone,
+                                        config,
+                                    ).await {
+                                        tracing::error!(session_id = %session_id_clone, error = ?e, "FileBrowser session error");
                                     }
-                                    crate::SessionType::TcpRelay => {
-                                        tokio::spawn(async move {
-                                            if let Err(e) = Self::handle_tcp_relay_session_mux(
-                                                node_id_clone,
-                                                session_id_clone.clone(),
-                                                session_rx,
-                                                outgoing_tx_clone,
-                                            ).await {
-                                                tracing::error!(session_id = %session_id_clone, error = ?e, "TcpRelay session error");
-                                            }
-                                            sessions_for_cleanup.lock().await.remove(&session_id_clone);
-                                        });
+                                    sessions_for_cleanup.lock().await.remove(&session_id_clone);
+                                });
+                            }
+                            crate::SessionType::FileTransfer => {
+                                let config = config.clone();
+                                let queue_depth = queue_depth.clone();
+                                tokio::spawn(async move {
+                                    if let Err(e) = Self::handle_file_transfer_session_mux(
+                                        node_id,
+                                        session_id_clone.clone(),
+                                        session_rx,
+                                        outgoing_tx_clone,
+                                        queue_depth,
+                                        config,
+                                    ).await {
+                                        tracing::error!(session_id = %session_id_clone, error = ?e, "FileTransfer session error");
                                     }
-                                    crate::SessionType::Ping => {
-                                        tokio::spawn(async move {
-                                            if let Err(e) = Self::handle_ping_session_mux(
-                                                node_id_clone,
-                                                session_id_clone.clone(),
-                                                session_rx,
-                                                outgoing_tx_clone,
-                                            ).await {
-                                                tracing::error!(session_id = %session_id_clone, error = ?e, "Ping session error");
-                                            }
-                                            sessions_for_cleanup.lock().await.remove(&session_id_clone);
-                                        });
+                                    sessions_for_cleanup.lock().await.remove(&session_id_clone);
+                                });
+                            }
+                            crate::SessionType::TcpRelay => {
+                                tokio::spawn(async move {
+                                    if let Err(e) = Self::handle_tcp_relay_session_mux(
+                                        node_id,
+                                        session_id_clone.clone(),
+                                        session_rx,
+                                        outgoing_tx_clone,
+                                    ).await {
+                                        tracing::error!(session_id = %session_id_clone, error = ?e, "TcpRelay session error");
                                     }
-                                    crate::SessionType::HttpProxy => {
-                                        // HttpProxy uses the same handler as TcpRelay
-                                        tokio::spawn(async move {
-                                            if let Err(e) = Self::handle_tcp_relay_session_mux(
-                                                node_id_clone,
-                                                session_id_clone.clone(),
-                                                session_rx,
-                                                outgoing_tx_clone,
-                                            ).await {
-                                                tracing::error!(session_id = %session_id_clone, error = ?e, "HttpProxy session error");
-                                            }
-                                            sessions_for_cleanup.lock().await.remove(&session_id_clone);
-                                        });
+                                    sessions_for_cleanup.lock().await.remove(&session_id_clone);
+                                });
+                            }
+                            crate::SessionType::Ping => {
+                                tokio::spawn(async move {
+                                    if let Err(e) = Self::handle_ping_session_mux(
+                                        node_id,
+                                        session_id_clone.clone(),
+                                        session_rx,
+                                        outgoing_tx_clone,
+                                    ).await {
+                                        tracing::error!(session_id = %session_id_clone, error = ?e, "Ping session error");
                                     }
-                                    crate::SessionType::Dns => {
-                                        tokio::spawn(async move {
-                                            if let Err(e) = Self::handle_dns_session_mux(
-                                                node_id_clone,
-                                                session_id_clone.clone(),
-                                                session_rx,
-                                                outgoing_tx_clone,
-                                            ).await {
-                                                tracing::error!(session_id = %session_id_clone, error = ?e, "Dns session error");
-                                            }
-                                            sessions_for_cleanup.lock().await.remove(&session_id_clone);
-                                        });
+                                    sessions_for_cleanup.lock().await.remove(&session_id_clone);
+                                });
+                            }
+                            crate::SessionType::HttpProxy => {
+                                // HttpProxy uses the same handler as TcpRelay
+                                tokio::spawn(async move {
+                                    if let Err(e) = Self::handle_tcp_relay_session_mux(
+                                        node_id,
+                                        session_id_clone.clone(),
+                                        session_rx,
+                                        outgoing_tx_clone,
+                                    ).await {
+                                        tracing::error!(session_id = %session_id_clone, error = ?e, "HttpProxy session error");
                                     }
-                                }
-                            } else {
-                                // Route message to existing session
-                                tracing::debug!(session_id = %session_id, "Routing message to existing session");
-                                let sessions_lock = sessions_clone.lock().await;
-                                tracing::debug!("Session map contains {} sessions", sessions_lock.len());
-                                if let Some(session_tx) = sessions_lock.get(&session_id) {
-                                    tracing::debug!(session_id = %session_id, "Found session, sending message");
-                                    if session_tx.send(client_msg).is_err() {
-                                        tracing::warn!(session_id = %session_id, "Failed to send to session (channel closed)");
-                                    } else {
-                                        tracing::debug!(session_id = %session_id, "Message sent to session successfully");
+                                    sessions_for_cleanup.lock().await.remove(&session_id_clone);
+                                });
+                            }
+                            crate::SessionType::Dns => {
+                                tokio::spawn(async move {
+                                    if let Err(e) = Self::handle_dns_session_mux(
+                                        node_id,
+                                        session_id_clone.clone(),
+                                        session_rx,
+                                        outgoing_tx_clone,
+                                    ).await {
+                                        tracing::error!(session_id = %session_id_clone, error = ?e, "Dns session error");
                                     }
-                                } else {
-                                    tracing::warn!(session_id = %session_id, "Unknown session - not found in session map");
-                                    // Log all known session IDs for debugging
-                                    for (id, _) in sessions_lock.iter() {
-                                        tracing::debug!("Known session: {}", id);
+                                    sessions_for_cleanup.lock().await.remove(&session_id_clone);
+                                });
+                            }
+                            crate::SessionType::Echo => {
+                                tokio::spawn(async move {
+                                    if let Err(e) = Self::handle_echo_session_mux(
+                                        node_id,
+                                        session_id_clone.clone(),
+                                        session_rx,
+                                        outgoing_tx_clone,
+                                    ).await {
+                                        tracing::error!(session_id = %session_id_clone, error = ?e, "Echo session error");
                                     }
-                                }
+                                    sessions_for_cleanup.lock().await.remove(&session_id_clone);
+                                });
+                            }
+                            crate::SessionType::UdpRelay => {
+                                tokio::spawn(async move {
+                                    if let Err(e) = Self::handle_udp_relay_session_mux(
+                                        node_id,
+                                        session_id_clone.clone(),
+                                        session_rx,
+                                        outgoing_tx_clone,
+                                    ).await {
+                                        tracing::error!(session_id = %session_id_clone, error = ?e, "UdpRelay session error");
+                                    }
+                                    sessions_for_cleanup.lock().await.remove(&session_id_clone);
+                                });
                             }
+                            crate::SessionType::ReverseRelay => {
+                                tokio::spawn(async move {
+                                    if let Err(e) = Self::handle_reverse_relay_session_mux(
+                                        node_id,
+                                        session_id_clone.clone(),
+                                        session_rx,
+                                        outgoing_tx_clone,
+                                    ).await {
+                                        tracing::error!(session_id = %session_id_clone, error = ?e, "ReverseRelay session error");
+                                    }
+                                    sessions_for_cleanup.lock().await.remove(&session_id_clone);
+                                });
+                            }
+                        }
+                    } else if let crate::ClientMessage::Heartbeats = client_msg {
+                        match heartbeat_interval() {
+                            Some(interval) => {
+                                tracing::debug!(session_id = %session_id, ?interval, "Starting heartbeats");
+                                tokio::spawn(send_heartbeats(session_id, interval, sessions_clone.clone(), outgoing_tx.clone()));
+                            }
+                            None => tracing::debug!(session_id = %session_id, "Heartbeats are turned off"),
                         }
-                        crate::MessagePayload::Server(_) => {
-                            tracing::warn!(session_id = %session_id, "Server received Server message");
+                    } else {
+                        // Route message to existing session
+                        tracing::debug!(session_id = %session_id, "Routing message to existing session");
+                        let sessions_lock = sessions_clone.lock().await;
+                        tracing::debug!("Session map contains {} sessions", sessions_lock.len());
+                        if let Some(session_tx) = sessions_lock.get(&session_id) {
+                            tracing::debug!(session_id = %session_id, "Found session, sending message");
+                            if session_tx.send(client_msg).is_err() {
+                                tracing::warn!(session_id = %session_id, "Failed to send to session (channel closed)");
+                            } else {
+                                tracing::debug!(session_id = %session_id, "Message sent to session successfully");
+                            }
+                        } else {
+                            tracing::warn!(session_id = %session_id, "Unknown session - not found in session map");
+                            // Log all known session IDs for debugging
+                            for (id, _) in sessions_lock.iter() {
+                                tracing::debug!("Known session: {}", id);
+                            }
                         }
                     }
                 }
-
-                drop(outgoing_tx);
-                let _ = send_task.await;
-                tracing::info!(node_id = %node_id_clone, "Stream handler exiting");
-            });
+                crate::MessagePayload::Server(_) => {
+                    tracing::warn!(session_id = %session_id, "Server received Server message");
+                }
+            }
         }
 
-        Ok(())
+        drop(outgoing_tx);
+        let _ = send_task.await;
+        tracing::info!(node_id = %node_id, "Stream handler exiting");
     }
-}
 
-impl KerrServer {
     async fn handle_shell_session(
         node_id: iroh::PublicKey,
         mut send: iroh::endpoint::SendStream,
@@ -521,18 +1957,9 @@ impl KerrServer {
             debug_log::log_debug(session_id, "PTY_CREATED: success (fd unknown)");
         }
 
-        // Spawn bash in the PTY with custom prompt
-        // Use 'bash -c' to set PS1 and then exec bash to replace the process
-        let username = std::env::var("USER").unwrap_or_else(|_| "user".to_string());
-        let prompt_cmd = format!(
-            "export PS1='{}@kerr \\w> ' && exec bash --norc --noprofile",
-            username
-        );
-
-        let mut cmd = CommandBuilder::new("bash");
-        cmd.arg("-c");
-        cmd.arg(&prompt_cmd);
-        cmd.env("TERM", "xterm-256color");
+        // Spawn the shell in the PTY with custom prompt
+        let mut cmd = shell_command(&ServerConfig::default());
+        cmd.env("TERM", DEFAULT_TERM);
 
         debug_log::log_bash_spawn_start(session_id);
         let child = pair
@@ -611,7 +2038,7 @@ impl KerrServer {
 
                         // Send error message to client
                         let msg = ServerMessage::Error {
-                            message: "Session ended: bash exited".to_string(),
+                            message: "Session ended: shell exited".to_string(),
                         };
                         if let Ok(encoded) = rkyv::to_bytes::<rkyv::rancor::Error>(&msg) {
                             let len = (encoded.len() as u32).to_be_bytes();
@@ -719,13 +2146,19 @@ impl KerrServer {
                                 ClientMessage::KeyEvent { data } => {
                                     debug_log::log_client_input(session_id, "KeyEvent", data.len());
                                     // Write key event to PTY
-                                    if let Err(e) = writer.write_all(&data) {
+                                    if let Err(e) = writer.write_all(&data).and_then(|_| writer.flush()) {
                                         tracing::error!(session_id = session_id, error = %e, "Failed to write to PTY");
-                                        break;
-                                    }
-                                    if let Err(e) = writer.flush() {
-                                        tracing::error!(session_id = session_id, error = %e, "Failed to flush PTY");
-                                        break;
+                                        let (message, fatal) = pty_write_failure(&e);
+                                        if let Ok(encoded) = rkyv::to_bytes::<rkyv::rancor::Error>(&ServerMessage::Error { message }) {
+                                            let len = (encoded.len() as u32).to_be_bytes();
+                                            let mut full_msg = Vec::new();
+                                            full_msg.extend_from_slice(&len);
+                                            full_msg.extend_from_slice(&encoded);
+                                            let _ = send_tx.send(full_msg);
+                                        }
+                                        if fatal {
+                                            break;
+                                        }
                                     }
                                 }
                                 ClientMessage::Resize { cols, rows } => {
@@ -781,6 +2214,8 @@ impl KerrServer {
         session_id: String,
         mut incoming: tokio::sync::mpsc::UnboundedReceiver<crate::ClientMessage>,
         outgoing: tokio::sync::mpsc::UnboundedSender<crate::MessageEnvelope>,
+        config: Arc<ServerConfig>,
+        start: ShellStart,
     ) -> Result<(), AcceptError> {
         let session_id_short = if session_id.len() >= 8 { &session_id[..8] } else { &session_id };
 
@@ -808,124 +2243,408 @@ impl KerrServer {
             debug_log::log_pty_created(session_id_short, pty_fd);
         }
 
-        // Spawn bash in the PTY
-        let username = std::env::var("USER").unwrap_or_else(|_| "user".to_string());
-        let prompt_cmd = format!(
-            "export PS1='{}@kerr \\w> ' && exec bash --norc --noprofile",
-            username
-        );
-
-        let mut cmd = CommandBuilder::new("bash");
-        cmd.arg("-c");
-        cmd.arg(&prompt_cmd);
-        cmd.env("TERM", "xterm-256color");
+        // Spawn the shell in the PTY
+        let mut cmd = shell_command(&config);
+        let term = match start.term {
+            Some(term) if valid_term(&term) => term,
+            Some(term) => {
+                tracing::warn!(session_id = %session_id, term = ?term, "Ignoring invalid TERM from client");
+                DEFAULT_TERM.to_string()
+            }
+            None => DEFAULT_TERM.to_string(),
+        };
+        cmd.env("TERM", term);
+
+        // Start in the directory the client asked for, falling back to $HOME. With a
+        // --root that directory must lie inside it, and the shell starts at the root
+        // instead when none is asked for or it's outside.
+        let root_dir = config.root.as_ref().map(|root| root.to_string_lossy().to_string());
+        let start_dir = match start.cwd {
+            Some(requested) => {
+                let confined = match &config.root {
+                    Some(root) => confine_path(root, Path::new(&requested), true),
+                    None => Ok(std::path::PathBuf::from(&requested)),
+                };
+                match confined {
+                    Ok(dir) if dir.is_dir() => Some(dir.to_string_lossy().to_string()),
+                    confined => {
+                        let fallback = root_dir.or_else(|| {
+                            std::env::var("HOME").ok().filter(|home| Path::new(home).is_dir())
+                        });
+                        let problem = if confined.is_err() { "is outside the server root" } else { "does not exist" };
+                        let message = format!(
+                            "\r\n[kerr] Warning: directory '{}' {}, starting in {}\r\n\r\n",
+                            requested,
+                            problem,
+                            fallback.as_deref().unwrap_or("the server's working directory"),
+                        );
+                        let notice = crate::MessageEnvelope {
+                            session_id: session_id.clone(),
+                            payload: crate::MessagePayload::Server(crate::ServerMessage::Output {
+                                data: message.into_bytes(),
+                            }),
+                        };
+                        let _ = outgoing.send(notice);
+                        fallback
+                    }
+                }
+            }
+            None => root_dir,
+        };
+        if let Some(dir) = start_dir {
+            tracing::info!(session_id = %session_id, cwd = %dir, "Starting shell in requested directory");
+            cmd.cwd(dir);
+        }
 
         debug_log::log_bash_spawn_start(session_id_short);
-        let child = pair
-            .slave
-            .spawn_command(cmd)
-            .map_err(|e| {
+        let child = match pair.slave.spawn_command(cmd) {
+            Ok(child) => child,
+            Err(e) => {
                 debug_log::log_bash_spawn_failed(session_id_short, &e.to_string());
-                AcceptError::from_err(PtyError(format!("Failed to spawn bash: {}", e)))
-            })?;
+                // Tell the client why, rather than just closing the session
+                let message = format!("Session ended: failed to start shell {}: {}", shell_path(&config).display(), e);
+                let _ = outgoing.send(crate::MessageEnvelope {
+                    session_id: session_id.clone(),
+                    payload: crate::MessagePayload::Server(crate::ServerMessage::Error { message: message.clone() }),
+                });
+                return Err(AcceptError::from_err(PtyError(message)));
+            }
+        };
 
         if let Some(pid) = child.process_id() {
             debug_log::log_bash_spawned(session_id_short, pid);
             tracing::info!(node_id = %node_id, session_id = %session_id, pid = pid, "Spawned bash in PTY");
         }
+        // Only the shell holds the slave side now, so reads end when it exits
+        drop(pair.slave);
 
         let mut reader = pair.master.try_clone_reader()
             .map_err(|e| AcceptError::from_err(PtyError(format!("Failed to clone reader: {}", e))))?;
         let mut writer = pair.master.take_writer()
             .map_err(|e| AcceptError::from_err(PtyError(format!("Failed to take writer: {}", e))))?;
 
+        // Queued before the PTY is read, so it shows up ahead of the prompt
+        if let Some(data) = motd_output(&config) {
+            let motd = crate::MessageEnvelope {
+                session_id: session_id.clone(),
+                payload: crate::MessagePayload::Server(crate::ServerMessage::Output { data }),
+            };
+            let _ = outgoing.send(motd);
+        }
+
         let master = Arc::new(std::sync::Mutex::new(pair.master));
         let master_clone = master.clone();
 
         let session_id_clone = session_id.clone();
-        let outgoing_clone = outgoing.clone();
+        let (pty_tx, pty_rx) = tokio::sync::mpsc::unbounded_channel::<PtyOutput>();
+        let read_buffer_size = config.pty_read_buffer.max(1);
 
-        // Task to read from PTY and send to client
+        // Task to read from PTY and hand the bytes to the output forwarder
         // IMPORTANT: PTY reading is BLOCKING I/O - must use spawn_blocking, not spawn!
+        let mut child = child;
         let pty_task = tokio::task::spawn_blocking(move || {
             tracing::info!(session_id = %session_id_clone, "PTY read task started");
-            loop {
+            let mut buf = vec![0u8; read_buffer_size];
+            let exited = loop {
                 tracing::debug!(session_id = %session_id_clone, "PTY task: waiting for data...");
-                let mut buf = [0u8; 8192];
 
                 match reader.read(&mut buf) {
-                    Ok(0) => {
-                        // Bash exited
-                        tracing::info!(session_id = %session_id_clone, "Bash exited");
-                        let envelope = crate::MessageEnvelope {
-                            session_id: session_id_clone.clone(),
-                            payload: crate::MessagePayload::Server(crate::ServerMessage::Error {
-                                message: "Session ended: bash exited".to_string(),
-                            }),
-                        };
-                        let _ = outgoing_clone.send(envelope);
-                        break;
-                    }
+                    Ok(0) => break true,
                     Ok(n) => {
                         tracing::debug!(session_id = %session_id_clone, bytes = n, "Read from PTY");
-                        let envelope = crate::MessageEnvelope {
-                            session_id: session_id_clone.clone(),
-                            payload: crate::MessagePayload::Server(crate::ServerMessage::Output {
-                                data: buf[..n].to_vec(),
-                            }),
-                        };
-                        if outgoing_clone.send(envelope).is_err() {
+                        STATS.pty_bytes_read.fetch_add(n as u64, Ordering::Relaxed);
+                        if pty_tx.send(PtyOutput::Data(buf[..n].to_vec())).is_err() {
                             tracing::warn!(session_id = %session_id_clone, "Failed to send PTY output (channel closed)");
-                            break;
+                            break false;
                         }
                     }
+                    // The master side reports EIO once the shell has closed the slave
+                    Err(e) if cfg!(unix) && e.raw_os_error() == Some(5) => break true,
                     Err(e) => {
                         tracing::error!(session_id = %session_id_clone, error = %e, "PTY read error");
-                        break;
+                        break false;
+                    }
+                }
+            };
+            if exited {
+                // Everything the shell wrote has been read; its exit code comes last
+                match child.wait() {
+                    Ok(status) => {
+                        tracing::info!(session_id = %session_id_clone, code = status.exit_code(), "Bash exited");
+                        let _ = pty_tx.send(PtyOutput::Exit(status.exit_code() as i32));
+                    }
+                    Err(e) => {
+                        tracing::warn!(session_id = %session_id_clone, error = %e, "Failed to get bash exit status");
                     }
                 }
+                let _ = pty_tx.send(PtyOutput::Eof);
             }
             tracing::info!(session_id = %session_id_clone, "PTY task ended");
         });
 
+        // Task to coalesce PTY output into fewer, larger Output envelopes
+        let forward_task = tokio::spawn(Self::forward_pty_output(
+            session_id.clone(),
+            pty_rx,
+            outgoing.clone(),
+            config.pty_coalesce,
+        ));
+
+        // Command logging is announced to the client before anything is typed
+        let mut command_log = if config.log_commands {
+            let notice = crate::MessageEnvelope {
+                session_id: session_id.clone(),
+                payload: crate::MessagePayload::Server(crate::ServerMessage::Output {
+                    data: b"\r\n[kerr] Notice: commands typed in this session are logged by the server.\r\n\r\n".to_vec(),
+                }),
+            };
+            let _ = outgoing.send(notice);
+            Some(CommandLineBuffer::default())
+        } else {
+            None
+        };
+
+        // Apply a resize to the PTY
+        let resize_pty = |cols: u16, rows: u16| {
+            let new_size = PtySize {
+                rows,
+                cols,
+                pixel_width: 0,
+                pixel_height: 0,
+            };
+            if let Ok(master_guard) = master_clone.lock() {
+                let _ = master_guard.resize(new_size);
+                tracing::info!(session_id = %session_id, cols = cols, rows = rows, "PTY resized successfully");
+            }
+        };
+        let mut resizes = crate::ResizeLimiter::new(config.max_resizes_per_sec);
+
         // Main loop: handle incoming messages
         tracing::info!(session_id = %session_id, "Shell session waiting for client messages");
-        while let Some(msg) = incoming.recv().await {
+        loop {
+            let msg = tokio::select! {
+                msg = incoming.recv() => match msg {
+                    Some(msg) => msg,
+                    None => break,
+                },
+                // A held-back resize whose turn has come
+                (cols, rows) = resizes.next_due() => {
+                    resize_pty(cols, rows);
+                    continue;
+                }
+            };
             match msg {
                 crate::ClientMessage::KeyEvent { data } => {
                     tracing::debug!(session_id = %session_id, bytes = data.len(), "Received KeyEvent");
-                    if writer.write_all(&data).is_err() {
-                        break;
+                    if let Some(buffer) = command_log.as_mut() {
+                        for command in buffer.feed(&data) {
+                            tracing::info!(target: "kerr::audit", node_id = %node_id, session_id = %session_id,
+                                command = %command, "Shell command");
+                        }
+                    }
+                    if let Err(e) = writer.write_all(&data).and_then(|_| writer.flush()) {
+                        tracing::error!(session_id = %session_id, error = %e, "Failed to write to PTY");
+                        let (message, fatal) = pty_write_failure(&e);
+                        let _ = outgoing.send(crate::MessageEnvelope {
+                            session_id: session_id.clone(),
+                            payload: crate::MessagePayload::Server(crate::ServerMessage::Error { message }),
+                        });
+                        if fatal {
+                            break;
+                        }
+                    }
+                }
+                crate::ClientMessage::Resize { cols, rows } => {
+                    tracing::info!(session_id = %session_id, cols = cols, rows = rows, "Received Resize");
+                    // Past the cap, resizes wait their turn and only the latest is applied
+                    if let Some((cols, rows)) = resizes.offer(cols, rows) {
+                        resize_pty(cols, rows);
+                    }
+                }
+                crate::ClientMessage::Disconnect => {
+                    tracing::info!(session_id = %session_id, "Client requested disconnect");
+                    break;
+                }
+                _ => {
+                    tracing::warn!(session_id = %session_id, "Unexpected message type for shell session");
+                }
+            }
+        }
+        if resizes.dropped() > 0 {
+            tracing::debug!(session_id = %session_id, dropped = resizes.dropped(), "Resizes superseded during the session");
+        }
+
+        pty_task.abort();
+        forward_task.abort();
+        debug_log::log_session_end(session_id_short);
+        tracing::info!(node_id = %node_id, session_id = %session_id, "Shell session closed");
+
+        Ok(())
+    }
+
+    /// Forward PTY output to the client, batching reads that arrive within `window`
+    async fn forward_pty_output(
+        session_id: String,
+        mut pty_rx: tokio::sync::mpsc::UnboundedReceiver<PtyOutput>,
+        outgoing: tokio::sync::mpsc::UnboundedSender<crate::MessageEnvelope>,
+        window: std::time::Duration,
+    ) {
+        let mut eof = false;
+        let mut exit_code = None;
+
+        while !eof {
+            let mut data = match pty_rx.recv().await {
+                Some(PtyOutput::Data(data)) => data,
+                Some(PtyOutput::Eof) => {
+                    eof = true;
+                    Vec::new()
+                }
+                Some(PtyOutput::Exit(code)) => {
+                    exit_code = Some(code);
+                    continue;
+                }
+                None => break,
+            };
+
+            // Keep collecting until the window closes or enough output is buffered
+            if !eof && !window.is_zero() {
+                let deadline = tokio::time::Instant::now() + window;
+                while data.len() < PTY_COALESCE_MAX_BYTES {
+                    match tokio::time::timeout_at(deadline, pty_rx.recv()).await {
+                        Ok(Some(PtyOutput::Data(more))) => data.extend_from_slice(&more),
+                        Ok(Some(PtyOutput::Eof)) => {
+                            eof = true;
+                            break;
+                        }
+                        Ok(Some(PtyOutput::Exit(code))) => {
+                            exit_code = Some(code);
+                            break;
+                        }
+                        Ok(None) | Err(_) => break,
+                    }
+                }
+            }
+
+            if !data.is_empty() {
+                let envelope = crate::MessageEnvelope {
+                    session_id: session_id.clone(),
+                    payload: crate::MessagePayload::Server(crate::ServerMessage::Output { data }),
+                };
+                if outgoing.send(envelope).is_err() {
+                    tracing::warn!(session_id = %session_id, "Failed to send PTY output (channel closed)");
+                    return;
+                }
+            }
+        }
+
+        if eof {
+            if let Some(code) = exit_code {
+                let envelope = crate::MessageEnvelope {
+                    session_id: session_id.clone(),
+                    payload: crate::MessagePayload::Server(crate::ServerMessage::Exit { code }),
+                };
+                let _ = outgoing.send(envelope);
+            }
+            let envelope = crate::MessageEnvelope {
+                session_id: session_id.clone(),
+                payload: crate::MessagePayload::Server(crate::ServerMessage::Error {
+                    message: "Session ended: shell exited".to_string(),
+                }),
+            };
+            let _ = outgoing.send(envelope);
+        }
+    }
+
+    /// Exec session handler for multiplexed mode: run `command` with `<shell> -c`, with
+    /// stdin closed and stdout and stderr on pipes, then report its exit code
+    async fn handle_exec_session_mux(
+        node_id: iroh::PublicKey,
+        session_id: String,
+        mut incoming: tokio::sync::mpsc::UnboundedReceiver<crate::ClientMessage>,
+        outgoing: tokio::sync::mpsc::UnboundedSender<crate::MessageEnvelope>,
+        config: Arc<ServerConfig>,
+        command: String,
+    ) -> Result<(), AcceptError> {
+        use tokio::io::AsyncReadExt;
+
+        let send = |payload| {
+            outgoing.send(crate::MessageEnvelope {
+                session_id: session_id.clone(),
+                payload: crate::MessagePayload::Server(payload),
+            })
+        };
+
+        // Announced on stderr, so stdout only carries the command's output
+        if config.log_commands {
+            let _ = send(crate::ServerMessage::ErrorOutput {
+                data: b"[kerr] Notice: commands run in this session are logged by the server.\n".to_vec(),
+            });
+            tracing::info!(target: "kerr::audit", node_id = %node_id, session_id = %session_id,
+                command = %command, "Shell command");
+        }
+
+        let shell = shell_path(&config);
+        let mut cmd = tokio::process::Command::new(&shell);
+        cmd.arg("-c")
+            .arg(&command)
+            .stdin(std::process::Stdio::null())
+            .stdout(std::process::Stdio::piped())
+            .stderr(std::process::Stdio::piped())
+            .kill_on_drop(true);
+        if let Some(root) = &config.root {
+            cmd.current_dir(root);
+        }
+        let mut child = match cmd.spawn() {
+            Ok(child) => child,
+            Err(e) => {
+                let message = format!("Session ended: failed to start shell {}: {}", shell.display(), e);
+                let _ = send(crate::ServerMessage::Error { message });
+                return Err(AcceptError::from_err(e));
+            }
+        };
+        tracing::info!(node_id = %node_id, session_id = %session_id, pid = ?child.id(), "Running command");
+
+        let (Some(mut stdout), Some(mut stderr)) = (child.stdout.take(), child.stderr.take()) else {
+            unreachable!("stdout and stderr are piped");
+        };
+        let mut stdout_buf = vec![0u8; config.pty_read_buffer.max(1)];
+        let mut stderr_buf = vec![0u8; config.pty_read_buffer.max(1)];
+        let (mut stdout_open, mut stderr_open) = (true, true);
+        while stdout_open || stderr_open {
+            tokio::select! {
+                read = stdout.read(&mut stdout_buf), if stdout_open => match read {
+                    Ok(n) if n > 0 => {
+                        let _ = send(crate::ServerMessage::Output { data: stdout_buf[..n].to_vec() });
                     }
-                    let _ = writer.flush();
-                }
-                crate::ClientMessage::Resize { cols, rows } => {
-                    tracing::info!(session_id = %session_id, cols = cols, rows = rows, "Received Resize");
-                    let new_size = PtySize {
-                        rows,
-                        cols,
-                        pixel_width: 0,
-                        pixel_height: 0,
-                    };
-                    if let Ok(master_guard) = master_clone.lock() {
-                        let _ = master_guard.resize(new_size);
-                        tracing::info!(session_id = %session_id, "PTY resized successfully");
+                    _ => stdout_open = false,
+                },
+                read = stderr.read(&mut stderr_buf), if stderr_open => match read {
+                    Ok(n) if n > 0 => {
+                        let _ = send(crate::ServerMessage::ErrorOutput { data: stderr_buf[..n].to_vec() });
                     }
-                }
-                crate::ClientMessage::Disconnect => {
-                    tracing::info!(session_id = %session_id, "Client requested disconnect");
-                    break;
-                }
-                _ => {
-                    tracing::warn!(session_id = %session_id, "Unexpected message type for shell session");
-                }
+                    _ => stderr_open = false,
+                },
+                msg = incoming.recv() => match msg {
+                    Some(crate::ClientMessage::Disconnect) | None => {
+                        // Dropping the child kills the command
+                        tracing::info!(session_id = %session_id, "Client left before the command finished");
+                        return Ok(());
+                    }
+                    Some(_) => tracing::warn!(session_id = %session_id, "Unexpected message type for exec session"),
+                },
             }
         }
 
-        pty_task.abort();
-        debug_log::log_session_end(session_id_short);
-        tracing::info!(node_id = %node_id, session_id = %session_id, "Shell session closed");
-
+        let message = match child.wait().await {
+            Ok(status) => {
+                let code = exit_status_code(status);
+                tracing::info!(session_id = %session_id, code = code, "Command exited");
+                let _ = send(crate::ServerMessage::Exit { code });
+                "Session ended: command exited".to_string()
+            }
+            Err(e) => format!("Session ended: failed to wait for the command: {}", e),
+        };
+        let _ = send(crate::ServerMessage::Error { message });
         Ok(())
     }
 
@@ -1151,7 +2870,7 @@ impl KerrServer {
                     let is_dir = file_path.is_dir();
 
                     // Calculate total size
-                    let total_size = match crate::transfer::calculate_size(file_path) {
+                    let total_size = match crate::transfer::calculate_size(file_path, crate::transfer::SymlinkMode::Skip) {
                         Ok(size) => size,
                         Err(e) => {
                             let err_msg = crate::ServerMessage::Error {
@@ -1201,7 +2920,7 @@ impl KerrServer {
                     }
 
                     // Get all files to send
-                    let files = match crate::transfer::get_files_recursive(file_path) {
+                    let files = match crate::transfer::get_files_recursive(file_path, crate::transfer::SymlinkMode::Skip) {
                         Ok(files) => files,
                         Err(e) => {
                             let err_msg = crate::ServerMessage::Error {
@@ -1441,26 +3160,7 @@ impl KerrServer {
                 crate::ClientMessage::FsMetadata { path } => {
                     println!("\r\nFsMetadata request: {}\r", path);
 
-                    match std::fs::metadata(Path::new(&path)) {
-                        Ok(metadata) => {
-                            use crate::custom_explorer::file_explorer::FileMetadata;
-
-                            let file_metadata = FileMetadata {
-                                size: metadata.len(),
-                                created: metadata.created().ok(),
-                                modified: metadata.modified().ok(),
-                                is_dir: metadata.is_dir(),
-                            };
-
-                            let metadata_json = serde_json::to_string(&file_metadata).unwrap();
-                            crate::ServerMessage::FsMetadataResponse { metadata_json }
-                        }
-                        Err(e) => {
-                            crate::ServerMessage::FsError {
-                                message: format!("Failed to get metadata: {}", e),
-                            }
-                        }
-                    }
+                    metadata_response(&path)
                 }
 
                 crate::ClientMessage::FsReadFile { path } => {
@@ -1481,40 +3181,76 @@ impl KerrServer {
                 crate::ClientMessage::FsHashFile { path } => {
                     println!("\r\nFsHashFile request: {}\r", path);
 
-                    match std::fs::read(Path::new(&path)) {
-                        Ok(data) => {
-                            // Calculate blake3 hash
-                            let hash = blake3::hash(&data);
-                            let hash_hex = hash.to_hex().to_string();
-                            crate::ServerMessage::FsHashResponse { hash: hash_hex }
+                    tokio::task::spawn_blocking(move || hash_response(&path, None))
+                        .await
+                        .unwrap_or_else(|e| crate::ServerMessage::FsError {
+                            message: format!("Failed to hash file: {}", e),
+                        })
+                }
+
+                crate::ClientMessage::FsDelete { ref path } | crate::ClientMessage::FsDeleteRecursive { ref path } => {
+                    let recursive = matches!(msg, crate::ClientMessage::FsDeleteRecursive { .. });
+                    println!("\r\nFsDelete request: {} (recursive: {})\r", path, recursive);
+
+                    match delete_path(Path::new(path), recursive) {
+                        Ok(()) => {
+                            println!("\r\nSuccessfully deleted: {}\r", path);
+                            crate::ServerMessage::FsDeleteResponse { success: true }
                         }
                         Err(e) => {
+                            eprintln!("\r\nFailed to delete {}: {}\r", path, e);
                             crate::ServerMessage::FsError {
-                                message: format!("Failed to hash file: {}", e),
+                                message: format!("Failed to delete: {}", e),
                             }
                         }
                     }
                 }
 
-                crate::ClientMessage::FsDelete { path } => {
-                    println!("\r\nFsDelete request: {}\r", path);
+                crate::ClientMessage::FsBatch { ops } => {
+                    println!("\r\nFsBatch request: {} operations\r", ops.len());
 
-                    let path_obj = Path::new(&path);
-                    let result = if path_obj.is_dir() {
-                        std::fs::remove_dir_all(path_obj)
-                    } else {
-                        std::fs::remove_file(path_obj)
-                    };
+                    crate::ServerMessage::FsBatchResponse {
+                        results: ops.iter().map(apply_fs_op).collect(),
+                    }
+                }
 
-                    match result {
-                        Ok(()) => {
-                            println!("\r\nSuccessfully deleted: {}\r", path);
-                            crate::ServerMessage::FsDeleteResponse { success: true }
+                crate::ClientMessage::FsMkdir { path } => {
+                    println!("\r\nFsMkdir request: {}\r", path);
+
+                    match crate::custom_explorer::filesystem::make_dir(Path::new(&path)) {
+                        Ok(()) => crate::ServerMessage::FsMkdirResponse { success: true },
+                        Err(e) => {
+                            eprintln!("\r\nFailed to create directory {}: {}\r", path, e);
+                            crate::ServerMessage::FsError {
+                                message: format!("Failed to create directory: {}", e),
+                            }
                         }
+                    }
+                }
+
+                crate::ClientMessage::FsRename { from, to } => {
+                    println!("\r\nFsRename request: {} -> {}\r", from, to);
+
+                    match crate::custom_explorer::filesystem::move_path(Path::new(&from), Path::new(&to)) {
+                        Ok(()) => crate::ServerMessage::FsRenameResponse { success: true },
                         Err(e) => {
-                            eprintln!("\r\nFailed to delete {}: {}\r", path, e);
+                            eprintln!("\r\nFailed to rename {}: {}\r", from, e);
                             crate::ServerMessage::FsError {
-                                message: format!("Failed to delete: {}", e),
+                                message: format!("Failed to rename: {}", e),
+                            }
+                        }
+                    }
+                }
+
+                crate::ClientMessage::FsWriteFile { path, data } => {
+                    println!("\r\nFsWriteFile request: {} ({} bytes)\r", path, data.len());
+
+                    match write_file_atomic(Path::new(&path), &data) {
+                        Ok(()) => crate::ServerMessage::FsWriteResponse { success: true },
+                        Err(e) => {
+                            eprintln!("\r\nFailed to write {}: {}\r", path, e);
+                            crate::ServerMessage::FsError {
+                                message: format!("Failed to write file: {}", e),
                             }
                         }
                     }
@@ -1562,15 +3298,37 @@ impl KerrServer {
         session_id: String,
         mut incoming: tokio::sync::mpsc::UnboundedReceiver<crate::ClientMessage>,
         outgoing: tokio::sync::mpsc::UnboundedSender<crate::MessageEnvelope>,
+        config: Arc<ServerConfig>,
     ) -> Result<(), AcceptError> {
         tracing::info!(node_id = %node_id, session_id = %session_id, "File browser session started");
 
         // File upload state (for uploads through the file browser session)
         let mut upload_file: Option<std::fs::File> = None;
         let mut upload_path: Option<String> = None;
+        // Set when an upload is refused; reported in place of the ack after EndUpload
+        let mut upload_rejected: Option<String> = None;
+        // Bytes written for the current upload, checked against `max_upload`
+        let mut upload_received: u64 = 0;
 
         // Process incoming file browser requests
         while let Some(msg) = incoming.recv().await {
+            let is_upload = matches!(msg, crate::ClientMessage::StartUpload { .. });
+            let msg = match confine_request(config.root.as_deref(), msg) {
+                Ok(msg) => msg,
+                Err(message) if is_upload => {
+                    upload_rejected = Some(message);
+                    continue;
+                }
+                Err(message) => {
+                    tracing::warn!(session_id = %session_id, %message, "Refused path outside the root");
+                    let response = crate::MessageEnvelope {
+                        session_id: session_id.clone(),
+                        payload: crate::MessagePayload::Server(crate::ServerMessage::FsError { message }),
+                    };
+                    let _ = outgoing.send(response);
+                    continue;
+                }
+            };
             match msg {
                 crate::ClientMessage::FsReadDir { path } => {
                     tracing::debug!(session_id = %session_id, path = %path, "FsReadDir request");
@@ -1647,6 +3405,21 @@ impl KerrServer {
                 crate::ClientMessage::FsReadFile { path } => {
                     tracing::debug!(session_id = %session_id, path = %path, "FsReadFile request");
 
+                    // Check the size before reading the whole file into memory
+                    if let Some(limit) = config.max_file_read
+                        && let Ok(metadata) = std::fs::metadata(&path)
+                        && metadata.len() > limit
+                    {
+                        let response = crate::MessageEnvelope {
+                            session_id: session_id.clone(),
+                            payload: crate::MessagePayload::Server(crate::ServerMessage::FsError {
+                                message: size_limit_message("File", metadata.len(), limit),
+                            }),
+                        };
+                        let _ = outgoing.send(response);
+                        continue;
+                    }
+
                     match std::fs::read(&path) {
                         Ok(data) => {
                             let response = crate::MessageEnvelope {
@@ -1668,19 +3441,130 @@ impl KerrServer {
                         }
                     }
                 }
-                crate::ClientMessage::FsDelete { path } => {
-                    tracing::debug!(session_id = %session_id, path = %path, "FsDelete request");
+                crate::ClientMessage::FsLimits => {
+                    let response = crate::MessageEnvelope {
+                        session_id: session_id.clone(),
+                        payload: crate::MessagePayload::Server(crate::ServerMessage::FsLimitsResponse {
+                            max_file_read: config.max_file_read,
+                            max_upload: config.max_upload,
+                        }),
+                    };
+                    let _ = outgoing.send(response);
+                }
+                crate::ClientMessage::FsMode => {
+                    let response = crate::MessageEnvelope {
+                        session_id: session_id.clone(),
+                        payload: crate::MessagePayload::Server(crate::ServerMessage::FsModeResponse {
+                            read_only: config.read_only,
+                            root: config.root.as_ref().map(|root| root.to_string_lossy().to_string()),
+                        }),
+                    };
+                    let _ = outgoing.send(response);
+                }
+                crate::ClientMessage::FsDelete { ref path } | crate::ClientMessage::FsDeleteRecursive { ref path } => {
+                    let recursive = matches!(msg, crate::ClientMessage::FsDeleteRecursive { .. });
+                    tracing::debug!(session_id = %session_id, path = %path, recursive, "FsDelete request");
+
+                    let payload = if config.read_only {
+                        crate::ServerMessage::FsError { message: read_only_message("Delete") }
+                    } else {
+                        match delete_path(Path::new(path), recursive) {
+                            Ok(()) => crate::ServerMessage::FsDeleteResponse { success: true },
+                            Err(e) => crate::ServerMessage::FsError {
+                                message: format!("Failed to delete: {}", e),
+                            },
+                        }
+                    };
+                    let response = crate::MessageEnvelope {
+                        session_id: session_id.clone(),
+                        payload: crate::MessagePayload::Server(payload),
+                    };
+                    let _ = outgoing.send(response);
+                }
+                crate::ClientMessage::FsMetadata { path } => {
+                    tracing::debug!(session_id = %session_id, path = %path, "FsMetadata request");
+
+                    let response = crate::MessageEnvelope {
+                        session_id: session_id.clone(),
+                        payload: crate::MessagePayload::Server(metadata_response(&path)),
+                    };
+                    let _ = outgoing.send(response);
+                }
+                crate::ClientMessage::FsMkdir { path } => {
+                    tracing::debug!(session_id = %session_id, path = %path, "FsMkdir request");
 
-                    let success = if std::path::Path::new(&path).is_dir() {
-                        std::fs::remove_dir_all(&path).is_ok()
+                    let payload = if config.read_only {
+                        crate::ServerMessage::FsError { message: read_only_message("Creating a directory") }
                     } else {
-                        std::fs::remove_file(&path).is_ok()
+                        match crate::custom_explorer::filesystem::make_dir(Path::new(&path)) {
+                            Ok(()) => crate::ServerMessage::FsMkdirResponse { success: true },
+                            Err(e) => crate::ServerMessage::FsError {
+                                message: format!("Failed to create directory: {}", e),
+                            },
+                        }
+                    };
+                    let response = crate::MessageEnvelope {
+                        session_id: session_id.clone(),
+                        payload: crate::MessagePayload::Server(payload),
                     };
+                    let _ = outgoing.send(response);
+                }
+                crate::ClientMessage::FsRename { from, to } => {
+                    tracing::debug!(session_id = %session_id, from = %from, to = %to, "FsRename request");
 
+                    let payload = if config.read_only {
+                        crate::ServerMessage::FsError { message: read_only_message("Rename") }
+                    } else {
+                        match crate::custom_explorer::filesystem::move_path(Path::new(&from), Path::new(&to)) {
+                            Ok(()) => crate::ServerMessage::FsRenameResponse { success: true },
+                            Err(e) => crate::ServerMessage::FsError {
+                                message: format!("Failed to rename: {}", e),
+                            },
+                        }
+                    };
+                    let response = crate::MessageEnvelope {
+                        session_id: session_id.clone(),
+                        payload: crate::MessagePayload::Server(payload),
+                    };
+                    let _ = outgoing.send(response);
+                }
+                crate::ClientMessage::FsWriteFile { path, data } => {
+                    tracing::debug!(session_id = %session_id, path = %path, bytes = data.len(), "FsWriteFile request");
+
+                    // Same limit as an upload through this session
+                    let payload = match config.max_upload {
+                        _ if config.read_only => crate::ServerMessage::FsError { message: read_only_message("Write") },
+                        Some(limit) if data.len() as u64 > limit => crate::ServerMessage::FsError {
+                            message: size_limit_message("File", data.len() as u64, limit),
+                        },
+                        _ => match write_file_atomic(Path::new(&path), &data) {
+                            Ok(()) => crate::ServerMessage::FsWriteResponse { success: true },
+                            Err(e) => crate::ServerMessage::FsError {
+                                message: format!("Failed to write file: {}", e),
+                            },
+                        },
+                    };
+                    let response = crate::MessageEnvelope {
+                        session_id: session_id.clone(),
+                        payload: crate::MessagePayload::Server(payload),
+                    };
+                    let _ = outgoing.send(response);
+                }
+                crate::ClientMessage::FsBatch { ops } => {
+                    tracing::debug!(session_id = %session_id, count = ops.len(), "FsBatch request");
+
+                    // Every batch operation changes something
+                    let results = if config.read_only {
+                        ops.iter()
+                            .map(|_| crate::FsOpResult { success: false, error: Some(read_only_message("Operation")) })
+                            .collect()
+                    } else {
+                        ops.iter().map(apply_fs_op).collect()
+                    };
                     let response = crate::MessageEnvelope {
                         session_id: session_id.clone(),
-                        payload: crate::MessagePayload::Server(crate::ServerMessage::FsDeleteResponse {
-                            success,
+                        payload: crate::MessagePayload::Server(crate::ServerMessage::FsBatchResponse {
+                            results,
                         }),
                     };
                     let _ = outgoing.send(response);
@@ -1688,33 +3572,38 @@ impl KerrServer {
                 crate::ClientMessage::FsHashFile { path } => {
                     tracing::debug!(session_id = %session_id, path = %path, "FsHashFile request");
 
-                    match std::fs::read(&path) {
-                        Ok(data) => {
-                            let hash = blake3::hash(&data);
-                            let response = crate::MessageEnvelope {
-                                session_id: session_id.clone(),
-                                payload: crate::MessagePayload::Server(crate::ServerMessage::FsHashResponse {
-                                    hash: hash.to_hex().to_string(),
-                                }),
-                            };
-                            let _ = outgoing.send(response);
-                        }
-                        Err(e) => {
-                            let response = crate::MessageEnvelope {
-                                session_id: session_id.clone(),
-                                payload: crate::MessagePayload::Server(crate::ServerMessage::FsError {
-                                    message: format!("Failed to hash file: {}", e),
-                                }),
-                            };
-                            let _ = outgoing.send(response);
-                        }
-                    }
+                    // Hashing a large file takes a while; keep it off the runtime threads
+                    let limit = config.max_hash_size;
+                    let message = tokio::task::spawn_blocking(move || hash_response(&path, limit))
+                        .await
+                        .unwrap_or_else(|e| crate::ServerMessage::FsError {
+                            message: format!("Failed to hash file: {}", e),
+                        });
+                    let response = crate::MessageEnvelope {
+                        session_id: session_id.clone(),
+                        payload: crate::MessagePayload::Server(message),
+                    };
+                    let _ = outgoing.send(response);
                 }
                 crate::ClientMessage::StartUpload { path, size, is_dir: _, force } => {
                     tracing::info!(session_id = %session_id, path = %path, size = size, force = force,
                         "File upload requested via browser session");
 
                     let file_path = std::path::Path::new(&path);
+                    upload_received = 0;
+
+                    // The client streams all chunks before reading a reply, so a refused
+                    // upload is reported after EndUpload instead of right away
+                    if config.read_only {
+                        upload_rejected = Some(read_only_message("Upload"));
+                        continue;
+                    }
+                    if let Some(limit) = config.max_upload
+                        && size > limit
+                    {
+                        upload_rejected = Some(size_limit_message("Upload", size, limit));
+                        continue;
+                    }
 
                     // If not force mode and file exists, return error
                     if !force && file_path.exists() && !file_path.is_dir() {
@@ -1773,6 +3662,20 @@ impl KerrServer {
                 }
                 crate::ClientMessage::FileChunk { data } => {
                     use std::io::Write;
+
+                    // Don't trust the declared size: stop once the limit is actually exceeded
+                    let received = upload_received + data.len() as u64;
+                    if let Some(limit) = config.max_upload
+                        && upload_file.is_some()
+                        && received > limit
+                    {
+                        upload_rejected = Some(size_limit_message("Upload", received, limit));
+                        upload_file = None;
+                        upload_path = None;
+                        continue;
+                    }
+                    upload_received = received;
+
                     if let Some(ref mut file) = upload_file {
                         if let Err(e) = file.write_all(&data) {
                             tracing::error!(session_id = %session_id, error = %e, "Failed to write to file");
@@ -1803,9 +3706,13 @@ impl KerrServer {
                     }
 
                     // Send single ack for the entire upload sequence
+                    let payload = match upload_rejected.take() {
+                        Some(message) => crate::ServerMessage::Error { message },
+                        None => crate::ServerMessage::UploadAck,
+                    };
                     let response = crate::MessageEnvelope {
                         session_id: session_id.clone(),
-                        payload: crate::MessagePayload::Server(crate::ServerMessage::UploadAck),
+                        payload: crate::MessagePayload::Server(payload),
                     };
                     let _ = outgoing.send(response);
 
@@ -1983,11 +3890,12 @@ impl KerrServer {
                         }
                         Err(e) => {
                             // Send error response
-                            eprintln!("\r\nFailed to connect to localhost:{}: {}\r", destination_port, e);
+                            // Includes a host name that doesn't resolve
+                            eprintln!("\r\nFailed to connect to {}:{}: {}\r", target_host, destination_port, e);
                             let response = crate::ServerMessage::TcpOpenResponse {
                                 stream_id,
                                 success: false,
-                                error: Some(format!("Failed to connect: {}", e)),
+                                error: Some(format!("Failed to connect to {}:{}: {}", target_host, destination_port, e)),
                             };
                             let encoded = match rkyv::to_bytes::<rkyv::rancor::Error>(&response) {
                                 Ok(e) => e,
@@ -2099,6 +4007,8 @@ impl KerrServer {
         session_id: String,
         mut incoming: tokio::sync::mpsc::UnboundedReceiver<crate::ClientMessage>,
         outgoing: tokio::sync::mpsc::UnboundedSender<crate::MessageEnvelope>,
+        mut queue_depth: tokio::sync::watch::Receiver<usize>,
+        config: Arc<ServerConfig>,
     ) -> Result<(), AcceptError> {
         use std::path::Path;
 
@@ -2110,9 +4020,54 @@ impl KerrServer {
         let mut upload_base_path: Option<String> = None; // base dir for directory uploads
         // Pending upload awaiting user confirmation: (path, size, is_dir, force)
         let mut pending_upload: Option<(String, u64, bool, bool)> = None;
+        // Flow control: bytes written for the current upload and the last amount acknowledged
+        let mut upload_total: u64 = 0;
+        let mut upload_received: u64 = 0;
+        let mut upload_acked: u64 = 0;
+        // First failure during the current upload, reported in UploadComplete
+        let mut upload_error: Option<String> = None;
+        // Clients that never send TransferOptions can't handle Symlink messages
+        let mut symlink_mode = crate::transfer::SymlinkMode::Skip;
+        // The same clients also expect `Preparing` while a download is enumerated
+        let mut announce_preparing = false;
+        // Files a resuming client already has, by relative path with the size and
+        // modification time it got; left out of the next directory download if unchanged
+        let mut skip_files: std::collections::HashMap<String, (u64, Option<i64>)> = std::collections::HashMap::new();
+        // Whether the client understands `FileHole` in downloads
+        let mut sparse = false;
+        // Whether the client understands `CompressedChunk` in downloads
+        let mut compress = false;
+        // Whether the client wants each downloaded file's hash
+        let mut hashes = false;
+        // How much of its file the client believes the next upload already has here
+        let mut resume_upload: Option<(u64, String)> = None;
+        // The resume request of an upload waiting on `pending_upload`'s confirmation
+        let mut pending_resume: Option<(u64, String)> = None;
+
+        // Messages that arrived while a download was being sent
+        let mut deferred: std::collections::VecDeque<crate::ClientMessage> = std::collections::VecDeque::new();
 
         // Process incoming messages
-        while let Some(msg) = incoming.recv().await {
+        loop {
+            let msg = match deferred.pop_front() {
+                Some(msg) => msg,
+                None => match incoming.recv().await {
+                    Some(msg) => msg,
+                    None => break,
+                },
+            };
+            let msg = match confine_request(config.root.as_deref(), msg) {
+                Ok(msg) => msg,
+                Err(message) => {
+                    tracing::warn!(session_id = %session_id, %message, "Refused path outside the root");
+                    let response = crate::MessageEnvelope {
+                        session_id: session_id.clone(),
+                        payload: crate::MessagePayload::Server(crate::ServerMessage::Error { message }),
+                    };
+                    let _ = outgoing.send(response);
+                    continue;
+                }
+            };
             match msg {
                 crate::ClientMessage::StartUpload { path, size, is_dir, force } => {
                     use std::io::Write;
@@ -2121,6 +4076,34 @@ impl KerrServer {
                         "Client requested upload");
 
                     let file_path = Path::new(&path);
+                    let resume_offset = resume_upload.take();
+                    upload_total = size;
+                    upload_received = 0;
+                    upload_acked = 0;
+                    upload_error = None;
+
+                    if config.read_only {
+                        let response = crate::MessageEnvelope {
+                            session_id: session_id.clone(),
+                            payload: crate::MessagePayload::Server(crate::ServerMessage::Error {
+                                message: read_only_message("Upload"),
+                            }),
+                        };
+                        let _ = outgoing.send(response);
+                        continue;
+                    }
+                    if let Some(limit) = config.max_upload
+                        && size > limit
+                    {
+                        let response = crate::MessageEnvelope {
+                            session_id: session_id.clone(),
+                            payload: crate::MessagePayload::Server(crate::ServerMessage::Error {
+                                message: size_limit_message("Upload", size, limit),
+                            }),
+                        };
+                        let _ = outgoing.send(response);
+                        continue;
+                    }
 
                     if is_dir {
                         // Directory upload: create the base directory
@@ -2146,13 +4129,40 @@ impl KerrServer {
                     } else {
                         // Single file upload
 
-                        // If not force mode and file exists, ask for confirmation
+                        // If not force mode and file exists, ask for confirmation. Resuming
+                        // keeps part of the existing file, so it needs the same go-ahead.
                         if !force && file_path.exists() && !file_path.is_dir() {
+                            let message = if resume_offset.is_some() {
+                                format!("File '{}' already exists. Resume the interrupted upload into it (or overwrite it if it differs)?", path)
+                            } else {
+                                format!("File '{}' already exists. Overwrite?", path)
+                            };
                             pending_upload = Some((path.clone(), size, is_dir, force));
+                            pending_resume = resume_offset;
                             let response = crate::MessageEnvelope {
                                 session_id: session_id.clone(),
                                 payload: crate::MessagePayload::Server(crate::ServerMessage::ConfirmPrompt {
-                                    message: format!("File '{}' already exists. Overwrite?", path),
+                                    message,
+                                }),
+                            };
+                            let _ = outgoing.send(response);
+                            continue;
+                        }
+
+                        // A client resuming its own interrupted upload continues after
+                        // what's already here, as long as that matches its copy
+                        if let Some((offset, hash)) = resume_offset
+                            && let Some(file) = resume_partial_upload(&session_id, &path, offset, &hash)
+                        {
+                            upload_file = Some(file);
+                            upload_path = Some(path.clone());
+                            upload_received = offset;
+                            upload_acked = offset;
+
+                            let response = crate::MessageEnvelope {
+                                session_id: session_id.clone(),
+                                payload: crate::MessagePayload::Server(crate::ServerMessage::UploadResumed {
+                                    offset,
                                 }),
                             };
                             let _ = outgoing.send(response);
@@ -2213,6 +4223,7 @@ impl KerrServer {
                     if !confirmed {
                         tracing::info!(session_id = %session_id, "Upload cancelled by user");
                         pending_upload = None;
+                        pending_resume = None;
                         continue;
                     }
 
@@ -2220,6 +4231,24 @@ impl KerrServer {
                     if let Some((path, _size, _is_dir, _force)) = pending_upload.take() {
                         let file_path = Path::new(&path);
 
+                        if let Some((offset, hash)) = pending_resume.take()
+                            && let Some(file) = resume_partial_upload(&session_id, &path, offset, &hash)
+                        {
+                            upload_file = Some(file);
+                            upload_path = Some(path);
+                            upload_received = offset;
+                            upload_acked = offset;
+
+                            let response = crate::MessageEnvelope {
+                                session_id: session_id.clone(),
+                                payload: crate::MessagePayload::Server(crate::ServerMessage::UploadResumed {
+                                    offset,
+                                }),
+                            };
+                            let _ = outgoing.send(response);
+                            continue;
+                        }
+
                         // Create parent directories if needed
                         if let Some(parent) = file_path.parent() {
                             if let Err(e) = std::fs::create_dir_all(parent) {
@@ -2263,9 +4292,27 @@ impl KerrServer {
                 crate::ClientMessage::FileStart { relative_path, size } => {
                     use std::io::Write;
 
+                    // The previous file in the directory is done; surface late write errors
+                    if let Err(e) = close_upload_file(&mut upload_file) {
+                        upload_error.get_or_insert(e);
+                    }
+
                     // Open a new file within a directory upload
                     if let Some(ref base_path) = upload_base_path {
-                        let full_path = Path::new(base_path).join(&relative_path);
+                        let full_path = match upload_entry_path(config.root.as_deref(), base_path, &relative_path, true) {
+                            Ok(full_path) => full_path,
+                            Err(message) => {
+                                let response = crate::MessageEnvelope {
+                                    session_id: session_id.clone(),
+                                    payload: crate::MessagePayload::Server(crate::ServerMessage::Error {
+                                        message: message.clone(),
+                                    }),
+                                };
+                                let _ = outgoing.send(response);
+                                upload_error.get_or_insert(message);
+                                continue;
+                            }
+                        };
 
                         // Create parent directories for nested files
                         if let Some(parent) = full_path.parent() {
@@ -2290,18 +4337,131 @@ impl KerrServer {
                                     }),
                                 };
                                 let _ = outgoing.send(response);
+                                upload_error.get_or_insert(format!("Failed to create file {}: {}", full_path.display(), e));
+                            }
+                        }
+                    } else {
+                        tracing::warn!(session_id = %session_id, "FileStart received without directory upload context");
+                    }
+                }
+                crate::ClientMessage::Symlink { relative_path, target } => {
+                    if let Err(e) = close_upload_file(&mut upload_file) {
+                        upload_error.get_or_insert(e);
+                    }
+
+                    let Some(ref base_path) = upload_base_path else {
+                        tracing::warn!(session_id = %session_id, "Symlink received without directory upload context");
+                        continue;
+                    };
+                    let created = upload_entry_path(config.root.as_deref(), base_path, &relative_path, false)
+                        .map_err(anyhow::Error::msg)
+                        .and_then(|full_path| {
+                            tracing::debug!(session_id = %session_id, path = ?full_path, target = %target,
+                                "Creating symlink within directory upload");
+                            crate::transfer::create_symlink(&full_path, &target)
+                        });
+                    if let Err(e) = created {
+                        tracing::error!(session_id = %session_id, relative_path = %relative_path, error = %e,
+                            "Failed to create symlink in directory upload");
+                        let response = crate::MessageEnvelope {
+                            session_id: session_id.clone(),
+                            payload: crate::MessagePayload::Server(crate::ServerMessage::Error {
+                                message: format!("{:#}", e),
+                            }),
+                        };
+                        let _ = outgoing.send(response);
+                        upload_error.get_or_insert(format!("{:#}", e));
+                    }
+                }
+                crate::ClientMessage::TransferOptions { dereference } => {
+                    announce_preparing = true;
+                    // Following links could lead a download out of the --root
+                    symlink_mode = if dereference && config.root.is_none() {
+                        crate::transfer::SymlinkMode::Follow
+                    } else {
+                        crate::transfer::SymlinkMode::Preserve
+                    };
+                }
+                crate::ClientMessage::SkipFiles { files } => {
+                    skip_files = files.into_iter().map(|file| (file.relative_path, (file.size, file.modified))).collect();
+                }
+                crate::ClientMessage::SparseFiles => {
+                    sparse = true;
+                }
+                crate::ClientMessage::CompressedChunks => {
+                    compress = true;
+                }
+                crate::ClientMessage::DownloadHashes => {
+                    hashes = true;
+                }
+                crate::ClientMessage::ResumeUpload { offset, hash } => {
+                    resume_upload = Some((offset, hash));
+                }
+                crate::ClientMessage::FileChunk { .. }
+                | crate::ClientMessage::FileHole { .. }
+                | crate::ClientMessage::CompressedChunk { .. } => {
+                    use std::io::Write;
+
+                    // A hole stands for `len` zero bytes the client didn't send
+                    let inflated;
+                    let chunk = match &msg {
+                        crate::ClientMessage::FileChunk { data } => Ok((data.as_slice(), 0)),
+                        crate::ClientMessage::FileHole { len } => crate::transfer::hole_len(*len).map(|len| (&[][..], len)),
+                        crate::ClientMessage::CompressedChunk { data } => match crate::transfer::decompress_chunk(data) {
+                            Ok(data) => {
+                                inflated = data;
+                                Ok((inflated.as_slice(), 0))
                             }
+                            Err(e) => Err(e),
+                        },
+                        _ => unreachable!(),
+                    };
+                    let (data, hole) = match chunk {
+                        Ok(chunk) => chunk,
+                        Err(e) => {
+                            tracing::error!(session_id = %session_id, error = %e, "Rejected upload chunk");
+                            let response = crate::MessageEnvelope {
+                                session_id: session_id.clone(),
+                                payload: crate::MessagePayload::Server(crate::ServerMessage::Error {
+                                    message: format!("{:#}", e),
+                                }),
+                            };
+                            let _ = outgoing.send(response);
+                            upload_error.get_or_insert(format!("{:#}", e));
+                            upload_file = None;
+                            upload_path = None;
+                            continue;
                         }
-                    } else {
-                        tracing::warn!(session_id = %session_id, "FileStart received without directory upload context");
+                    };
+                    let chunk_len = data.len() as u64 + hole;
+                    let received = upload_received.saturating_add(chunk_len);
+
+                    // Don't trust the declared size: stop once the limit is actually exceeded
+                    if let Some(limit) = config.max_upload
+                        && upload_file.is_some()
+                        && received > limit
+                    {
+                        let response = crate::MessageEnvelope {
+                            session_id: session_id.clone(),
+                            payload: crate::MessagePayload::Server(crate::ServerMessage::Error {
+                                message: size_limit_message("Upload", received, limit),
+                            }),
+                        };
+                        let _ = outgoing.send(response);
+                        upload_error.get_or_insert(size_limit_message("Upload", received, limit));
+                        upload_file = None;
+                        upload_path = None;
+                        continue;
                     }
-                }
-                crate::ClientMessage::FileChunk { data } => {
-                    use std::io::Write;
 
                     // Write chunk to file
                     if let Some(ref mut file) = upload_file {
-                        if let Err(e) = file.write_all(&data) {
+                        let written = if hole > 0 {
+                            crate::transfer::write_hole(file, hole)
+                        } else {
+                            file.write_all(data)
+                        };
+                        if let Err(e) = written {
                             tracing::error!(session_id = %session_id, error = %e, "Failed to write to file");
                             let response = crate::MessageEnvelope {
                                 session_id: session_id.clone(),
@@ -2310,24 +4470,100 @@ impl KerrServer {
                                 }),
                             };
                             let _ = outgoing.send(response);
+                            upload_error.get_or_insert(format!("Failed to write to file: {}", e));
                             // Clear upload state
                             upload_file = None;
                             upload_path = None;
+                        } else {
+                            upload_received = received;
+
+                            // Ack periodically so the sender can bound its in-flight data
+                            if upload_received - upload_acked >= crate::transfer::UPLOAD_ACK_INTERVAL {
+                                upload_acked = upload_received;
+                                let response = crate::MessageEnvelope {
+                                    session_id: session_id.clone(),
+                                    payload: crate::MessagePayload::Server(crate::ServerMessage::Progress {
+                                        bytes_transferred: upload_received,
+                                        total_bytes: upload_total,
+                                    }),
+                                };
+                                let _ = outgoing.send(response);
+                            }
                         }
                     } else {
                         tracing::warn!(session_id = %session_id, "Received file chunk without active file");
                     }
                 }
                 crate::ClientMessage::EndUpload => {
-                    if let Some(path) = &upload_path {
-                        tracing::info!(session_id = %session_id, path = %path, "File upload completed");
+                    let active = upload_path.is_some() || upload_base_path.is_some() || upload_error.is_some();
+
+                    // Close the file, flushing it to disk so late I/O errors are reported
+                    if let Err(e) = close_upload_file(&mut upload_file) {
+                        upload_error.get_or_insert(e);
                     }
-                    // Close the file and clear all upload state
-                    upload_file = None;
+                    match (&upload_path, &upload_error) {
+                        (_, Some(error)) => {
+                            tracing::warn!(session_id = %session_id, error = %error, "File upload failed");
+                        }
+                        (Some(path), None) => {
+                            tracing::info!(session_id = %session_id, path = %path, "File upload completed");
+                        }
+                        (None, None) => {}
+                    }
+
+                    if active {
+                        let response = crate::MessageEnvelope {
+                            session_id: session_id.clone(),
+                            payload: crate::MessagePayload::Server(crate::ServerMessage::UploadComplete {
+                                success: upload_error.is_none(),
+                                error: upload_error.take(),
+                            }),
+                        };
+                        let _ = outgoing.send(response);
+                    }
+
+                    // Clear all upload state
                     upload_path = None;
                     upload_base_path = None;
 
                     pending_upload = None;
+                    pending_resume = None;
+                }
+                crate::ClientMessage::FileAttributes { relative_path, modified, mode } => {
+                    let target = if relative_path.is_empty() {
+                        upload_path.clone().map(std::path::PathBuf::from)
+                    } else {
+                        upload_base_path.as_ref().and_then(|base| {
+                            upload_entry_path(config.root.as_deref(), base, &relative_path, true).ok()
+                        })
+                    };
+
+                    if let Some(target) = target {
+                        // Close the file so the mtime isn't bumped by a later write
+                        if let Err(e) = close_upload_file(&mut upload_file) {
+                            upload_error.get_or_insert(e);
+                        }
+                        if let Some(mode) = mode
+                            && let Err(e) = crate::transfer::set_mode_bits(&target, mode)
+                        {
+                            tracing::warn!(session_id = %session_id, path = ?target, error = %e,
+                                "Failed to apply file permissions");
+                        }
+                        if let Some(modified) = modified
+                            && let Err(e) = crate::transfer::set_modified_secs(&target, modified)
+                        {
+                            tracing::warn!(session_id = %session_id, path = ?target, error = %e,
+                                "Failed to apply file attributes");
+                        }
+                    }
+                }
+                crate::ClientMessage::FsMetadata { path } => {
+                    // Pre-flight check so `send` can resolve the destination before streaming
+                    let response = crate::MessageEnvelope {
+                        session_id: session_id.clone(),
+                        payload: crate::MessagePayload::Server(metadata_response(&path)),
+                    };
+                    let _ = outgoing.send(response);
                 }
                 crate::ClientMessage::RequestDownload { path, offset } => {
                     tracing::info!(session_id = %session_id, path = %path, offset = offset, "Client requested download");
@@ -2347,14 +4583,39 @@ impl KerrServer {
 
                     let is_dir = file_path.is_dir();
 
-                    // Calculate total size
-                    let total_size = match crate::transfer::calculate_size(file_path) {
-                        Ok(size) => size,
+                    // Size the download and list its files off the runtime; a huge tree can take
+                    // a while, so keep telling the client we're still working on it
+                    let scanned = Arc::new(AtomicU64::new(0));
+                    let mut scan = tokio::task::spawn_blocking({
+                        let scan_path = file_path.to_path_buf();
+                        let scanned = scanned.clone();
+                        move || crate::transfer::scan_download(&scan_path, symlink_mode, &scanned)
+                    });
+                    let mut ticker = tokio::time::interval_at(
+                        tokio::time::Instant::now() + PREPARING_INTERVAL,
+                        PREPARING_INTERVAL,
+                    );
+                    let scan_result = loop {
+                        tokio::select! {
+                            result = &mut scan => break result,
+                            _ = ticker.tick(), if announce_preparing => {
+                                let response = crate::MessageEnvelope {
+                                    session_id: session_id.clone(),
+                                    payload: crate::MessagePayload::Server(crate::ServerMessage::Preparing {
+                                        entries_scanned: scanned.load(Ordering::Relaxed),
+                                    }),
+                                };
+                                let _ = outgoing.send(response);
+                            }
+                        }
+                    };
+                    let (total_size, files) = match scan_result.map_err(anyhow::Error::from).and_then(|r| r) {
+                        Ok(scanned) => scanned,
                         Err(e) => {
                             let response = crate::MessageEnvelope {
                                 session_id: session_id.clone(),
                                 payload: crate::MessagePayload::Server(crate::ServerMessage::Error {
-                                    message: format!("Failed to calculate size: {}", e),
+                                    message: format!("Failed to read files: {}", e),
                                 }),
                             };
                             let _ = outgoing.send(response);
@@ -2387,29 +4648,45 @@ impl KerrServer {
                     };
                     let _ = outgoing.send(response);
 
-                    // Get all files to send
-                    let files = match crate::transfer::get_files_recursive(file_path) {
-                        Ok(files) => files,
-                        Err(e) => {
-                            let response = crate::MessageEnvelope {
-                                session_id: session_id.clone(),
-                                payload: crate::MessagePayload::Server(crate::ServerMessage::Error {
-                                    message: format!("Failed to read files: {}", e),
-                                }),
-                            };
-                            let _ = outgoing.send(response);
-                            continue;
-                        }
-                    };
-
                     use std::io::{Read, Seek, SeekFrom};
                     let mut bytes_sent = 0u64;
+                    let mut skipped = 0usize;
+                    // Set when the client cancels or goes away partway through
+                    let mut cancelled = false;
 
                     if is_dir {
                         // Directory download: send FileStart before each file's data
-                        for file in &files {
+                        'files: for file in &files {
                             let relative = file.strip_prefix(file_path).unwrap_or(file);
                             let relative_str = relative.to_string_lossy().to_string();
+                            // A file changed since the client got it is sent again
+                            if let Some(&(size, modified)) = skip_files.get(&relative_str)
+                                && std::fs::metadata(file).is_ok_and(|m| m.len() == size)
+                                && crate::transfer::modified_secs(file) == modified
+                            {
+                                skipped += 1;
+                                continue;
+                            }
+
+                            if symlink_mode == crate::transfer::SymlinkMode::Preserve && file.is_symlink() {
+                                match crate::transfer::symlink_target(file) {
+                                    Ok(target) => {
+                                        let response = crate::MessageEnvelope {
+                                            session_id: session_id.clone(),
+                                            payload: crate::MessagePayload::Server(crate::ServerMessage::Symlink {
+                                                relative_path: relative_str,
+                                                target,
+                                            }),
+                                        };
+                                        let _ = outgoing.send(response);
+                                    }
+                                    Err(e) => {
+                                        tracing::error!(session_id = %session_id, file = ?file, error = %e,
+                                            "Failed to read symlink");
+                                    }
+                                }
+                                continue;
+                            }
 
                             let file_size = match std::fs::metadata(file) {
                                 Ok(m) => m.len(),
@@ -2440,6 +4717,8 @@ impl KerrServer {
                             };
 
                             let mut buffer = vec![0u8; crate::transfer::CHUNK_SIZE];
+                            let mut try_compress = compress;
+                            let mut hasher = blake3::Hasher::new();
                             loop {
                                 let n = match f.read(&mut buffer) {
                                     Ok(n) => n,
@@ -2451,21 +4730,63 @@ impl KerrServer {
                                 };
                                 if n == 0 { break; }
 
+                                // All-zero chunks go as holes so sparse files stay sparse
+                                let chunk = &buffer[..n];
+                                if hashes {
+                                    hasher.update(chunk);
+                                }
+                                let payload = if sparse && crate::transfer::is_hole(chunk) {
+                                    crate::ServerMessage::FileHole { len: n as u64 }
+                                } else if let Some(data) = try_compress.then(|| crate::transfer::compress_chunk(chunk)).flatten() {
+                                    crate::ServerMessage::CompressedChunk { data }
+                                } else {
+                                    // Data that doesn't shrink is likely compressed already; stop trying for this file
+                                    try_compress = false;
+                                    crate::ServerMessage::FileChunk { data: chunk.to_vec() }
+                                };
+                                let response = crate::MessageEnvelope {
+                                    session_id: session_id.clone(),
+                                    payload: crate::MessagePayload::Server(payload),
+                                };
+                                // Mark what the send task reported so far as seen, so the wait
+                                // below is for a write made after this chunk was queued
+                                queue_depth.borrow_and_update();
+                                if outgoing.send(response).is_err()
+                                    || !wait_for_send_queue(&mut queue_depth).await
+                                    || download_cancelled(&mut incoming, &mut deferred)
+                                {
+                                    cancelled = true;
+                                    break 'files;
+                                }
+                                bytes_sent += n as u64;
+                            }
+
+                            if hashes {
                                 let response = crate::MessageEnvelope {
                                     session_id: session_id.clone(),
-                                    payload: crate::MessagePayload::Server(crate::ServerMessage::FileChunk {
-                                        data: buffer[..n].to_vec(),
+                                    payload: crate::MessagePayload::Server(crate::ServerMessage::DownloadComplete {
+                                        hash: hasher.finalize().to_hex().to_string(),
                                     }),
                                 };
                                 let _ = outgoing.send(response);
-                                bytes_sent += n as u64;
                             }
+
+                            // Let the client restore the original modification time and mode
+                            let response = crate::MessageEnvelope {
+                                session_id: session_id.clone(),
+                                payload: crate::MessagePayload::Server(crate::ServerMessage::FileAttributes {
+                                    relative_path: relative.to_string_lossy().to_string(),
+                                    modified: crate::transfer::modified_secs(file),
+                                    mode: crate::transfer::mode_bits(file),
+                                }),
+                            };
+                            let _ = outgoing.send(response);
                         }
                     } else {
                         // Single file download with resume support
                         let mut bytes_to_skip = offset;
 
-                        for file in &files {
+                        'files: for file in &files {
                             let mut f = match std::fs::File::open(file) {
                                 Ok(f) => f,
                                 Err(e) => {
@@ -2490,9 +4811,16 @@ impl KerrServer {
                                 continue;
                             }
 
-                            // Seek to the offset position if needed
+                            // Seek to the offset position if needed. The hash covers the whole
+                            // file, so then the part the client already has is read through it.
+                            let mut hasher = blake3::Hasher::new();
                             if bytes_to_skip > 0 {
-                                if let Err(e) = f.seek(SeekFrom::Start(bytes_to_skip)) {
+                                let skipped = if hashes {
+                                    std::io::copy(&mut (&mut f).take(bytes_to_skip), &mut hasher)
+                                } else {
+                                    f.seek(SeekFrom::Start(bytes_to_skip))
+                                };
+                                if let Err(e) = skipped {
                                     tracing::error!(session_id = %session_id, file = ?file, error = %e,
                                         "Failed to seek to offset");
                                     continue;
@@ -2501,6 +4829,7 @@ impl KerrServer {
                             }
 
                             let mut buffer = vec![0u8; crate::transfer::CHUNK_SIZE];
+                            let mut try_compress = compress;
                             loop {
                                 let n = match f.read(&mut buffer) {
                                     Ok(n) => n,
@@ -2512,18 +4841,65 @@ impl KerrServer {
                                 };
                                 if n == 0 { break; }
 
+                                // All-zero chunks go as holes so sparse files stay sparse
+                                let chunk = &buffer[..n];
+                                if hashes {
+                                    hasher.update(chunk);
+                                }
+                                let payload = if sparse && crate::transfer::is_hole(chunk) {
+                                    crate::ServerMessage::FileHole { len: n as u64 }
+                                } else if let Some(data) = try_compress.then(|| crate::transfer::compress_chunk(chunk)).flatten() {
+                                    crate::ServerMessage::CompressedChunk { data }
+                                } else {
+                                    // Data that doesn't shrink is likely compressed already; stop trying for this file
+                                    try_compress = false;
+                                    crate::ServerMessage::FileChunk { data: chunk.to_vec() }
+                                };
+                                let response = crate::MessageEnvelope {
+                                    session_id: session_id.clone(),
+                                    payload: crate::MessagePayload::Server(payload),
+                                };
+                                queue_depth.borrow_and_update();
+                                if outgoing.send(response).is_err()
+                                    || !wait_for_send_queue(&mut queue_depth).await
+                                    || download_cancelled(&mut incoming, &mut deferred)
+                                {
+                                    cancelled = true;
+                                    break 'files;
+                                }
+                                bytes_sent += n as u64;
+                            }
+
+                            if hashes {
                                 let response = crate::MessageEnvelope {
                                     session_id: session_id.clone(),
-                                    payload: crate::MessagePayload::Server(crate::ServerMessage::FileChunk {
-                                        data: buffer[..n].to_vec(),
+                                    payload: crate::MessagePayload::Server(crate::ServerMessage::DownloadComplete {
+                                        hash: hasher.finalize().to_hex().to_string(),
                                     }),
                                 };
                                 let _ = outgoing.send(response);
-                                bytes_sent += n as u64;
                             }
+
+                            // Let the client restore the original modification time and mode
+                            let response = crate::MessageEnvelope {
+                                session_id: session_id.clone(),
+                                payload: crate::MessagePayload::Server(crate::ServerMessage::FileAttributes {
+                                    relative_path: String::new(),
+                                    modified: crate::transfer::modified_secs(file),
+                                    mode: crate::transfer::mode_bits(file),
+                                }),
+                            };
+                            let _ = outgoing.send(response);
                         }
                     }
 
+                    if cancelled {
+                        tracing::info!(session_id = %session_id, path = %path, bytes_sent = bytes_sent,
+                            "Download cancelled by client");
+                        skip_files.clear();
+                        continue;
+                    }
+
                     // Send EndDownload message
                     let response = crate::MessageEnvelope {
                         session_id: session_id.clone(),
@@ -2532,8 +4908,11 @@ impl KerrServer {
                     let _ = outgoing.send(response);
 
                     tracing::info!(session_id = %session_id, path = %path, bytes_sent = bytes_sent,
-                        "Download completed");
+                        skipped = skipped, "Download completed");
+                    skip_files.clear();
                 }
+                // Only meaningful while a download is being sent
+                crate::ClientMessage::CancelDownload => {}
                 crate::ClientMessage::Disconnect => break,
                 _ => {}
             }
@@ -2644,49 +5023,206 @@ impl KerrServer {
                                     }
                                 });
 
-                                // Wait for either task to complete
-                                tokio::select! {
-                                    _ = read_task => {}
-                                    _ = write_task => {}
-                                }
+                                // Wait for either task to complete
+                                tokio::select! {
+                                    _ = read_task => {}
+                                    _ = write_task => {}
+                                }
+
+                                // Send close response
+                                let close_response = crate::MessageEnvelope {
+                                    session_id: session_id_for_task.clone(),
+                                    payload: crate::MessagePayload::Server(crate::ServerMessage::TcpCloseResponse {
+                                        stream_id,
+                                        error: None,
+                                    }),
+                                };
+                                let _ = outgoing_for_task.send(close_response);
+
+                                // Remove from connections map
+                                tcp_connections_for_task.lock().await.remove(&stream_id);
+                                tracing::info!(session_id = %session_id_for_task, stream_id = stream_id, "TCP connection closed");
+                            });
+                        }
+                        Err(e) => {
+                            // Send error response
+                            tracing::error!(session_id = %session_id, stream_id = stream_id, port = destination_port, error = %e,
+                                "Failed to connect to {}:{}", target_host, destination_port);
+                            let response = crate::MessageEnvelope {
+                                session_id: session_id.clone(),
+                                payload: crate::MessagePayload::Server(crate::ServerMessage::TcpOpenResponse {
+                                    stream_id,
+                                    success: false,
+                                    error: Some(format!("Failed to connect to {}:{}: {}", target_host, destination_port, e)),
+                                }),
+                            };
+                            let _ = outgoing.send(response);
+                        }
+                    }
+                }
+                crate::ClientMessage::TcpData { stream_id, data } => {
+                    tracing::debug!(session_id = %session_id, stream_id = stream_id, bytes = data.len(),
+                        "Forwarding data to TCP connection");
+                    // Forward data to the appropriate TCP connection
+                    let connections = tcp_connections.lock().await;
+                    if let Some(tx) = connections.get(&stream_id) {
+                        if tx.send(data).await.is_err() {
+                            tracing::error!(session_id = %session_id, stream_id = stream_id,
+                                "Failed to forward data to TCP connection");
+                        }
+                    } else {
+                        tracing::warn!(session_id = %session_id, stream_id = stream_id,
+                            "Received data for unknown stream_id");
+                    }
+                }
+                crate::ClientMessage::TcpClose { stream_id } => {
+                    tracing::info!(session_id = %session_id, stream_id = stream_id, "Closing TCP connection");
+                    // Remove the connection (the task will detect the channel closure and clean up)
+                    tcp_connections.lock().await.remove(&stream_id);
+                }
+                crate::ClientMessage::Disconnect => {
+                    tracing::info!(session_id = %session_id, "Client requested disconnect");
+                    break;
+                }
+                _ => {
+                    tracing::warn!(session_id = %session_id, "Unexpected message type for TCP relay session");
+                }
+            }
+        }
+
+        tracing::info!(session_id = %session_id, "TCP relay session closed");
+        Ok(())
+    }
+
+    /// UDP relay session handler for multiplexed mode (single stream). Each stream is
+    /// one peer on the client side and gets its own socket, so replies find their way back.
+    async fn handle_udp_relay_session_mux(
+        _node_id: iroh::PublicKey,
+        session_id: String,
+        mut incoming: tokio::sync::mpsc::UnboundedReceiver<crate::ClientMessage>,
+        outgoing: tokio::sync::mpsc::UnboundedSender<crate::MessageEnvelope>,
+    ) -> Result<(), AcceptError> {
+        use std::collections::HashMap;
+        use tokio::sync::mpsc::error::TrySendError;
+
+        tracing::info!(session_id = %session_id, "UDP relay session started (mux mode)");
+
+        // Datagrams for each stream's socket; dropping a sender ends its task
+        let mut udp_streams: HashMap<u32, tokio::sync::mpsc::Sender<Vec<u8>>> = HashMap::new();
+
+        while let Some(msg) = incoming.recv().await {
+            match msg {
+                crate::ClientMessage::UdpOpen { stream_id, destination_host, destination_port } => {
+                    let target_host = destination_host.as_deref().unwrap_or("127.0.0.1");
+                    tracing::info!(session_id = %session_id, stream_id = stream_id, host = target_host, port = destination_port,
+                        "Opening UDP stream to {}:{}", target_host, destination_port);
+
+                    let (success, error) = match connect_udp(target_host, destination_port).await {
+                        Ok(socket) => {
+                            let (tx, rx) = tokio::sync::mpsc::channel::<Vec<u8>>(UDP_STREAM_QUEUE);
+                            udp_streams.insert(stream_id, tx);
+                            tokio::spawn(Self::relay_udp_stream(session_id.clone(), stream_id, socket, rx, outgoing.clone()));
+                            (true, None)
+                        }
+                        Err(e) => {
+                            tracing::error!(session_id = %session_id, stream_id = stream_id, port = destination_port, error = %e,
+                                "Failed to open UDP socket for {}:{}", target_host, destination_port);
+                            (false, Some(format!("Failed to open UDP socket: {}", e)))
+                        }
+                    };
+                    let response = crate::MessageEnvelope {
+                        session_id: session_id.clone(),
+                        payload: crate::MessagePayload::Server(crate::ServerMessage::UdpOpenResponse { stream_id, success, error }),
+                    };
+                    let _ = outgoing.send(response);
+                }
+                crate::ClientMessage::UdpData { stream_id, data } => {
+                    let Some(tx) = udp_streams.get(&stream_id) else {
+                        tracing::warn!(session_id = %session_id, stream_id = stream_id, "Received data for unknown stream_id");
+                        continue;
+                    };
+                    match tx.try_send(data) {
+                        Ok(()) => {}
+                        Err(TrySendError::Full(_)) => {
+                            tracing::debug!(session_id = %session_id, stream_id = stream_id, "UDP stream is behind, dropping datagram");
+                        }
+                        Err(TrySendError::Closed(_)) => {
+                            udp_streams.remove(&stream_id);
+                        }
+                    }
+                }
+                crate::ClientMessage::UdpClose { stream_id } => {
+                    tracing::info!(session_id = %session_id, stream_id = stream_id, "Closing UDP stream");
+                    udp_streams.remove(&stream_id);
+                }
+                crate::ClientMessage::Disconnect => {
+                    tracing::info!(session_id = %session_id, "Client requested disconnect");
+                    break;
+                }
+                _ => {
+                    tracing::warn!(session_id = %session_id, "Unexpected message type for UDP relay session");
+                }
+            }
+        }
+
+        tracing::info!(session_id = %session_id, "UDP relay session closed");
+        Ok(())
+    }
 
-                                // Send close response
-                                let close_response = crate::MessageEnvelope {
-                                    session_id: session_id_for_task.clone(),
-                                    payload: crate::MessagePayload::Server(crate::ServerMessage::TcpCloseResponse {
-                                        stream_id,
-                                        error: None,
-                                    }),
-                                };
-                                let _ = outgoing_for_task.send(close_response);
+    /// Reverse relay session handler for multiplexed mode (single stream): listens on a
+    /// loopback port for the client and hands each accepted connection to it as a stream
+    async fn handle_reverse_relay_session_mux(
+        node_id: iroh::PublicKey,
+        session_id: String,
+        mut incoming: tokio::sync::mpsc::UnboundedReceiver<crate::ClientMessage>,
+        outgoing: tokio::sync::mpsc::UnboundedSender<crate::MessageEnvelope>,
+    ) -> Result<(), AcceptError> {
+        use std::collections::HashMap;
 
-                                // Remove from connections map
-                                tcp_connections_for_task.lock().await.remove(&stream_id);
-                                tracing::info!(session_id = %session_id_for_task, stream_id = stream_id, "TCP connection closed");
-                            });
+        tracing::info!(session_id = %session_id, "Reverse relay session started (mux mode)");
+
+        // Shared with the accept task, which adds a connection for every stream it opens
+        let tcp_connections: Arc<tokio::sync::Mutex<HashMap<u32, tokio::sync::mpsc::Sender<Vec<u8>>>>> =
+            Arc::new(tokio::sync::Mutex::new(HashMap::new()));
+        let mut accept_task: Option<tokio::task::JoinHandle<()>> = None;
+
+        while let Some(msg) = incoming.recv().await {
+            match msg {
+                crate::ClientMessage::ReverseListen { port } => {
+                    let listener = if accept_task.is_some() {
+                        Err("this session is already listening".to_string())
+                    } else {
+                        tokio::net::TcpListener::bind(("127.0.0.1", port))
+                            .await
+                            .map_err(|e| format!("Failed to listen on port {}: {}", port, e))
+                    };
+                    let (success, error) = match listener {
+                        Ok(listener) => {
+                            tracing::info!(node_id = %node_id, session_id = %session_id, port = port,
+                                "Listening on 127.0.0.1:{} for reverse relay", port);
+                            accept_task = Some(tokio::spawn(Self::accept_reverse_connections(
+                                session_id.clone(),
+                                listener,
+                                Arc::clone(&tcp_connections),
+                                outgoing.clone(),
+                            )));
+                            (true, None)
                         }
                         Err(e) => {
-                            // Send error response
-                            tracing::error!(session_id = %session_id, stream_id = stream_id, port = destination_port, error = %e,
-                                "Failed to connect to localhost:{}", destination_port);
-                            let response = crate::MessageEnvelope {
-                                session_id: session_id.clone(),
-                                payload: crate::MessagePayload::Server(crate::ServerMessage::TcpOpenResponse {
-                                    stream_id,
-                                    success: false,
-                                    error: Some(format!("Failed to connect: {}", e)),
-                                }),
-                            };
-                            let _ = outgoing.send(response);
+                            tracing::warn!(session_id = %session_id, port = port, error = %e, "Reverse relay listen failed");
+                            (false, Some(e))
                         }
-                    }
+                    };
+                    let response = crate::MessageEnvelope {
+                        session_id: session_id.clone(),
+                        payload: crate::MessagePayload::Server(crate::ServerMessage::ReverseListenResponse { success, error }),
+                    };
+                    let _ = outgoing.send(response);
                 }
                 crate::ClientMessage::TcpData { stream_id, data } => {
-                    tracing::debug!(session_id = %session_id, stream_id = stream_id, bytes = data.len(),
-                        "Forwarding data to TCP connection");
-                    // Forward data to the appropriate TCP connection
-                    let connections = tcp_connections.lock().await;
-                    if let Some(tx) = connections.get(&stream_id) {
+                    // Not holding the lock while a slow connection's queue is full
+                    let tx = tcp_connections.lock().await.get(&stream_id).cloned();
+                    if let Some(tx) = tx {
                         if tx.send(data).await.is_err() {
                             tracing::error!(session_id = %session_id, stream_id = stream_id,
                                 "Failed to forward data to TCP connection");
@@ -2697,8 +5233,7 @@ impl KerrServer {
                     }
                 }
                 crate::ClientMessage::TcpClose { stream_id } => {
-                    tracing::info!(session_id = %session_id, stream_id = stream_id, "Closing TCP connection");
-                    // Remove the connection (the task will detect the channel closure and clean up)
+                    tracing::info!(session_id = %session_id, stream_id = stream_id, "Closing reverse relay connection");
                     tcp_connections.lock().await.remove(&stream_id);
                 }
                 crate::ClientMessage::Disconnect => {
@@ -2706,15 +5241,178 @@ impl KerrServer {
                     break;
                 }
                 _ => {
-                    tracing::warn!(session_id = %session_id, "Unexpected message type for TCP relay session");
+                    tracing::warn!(session_id = %session_id, "Unexpected message type for reverse relay session");
                 }
             }
         }
 
-        tracing::info!(session_id = %session_id, "TCP relay session closed");
+        // Stop listening, and close the connections still open
+        if let Some(accept_task) = accept_task {
+            accept_task.abort();
+        }
+        tcp_connections.lock().await.clear();
+
+        tracing::info!(session_id = %session_id, "Reverse relay session closed");
         Ok(())
     }
 
+    /// Accept connections on a reverse relay's port, announcing each to the client with
+    /// `TcpIncoming` and relaying it like a forward relay's stream
+    async fn accept_reverse_connections(
+        session_id: String,
+        listener: tokio::net::TcpListener,
+        tcp_connections: Arc<tokio::sync::Mutex<std::collections::HashMap<u32, tokio::sync::mpsc::Sender<Vec<u8>>>>>,
+        outgoing: tokio::sync::mpsc::UnboundedSender<crate::MessageEnvelope>,
+    ) {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let mut next_stream_id = 1u32;
+        loop {
+            let (tcp_stream, peer) = match listener.accept().await {
+                Ok(accepted) => accepted,
+                Err(e) => {
+                    tracing::warn!(session_id = %session_id, error = %e, "Failed to accept reverse relay connection");
+                    continue;
+                }
+            };
+            let stream_id = next_stream_id;
+            next_stream_id += 1;
+            tracing::info!(session_id = %session_id, stream_id = stream_id, peer = %peer, "Reverse relay connection accepted");
+
+            // Registered before the client hears of it, so its first TcpData has somewhere to go
+            let (tx, mut rx) = tokio::sync::mpsc::channel::<Vec<u8>>(100);
+            tcp_connections.lock().await.insert(stream_id, tx);
+            let announce = crate::MessageEnvelope {
+                session_id: session_id.clone(),
+                payload: crate::MessagePayload::Server(crate::ServerMessage::TcpIncoming { stream_id, peer: peer.to_string() }),
+            };
+            if outgoing.send(announce).is_err() {
+                break;
+            }
+
+            let outgoing_for_task = outgoing.clone();
+            let tcp_connections_for_task = Arc::clone(&tcp_connections);
+            let session_id_for_task = session_id.clone();
+            tokio::spawn(async move {
+                let (mut tcp_read, mut tcp_write) = tcp_stream.into_split();
+
+                // Task to read from the accepted connection and send to client
+                let read_task = {
+                    let outgoing_for_read = outgoing_for_task.clone();
+                    let session_id_for_read = session_id_for_task.clone();
+                    tokio::spawn(async move {
+                        let mut buf = vec![0u8; 65536];
+                        loop {
+                            match tcp_read.read(&mut buf).await {
+                                Ok(0) => break,
+                                Ok(n) => {
+                                    let response = crate::MessageEnvelope {
+                                        session_id: session_id_for_read.clone(),
+                                        payload: crate::MessagePayload::Server(crate::ServerMessage::TcpDataResponse {
+                                            stream_id,
+                                            data: buf[..n].to_vec(),
+                                        }),
+                                    };
+                                    if outgoing_for_read.send(response).is_err() {
+                                        break;
+                                    }
+                                }
+                                Err(e) => {
+                                    tracing::error!(session_id = %session_id_for_read, stream_id = stream_id, error = %e,
+                                        "TCP read error");
+                                    break;
+                                }
+                            }
+                        }
+                    })
+                };
+
+                // Task to receive from client and write to the accepted connection
+                let write_task = tokio::spawn(async move {
+                    while let Some(data) = rx.recv().await {
+                        if tcp_write.write_all(&data).await.is_err() {
+                            break;
+                        }
+                    }
+                });
+
+                tokio::select! {
+                    _ = read_task => {}
+                    _ = write_task => {}
+                }
+
+                let close_response = crate::MessageEnvelope {
+                    session_id: session_id_for_task.clone(),
+                    payload: crate::MessagePayload::Server(crate::ServerMessage::TcpCloseResponse {
+                        stream_id,
+                        error: None,
+                    }),
+                };
+                let _ = outgoing_for_task.send(close_response);
+
+                tcp_connections_for_task.lock().await.remove(&stream_id);
+                tracing::info!(session_id = %session_id_for_task, stream_id = stream_id, "Reverse relay connection closed");
+            });
+        }
+    }
+
+    /// Relay one UDP stream: datagrams from the client go out on `socket` and each one
+    /// that comes back is sent to the client whole. Ends when the client closes the stream.
+    async fn relay_udp_stream(
+        session_id: String,
+        stream_id: u32,
+        socket: tokio::net::UdpSocket,
+        mut rx: tokio::sync::mpsc::Receiver<Vec<u8>>,
+        outgoing: tokio::sync::mpsc::UnboundedSender<crate::MessageEnvelope>,
+    ) {
+        let mut buf = vec![0u8; 65536];
+        let error = loop {
+            tokio::select! {
+                data = rx.recv() => match data {
+                    Some(data) => {
+                        if let Err(e) = socket.send(&data).await {
+                            tracing::debug!(session_id = %session_id, stream_id = stream_id, error = %e, "UDP send failed");
+                        }
+                    }
+                    None => break None,
+                },
+                received = socket.recv(&mut buf) => match received {
+                    Ok(n) => {
+                        let response = crate::MessageEnvelope {
+                            session_id: session_id.clone(),
+                            payload: crate::MessagePayload::Server(crate::ServerMessage::UdpDataResponse {
+                                stream_id,
+                                data: buf[..n].to_vec(),
+                            }),
+                        };
+                        if outgoing.send(response).is_err() {
+                            break None;
+                        }
+                    }
+                    // Nothing listening on the port (yet); UDP carries on regardless
+                    Err(e) if e.kind() == std::io::ErrorKind::ConnectionRefused => {
+                        tracing::debug!(session_id = %session_id, stream_id = stream_id, "UDP destination refused a datagram");
+                    }
+                    Err(e) => break Some(e.to_string()),
+                },
+            }
+        };
+
+        // Only a stream the server gave up on needs closing on the client's side
+        if let Some(error) = error {
+            tracing::error!(session_id = %session_id, stream_id = stream_id, error = %error, "UDP stream failed");
+            let close_response = crate::MessageEnvelope {
+                session_id: session_id.clone(),
+                payload: crate::MessagePayload::Server(crate::ServerMessage::UdpCloseResponse {
+                    stream_id,
+                    error: Some(error),
+                }),
+            };
+            let _ = outgoing.send(close_response);
+        }
+        tracing::info!(session_id = %session_id, stream_id = stream_id, "UDP stream closed");
+    }
+
     /// Ping session handler for multiplexed mode (single stream)
     async fn handle_ping_session_mux(
         _node_id: iroh::PublicKey,
@@ -2734,6 +5432,15 @@ impl KerrServer {
                     };
                     let _ = outgoing.send(response);
                 }
+                crate::ClientMessage::VersionRequest => {
+                    let response = crate::MessageEnvelope {
+                        session_id: session_id.clone(),
+                        payload: crate::MessagePayload::Server(crate::ServerMessage::VersionResponse {
+                            version: crate::VERSION.to_string(),
+                        }),
+                    };
+                    let _ = outgoing.send(response);
+                }
                 crate::ClientMessage::Disconnect => break,
                 _ => {}
             }
@@ -2743,6 +5450,34 @@ impl KerrServer {
         Ok(())
     }
 
+    /// Echo session handler for `kerr echo-test`: input comes straight back as output,
+    /// exercising the protocol without a PTY
+    async fn handle_echo_session_mux(
+        _node_id: iroh::PublicKey,
+        session_id: String,
+        mut incoming: tokio::sync::mpsc::UnboundedReceiver<crate::ClientMessage>,
+        outgoing: tokio::sync::mpsc::UnboundedSender<crate::MessageEnvelope>,
+    ) -> Result<(), AcceptError> {
+        tracing::info!(session_id = %session_id, "Echo session started (mux mode)");
+
+        while let Some(msg) = incoming.recv().await {
+            match msg {
+                crate::ClientMessage::KeyEvent { data } => {
+                    let response = crate::MessageEnvelope {
+                        session_id: session_id.clone(),
+                        payload: crate::MessagePayload::Server(crate::ServerMessage::Output { data }),
+                    };
+                    let _ = outgoing.send(response);
+                }
+                crate::ClientMessage::Disconnect => break,
+                _ => {}
+            }
+        }
+
+        tracing::info!(session_id = %session_id, "Echo session closed");
+        Ok(())
+    }
+
     async fn handle_dns_session_mux(
         _node_id: iroh::PublicKey,
         session_id: String,
@@ -2841,4 +5576,474 @@ impl KerrServer {
         tracing::info!(session_id = %session_id, "DNS session closed");
         Ok(())
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{TempDir, TestClient};
+    use crate::transfer::CHUNK_SIZE;
+    use crate::{ClientMessage, ServerMessage, SessionType};
+
+    /// Upload `chunks` as a single file to `path` and return the server's `UploadComplete`
+    async fn upload(client: &mut TestClient, path: &str, size: u64, chunks: Vec<ClientMessage>) -> ServerMessage {
+        client.send(ClientMessage::StartUpload { path: path.to_string(), size, is_dir: false, force: true }).await;
+        assert!(matches!(client.recv_reply().await, ServerMessage::UploadAck));
+        for chunk in chunks {
+            client.send(chunk).await;
+        }
+        client.send(ClientMessage::EndUpload).await;
+        client.recv_reply().await
+    }
+
+    /// Download `path` and return its bytes and how many of them arrived as holes
+    async fn download(client: &mut TestClient, path: &str) -> (Vec<u8>, u64) {
+        client.send(ClientMessage::RequestDownload { path: path.to_string(), offset: 0 }).await;
+        let mut data = Vec::new();
+        let mut holes = 0;
+        loop {
+            match client.recv().await {
+                ServerMessage::FileChunk { data: chunk } => data.extend_from_slice(&chunk),
+                ServerMessage::FileHole { len } => {
+                    data.resize(data.len() + len as usize, 0);
+                    holes += len;
+                }
+                ServerMessage::EndDownload => return (data, holes),
+                ServerMessage::Error { message } => panic!("Download failed: {}", message),
+                _ => {}
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn wrong_password_fails_later_attempts() {
+        let gate = PasswordGate::new("1234");
+        let auth = AuthAttempts::default();
+        assert!(!auth.has_failed());
+        assert!(!auth.check(&gate, "0000").await);
+        assert!(auth.has_failed());
+        assert!(!auth.check(&gate, "1234").await);
+
+        assert!(AuthAttempts::default().check(&gate, "1234").await);
+    }
+
+    #[tokio::test]
+    async fn wrong_passwords_are_limited_across_connections() {
+        let gate = PasswordGate::new("1234");
+        // One token a second, and a burst of 60
+        let throttle = Arc::new(AuthThrottle::new(60));
+        for _ in 0..60 {
+            assert!(!throttle.check(|| false).await);
+        }
+
+        // A new connection, as from a fresh node id, waits for the bucket to refill
+        let start = std::time::Instant::now();
+        assert!(!AuthAttempts::new(throttle.clone()).check(&gate, "0000").await);
+        assert!(start.elapsed() >= std::time::Duration::from_millis(900), "{:?}", start.elapsed());
+
+        // Another connection waits too, even with the right password, once the token
+        // that refilled during the wrong answer's delay is used up
+        assert!(!throttle.check(|| false).await);
+        let start = std::time::Instant::now();
+        assert!(AuthAttempts::new(throttle).check(&gate, "1234").await);
+        assert!(start.elapsed() >= std::time::Duration::from_millis(900), "{:?}", start.elapsed());
+    }
+
+    /// A root holding `inside/file`, next to an `outside` directory it links to
+    fn jail(name: &str) -> (TempDir, std::path::PathBuf) {
+        let dir = TempDir::new(name);
+        let root = dir.path().join("root");
+        std::fs::create_dir_all(root.join("inside")).unwrap();
+        std::fs::write(root.join("inside/file"), b"data").unwrap();
+        std::fs::create_dir(dir.path().join("outside")).unwrap();
+        std::fs::write(dir.path().join("outside/secret"), b"secret").unwrap();
+        #[cfg(unix)]
+        std::os::unix::fs::symlink(dir.path().join("outside"), root.join("escape")).unwrap();
+        let root = std::fs::canonicalize(root).unwrap();
+        (dir, root)
+    }
+
+    #[test]
+    fn confine_path_resolves_paths_inside_the_root() {
+        let (_dir, root) = jail("confine-inside");
+        assert_eq!(confine_path(&root, Path::new("inside/file"), true).unwrap(), root.join("inside/file"));
+        assert_eq!(confine_path(&root, Path::new("inside/new/file"), true).unwrap(), root.join("inside/new/file"));
+        assert_eq!(confine_path(&root, Path::new("inside/../inside/file"), true).unwrap(), root.join("inside/file"));
+        assert_eq!(confine_path(&root, &root.join("inside"), true).unwrap(), root.join("inside"));
+        assert_eq!(confine_path(&root, Path::new(""), true).unwrap(), root);
+    }
+
+    #[test]
+    fn confine_path_refuses_parent_traversal() {
+        let (_dir, root) = jail("confine-traversal");
+        for path in ["..", "../outside/secret", "inside/../../outside", "inside/missing/../../../x", "/etc/passwd"] {
+            assert!(confine_path(&root, Path::new(path), true).is_err(), "{} was allowed", path);
+            assert!(confine_path(&root, Path::new(path), false).is_err(), "{} was allowed", path);
+        }
+        // The root itself can't be deleted or renamed
+        assert!(confine_path(&root, Path::new(""), false).is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn confine_path_refuses_symlink_escapes() {
+        let (_dir, root) = jail("confine-symlink");
+        assert!(confine_path(&root, Path::new("escape"), true).is_err());
+        assert!(confine_path(&root, Path::new("escape/secret"), true).is_err());
+        assert!(confine_path(&root, Path::new("escape/new/file"), true).is_err());
+        assert!(confine_path(&root, Path::new("escape/secret"), false).is_err());
+        // The link itself lies inside, so it may be deleted or renamed
+        assert_eq!(confine_path(&root, Path::new("escape"), false).unwrap(), root.join("escape"));
+    }
+
+    #[test]
+    fn confine_request_rewrites_or_refuses_paths() {
+        let (_dir, root) = jail("confine-request");
+        let msg = confine_request(Some(&root), ClientMessage::FsReadDir { path: "inside".to_string() }).unwrap();
+        assert!(matches!(msg, ClientMessage::FsReadDir { path } if Path::new(&path) == root.join("inside")));
+
+        let download = ClientMessage::RequestDownload { path: "../outside/secret".to_string(), offset: 0 };
+        assert!(confine_request(Some(&root), download).is_err());
+        let batch = ClientMessage::FsBatch {
+            ops: vec![
+                crate::FsOp::Mkdir { path: "inside/new".to_string() },
+                crate::FsOp::Rename { from: "inside/file".to_string(), to: "../stolen".to_string() },
+            ],
+        };
+        assert!(confine_request(Some(&root), batch).is_err());
+
+        // Without a root nothing is changed
+        let msg = confine_request(None, ClientMessage::FsReadDir { path: "../x".to_string() }).unwrap();
+        assert!(matches!(msg, ClientMessage::FsReadDir { path } if path == "../x"));
+    }
+
+    #[tokio::test]
+    async fn upload_outside_the_root_is_rejected() {
+        let (dir, root) = jail("upload-root");
+        let config = ServerConfig { root: Some(root.clone()), ..ServerConfig::default() };
+        let mut client = TestClient::start(config, SessionType::FileTransfer).await;
+
+        for path in ["../outside/stolen", "inside/../../outside/stolen"] {
+            client.send(ClientMessage::StartUpload { path: path.to_string(), size: 4, is_dir: false, force: true }).await;
+            assert!(matches!(client.recv_reply().await, ServerMessage::Error { .. }), "{} was allowed", path);
+        }
+
+        // Entries of a directory upload are confined too
+        client.send(ClientMessage::StartUpload { path: "inside/dir".to_string(), size: 4, is_dir: true, force: true }).await;
+        assert!(matches!(client.recv_reply().await, ServerMessage::UploadAck));
+        client.send(ClientMessage::FileStart { relative_path: "../../../outside/stolen".to_string(), size: 4 }).await;
+        assert!(matches!(client.recv_reply().await, ServerMessage::Error { .. }));
+        client.send(ClientMessage::FileChunk { data: b"data".to_vec() }).await;
+        client.send(ClientMessage::EndUpload).await;
+        assert!(matches!(client.recv_reply().await, ServerMessage::UploadComplete { success: false, .. }));
+
+        assert!(!dir.path().join("outside/stolen").exists());
+    }
+
+    #[test]
+    fn batch_delete_keeps_non_empty_directories() {
+        let dir = TempDir::new("batch-delete");
+        std::fs::create_dir(dir.path().join("full")).unwrap();
+        std::fs::write(dir.path().join("full/file"), b"data").unwrap();
+        std::fs::create_dir(dir.path().join("empty")).unwrap();
+
+        assert!(!apply_fs_op(&crate::FsOp::Delete { path: dir.join("full") }).success);
+        assert!(dir.path().join("full/file").exists());
+        assert!(apply_fs_op(&crate::FsOp::Delete { path: dir.join("full/file") }).success);
+        assert!(apply_fs_op(&crate::FsOp::Delete { path: dir.join("empty") }).success);
+        assert!(!dir.path().join("empty").exists());
+    }
+
+    #[tokio::test]
+    async fn exec_runs_the_command_without_a_terminal() {
+        let config = ServerConfig { shell: Some("/bin/sh".into()), ..ServerConfig::default() };
+        // `cat` would wait forever on a terminal; with stdin closed it ends at once
+        let command = "cat; echo out; echo err >&2; exit 3".to_string();
+        let mut client = TestClient::start_with(config, ClientMessage::ExecHello { command }).await;
+
+        let (mut stdout, mut stderr) = (Vec::new(), Vec::new());
+        let code = loop {
+            match client.recv().await {
+                ServerMessage::Output { data } => stdout.extend_from_slice(&data),
+                ServerMessage::ErrorOutput { data } => stderr.extend_from_slice(&data),
+                ServerMessage::Exit { code } => break code,
+                other => panic!("Unexpected {:?}", other),
+            }
+        };
+        assert_eq!(code, 3);
+        assert_eq!(stdout, b"out\n");
+        assert_eq!(stderr, b"err\n");
+        assert!(matches!(client.recv().await, ServerMessage::Error { message } if message.starts_with("Session ended")));
+    }
+
+    #[tokio::test]
+    async fn read_only_refuses_exec() {
+        let config = ServerConfig { read_only: true, ..ServerConfig::default() };
+        let mut client = TestClient::start_with(config, ClientMessage::ExecHello { command: "true".to_string() }).await;
+        assert!(matches!(client.recv().await, ServerMessage::Error { message } if message.contains("read-only")));
+    }
+
+    #[tokio::test]
+    async fn tcp_relay_reaches_a_named_host() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let mut client = TestClient::start(ServerConfig::default(), SessionType::TcpRelay).await;
+
+        client.send(ClientMessage::TcpOpen { stream_id: 1, destination_host: Some("localhost".to_string()), destination_port: port }).await;
+        let (mut socket, _) = listener.accept().await.unwrap();
+        assert!(matches!(client.recv().await, ServerMessage::TcpOpenResponse { stream_id: 1, success: true, error: None }));
+
+        client.send(ClientMessage::TcpData { stream_id: 1, data: b"ping".to_vec() }).await;
+        let mut buf = [0u8; 4];
+        socket.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"ping");
+        socket.write_all(b"pong").await.unwrap();
+        assert!(matches!(client.recv().await, ServerMessage::TcpDataResponse { stream_id: 1, data } if data == b"pong"));
+
+        drop(socket);
+        assert!(matches!(client.recv().await, ServerMessage::TcpCloseResponse { stream_id: 1, .. }));
+    }
+
+    #[tokio::test]
+    async fn tcp_relay_reports_an_unknown_host() {
+        let mut client = TestClient::start(ServerConfig::default(), SessionType::TcpRelay).await;
+        client.send(ClientMessage::TcpOpen { stream_id: 2, destination_host: Some("nonexistent.invalid".to_string()), destination_port: 80 }).await;
+        match client.recv().await {
+            ServerMessage::TcpOpenResponse { stream_id: 2, success: false, error: Some(error) } => {
+                assert!(error.contains("nonexistent.invalid:80"), "{}", error);
+            }
+            other => panic!("Unexpected {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn read_only_refuses_reverse_relay() {
+        let config = ServerConfig { read_only: true, ..ServerConfig::default() };
+        let mut client = TestClient::start(config, SessionType::ReverseRelay).await;
+        assert!(matches!(client.recv().await, ServerMessage::Error { message } if message.contains("read-only")));
+    }
+
+    #[test]
+    fn config_file_overrides_read_only_and_shell() {
+        let dir = TempDir::new("config-file");
+        let path = dir.path().join("kerr.json");
+        std::fs::write(&path, r#"{"read_only": true, "shell": "/bin/zsh"}"#).unwrap();
+        let base = ServerConfig { config_file: Some(path.clone()), ..ServerConfig::default() };
+
+        let config = base.with_config_file().unwrap();
+        assert!(config.read_only);
+        assert_eq!(config.shell.as_deref(), Some(Path::new("/bin/zsh")));
+
+        // Settings left out keep the command line's values
+        std::fs::write(&path, r#"{"log_commands": false}"#).unwrap();
+        let base = ServerConfig { read_only: true, ..base };
+        let config = base.with_config_file().unwrap();
+        assert!(config.read_only);
+        assert_eq!(config.shell, None);
+    }
+
+    #[tokio::test]
+    async fn ping_session_reports_the_version() {
+        let mut client = TestClient::start(ServerConfig::default(), SessionType::Ping).await;
+        client.send(ClientMessage::VersionRequest).await;
+        assert!(matches!(client.recv().await, ServerMessage::VersionResponse { version } if version == crate::VERSION));
+    }
+
+    #[tokio::test]
+    async fn zero_byte_file_round_trip() {
+        let dir = TempDir::new("zero-byte");
+        let path = dir.join("empty");
+        let mut client = TestClient::start(ServerConfig::default(), SessionType::FileTransfer).await;
+
+        let complete = upload(&mut client, &path, 0, Vec::new()).await;
+        assert!(matches!(complete, ServerMessage::UploadComplete { success: true, .. }), "{:?}", complete);
+        assert_eq!(std::fs::metadata(&path).unwrap().len(), 0);
+
+        let (data, _) = download(&mut client, &path).await;
+        assert!(data.is_empty());
+    }
+
+    #[tokio::test]
+    async fn unaligned_file_round_trip() {
+        let dir = TempDir::new("unaligned");
+        let path = dir.join("file");
+        let mut client = TestClient::start(ServerConfig::default(), SessionType::FileTransfer).await;
+
+        let expected: Vec<u8> = (0..2 * CHUNK_SIZE + 123).map(|i| (i % 251) as u8).collect();
+        let chunks = expected.chunks(CHUNK_SIZE).map(|chunk| ClientMessage::FileChunk { data: chunk.to_vec() }).collect();
+        let complete = upload(&mut client, &path, expected.len() as u64, chunks).await;
+        assert!(matches!(complete, ServerMessage::UploadComplete { success: true, .. }), "{:?}", complete);
+        assert_eq!(std::fs::read(&path).unwrap(), expected);
+
+        let (data, _) = download(&mut client, &path).await;
+        assert_eq!(data, expected);
+    }
+
+    #[tokio::test]
+    async fn cancelled_download_stops_sending() {
+        let dir = TempDir::new("cancel-download");
+        let big = dir.join("big");
+        let size = 512 * CHUNK_SIZE;
+        std::fs::write(&big, vec![0xab; size]).unwrap();
+        let small = dir.join("small");
+        std::fs::write(&small, b"small").unwrap();
+        let mut client = TestClient::start(ServerConfig::default(), SessionType::FileTransfer).await;
+
+        client.send(ClientMessage::RequestDownload { path: big, offset: 0 }).await;
+        assert!(matches!(client.recv_reply().await, ServerMessage::StartDownload { .. }));
+        let mut received = 0;
+        while received < 4 * CHUNK_SIZE {
+            match client.recv().await {
+                ServerMessage::FileChunk { data } => received += data.len(),
+                other => panic!("Unexpected {:?}", other),
+            }
+        }
+        client.send(ClientMessage::CancelDownload).await;
+
+        // Whatever was already queued still arrives, then the next download starts
+        client.send(ClientMessage::RequestDownload { path: small, offset: 0 }).await;
+        loop {
+            match client.recv().await {
+                ServerMessage::FileChunk { data } => received += data.len(),
+                ServerMessage::StartDownload { size, .. } => {
+                    assert_eq!(size, 5);
+                    break;
+                }
+                _ => {}
+            }
+        }
+        assert!(received < size / 2, "{} of {} bytes sent after cancelling", received, size);
+    }
+
+    #[tokio::test]
+    async fn resumed_directory_pull_skips_only_unchanged_files() {
+        let dir = TempDir::new("resume-pull");
+        std::fs::create_dir(dir.path().join("tree")).unwrap();
+        for name in ["a", "b", "c", "d"] {
+            std::fs::write(dir.path().join("tree").join(name), name.repeat(100)).unwrap();
+        }
+        let tree = dir.join("tree");
+
+        // The first pull is cut off after two files arrive in full
+        let mut client = TestClient::start(ServerConfig::default(), SessionType::FileTransfer).await;
+        client.send(ClientMessage::RequestDownload { path: tree.clone(), offset: 0 }).await;
+        let mut completed = Vec::new();
+        let mut current = None;
+        while completed.len() < 2 {
+            match client.recv().await {
+                ServerMessage::FileStart { relative_path, size } => current = Some((relative_path, size)),
+                ServerMessage::FileAttributes { relative_path, modified, .. } => {
+                    let (started, size) = current.take().unwrap();
+                    assert_eq!(started, relative_path);
+                    completed.push(crate::CompletedFile { relative_path, size, modified });
+                }
+                _ => {}
+            }
+        }
+        drop(client);
+
+        // The second completed file changes on the server, keeping its size
+        let changed = completed[1].relative_path.clone();
+        let changed_path = dir.path().join("tree").join(&changed);
+        std::fs::write(&changed_path, "x".repeat(100)).unwrap();
+        crate::transfer::set_modified_secs(&changed_path, completed[1].modified.unwrap() - 60).unwrap();
+
+        let mut client = TestClient::start(ServerConfig::default(), SessionType::FileTransfer).await;
+        client.send(ClientMessage::SkipFiles { files: completed.clone() }).await;
+        client.send(ClientMessage::RequestDownload { path: tree, offset: 0 }).await;
+        let mut sent = Vec::new();
+        loop {
+            match client.recv().await {
+                ServerMessage::FileStart { relative_path, .. } => sent.push(relative_path),
+                ServerMessage::FileChunk { data } if sent.last() == Some(&changed) => assert_eq!(data, b"x".repeat(100)),
+                ServerMessage::EndDownload => break,
+                ServerMessage::Error { message } => panic!("Download failed: {}", message),
+                _ => {}
+            }
+        }
+        sent.sort();
+        let mut expected: Vec<String> = ["a", "b", "c", "d"].iter().map(|name| name.to_string())
+            .filter(|name| *name != completed[0].relative_path)
+            .collect();
+        expected.sort();
+        assert_eq!(sent, expected);
+    }
+
+    #[tokio::test]
+    async fn sparse_file_round_trip() {
+        let dir = TempDir::new("sparse");
+        let path = dir.join("image");
+        let mut client = TestClient::start(ServerConfig::default(), SessionType::FileTransfer).await;
+        client.send(ClientMessage::SparseFiles).await;
+
+        let hole = CHUNK_SIZE as u64;
+        let chunks = vec![
+            ClientMessage::FileHole { len: hole },
+            ClientMessage::FileChunk { data: vec![0xab; CHUNK_SIZE] },
+            ClientMessage::FileHole { len: hole },
+        ];
+        let complete = upload(&mut client, &path, 3 * hole, chunks).await;
+        assert!(matches!(complete, ServerMessage::UploadComplete { success: true, .. }), "{:?}", complete);
+
+        let mut expected = vec![0; CHUNK_SIZE];
+        expected.extend(vec![0xab; CHUNK_SIZE]);
+        expected.extend(vec![0; CHUNK_SIZE]);
+        assert_eq!(std::fs::read(&path).unwrap(), expected);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::MetadataExt;
+            assert!(std::fs::metadata(&path).unwrap().blocks() * 512 < 3 * hole, "holes were written out");
+        }
+
+        let (data, holes) = download(&mut client, &path).await;
+        assert_eq!(data, expected);
+        assert_eq!(holes, 2 * hole);
+    }
+
+    #[tokio::test]
+    async fn timestamps_round_trip() {
+        let dir = TempDir::new("timestamps");
+        let path = dir.join("file");
+        let mut client = TestClient::start(ServerConfig::default(), SessionType::FileTransfer).await;
+
+        let modified = 1_500_000_000;
+        let chunks = vec![
+            ClientMessage::FileChunk { data: b"data".to_vec() },
+            ClientMessage::FileAttributes { relative_path: String::new(), modified: Some(modified), mode: None },
+        ];
+        let complete = upload(&mut client, &path, 4, chunks).await;
+        assert!(matches!(complete, ServerMessage::UploadComplete { success: true, .. }), "{:?}", complete);
+        assert_eq!(crate::transfer::modified_secs(Path::new(&path)), Some(modified));
+
+        client.send(ClientMessage::RequestDownload { path: path.clone(), offset: 0 }).await;
+        let mut attributes = None;
+        loop {
+            match client.recv().await {
+                ServerMessage::FileAttributes { modified, .. } => attributes = Some(modified),
+                ServerMessage::EndDownload => break,
+                ServerMessage::Error { message } => panic!("Download failed: {}", message),
+                _ => {}
+            }
+        }
+        assert_eq!(attributes, Some(Some(modified)));
+    }
+
+    #[tokio::test]
+    async fn oversized_hole_is_rejected() {
+        let dir = TempDir::new("oversized-hole");
+        let path = dir.join("file");
+        let config = ServerConfig { max_upload: Some(1 << 20), ..ServerConfig::default() };
+        let mut client = TestClient::start(config, SessionType::FileTransfer).await;
+        client.send(ClientMessage::SparseFiles).await;
+
+        client.send(ClientMessage::StartUpload { path: path.clone(), size: 0, is_dir: false, force: true }).await;
+        assert!(matches!(client.recv_reply().await, ServerMessage::UploadAck));
+        client.send(ClientMessage::FileHole { len: u64::MAX }).await;
+        assert!(matches!(client.recv_reply().await, ServerMessage::Error { .. }));
+        client.send(ClientMessage::EndUpload).await;
+        assert!(matches!(client.recv_reply().await, ServerMessage::UploadComplete { success: false, .. }));
+        assert_eq!(std::fs::metadata(&path).unwrap().len(), 0);
+    }
+}
@@ -0,0 +1,92 @@
+//! Helpers shared by the unit tests: scratch directories and a server stream
+//! driven over `transport::memory_pair`
+
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU32, Ordering};
+
+use crate::transport::{BoxedRead, BoxedWrite};
+use crate::{ClientMessage, MessageEnvelope, MessagePayload, ServerMessage};
+
+/// A fresh directory under the system temp dir, removed again on drop
+pub struct TempDir(PathBuf);
+
+impl TempDir {
+    pub fn new(name: &str) -> Self {
+        static COUNTER: AtomicU32 = AtomicU32::new(0);
+        let n = COUNTER.fetch_add(1, Ordering::Relaxed);
+        let path = std::env::temp_dir().join(format!("kerr-test-{}-{}-{}", name, std::process::id(), n));
+        let _ = std::fs::remove_dir_all(&path);
+        std::fs::create_dir_all(&path).expect("Failed to create test directory");
+        Self(path)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.0
+    }
+
+    /// `name` inside the directory, as the string paths the protocol uses
+    pub fn join(&self, name: &str) -> String {
+        self.0.join(name).to_string_lossy().to_string()
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+/// Node id the in-memory server sees for its peer
+pub fn node_id() -> iroh::PublicKey {
+    iroh::SecretKey::from_bytes(&[7; 32]).public()
+}
+
+/// The client end of one server stream running in memory, speaking for one session
+pub struct TestClient {
+    send: BoxedWrite,
+    recv: BoxedRead,
+    session_id: String,
+}
+
+impl TestClient {
+    /// Serve a stream with `config` and open a `session_type` session on it
+    pub async fn start(config: crate::server::ServerConfig, session_type: crate::SessionType) -> Self {
+        Self::start_with(config, ClientMessage::Hello { session_type }).await
+    }
+
+    /// Serve a stream with `config` and open a session on it with the handshake `hello`
+    pub async fn start_with(config: crate::server::ServerConfig, hello: ClientMessage) -> Self {
+        let ((send, recv), (server_send, server_recv)) = crate::transport::memory_pair(1 << 20);
+        tokio::spawn(crate::server::serve_stream(node_id(), server_send, server_recv, config));
+        let mut client = Self { send, recv, session_id: "test_session".to_string() };
+        client.send(hello).await;
+        client
+    }
+
+    pub async fn send(&mut self, msg: ClientMessage) {
+        let envelope = MessageEnvelope { session_id: self.session_id.clone(), payload: MessagePayload::Client(msg) };
+        crate::send_envelope(&mut self.send, &envelope).await.expect("Failed to send envelope");
+    }
+
+    /// The next server message, failing the test if none arrives within a few seconds
+    pub async fn recv(&mut self) -> ServerMessage {
+        let envelope = tokio::time::timeout(std::time::Duration::from_secs(5), crate::recv_envelope(&mut self.recv))
+            .await
+            .expect("Timed out waiting for the server")
+            .expect("Failed to receive envelope");
+        match envelope.payload {
+            MessagePayload::Server(msg) => msg,
+            MessagePayload::Client(msg) => panic!("Server sent a client message: {:?}", msg),
+        }
+    }
+
+    /// The next server message that isn't upload flow control
+    pub async fn recv_reply(&mut self) -> ServerMessage {
+        loop {
+            match self.recv().await {
+                ServerMessage::Progress { .. } => {}
+                msg => return msg,
+            }
+        }
+    }
+}
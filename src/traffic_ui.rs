@@ -4,15 +4,58 @@ use ratatui::{
     style::{Color, Modifier, Style, Stylize},
     symbols,
     text::Span,
-    widgets::{Axis, Block, Borders, Chart, Dataset, GraphType},
+    widgets::{Axis, Block, Borders, Cell, Chart, Dataset, GraphType, Row, Table},
     DefaultTerminal, Frame,
 };
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
 use std::sync::atomic::{AtomicU64, Ordering};
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 use std::time::{Duration, Instant};
 
+/// A forwarded TCP connection or UDP peer, as listed in the streams view
+pub struct OpenStream {
+    pub peer: SocketAddr,
+    pub opened: Instant,
+    pub upload_bytes: AtomicU64,
+    pub download_bytes: AtomicU64,
+}
+
+impl OpenStream {
+    pub fn new(peer: SocketAddr) -> Self {
+        Self {
+            peer,
+            opened: Instant::now(),
+            upload_bytes: AtomicU64::new(0),
+            download_bytes: AtomicU64::new(0),
+        }
+    }
+}
+
+/// Open relay streams by stream_id, shared between the relay tasks and the UI
+pub type OpenStreams = Arc<Mutex<BTreeMap<u32, Arc<OpenStream>>>>;
+
+fn format_bytes(bytes: u64) -> String {
+    match bytes {
+        b if b >= 1024 * 1024 => format!("{:.2} MB", b as f64 / (1024.0 * 1024.0)),
+        b if b >= 1024 => format!("{:.1} KB", b as f64 / 1024.0),
+        b => format!("{} B", b),
+    }
+}
+
+fn format_duration(d: Duration) -> String {
+    let secs = d.as_secs();
+    if secs >= 3600 {
+        format!("{}h{:02}m", secs / 3600, (secs % 3600) / 60)
+    } else {
+        format!("{}m{:02}s", secs / 60, secs % 60)
+    }
+}
+
 pub struct TrafficApp {
-    local_port: u16,
+    /// "TCP" or "UDP", for the title
+    protocol: &'static str,
+    local_addr: SocketAddr,
     remote_port: u16,
     upload_bytes: Arc<AtomicU64>,
     download_bytes: Arc<AtomicU64>,
@@ -24,18 +67,28 @@ pub struct TrafficApp {
     last_download: u64,
     max_speed: f64,
     shutdown_rx: tokio::sync::mpsc::Receiver<()>,
+    open_streams: OpenStreams,
+    /// Accepted connections waiting for a free stream slot
+    queued_streams: Arc<AtomicU64>,
+    /// Show the open streams table instead of the chart
+    show_streams: bool,
 }
 
 impl TrafficApp {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
-        local_port: u16,
+        protocol: &'static str,
+        local_addr: SocketAddr,
         remote_port: u16,
         upload_bytes: Arc<AtomicU64>,
         download_bytes: Arc<AtomicU64>,
         shutdown_rx: tokio::sync::mpsc::Receiver<()>,
+        open_streams: OpenStreams,
+        queued_streams: Arc<AtomicU64>,
     ) -> Self {
         Self {
-            local_port,
+            protocol,
+            local_addr,
             remote_port,
             upload_bytes,
             download_bytes,
@@ -47,6 +100,9 @@ impl TrafficApp {
             last_download: 0,
             max_speed: 100.0, // Start with 100 KB/s max
             shutdown_rx,
+            open_streams,
+            queued_streams,
+            show_streams: false,
         }
     }
 
@@ -63,6 +119,9 @@ impl TrafficApp {
                     if key.code == KeyCode::Char('q') || key.code == KeyCode::Esc {
                         return Ok(());
                     }
+                    if key.code == KeyCode::Char('s') {
+                        self.show_streams = !self.show_streams;
+                    }
                 }
             }
 
@@ -124,16 +183,26 @@ impl TrafficApp {
             0.0
         };
 
+        let queued = self.queued_streams.load(Ordering::Relaxed);
+        let queued_note = if queued > 0 { format!(" | Queued: {}", queued) } else { String::new() };
+
         let title = format!(
-            " TCP Relay: localhost:{} -> remote:{} | Upload: {:.2} MB ({:.1} KB/s) | Download: {:.2} MB ({:.1} KB/s) | Press 'q' to quit ",
-            self.local_port,
+            " {} Relay: {} -> remote:{} | Upload: {:.2} MB ({:.1} KB/s) | Download: {:.2} MB ({:.1} KB/s){} | 's' streams, 'q' quit ",
+            self.protocol,
+            self.local_addr,
             self.remote_port,
             total_upload_mb,
             current_upload_kbs,
             total_download_mb,
-            current_download_kbs
+            current_download_kbs,
+            queued_note
         );
 
+        if self.show_streams {
+            self.draw_streams(frame, areas[0], title);
+            return;
+        }
+
         let datasets = vec![
             Dataset::default()
                 .name("Upload")
@@ -192,17 +261,67 @@ impl TrafficApp {
 
         frame.render_widget(chart, areas[0]);
     }
+
+    fn draw_streams(&self, frame: &mut Frame, area: ratatui::layout::Rect, title: String) {
+        let rows: Vec<Row> = match self.open_streams.lock() {
+            Ok(streams) => streams
+                .iter()
+                .map(|(id, stream)| {
+                    Row::new(vec![
+                        Cell::from(id.to_string()),
+                        Cell::from(stream.peer.to_string()),
+                        Cell::from(format_duration(stream.opened.elapsed())),
+                        Cell::from(format_bytes(stream.upload_bytes.load(Ordering::Relaxed))),
+                        Cell::from(format_bytes(stream.download_bytes.load(Ordering::Relaxed))),
+                    ])
+                })
+                .collect(),
+            Err(_) => Vec::new(),
+        };
+        let count = rows.len();
+
+        let header = Row::new(vec!["Stream", "Local peer", "Open for", "Upload", "Download"])
+            .style(Style::default().add_modifier(Modifier::BOLD).fg(Color::Yellow));
+        let table = Table::new(
+            rows,
+            [
+                Constraint::Length(8),
+                Constraint::Min(22),
+                Constraint::Length(10),
+                Constraint::Length(12),
+                Constraint::Length(12),
+            ],
+        )
+        .header(header)
+        .block(
+            Block::default()
+                .title(title.cyan().bold())
+                .title_bottom(format!(
+                    " {} open stream(s), {} queued ",
+                    count,
+                    self.queued_streams.load(Ordering::Relaxed)
+                ))
+                .borders(Borders::ALL)
+                .border_style(Style::default().fg(Color::White)),
+        );
+
+        frame.render_widget(table, area);
+    }
 }
 
+#[allow(clippy::too_many_arguments)]
 pub fn run_traffic_ui(
-    local_port: u16,
+    protocol: &'static str,
+    local_addr: SocketAddr,
     remote_port: u16,
     upload_bytes: Arc<AtomicU64>,
     download_bytes: Arc<AtomicU64>,
     shutdown_rx: tokio::sync::mpsc::Receiver<()>,
+    open_streams: OpenStreams,
+    queued_streams: Arc<AtomicU64>,
 ) -> std::io::Result<()> {
     let terminal = ratatui::init();
-    let app = TrafficApp::new(local_port, remote_port, upload_bytes, download_bytes, shutdown_rx);
+    let app = TrafficApp::new(protocol, local_addr, remote_port, upload_bytes, download_bytes, shutdown_rx, open_streams, queued_streams);
     let result = app.run(terminal);
     ratatui::restore();
     result
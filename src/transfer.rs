@@ -2,16 +2,49 @@
 
 use std::path::{Path, PathBuf};
 use std::fs;
+use std::sync::atomic::{AtomicU64, Ordering};
 use walkdir::WalkDir;
 use anyhow::{Result, Context};
 
+/// How symlinks inside a transferred directory are handled.
+/// The path given on the command line is always followed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SymlinkMode {
+    /// Recreate the link itself on the other side
+    #[default]
+    Preserve,
+    /// Copy what the link points to
+    Follow,
+    /// Leave links out, for peers that can't recreate them
+    Skip,
+}
+
+/// Entries below `path`, with unreadable ones left out. Only `SymlinkMode::Follow`
+/// descends into linked directories; walkdir then reports a link back to an ancestor
+/// as an error instead of recursing, so a looped tree still gives a finite list.
+fn walk(path: &Path, mode: SymlinkMode) -> impl Iterator<Item = walkdir::DirEntry> {
+    WalkDir::new(path)
+        .follow_links(mode == SymlinkMode::Follow)
+        .into_iter()
+        .filter_map(|entry| match entry {
+            Ok(entry) => Some(entry),
+            Err(e) => {
+                if let Some(ancestor) = e.loop_ancestor() {
+                    tracing::warn!(path = ?e.path(), ancestor = ?ancestor, "Skipping symlink loop");
+                }
+                None
+            }
+        })
+}
+
 /// Calculate total size of a file or directory
-pub fn calculate_size(path: &Path) -> Result<u64> {
+/// Preserved symlinks count as zero bytes.
+pub fn calculate_size(path: &Path, mode: SymlinkMode) -> Result<u64> {
     if path.is_file() {
         Ok(fs::metadata(path)?.len())
     } else if path.is_dir() {
         let mut total = 0;
-        for entry in WalkDir::new(path).into_iter().filter_map(|e| e.ok()) {
+        for entry in walk(path, mode) {
             if entry.file_type().is_file() {
                 total += entry.metadata()?.len();
             }
@@ -23,14 +56,16 @@ pub fn calculate_size(path: &Path) -> Result<u64> {
 }
 
 /// Get all files in a directory recursively
-pub fn get_files_recursive(path: &Path) -> Result<Vec<PathBuf>> {
+/// With `SymlinkMode::Preserve` the symlinks are listed too, to be sent as links.
+pub fn get_files_recursive(path: &Path, mode: SymlinkMode) -> Result<Vec<PathBuf>> {
     let mut files = Vec::new();
 
     if path.is_file() {
         files.push(path.to_path_buf());
     } else if path.is_dir() {
-        for entry in WalkDir::new(path).into_iter().filter_map(|e| e.ok()) {
-            if entry.file_type().is_file() {
+        for entry in walk(path, mode) {
+            let preserved_link = mode == SymlinkMode::Preserve && entry.path_is_symlink();
+            if entry.file_type().is_file() || preserved_link {
                 files.push(entry.path().to_path_buf());
             }
         }
@@ -39,6 +74,65 @@ pub fn get_files_recursive(path: &Path) -> Result<Vec<PathBuf>> {
     Ok(files)
 }
 
+/// Total size and file list for a download in a single walk, counting entries into
+/// `scanned` as it goes so a caller on another thread can report progress.
+/// Equivalent to `calculate_size` followed by `get_files_recursive`.
+pub fn scan_download(path: &Path, mode: SymlinkMode, scanned: &AtomicU64) -> Result<(u64, Vec<PathBuf>)> {
+    if path.is_file() {
+        return Ok((fs::metadata(path)?.len(), vec![path.to_path_buf()]));
+    }
+    if !path.is_dir() {
+        anyhow::bail!("Path does not exist: {}", path.display());
+    }
+
+    let mut total = 0;
+    let mut files = Vec::new();
+    for entry in walk(path, mode) {
+        scanned.fetch_add(1, Ordering::Relaxed);
+        if entry.file_type().is_file() {
+            total += entry.metadata()?.len();
+            files.push(entry.path().to_path_buf());
+        } else if mode == SymlinkMode::Preserve && entry.path_is_symlink() {
+            files.push(entry.path().to_path_buf());
+        }
+    }
+    Ok((total, files))
+}
+
+/// Where the symlink at `path` points, as sent to the peer
+pub fn symlink_target(path: &Path) -> Result<String> {
+    let target = fs::read_link(path)
+        .with_context(|| format!("Failed to read symlink: {}", path.display()))?;
+    Ok(target.to_string_lossy().to_string())
+}
+
+/// Recreate a symlink received from the peer, replacing a file or link already at `path`
+pub fn create_symlink(path: &Path, target: &str) -> Result<()> {
+    ensure_parent_dir(path)?;
+    if fs::symlink_metadata(path).is_ok_and(|m| !m.is_dir()) {
+        fs::remove_file(path)
+            .with_context(|| format!("Failed to replace {}", path.display()))?;
+    }
+
+    #[cfg(unix)]
+    let result = std::os::unix::fs::symlink(target, path);
+
+    #[cfg(windows)]
+    let result = {
+        let resolved = path.parent().unwrap_or(Path::new("")).join(target);
+        if resolved.is_dir() {
+            std::os::windows::fs::symlink_dir(target, path)
+        } else {
+            std::os::windows::fs::symlink_file(target, path)
+        }
+    };
+
+    #[cfg(not(any(unix, windows)))]
+    let result: std::io::Result<()> = Err(std::io::Error::other("symlinks are not supported on this platform"));
+
+    result.with_context(|| format!("Failed to create symlink: {} -> {}", path.display(), target))
+}
+
 /// Create parent directories if they don't exist
 pub fn ensure_parent_dir(path: &Path) -> Result<()> {
     if let Some(parent) = path.parent() {
@@ -48,5 +142,228 @@ pub fn ensure_parent_dir(path: &Path) -> Result<()> {
     Ok(())
 }
 
+/// Get a file's modification time as seconds since the Unix epoch
+pub fn modified_secs(path: &Path) -> Option<i64> {
+    let metadata = fs::metadata(path).ok()?;
+    Some(filetime::FileTime::from_last_modification_time(&metadata).unix_seconds())
+}
+
+/// Set a file's modification time from seconds since the Unix epoch
+pub fn set_modified_secs(path: &Path, secs: i64) -> Result<()> {
+    filetime::set_file_mtime(path, filetime::FileTime::from_unix_time(secs, 0))
+        .with_context(|| format!("Failed to set modification time: {}", path.display()))
+}
+
+/// Get a file's Unix permission bits (None on platforms without them)
+pub fn mode_bits(path: &Path) -> Option<u32> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::metadata(path).ok().map(|m| m.permissions().mode() & 0o7777)
+    }
+
+    #[cfg(not(unix))]
+    {
+        let _ = path;
+        None
+    }
+}
+
+/// Apply Unix permission bits received from the peer
+/// Only the rwx bits are applied: setuid, setgid and sticky are never taken from the
+/// peer. On platforms without Unix permissions this is a no-op.
+pub fn set_mode_bits(path: &Path, mode: u32) -> Result<()> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(path, fs::Permissions::from_mode(mode & 0o777))
+            .with_context(|| format!("Failed to set permissions: {}", path.display()))?;
+    }
+
+    #[cfg(not(unix))]
+    {
+        let _ = (path, mode);
+    }
+
+    Ok(())
+}
+
+/// Whether a chunk read from a file is all zeros, so it can be sent as a `FileHole`
+/// and the receiver can leave a gap instead of writing it (keeping sparse files sparse)
+pub fn is_hole(data: &[u8]) -> bool {
+    !data.is_empty() && data.iter().all(|&b| b == 0)
+}
+
+/// Check the length of a received `FileHole`. A hole stands in for one chunk, so
+/// anything longer than `CHUNK_SIZE` is refused rather than zero-filled.
+pub fn hole_len(len: u64) -> Result<u64> {
+    if len > CHUNK_SIZE as u64 {
+        anyhow::bail!("File hole of {} bytes is larger than a chunk ({} bytes)", len, CHUNK_SIZE);
+    }
+    Ok(len)
+}
+
+/// Skip `len` bytes at the end of a file being written sequentially (including one
+/// opened for appending), leaving a hole rather than writing zeros
+pub fn write_hole(file: &mut fs::File, len: u64) -> std::io::Result<()> {
+    use std::io::{Seek, SeekFrom};
+
+    let end = file.metadata()?.len().checked_add(len)
+        .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::InvalidInput, "file hole overflows the file size"))?;
+    file.set_len(end)?;
+    file.seek(SeekFrom::Start(end))?;
+    Ok(())
+}
+
+/// Gzip a chunk for sending as `CompressedChunk`, or None if that wouldn't make it
+/// smaller (already-compressed data such as archives and images)
+pub fn compress_chunk(data: &[u8]) -> Option<Vec<u8>> {
+    use std::io::Write;
+
+    let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::fast());
+    encoder.write_all(data).ok()?;
+    let compressed = encoder.finish().ok()?;
+    (compressed.len() < data.len()).then_some(compressed)
+}
+
+/// Unpack a `CompressedChunk`. Chunks are never larger than `CHUNK_SIZE`, so
+/// anything that inflates beyond that is refused rather than buffered.
+pub fn decompress_chunk(data: &[u8]) -> Result<Vec<u8>> {
+    use std::io::Read;
+
+    let mut chunk = Vec::new();
+    flate2::read::GzDecoder::new(data)
+        .take(CHUNK_SIZE as u64 + 1)
+        .read_to_end(&mut chunk)
+        .context("Corrupt compressed chunk")?;
+    if chunk.len() > CHUNK_SIZE {
+        anyhow::bail!("Compressed chunk inflates beyond {} bytes", CHUNK_SIZE);
+    }
+    Ok(chunk)
+}
+
+/// Parse a transfer rate such as `500k` or `2M` (1024-based suffixes K, M and G,
+/// optionally followed by `B`) into bytes per second
+pub fn parse_rate(s: &str) -> std::result::Result<u64, String> {
+    let s = s.trim();
+    let digits = s.trim_end_matches(|c: char| c.is_ascii_alphabetic());
+    let suffix = s[digits.len()..].to_ascii_lowercase();
+    let scale: u64 = match suffix.strip_suffix('b').unwrap_or(&suffix) {
+        "" => 1,
+        "k" => 1 << 10,
+        "m" => 1 << 20,
+        "g" => 1 << 30,
+        suffix => return Err(format!("unknown size suffix '{}' (use K, M or G)", suffix)),
+    };
+    let value: u64 = digits.parse().map_err(|_| format!("'{}' is not a byte rate", s))?;
+    value.checked_mul(scale).ok_or_else(|| format!("'{}' is too large", s))
+}
+
+/// Token bucket that holds a transfer to a byte rate, letting up to a second's worth
+/// (and never less than a chunk) through in a burst. A rate of 0 means unlimited.
+pub struct RateLimiter {
+    rate: f64,
+    burst: f64,
+    tokens: f64,
+    last: std::time::Instant,
+}
+
+impl RateLimiter {
+    pub fn new(bytes_per_sec: u64) -> Self {
+        let rate = bytes_per_sec as f64;
+        let burst = rate.max(CHUNK_SIZE as f64);
+        Self { rate, burst, tokens: burst, last: std::time::Instant::now() }
+    }
+
+    /// Count `len` bytes as transferred; `ready` then waits until the rate allows more
+    pub fn consume(&mut self, len: u64) {
+        if self.rate > 0.0 {
+            self.refill();
+            self.tokens -= len as f64;
+        }
+    }
+
+    /// Wait until the bytes counted so far fit within the rate
+    pub async fn ready(&mut self) {
+        if self.rate > 0.0 {
+            self.refill();
+            if self.tokens < 0.0 {
+                tokio::time::sleep(std::time::Duration::from_secs_f64(-self.tokens / self.rate)).await;
+                self.refill();
+            }
+        }
+    }
+
+    fn refill(&mut self) {
+        let now = std::time::Instant::now();
+        let elapsed = now.duration_since(self.last).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.burst);
+        self.last = now;
+    }
+}
+
 /// Chunk size for file transfers (64KB)
 pub const CHUNK_SIZE: usize = 65536;
+
+/// The server acknowledges uploads with a `Progress` message every this many bytes
+pub const UPLOAD_ACK_INTERVAL: u64 = 4 * CHUNK_SIZE as u64;
+
+/// Maximum unacknowledged upload data the sender keeps in flight.
+/// Must be larger than `UPLOAD_ACK_INTERVAL` or the sender would wait forever.
+pub const UPLOAD_WINDOW: u64 = 16 * CHUNK_SIZE as u64;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hole_longer_than_a_chunk_is_refused() {
+        assert_eq!(hole_len(CHUNK_SIZE as u64).unwrap(), CHUNK_SIZE as u64);
+        assert!(hole_len(CHUNK_SIZE as u64 + 1).is_err());
+        assert!(hole_len(u64::MAX).is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn set_mode_bits_drops_special_bits() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = crate::test_util::TempDir::new("mode-bits");
+        let path = dir.path().join("file");
+        fs::write(&path, b"").unwrap();
+        set_mode_bits(&path, 0o4755).unwrap();
+        assert_eq!(fs::metadata(&path).unwrap().permissions().mode() & 0o7777, 0o755);
+        set_mode_bits(&path, 0o7640).unwrap();
+        assert_eq!(fs::metadata(&path).unwrap().permissions().mode() & 0o7777, 0o640);
+    }
+
+    #[test]
+    fn write_hole_refuses_to_overflow() {
+        let dir = crate::test_util::TempDir::new("write-hole");
+        let mut file = fs::File::create(dir.path().join("file")).unwrap();
+        write_hole(&mut file, 10).unwrap();
+        assert_eq!(file.metadata().unwrap().len(), 10);
+        assert!(write_hole(&mut file, u64::MAX).is_err());
+        assert_eq!(file.metadata().unwrap().len(), 10);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlink_loop_terminates() {
+        let dir = crate::test_util::TempDir::new("symlink-loop");
+        let tree = dir.path().join("tree");
+        fs::create_dir_all(tree.join("sub")).unwrap();
+        fs::write(tree.join("sub/file"), b"data").unwrap();
+        std::os::unix::fs::symlink(&tree, tree.join("sub/up")).unwrap();
+        std::os::unix::fs::symlink("loop", tree.join("loop")).unwrap();
+
+        for mode in [SymlinkMode::Follow, SymlinkMode::Preserve, SymlinkMode::Skip] {
+            assert_eq!(calculate_size(&tree, mode).unwrap(), 4, "{:?}", mode);
+            let files = get_files_recursive(&tree, mode).unwrap();
+            assert!(files.contains(&tree.join("sub/file")), "{:?}", mode);
+            let (total, scanned_files) = scan_download(&tree, mode, &AtomicU64::new(0)).unwrap();
+            assert_eq!(total, 4, "{:?}", mode);
+            assert_eq!(scanned_files, files, "{:?}", mode);
+        }
+    }
+}
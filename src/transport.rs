@@ -0,0 +1,33 @@
+//! Byte streams that carry the envelope protocol
+//!
+//! Everything above the framing in `send_envelope`/`recv_envelope` only needs an
+//! ordered, reliable byte stream in each direction. In production that's an iroh
+//! QUIC bi stream; `memory_pair` gives two connected in-memory ends so server
+//! sessions and `RemoteFilesystem` can be driven without a network.
+
+use tokio::io::{AsyncRead, AsyncWrite};
+
+/// Write half of an envelope stream (an iroh `SendStream`, a duplex half, ...)
+pub trait EnvelopeWrite: AsyncWrite + Unpin + Send {}
+
+impl<T: AsyncWrite + Unpin + Send + ?Sized> EnvelopeWrite for T {}
+
+/// Read half of an envelope stream (an iroh `RecvStream`, a duplex half, ...)
+pub trait EnvelopeRead: AsyncRead + Unpin + Send {}
+
+impl<T: AsyncRead + Unpin + Send + ?Sized> EnvelopeRead for T {}
+
+pub type BoxedWrite = Box<dyn EnvelopeWrite>;
+pub type BoxedRead = Box<dyn EnvelopeRead>;
+
+/// Two connected in-memory stream pairs: `(client, server)`.
+/// Bytes written to one side's write half come out of the other side's read half.
+pub fn memory_pair(buffer: usize) -> ((BoxedWrite, BoxedRead), (BoxedWrite, BoxedRead)) {
+    let (client, server) = tokio::io::duplex(buffer);
+    let (client_read, client_write) = tokio::io::split(client);
+    let (server_read, server_write) = tokio::io::split(server);
+    (
+        (Box::new(client_write), Box::new(client_read)),
+        (Box::new(server_write), Box::new(server_read)),
+    )
+}
@@ -34,6 +34,62 @@ struct PortForwardingSession {
     stop_tx: mpsc::UnboundedSender<()>,
 }
 
+/// A web UI upload or download in flight, reported by /api/transfers
+struct ActiveTransfer {
+    path: String,
+    direction: &'static str,
+    total_bytes: u64,
+    bytes: Arc<std::sync::atomic::AtomicU64>,
+    started: std::time::Instant,
+}
+
+type TransferRegistry = Arc<std::sync::Mutex<HashMap<String, ActiveTransfer>>>;
+
+/// Keeps a transfer listed while alive; logs its average rate when dropped
+struct TransferGuard {
+    id: String,
+    registry: TransferRegistry,
+    bytes: Arc<std::sync::atomic::AtomicU64>,
+}
+
+impl TransferGuard {
+    fn start(registry: &TransferRegistry, path: &str, direction: &'static str, total_bytes: u64) -> Self {
+        let id = format!("{}_{}", direction, rand::random::<u32>());
+        let bytes = Arc::new(std::sync::atomic::AtomicU64::new(0));
+        registry.lock().unwrap_or_else(|e| e.into_inner()).insert(id.clone(), ActiveTransfer {
+            path: path.to_string(),
+            direction,
+            total_bytes,
+            bytes: Arc::clone(&bytes),
+            started: std::time::Instant::now(),
+        });
+        Self { id, registry: Arc::clone(registry), bytes }
+    }
+
+    fn add(&self, n: u64) {
+        self.bytes.fetch_add(n, std::sync::atomic::Ordering::Relaxed);
+    }
+}
+
+impl Drop for TransferGuard {
+    fn drop(&mut self) {
+        let removed = self.registry.lock().unwrap_or_else(|e| e.into_inner()).remove(&self.id);
+        if let Some(transfer) = removed {
+            let bytes = transfer.bytes.load(std::sync::atomic::Ordering::Relaxed);
+            let secs = transfer.started.elapsed().as_secs_f64();
+            eprintln!(
+                "[TRANSFER] {} {}: {}/{} bytes in {:.1}s ({:.2} MB/s)",
+                transfer.direction,
+                transfer.path,
+                bytes,
+                transfer.total_bytes,
+                secs,
+                bytes as f64 / secs.max(0.001) / 1_000_000.0
+            );
+        }
+    }
+}
+
 /// Shared state for the web UI
 struct AppState {
     remote_fs: Arc<Mutex<Option<Arc<RemoteFilesystem>>>>,
@@ -43,12 +99,22 @@ struct AppState {
     connection_string: Arc<Mutex<Option<String>>>,
     connection_alias: Arc<Mutex<Option<String>>>,
     port_forwardings: Arc<Mutex<HashMap<String, PortForwardingSession>>>,
+    transfers: TransferRegistry,
+    /// Confirmation tokens for recursive deletes, mapped to the directory they allow
+    /// deleting and when they were handed out
+    delete_tokens: Arc<std::sync::Mutex<HashMap<String, (String, std::time::Instant)>>>,
+    /// Secret for this run, set as a cookie on the page so only the UI it serves can
+    /// read stored connection strings
+    ui_token: String,
 }
 
+/// Cookie carrying `AppState::ui_token`
+const UI_TOKEN_COOKIE: &str = "kerr_ui_token";
+
 /// Run the web UI server
 pub async fn run_web_ui(connection_string: Option<String>, port: u16) -> Result<()> {
     // Create endpoint for future connections
-    let endpoint = iroh::endpoint::Endpoint::bind(iroh::endpoint::presets::N0).await?;
+    let endpoint = crate::bind_endpoint().await?;
 
     // If connection string is provided, connect immediately
     let (node_addr, connection, remote_fs, conn_str_stored, conn_alias) = if let Some(conn_str) = connection_string {
@@ -72,6 +138,9 @@ pub async fn run_web_ui(connection_string: Option<String>, port: u16) -> Result<
         connection_string: Arc::new(Mutex::new(conn_str_stored)),
         connection_alias: Arc::new(Mutex::new(conn_alias)),
         port_forwardings: Arc::new(Mutex::new(HashMap::new())),
+        transfers: Arc::new(std::sync::Mutex::new(HashMap::new())),
+        delete_tokens: Arc::new(std::sync::Mutex::new(HashMap::new())),
+        ui_token: base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(rand::random::<[u8; 32]>()),
     });
 
     // Build our application router
@@ -81,6 +150,7 @@ pub async fn run_web_ui(connection_string: Option<String>, port: u16) -> Result<
         .route("/api/auth/callback", get(handle_oauth_callback))
         .route("/api/connection/status", get(connection_status))
         .route("/api/connection/list", get(list_connections))
+        .route("/api/connection/string", get(connection_string_for_alias))
         .route("/api/connection/connect", post(connect_to_connection))
         .route("/api/connection/disconnect", post(disconnect_connection))
         .route("/ws/shell", get(websocket_handler))
@@ -91,6 +161,10 @@ pub async fn run_web_ui(connection_string: Option<String>, port: u16) -> Result<
         .route("/api/file/content", post(write_file))
         .route("/api/file/metadata", get(get_metadata))
         .route("/api/file/delete", delete(delete_file))
+        .route("/api/file/mkdir", post(create_directory))
+        .route("/api/files/batch", post(batch_files))
+        .route("/api/files/limits", get(file_limits))
+        .route("/api/transfers", get(list_transfers))
         .route("/api/port-forward/create", post(create_port_forward))
         .route("/api/port-forward/disconnect", post(disconnect_port_forward))
         .fallback(static_handler)
@@ -114,7 +188,10 @@ async fn connect_to_remote(
 ) -> Result<(iroh::endpoint::Connection, RemoteFilesystem)> {
     eprintln!("[CONNECT] Connecting to remote host (single-stream mode)...");
     // Connect to the remote host
-    let conn = endpoint.connect(addr.clone(), crate::ALPN).await?;
+    let timeout = crate::connect_timeout();
+    let conn = tokio::time::timeout(timeout, endpoint.connect(addr.clone(), crate::ALPN))
+        .await
+        .map_err(|_| anyhow::anyhow!("No answer within {}s (see --connect-timeout)", timeout.as_secs()))??;
     eprintln!("[CONNECT] Connection established!");
 
     eprintln!("[CONNECT] Opening single bidirectional stream for multiplexing...");
@@ -141,14 +218,57 @@ async fn connect_to_remote(
         send,
         recv,
         "browser_1".to_string(),
-    );
+    )
+    .with_reconnect(conn.clone());
     eprintln!("[CONNECT] RemoteFilesystem created successfully!");
 
     Ok((conn, remote_fs))
 }
 
+/// Whether the request was addressed to this machine by name, rather than through
+/// some other host name that resolves here (DNS rebinding)
+fn is_loopback_host(headers: &axum::http::HeaderMap) -> bool {
+    let Some(host) = headers.get(header::HOST).and_then(|h| h.to_str().ok()) else {
+        return false;
+    };
+    let name = match host.rsplit_once(':') {
+        Some((name, port)) if !port.contains(']') => name,
+        _ => host,
+    };
+    matches!(name, "127.0.0.1" | "localhost" | "[::1]")
+}
+
+/// Whether the request carries this run's UI token cookie
+fn has_ui_token(headers: &axum::http::HeaderMap, token: &str) -> bool {
+    headers
+        .get_all(header::COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(';'))
+        .filter_map(|cookie| cookie.trim().split_once('='))
+        .any(|(name, value)| name == UI_TOKEN_COOKIE && value == token)
+}
+
+/// The UI page, handing out the token cookie when it's loaded from this machine
+fn index_response(state: &AppState, headers: &axum::http::HeaderMap, data: Vec<u8>) -> Response {
+    let mut response = Response::builder()
+        .status(StatusCode::OK)
+        .header(header::CONTENT_TYPE, "text/html");
+    if is_loopback_host(headers) {
+        response = response.header(
+            header::SET_COOKIE,
+            format!("{}={}; Path=/; HttpOnly; SameSite=Strict", UI_TOKEN_COOKIE, state.ui_token),
+        );
+    }
+    response.body(Body::from(data)).unwrap()
+}
+
 /// Serve static files from embedded assets
-async fn static_handler(uri: axum::http::Uri) -> impl IntoResponse {
+async fn static_handler(
+    State(state): State<Arc<AppState>>,
+    headers: axum::http::HeaderMap,
+    uri: axum::http::Uri,
+) -> impl IntoResponse {
     let path = uri.path().trim_start_matches('/');
 
     // If path is empty, serve index.html
@@ -159,6 +279,7 @@ async fn static_handler(uri: axum::http::Uri) -> impl IntoResponse {
     };
 
     match Asset::get(path) {
+        Some(content) if path == "index.html" => index_response(&state, &headers, content.data.to_vec()),
         Some(content) => {
             let mime = mime_guess::from_path(path).first_or_octet_stream();
             Response::builder()
@@ -171,11 +292,7 @@ async fn static_handler(uri: axum::http::Uri) -> impl IntoResponse {
             // For SPA routing, serve index.html for non-API routes
             if !path.starts_with("api/") {
                 if let Some(index) = Asset::get("index.html") {
-                    return Response::builder()
-                        .status(StatusCode::OK)
-                        .header(header::CONTENT_TYPE, "text/html")
-                        .body(Body::from(index.data.to_vec()))
-                        .unwrap();
+                    return index_response(&state, &headers, index.data.to_vec());
                 }
             }
             Response::builder()
@@ -313,11 +430,13 @@ async fn connection_status(State(state): State<Arc<AppState>>) -> Json<Connectio
     let node_addr = state.node_addr.lock().await;
     let conn_str = state.connection_string.lock().await;
     let conn_alias = state.connection_alias.lock().await;
+    let health = state.remote_fs.lock().await.as_ref().map(|fs| fs.health());
 
     Json(ConnectionStatusResponse {
         connected: node_addr.is_some(),
         connection_string: conn_str.clone(),
         connection_alias: conn_alias.clone(),
+        health,
     })
 }
 
@@ -326,6 +445,8 @@ struct ConnectionStatusResponse {
     connected: bool,
     connection_string: Option<String>,
     connection_alias: Option<String>,
+    /// File browser stream health (None when not connected)
+    health: Option<crate::custom_explorer::FsHealth>,
 }
 
 /// List registered connections
@@ -339,6 +460,71 @@ async fn list_connections() -> Result<Json<crate::auth::ConnectionsListResponse>
     }
 }
 
+#[derive(Deserialize)]
+struct ConnectionStringQuery {
+    alias: String,
+}
+
+#[derive(Serialize)]
+struct ConnectionStringResponse {
+    alias: String,
+    host_name: String,
+    connection_string: String,
+    /// CLI commands for this connection, as printed by `kerr ls`
+    commands: ConnectionCommands,
+}
+
+#[derive(Serialize)]
+struct ConnectionCommands {
+    connect: String,
+    send: String,
+    pull: String,
+    browse: String,
+}
+
+/// Get the stored connection string for a registered alias. Requires a login session
+/// and the token cookie, so other local programs and web pages can't read it.
+async fn connection_string_for_alias(
+    State(state): State<Arc<AppState>>,
+    headers: axum::http::HeaderMap,
+    Query(query): Query<ConnectionStringQuery>,
+) -> Result<Json<ConnectionStringResponse>, (StatusCode, String)> {
+    if !is_loopback_host(&headers) || !has_ui_token(&headers, &state.ui_token) {
+        return Err((StatusCode::FORBIDDEN, "Missing or wrong UI token; reload the page".to_string()));
+    }
+    if crate::auth::load_session().is_err() {
+        return Err((StatusCode::UNAUTHORIZED, "Not logged in".to_string()));
+    }
+
+    let response = crate::auth::fetch_connections().await.map_err(|e| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("Failed to fetch connections: {}", e),
+        )
+    })?;
+
+    let connection = response
+        .connections
+        .into_iter()
+        .find(|c| c.alias.as_deref() == Some(query.alias.as_str()))
+        .ok_or_else(|| (StatusCode::NOT_FOUND, format!("No connection with alias '{}'", query.alias)))?;
+
+    let conn_str = &connection.connection_string;
+    let commands = ConnectionCommands {
+        connect: format!("kerr connect {}", conn_str),
+        send: format!("kerr send {} <local> <remote>", conn_str),
+        pull: format!("kerr pull {} <remote> <local>", conn_str),
+        browse: format!("kerr browse {}", conn_str),
+    };
+
+    Ok(Json(ConnectionStringResponse {
+        alias: query.alias,
+        host_name: connection.host_name,
+        connection_string: connection.connection_string,
+        commands,
+    }))
+}
+
 #[derive(Deserialize)]
 struct ConnectRequest {
     connection_string: String,
@@ -471,6 +657,26 @@ enum TerminalMessage {
     Resize { cols: u16, rows: u16 },
 }
 
+/// Length of `bytes` up to (not including) a trailing UTF-8 character that is
+/// still missing continuation bytes. Invalid bytes are left for lossy decoding.
+fn complete_utf8_len(bytes: &[u8]) -> usize {
+    // A character is at most 4 bytes, so its lead byte is within the last 3
+    for back in 1..=bytes.len().min(3) {
+        let byte = bytes[bytes.len() - back];
+        if byte & 0xC0 == 0x80 {
+            continue; // continuation byte, keep looking for the lead
+        }
+        let needed = match byte {
+            0xC0..=0xDF => 2,
+            0xE0..=0xEF => 3,
+            0xF0..=0xF7 => 4,
+            _ => 1,
+        };
+        return if needed > back { bytes.len() - back } else { bytes.len() };
+    }
+    bytes.len()
+}
+
 /// Handle shell WebSocket connection
 async fn handle_shell_socket(socket: WebSocket, state: Arc<AppState>) {
     eprintln!("[HANDLE_SHELL_SOCKET] Function entered!");
@@ -554,6 +760,8 @@ async fn handle_shell_socket(socket: WebSocket, state: Arc<AppState>) {
         debug_log::log_quic_to_ws_task_started(&session_id_shell_to_ws);
         let mut recv_guard = recv_clone.lock().await;
         let mut msg_count = 0;
+        // Trailing bytes of a UTF-8 character split across Output chunks
+        let mut pending_utf8: Vec<u8> = Vec::new();
         loop {
             // Receive envelope
             debug_log::log_quic_read_start(&session_id_shell_to_ws);
@@ -583,8 +791,14 @@ async fn handle_shell_socket(socket: WebSocket, state: Arc<AppState>) {
             msg_count += 1;
             match msg {
                 crate::ServerMessage::Output { data } => {
-                    // Convert bytes to string for WebSocket
-                    let text = String::from_utf8_lossy(&data).to_string();
+                    // Convert bytes to string for WebSocket, holding back a split character
+                    pending_utf8.extend_from_slice(&data);
+                    let complete = complete_utf8_len(&pending_utf8);
+                    let text = String::from_utf8_lossy(&pending_utf8[..complete]).to_string();
+                    pending_utf8.drain(..complete);
+                    if text.is_empty() {
+                        continue;
+                    }
                     eprintln!("[WS->SHELL] Sending output to WebSocket: {} bytes", text.len());
                     debug_log::log_ws_msg_sent(&session_id_shell_to_ws, text.len());
                     if let Err(e) = ws_sender.send(Message::Text(text.into())).await {
@@ -864,7 +1078,6 @@ async fn read_file(
 }
 
 #[derive(Deserialize)]
-#[allow(dead_code)]
 struct WriteFileRequest {
     path: String,
     content: String,
@@ -876,24 +1089,11 @@ struct WriteFileResponse {
     message: String,
 }
 
-/// Write file content (placeholder - not implemented yet in RemoteFilesystem)
+/// Write file content, replacing the file (missing parent directories are created)
 async fn write_file(
-    State(_state): State<Arc<AppState>>,
-    Json(_request): Json<WriteFileRequest>,
-) -> Result<Json<WriteFileResponse>, (StatusCode, String)> {
-    // Note: This would require adding write support to the RemoteFilesystem
-    // and the corresponding server-side handling. For now, return not implemented.
-    Err((
-        StatusCode::NOT_IMPLEMENTED,
-        "File writing not yet implemented".to_string(),
-    ))
-}
-
-/// Download a file
-async fn download_file(
     State(state): State<Arc<AppState>>,
-    Query(query): Query<FilePathQuery>,
-) -> Result<Response, (StatusCode, String)> {
+    Json(request): Json<WriteFileRequest>,
+) -> Result<Json<WriteFileResponse>, (StatusCode, String)> {
     // Get the remote filesystem
     let remote_fs = {
         let fs_lock = state.remote_fs.lock().await;
@@ -908,47 +1108,120 @@ async fn download_file(
         }
     };
 
-    let path = PathBuf::from(&query.path);
+    let path = PathBuf::from(&request.path);
 
-    // Read the file content
-    match remote_fs.read_file(&path).await {
-        Ok(content) => {
-            // Extract filename from path
-            let filename = path
-                .file_name()
-                .and_then(|n| n.to_str())
-                .unwrap_or("download");
-
-            // Determine MIME type
-            let mime_type = mime_guess::from_path(&path)
-                .first_or_octet_stream()
-                .to_string();
-
-            // Build response with appropriate headers
-            let response = Response::builder()
-                .status(StatusCode::OK)
-                .header(header::CONTENT_TYPE, mime_type)
-                .header(
-                    header::CONTENT_DISPOSITION,
-                    format!("attachment; filename=\"{}\"", filename),
-                )
-                .body(Body::from(content))
-                .map_err(|e| {
-                    (
-                        StatusCode::INTERNAL_SERVER_ERROR,
-                        format!("Failed to build response: {}", e),
-                    )
-                })?;
-
-            Ok(response)
-        }
+    match remote_fs.write_file(&path, request.content.as_bytes()).await {
+        Ok(()) => Ok(Json(WriteFileResponse {
+            success: true,
+            message: format!("Wrote {} bytes", request.content.len()),
+        })),
         Err(e) => Err((
             StatusCode::INTERNAL_SERVER_ERROR,
-            format!("Failed to read file: {}", e),
+            format!("Failed to write file: {}", e),
         )),
     }
 }
 
+/// Download a file
+///
+/// Streams the file over its own transfer session so the browser sees bytes
+/// (and its native progress bar) as they arrive instead of after a full read.
+async fn download_file(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<FilePathQuery>,
+) -> Result<Response, (StatusCode, String)> {
+    let connection = {
+        let conn_lock = state.connection.lock().await;
+        match conn_lock.as_ref() {
+            Some(conn) => Arc::clone(conn),
+            None => {
+                return Err((
+                    StatusCode::SERVICE_UNAVAILABLE,
+                    "Not connected to remote host".to_string(),
+                ))
+            }
+        }
+    };
+
+    let internal = |e: String| (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to read file: {}", e));
+
+    let (mut send, mut recv) = connection.open_bi().await.map_err(|e| internal(e.to_string()))?;
+    let session_id = format!("web_download_{}", rand::random::<u64>());
+    for msg in [
+        crate::ClientMessage::Hello { session_type: crate::SessionType::FileTransfer },
+        crate::ClientMessage::RequestDownload { path: query.path.clone(), offset: 0 },
+    ] {
+        let envelope = crate::MessageEnvelope {
+            session_id: session_id.clone(),
+            payload: crate::MessagePayload::Client(msg),
+        };
+        crate::send_envelope(&mut send, &envelope).await.map_err(|e| internal(e.to_string()))?;
+    }
+
+    let size = match crate::recv_envelope(&mut recv).await.map_err(|e| internal(e.to_string()))?.payload {
+        crate::MessagePayload::Server(crate::ServerMessage::StartDownload { size, is_dir: false }) => size,
+        crate::MessagePayload::Server(crate::ServerMessage::StartDownload { is_dir: true, .. }) => {
+            return Err((StatusCode::BAD_REQUEST, format!("{} is a directory", query.path)));
+        }
+        crate::MessagePayload::Server(crate::ServerMessage::Error { message }) => {
+            return Err(internal(message));
+        }
+        _ => return Err(internal("unexpected server response".to_string())),
+    };
+
+    let path = PathBuf::from(&query.path);
+    let filename = path
+        .file_name()
+        .and_then(|n| n.to_str())
+        .unwrap_or("download")
+        .to_string();
+    let mime_type = mime_guess::from_path(&path)
+        .first_or_octet_stream()
+        .to_string();
+
+    // The send half rides along so the session stays open until the body is done
+    let guard = TransferGuard::start(&state.transfers, &query.path, "download", size);
+    let chunks = futures::stream::unfold(Some((send, recv, guard)), |session| async move {
+        let (send, mut recv, guard) = session?;
+        loop {
+            let envelope = match crate::recv_envelope(&mut recv).await {
+                Ok(envelope) => envelope,
+                Err(e) => return Some((Err(std::io::Error::other(e.to_string())), None)),
+            };
+            match envelope.payload {
+                crate::MessagePayload::Server(crate::ServerMessage::FileChunk { data }) => {
+                    guard.add(data.len() as u64);
+                    return Some((Ok(axum::body::Bytes::from(data)), Some((send, recv, guard))));
+                }
+                crate::MessagePayload::Server(crate::ServerMessage::FileAttributes { .. }) => {}
+                crate::MessagePayload::Server(crate::ServerMessage::EndDownload) => return None,
+                crate::MessagePayload::Server(crate::ServerMessage::Error { message }) => {
+                    return Some((Err(std::io::Error::other(message)), None));
+                }
+                _ => {
+                    return Some((Err(std::io::Error::other("unexpected server message during download")), None));
+                }
+            }
+        }
+    });
+
+    Response::builder()
+        .status(StatusCode::OK)
+        .header(header::CONTENT_TYPE, mime_type)
+        .header(header::CONTENT_LENGTH, size)
+        .header(
+            header::CONTENT_DISPOSITION,
+            format!("attachment; filename=\"{}\"", filename),
+        )
+        .body(Body::from_stream(chunks))
+        .map_err(|e| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Failed to build response: {}", e),
+            )
+        })
+}
+
 /// Upload a file
 async fn upload_file(
     State(state): State<Arc<AppState>>,
@@ -1014,7 +1287,8 @@ async fn upload_file(
     };
 
     let path = std::path::PathBuf::from(&target_path);
-    remote_fs.upload_file(&path, &file_data).await.map_err(|e| {
+    let guard = TransferGuard::start(&state.transfers, &target_path, "upload", file_data.len() as u64);
+    remote_fs.upload_file_with_progress(&path, &file_data, &|n| guard.add(n)).await.map_err(|e| {
         (
             StatusCode::INTERNAL_SERVER_ERROR,
             format!("Failed to upload file: {}", e),
@@ -1027,10 +1301,65 @@ async fn upload_file(
     })))
 }
 
+/// One entry of /api/transfers
+#[derive(Serialize)]
+struct TransferStatus {
+    id: String,
+    path: String,
+    direction: &'static str,
+    bytes_transferred: u64,
+    total_bytes: u64,
+    elapsed_ms: u64,
+    bytes_per_sec: u64,
+}
+
+/// List web UI uploads/downloads in flight with their current throughput
+async fn list_transfers(State(state): State<Arc<AppState>>) -> Json<Vec<TransferStatus>> {
+    let transfers = state.transfers.lock().unwrap_or_else(|e| e.into_inner());
+    let statuses = transfers
+        .iter()
+        .map(|(id, transfer)| {
+            let bytes = transfer.bytes.load(std::sync::atomic::Ordering::Relaxed);
+            let elapsed = transfer.started.elapsed();
+            TransferStatus {
+                id: id.clone(),
+                path: transfer.path.clone(),
+                direction: transfer.direction,
+                bytes_transferred: bytes,
+                total_bytes: transfer.total_bytes,
+                elapsed_ms: elapsed.as_millis() as u64,
+                bytes_per_sec: (bytes as f64 / elapsed.as_secs_f64().max(0.001)) as u64,
+            }
+        })
+        .collect();
+    Json(statuses)
+}
+
+#[derive(Deserialize)]
+struct DeleteQuery {
+    path: String,
+    /// Delete a non-empty directory with everything in it
+    #[serde(default)]
+    recursive: bool,
+    /// Token from the 409 reply to a plain delete of the same directory
+    token: Option<String>,
+}
+
+/// How long a recursive delete's confirmation token stays valid
+const DELETE_TOKEN_TTL: std::time::Duration = std::time::Duration::from_secs(5 * 60);
+
+/// Forget confirmation tokens older than `DELETE_TOKEN_TTL`
+fn expire_delete_tokens(tokens: &mut HashMap<String, (String, std::time::Instant)>) {
+    tokens.retain(|_, (_, issued)| issued.elapsed() < DELETE_TOKEN_TTL);
+}
+
 /// Delete a file or directory
+///
+/// A non-empty directory is refused with 409 and a `confirm_token`; the frontend asks the
+/// user and repeats the request with `recursive=true` and that token.
 async fn delete_file(
     State(state): State<Arc<AppState>>,
-    Query(query): Query<FilePathQuery>,
+    Query(query): Query<DeleteQuery>,
 ) -> Result<Json<serde_json::Value>, (StatusCode, String)> {
     // Get the remote filesystem
     let remote_fs = {
@@ -1048,7 +1377,38 @@ async fn delete_file(
 
     let path = PathBuf::from(&query.path);
 
-    match remote_fs.delete_file(&path).await {
+    let result = if query.recursive {
+        let confirmed = query.token.as_ref().is_some_and(|token| {
+            let mut tokens = state.delete_tokens.lock().unwrap();
+            expire_delete_tokens(&mut tokens);
+            tokens.get(token).is_some_and(|(path, _)| *path == query.path) && tokens.remove(token).is_some()
+        });
+        if !confirmed {
+            return Err((
+                StatusCode::FORBIDDEN,
+                "Recursive delete needs the confirmation token from a previous delete request".to_string(),
+            ));
+        }
+        remote_fs.delete_recursive(&path).await
+    } else {
+        match remote_fs.delete_file(&path).await {
+            Err(_) if remote_fs.is_dir(&path).await.unwrap_or(false) => {
+                let token = format!("{:016x}", rand::random::<u64>());
+                let mut tokens = state.delete_tokens.lock().unwrap();
+                expire_delete_tokens(&mut tokens);
+                tokens.insert(token.clone(), (query.path.clone(), std::time::Instant::now()));
+                drop(tokens);
+                let body = serde_json::json!({
+                    "error": "Directory is not empty",
+                    "confirm_token": token,
+                });
+                return Err((StatusCode::CONFLICT, body.to_string()));
+            }
+            result => result,
+        }
+    };
+
+    match result {
         Ok(()) => Ok(Json(serde_json::json!({
             "success": true,
         }))),
@@ -1059,6 +1419,113 @@ async fn delete_file(
     }
 }
 
+#[derive(Deserialize)]
+struct MkdirRequest {
+    path: String,
+}
+
+/// Create a directory (and any missing parents)
+async fn create_directory(
+    State(state): State<Arc<AppState>>,
+    Json(request): Json<MkdirRequest>,
+) -> Result<Json<serde_json::Value>, (StatusCode, String)> {
+    // Get the remote filesystem
+    let remote_fs = {
+        let fs_lock = state.remote_fs.lock().await;
+        match fs_lock.as_ref() {
+            Some(fs) => Arc::clone(fs),
+            None => {
+                return Err((
+                    StatusCode::SERVICE_UNAVAILABLE,
+                    "Not connected to remote host".to_string(),
+                ))
+            }
+        }
+    };
+
+    match remote_fs.create_dir(&PathBuf::from(&request.path)).await {
+        Ok(()) => Ok(Json(serde_json::json!({
+            "success": true,
+        }))),
+        Err(e) => Err((
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("Failed to create directory: {}", e),
+        )),
+    }
+}
+
+#[derive(Deserialize)]
+struct BatchRequest {
+    ops: Vec<crate::FsOp>,
+}
+
+#[derive(Serialize)]
+struct BatchResponse {
+    results: Vec<crate::FsOpResult>,
+}
+
+/// Run several file operations (mkdir/delete/rename/chmod) in one round trip
+async fn batch_files(
+    State(state): State<Arc<AppState>>,
+    Json(request): Json<BatchRequest>,
+) -> Result<Json<BatchResponse>, (StatusCode, String)> {
+    // Get the remote filesystem
+    let remote_fs = {
+        let fs_lock = state.remote_fs.lock().await;
+        match fs_lock.as_ref() {
+            Some(fs) => Arc::clone(fs),
+            None => {
+                return Err((
+                    StatusCode::SERVICE_UNAVAILABLE,
+                    "Not connected to remote host".to_string(),
+                ))
+            }
+        }
+    };
+
+    match remote_fs.batch(request.ops).await {
+        Ok(results) => Ok(Json(BatchResponse { results })),
+        Err(e) => Err((
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("Failed to run batch: {}", e),
+        )),
+    }
+}
+
+/// Server file size limits in bytes (null = unlimited)
+#[derive(Serialize)]
+struct FileLimitsResponse {
+    max_file_read: Option<u64>,
+    max_upload: Option<u64>,
+}
+
+/// Get the remote server's file size limits so the UI can refuse oversized files up front
+async fn file_limits(
+    State(state): State<Arc<AppState>>,
+) -> Result<Json<FileLimitsResponse>, (StatusCode, String)> {
+    // Get the remote filesystem
+    let remote_fs = {
+        let fs_lock = state.remote_fs.lock().await;
+        match fs_lock.as_ref() {
+            Some(fs) => Arc::clone(fs),
+            None => {
+                return Err((
+                    StatusCode::SERVICE_UNAVAILABLE,
+                    "Not connected to remote host".to_string(),
+                ))
+            }
+        }
+    };
+
+    match remote_fs.limits().await {
+        Ok((max_file_read, max_upload)) => Ok(Json(FileLimitsResponse { max_file_read, max_upload })),
+        Err(e) => Err((
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("Failed to get file limits: {}", e),
+        )),
+    }
+}
+
 /// Request to create a port forwarding
 #[derive(Deserialize)]
 struct CreatePortForwardRequest {
@@ -1434,3 +1901,57 @@ async fn disconnect_port_forward(
         ))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderMap;
+
+    fn headers(pairs: &[(header::HeaderName, &str)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, value) in pairs {
+            headers.append(name.clone(), value.parse().unwrap());
+        }
+        headers
+    }
+
+    #[test]
+    fn only_loopback_host_names_are_local() {
+        for host in ["127.0.0.1:3000", "localhost:3000", "localhost", "[::1]:3000"] {
+            assert!(is_loopback_host(&headers(&[(header::HOST, host)])), "{}", host);
+        }
+        for host in ["evil.example.com:3000", "127.0.0.1.evil.example.com", "localhost.evil:3000"] {
+            assert!(!is_loopback_host(&headers(&[(header::HOST, host)])), "{}", host);
+        }
+        assert!(!is_loopback_host(&HeaderMap::new()));
+    }
+
+    #[test]
+    fn ui_token_must_match_the_cookie() {
+        let cookie = |value: &str| headers(&[(header::COOKIE, value)]);
+        assert!(has_ui_token(&cookie("kerr_ui_token=secret"), "secret"));
+        assert!(has_ui_token(&cookie("theme=dark; kerr_ui_token=secret"), "secret"));
+        assert!(!has_ui_token(&cookie("kerr_ui_token=guess"), "secret"));
+        assert!(!has_ui_token(&cookie("other=secret"), "secret"));
+        assert!(!has_ui_token(&HeaderMap::new(), "secret"));
+    }
+
+    #[test]
+    fn split_utf8_characters_wait_for_the_next_chunk() {
+        for text in ["é", "€", "😀"] {
+            let bytes = format!("ab{}", text).into_bytes();
+            // Every cut inside the character holds it back whole
+            for cut in 3..bytes.len() {
+                assert_eq!(complete_utf8_len(&bytes[..cut]), 2, "{:?} cut at {}", text, cut);
+                let mut joined = bytes[2..cut].to_vec();
+                joined.extend_from_slice(&bytes[cut..]);
+                assert_eq!(complete_utf8_len(&joined), joined.len());
+                assert_eq!(std::str::from_utf8(&joined).unwrap(), text);
+            }
+            assert_eq!(complete_utf8_len(&bytes), bytes.len());
+        }
+        // Invalid bytes aren't held back
+        assert_eq!(complete_utf8_len(b"ab\xff"), 3);
+        assert_eq!(complete_utf8_len(b"\x80\x80\x80"), 3);
+    }
+}